@@ -0,0 +1,8 @@
+// build.rs
+// Generates the gRPC service from its protobuf definition. protox compiles it, so no protoc is needed
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let descriptors = protox::compile(["proto/restaurant.proto"], ["proto"])?;
+    tonic_build::configure().compile_fds(descriptors)?;
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
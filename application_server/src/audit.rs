@@ -0,0 +1,244 @@
+// src/audit.rs
+use crate::errors::{request_id, REQUEST_ID_HEADER};
+use crate::models::{Menu, OrderResponse, PaymentResponse, PromotionResponse, ServiceChargeRuleResponse, SubBillResponse, Table, Webhook};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::env;
+use std::future::Future;
+use std::time::Duration;
+use warp::http::{HeaderMap, HeaderValue, Method, Request};
+use warp::hyper::service::Service;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+/// Staff member making the request, e.g. their login or badge number
+pub const ACTOR_HEADER: &str = "x-staff-id";
+/// Terminal the request is made from, the User-Agent is recorded without it
+pub const DEVICE_HEADER: &str = "x-device-id";
+
+/// Days entries are kept if AUDIT_RETENTION_DAYS is not set
+const RETENTION_DAYS: i64 = 90;
+
+/// How often entries past the retention are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+tokio::task_local! {
+    static CONTEXT: Context;
+}
+
+/// Who makes a request and how, the changes made while it is served are audited with it
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub request_id: String,
+    method: String,
+    path: String,
+    actor: Option<String>,
+    device: Option<String>,
+}
+
+impl Context {
+    /// The context of a request, its id is the X-Request-Id it was sent with if that can be used
+    pub fn new(method: &str, path: &str, headers: &HeaderMap) -> Context {
+        let header = |name: &str| {
+            let value = headers.get(name)?.to_str().ok()?.trim();
+            (!value.is_empty()).then(|| value.chars().take(100).collect::<String>())
+        };
+        Context {
+            request_id: request_id(headers.clone()),
+            method: method.to_string(),
+            path: path.to_string(),
+            actor: header(ACTOR_HEADER),
+            device: header(DEVICE_HEADER).or_else(|| header("user-agent")),
+        }
+    }
+
+    /// Run a future serving the request, what it changes is audited with this context
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CONTEXT.scope(self, future).await
+    }
+
+    /// Run a call serving the request, what it changes is audited with this context
+    pub fn sync_scope<T>(self, run: impl FnOnce() -> T) -> T {
+        CONTEXT.sync_scope(self, run)
+    }
+}
+
+/// Requests with these methods only read, they are not audited
+pub fn changes(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// What the audit log records changes of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entity {
+    Table,
+    Menu,
+    Order,
+    Payment,
+    SubBill,
+    Promotion,
+    ServiceChargeRule,
+    Webhook,
+}
+
+impl Entity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Entity::Table => "table",
+            Entity::Menu => "menu",
+            Entity::Order => "order",
+            Entity::Payment => "payment",
+            Entity::SubBill => "sub_bill",
+            Entity::Promotion => "promotion",
+            Entity::ServiceChargeRule => "service_charge_rule",
+            Entity::Webhook => "webhook",
+        }
+    }
+}
+
+/// An entity as the API shows it, to be passed to record after it is changed. None if it does not exist,
+/// or outside of a request as nothing is audited then
+pub fn snapshot(conn: &Connection, entity: Entity, id: i64) -> rusqlite::Result<Option<Value>> {
+    match CONTEXT.try_with(|_| ()) {
+        Ok(()) => read(conn, entity, id),
+        Err(_) => Ok(None),
+    }
+}
+
+fn read(conn: &Connection, entity: Entity, id: i64) -> rusqlite::Result<Option<Value>> {
+    fn value<T: Serialize>(entity: Option<T>) -> Option<Value> {
+        entity.and_then(|entity| serde_json::to_value(entity).ok())
+    }
+    Ok(match entity {
+        Entity::Table => value(Table::get(conn, id)?),
+        Entity::Menu => value(Menu::get(conn, id)?),
+        Entity::Order => value(OrderResponse::get(conn, id)?),
+        Entity::Payment => value(PaymentResponse::get(conn, id)?),
+        Entity::SubBill => value(SubBillResponse::get(conn, id)?),
+        Entity::Promotion => value(PromotionResponse::list(conn)?.into_iter().find(|promotion| promotion.id == id)),
+        Entity::ServiceChargeRule => value(ServiceChargeRuleResponse::list(conn)?.into_iter().find(|rule| rule.id == id)),
+        Entity::Webhook => value(Webhook::get(conn, id)?),
+    })
+}
+
+/// Write a change to an entity to the audit log, with its snapshot from before the change and as it is now.
+/// Call it in the transaction of the change so the two are kept or lost together. Changes made outside of a
+/// request, e.g. from the command line, are not audited
+pub fn record(conn: &Connection, entity: Entity, id: i64, before: Option<Value>) -> rusqlite::Result<()> {
+    let Ok(context) = CONTEXT.try_with(Context::clone) else {
+        return Ok(());
+    };
+    let after = read(conn, entity, id)?;
+    // Nothing the API shows was there before or after, e.g. the entity was not found
+    if before.is_none() && after.is_none() {
+        return Ok(());
+    }
+    insert(conn, &context, None, Some((entity, id)), before, after)
+}
+
+/// Give the changes of a request the status it was answered with once it was. A request that failed without
+/// changing anything is written on its own, so refused attempts are audited too. Failing to audit does not fail the request
+pub fn finish(conn: &Connection, context: &Context, status: u16) {
+    let result = conn
+        .execute("UPDATE audit_log SET status = ?1 WHERE request_id = ?2 AND status IS NULL", params![status, context.request_id])
+        .and_then(|updated| match updated == 0 && !(200..300).contains(&status) {
+            true => insert(conn, context, Some(status), None, None, None),
+            false => Ok(()),
+        });
+    if let Err(err) = result {
+        eprintln!("[{}] Failed to write the audit log: {}", context.request_id, err);
+    }
+}
+
+/// Serve an HTTP request in its audit context. Requests that only read are passed on as they are, without touching the database
+pub async fn serve<S>(mut service: S, connect: impl FnOnce() -> Connection, mut req: Request<Body>) -> Result<Response, S::Error>
+where
+    S: Service<Request<Body>, Response = Response>,
+{
+    if !changes(req.method()) {
+        return service.call(req).await;
+    }
+    let context = Context::new(req.method().as_str(), req.uri().path(), req.headers());
+    // The response is stamped with the same id
+    if let Ok(request_id) = HeaderValue::from_str(&context.request_id) {
+        req.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+    let resp = context.clone().scope(service.call(req)).await?;
+    finish(&connect(), &context, resp.status().as_u16());
+    Ok(resp)
+}
+
+fn insert(conn: &Connection, context: &Context, status: Option<u16>, entity: Option<(Entity, i64)>, before: Option<Value>, after: Option<Value>) -> rusqlite::Result<()> {
+    let changes = diff(before.as_ref().unwrap_or(&Value::Null), after.as_ref().unwrap_or(&Value::Null));
+    let to_json = |value: Option<Value>| value.as_ref().map(Value::to_string);
+    conn.execute(
+        "INSERT INTO audit_log (request_id, actor, device, method, path, status, entity_type, entity_id, before, after, diff)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            context.request_id,
+            context.actor,
+            context.device,
+            context.method,
+            context.path,
+            status,
+            entity.map(|(entity, _)| entity.as_str()),
+            entity.map(|(_, id)| id),
+            to_json(before),
+            to_json(after),
+            Value::Object(changes).to_string(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// The values that differ between two versions of an entity by JSON pointer, e.g.
+/// {"/menus/0/quantity": {"before": 1, "after": 2}}. An entity made or deleted is one change at ""
+pub fn diff(before: &Value, after: &Value) -> Map<String, Value> {
+    let mut changes = Map::new();
+    compare(String::new(), before, after, &mut changes);
+    changes
+}
+
+fn compare(pointer: String, before: &Value, after: &Value, changes: &mut Map<String, Value>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+            for key in keys {
+                let field = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                compare(field, before.get(key).unwrap_or(&Value::Null), after.get(key).unwrap_or(&Value::Null), changes);
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            for index in 0..before.len().max(after.len()) {
+                compare(format!("{}/{}", pointer, index), before.get(index).unwrap_or(&Value::Null), after.get(index).unwrap_or(&Value::Null), changes);
+            }
+        }
+        _ if before != after => {
+            changes.insert(pointer, json!({"before": before, "after": after}));
+        }
+        _ => {}
+    }
+}
+
+/// Days the audit log is kept, configured with e.g. AUDIT_RETENTION_DAYS=365
+pub fn retention_days() -> i64 {
+    env::var("AUDIT_RETENTION_DAYS").ok().and_then(|days| days.parse().ok()).filter(|days| *days > 0).unwrap_or(RETENTION_DAYS)
+}
+
+/// Delete the entries older than a number of days, returns how many were deleted
+pub fn prune(conn: &Connection, days: i64) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM audit_log WHERE created_at < datetime('now', ?1)", params![format!("-{} days", days)])
+}
+
+/// Delete the entries past the retention every hour until the server stops
+pub async fn prune_periodically(connect: impl Fn() -> Connection) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = prune(&connect(), retention_days()) {
+            eprintln!("Failed to prune the audit log: {}", err);
+        }
+    }
+}
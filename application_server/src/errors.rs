@@ -0,0 +1,229 @@
+// src/errors.rs
+use crate::payments::PaymentError;
+use crate::validation::{field_errors, FieldError};
+use rand::Rng;
+use async_graphql::ErrorExtensions;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tonic::metadata::MetadataValue;
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::reply::{Reply, Response};
+
+/// Header carrying the request id, taken from the request if the client sent a usable one
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Every error an endpoint can respond with. Each maps to a status code and a stable code
+/// clients can match on. Database and internal errors are logged and never shown to clients
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request can't be handled as sent, details can explain what is wrong
+    BadRequest(String, Option<Value>),
+    /// The request is well formed but breaks the rules of its fields, one error per failed rule
+    Validation(Vec<FieldError>),
+    Forbidden(String),
+    NotFound(String),
+    MethodNotAllowed,
+    Conflict(String),
+    PreconditionFailed(String),
+    PaymentRequired(String),
+    Unavailable(String),
+    Database(rusqlite::Error),
+    Internal(String),
+}
+
+/// Body of every error response
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ErrorEnvelope {
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<Value>,
+    pub request_id: Option<String>,
+    #[serde(skip)]
+    internal: Option<String>, // What went wrong inside, only logged
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::BadRequest(message.into(), None)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(..) => StatusCode::BAD_REQUEST,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::PaymentRequired(_) => StatusCode::PAYMENT_REQUIRED,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(..) => "bad_request",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::PaymentRequired(_) => "payment_required",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Database(_) | ApiError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn envelope(self) -> ErrorEnvelope {
+        let code = self.code();
+        let (message, details, internal) = match self {
+            ApiError::BadRequest(message, details) => (message, details, None),
+            ApiError::Validation(errors) => ("Request validation failed".to_string(), Some(json!(errors)), None),
+            ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::PreconditionFailed(message)
+            | ApiError::PaymentRequired(message)
+            | ApiError::Unavailable(message) => (message, None, None),
+            ApiError::MethodNotAllowed => ("Method not allowed".to_string(), None, None),
+            ApiError::Database(err) => ("Internal server error".to_string(), None, Some(err.to_string())),
+            ApiError::Internal(err) => ("Internal server error".to_string(), None, Some(err)),
+        };
+        ErrorEnvelope { code, message, details, request_id: None, internal }
+    }
+}
+
+// Extended result codes of constraint violations, not exported by this version of rusqlite
+const SQLITE_CONSTRAINT_FOREIGNKEY: i32 = rusqlite::ffi::SQLITE_CONSTRAINT | (3 << 8);
+const SQLITE_CONSTRAINT_PRIMARYKEY: i32 = rusqlite::ffi::SQLITE_CONSTRAINT | (6 << 8);
+const SQLITE_CONSTRAINT_UNIQUE: i32 = rusqlite::ffi::SQLITE_CONSTRAINT | (8 << 8);
+
+/// Constraint violations are the client's fault, anything else from the database is internal
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> ApiError {
+        match &err {
+            rusqlite::Error::SqliteFailure(failure, _) if failure.code == rusqlite::ErrorCode::ConstraintViolation => {
+                match failure.extended_code {
+                    SQLITE_CONSTRAINT_UNIQUE | SQLITE_CONSTRAINT_PRIMARYKEY => {
+                        ApiError::Conflict("Resource already exists".to_string())
+                    }
+                    SQLITE_CONSTRAINT_FOREIGNKEY => ApiError::bad_request("A referenced resource does not exist"),
+                    _ => ApiError::bad_request("A required value is missing or invalid"),
+                }
+            }
+            _ => ApiError::Database(err),
+        }
+    }
+}
+
+impl From<validator::ValidationErrors> for ApiError {
+    fn from(errors: validator::ValidationErrors) -> ApiError {
+        ApiError::Validation(field_errors(&errors))
+    }
+}
+
+impl From<PaymentError> for ApiError {
+    fn from(err: PaymentError) -> ApiError {
+        match err {
+            PaymentError::Declined(_) => ApiError::PaymentRequired(err.to_string()),
+            PaymentError::Unavailable(_) => ApiError::Unavailable(err.to_string()),
+        }
+    }
+}
+
+/// GraphQL errors carry the code and details of the error envelope as extensions. Internal errors are only logged
+impl From<ApiError> for async_graphql::Error {
+    fn from(err: ApiError) -> async_graphql::Error {
+        let mut envelope = err.envelope();
+        if let Some(internal) = envelope.internal.take() {
+            eprintln!("[graphql] {}", internal);
+        }
+        let details = envelope.details.and_then(|details| async_graphql::Value::from_json(details).ok());
+        async_graphql::Error::new(envelope.message).extend_with(|_, extensions| {
+            extensions.set("code", envelope.code);
+            if let Some(details) = details {
+                extensions.set("details", details);
+            }
+        })
+    }
+}
+
+/// gRPC errors carry the code of the error envelope in the error-code metadata and its details as JSON
+/// in error-details. Internal errors are only logged
+impl From<ApiError> for tonic::Status {
+    fn from(err: ApiError) -> tonic::Status {
+        let code = match err {
+            ApiError::BadRequest(..) | ApiError::Validation(_) => tonic::Code::InvalidArgument,
+            ApiError::Forbidden(_) => tonic::Code::PermissionDenied,
+            ApiError::NotFound(_) => tonic::Code::NotFound,
+            ApiError::MethodNotAllowed => tonic::Code::Unimplemented,
+            ApiError::Conflict(_) | ApiError::PaymentRequired(_) => tonic::Code::FailedPrecondition,
+            ApiError::PreconditionFailed(_) => tonic::Code::Aborted, // The order changed since the client saw it
+            ApiError::Unavailable(_) => tonic::Code::Unavailable,
+            ApiError::Database(_) | ApiError::Internal(_) => tonic::Code::Internal,
+        };
+        let mut envelope = err.envelope();
+        if let Some(internal) = envelope.internal.take() {
+            eprintln!("[grpc] {}", internal);
+        }
+        let mut status = tonic::Status::new(code, envelope.message);
+        status.metadata_mut().insert("error-code", MetadataValue::from_static(envelope.code));
+        if let Some(details) = envelope.details.and_then(|details| MetadataValue::try_from(details.to_string()).ok()) {
+            status.metadata_mut().insert("error-details", details);
+        }
+        status
+    }
+}
+
+impl Reply for ApiError {
+    /// The envelope is kept in the response extensions so the request id can be filled in later
+    fn into_response(self) -> Response {
+        let status = self.status();
+        envelope_response(status, self.envelope())
+    }
+}
+
+fn envelope_response(status: StatusCode, envelope: ErrorEnvelope) -> Response {
+    let mut resp = warp::reply::with_status(warp::reply::json(&envelope), status).into_response();
+    resp.extensions_mut().insert(envelope);
+    resp
+}
+
+/// Turn the result of a handler into its response, errors become the error envelope
+pub fn respond<T: Reply>(result: Result<T, ApiError>) -> Result<Response, warp::Rejection> {
+    match result {
+        Ok(reply) => Ok(reply.into_response()),
+        Err(err) => Ok(err.into_response()),
+    }
+}
+
+/// Request id of the client if it is usable, otherwise a new one
+pub fn request_id(headers: HeaderMap) -> String {
+    match headers.get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok()) {
+        Some(id) if !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') => id.to_string(),
+        _ => format!("{:016x}", rand::thread_rng().gen::<u64>()),
+    }
+}
+
+/// Add the request id to a response. Error envelopes get it in the body too and internal
+/// errors are logged with it
+pub fn stamp_request_id(request_id: String, reply: impl Reply) -> Response {
+    let mut resp = reply.into_response();
+    if let Some(mut envelope) = resp.extensions_mut().remove::<ErrorEnvelope>() {
+        if let Some(internal) = envelope.internal.take() {
+            eprintln!("[{}] {}", request_id, internal);
+        }
+        envelope.request_id = Some(request_id.clone());
+        // Only the body changes, headers sent more than once like Link or Set-Cookie are kept as they are
+        *resp.body_mut() = warp::hyper::Body::from(serde_json::to_vec(&envelope).unwrap_or_default());
+        resp.extensions_mut().insert(envelope);
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    resp
+}
@@ -0,0 +1,376 @@
+// src/events.rs
+use crate::models::{OrderItemResponse, OrderState};
+use rand::Rng;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+
+/// Events a slow subscriber may fall behind by before it misses some
+const CAPACITY: usize = 256;
+
+/// Events kept for reconnecting clients to catch up on
+const HISTORY: usize = 1024;
+
+/// How often the outbox is checked for events committed without a wake up
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What happened to a table, a menu, an order or what is on it, a promotion or a service charge rule
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    TableCreated, // Or brought back from the archive
+    TableUpdated,
+    TableArchived,
+    TableDeleted,
+    ServerAssigned, // To a table, or taken off it
+    MenuCreated,
+    MenuUpdated,
+    MenuArchived,
+    MenuDeleted,
+    OrderCreated,
+    OrderClosed, // Fully paid
+    OrderDeleted, // Its last item was removed
+    OrderMoved, // To another table
+    ItemAdded,
+    ItemUpdated, // The quantity changed
+    ItemRemoved,
+    PaymentCaptured,
+    PaymentRefunded,
+    PaymentVoided,
+    CouponRedeemed,
+    DiscountGiven, // By a manager
+    PromotionCreated,
+    ServiceChargeRuleCreated,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 23] = [
+        EventKind::TableCreated,
+        EventKind::TableUpdated,
+        EventKind::TableArchived,
+        EventKind::TableDeleted,
+        EventKind::ServerAssigned,
+        EventKind::MenuCreated,
+        EventKind::MenuUpdated,
+        EventKind::MenuArchived,
+        EventKind::MenuDeleted,
+        EventKind::OrderCreated,
+        EventKind::OrderClosed,
+        EventKind::OrderDeleted,
+        EventKind::OrderMoved,
+        EventKind::ItemAdded,
+        EventKind::ItemUpdated,
+        EventKind::ItemRemoved,
+        EventKind::PaymentCaptured,
+        EventKind::PaymentRefunded,
+        EventKind::PaymentVoided,
+        EventKind::CouponRedeemed,
+        EventKind::DiscountGiven,
+        EventKind::PromotionCreated,
+        EventKind::ServiceChargeRuleCreated,
+    ];
+
+    pub fn parse(kind: &str) -> Option<EventKind> {
+        EventKind::ALL.into_iter().find(|known| known.as_str() == kind)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::TableCreated => "table_created",
+            EventKind::TableUpdated => "table_updated",
+            EventKind::TableArchived => "table_archived",
+            EventKind::TableDeleted => "table_deleted",
+            EventKind::ServerAssigned => "server_assigned",
+            EventKind::MenuCreated => "menu_created",
+            EventKind::MenuUpdated => "menu_updated",
+            EventKind::MenuArchived => "menu_archived",
+            EventKind::MenuDeleted => "menu_deleted",
+            EventKind::OrderCreated => "order_created",
+            EventKind::OrderClosed => "order_closed",
+            EventKind::OrderDeleted => "order_deleted",
+            EventKind::OrderMoved => "order_moved",
+            EventKind::ItemAdded => "item_added",
+            EventKind::ItemUpdated => "item_updated",
+            EventKind::ItemRemoved => "item_removed",
+            EventKind::PaymentCaptured => "payment_captured",
+            EventKind::PaymentRefunded => "payment_refunded",
+            EventKind::PaymentVoided => "payment_voided",
+            EventKind::CouponRedeemed => "coupon_redeemed",
+            EventKind::DiscountGiven => "discount_given",
+            EventKind::PromotionCreated => "promotion_created",
+            EventKind::ServiceChargeRuleCreated => "service_charge_rule_created",
+        }
+    }
+
+    pub fn is_item(self) -> bool {
+        matches!(self, EventKind::ItemAdded | EventKind::ItemUpdated | EventKind::ItemRemoved)
+    }
+}
+
+/// A change to the data published to live subscribers, after it was committed
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub seq: u64, // Goes up by one per event published since the server started
+    pub kind: EventKind,
+    pub table_id: Option<i64>, // None for changes to menus, promotions and service charge rules
+    pub order_id: Option<i64>, // None for changes to the table itself
+    pub item_id: Option<i64>,
+    pub entity_id: Option<i64>, // The menu, payment, coupon promotion, discount, promotion or rule that changed
+    pub station: Option<String>, // Kitchen station of the menu of the item
+    pub item: Option<OrderItemResponse>, // The item as it is now, None once removed
+}
+
+struct Bus {
+    epoch: u32, // Tells the resume tokens of this run of the server from those of an earlier one
+    sender: broadcast::Sender<Event>,
+    history: Mutex<History>,
+    committed: Notify, // Wakes the dispatcher when events were committed to the outbox
+    publishing: Mutex<()>, // Keeps two dispatchers from publishing the same events
+}
+
+struct History {
+    last_seq: u64,
+    events: VecDeque<Event>,
+}
+
+impl History {
+    /// Events after seq, None if some of them are no longer kept or seq was never published
+    fn after(&self, seq: u64) -> Option<Vec<Event>> {
+        let oldest = self.events.front().map_or(self.last_seq + 1, |event| event.seq);
+        if seq > self.last_seq || seq + 1 < oldest {
+            return None;
+        }
+        Some(self.events.iter().filter(|event| event.seq > seq).cloned().collect())
+    }
+}
+
+fn bus() -> &'static Bus {
+    static BUS: OnceLock<Bus> = OnceLock::new();
+    BUS.get_or_init(|| Bus {
+        epoch: rand::thread_rng().gen(),
+        sender: broadcast::channel(CAPACITY).0,
+        history: Mutex::new(History { last_seq: 0, events: VecDeque::with_capacity(HISTORY) }),
+        committed: Notify::new(),
+        publishing: Mutex::new(()),
+    })
+}
+
+/// Publishing never fails, so a poisoned history is still usable
+fn history() -> MutexGuard<'static, History> {
+    bus().history.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Receive the events published from now on
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    bus().sender.subscribe()
+}
+
+/// Position after an event of this run of the server, parsed from a resume token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResumePoint {
+    epoch: u32,
+    seq: u64,
+}
+
+impl ResumePoint {
+    pub fn parse(token: &str) -> Option<ResumePoint> {
+        let (epoch, seq) = token.split_once('.')?;
+        Some(ResumePoint { epoch: u32::from_str_radix(epoch, 16).ok()?, seq: seq.parse().ok()? })
+    }
+}
+
+/// Token a client sends back to resume after the event with seq, e.g. 9f86d081.42
+pub fn resume_token(seq: u64) -> String {
+    format!("{:08x}.{}", bus().epoch, seq)
+}
+
+/// Events after a resume point and a receiver of the events published after them
+pub struct Subscription {
+    pub last_seq: u64, // The newest event published before the receiver was made
+    pub missed: Option<Vec<Event>>, // None if they can't be replayed, from an earlier run or no longer kept
+    pub receiver: broadcast::Receiver<Event>,
+}
+
+/// Subscribe from a resume point, or from now on. No event is missed or repeated between the two
+pub fn subscribe_from(point: Option<ResumePoint>) -> Subscription {
+    let history = history();
+    let receiver = bus().sender.subscribe();
+    let missed = match point {
+        Some(point) if point.epoch == bus().epoch => history.after(point.seq),
+        Some(_) => None,
+        None => Some(Vec::new()),
+    };
+    Subscription { last_seq: history.last_seq, missed, receiver }
+}
+
+/// The newest event published
+pub fn latest_seq() -> u64 {
+    history().last_seq
+}
+
+/// Events after seq that are still kept, to catch up a subscriber that fell behind
+pub fn since(seq: u64) -> Option<Vec<Event>> {
+    history().after(seq)
+}
+
+fn publish(mut event: Event) {
+    let mut history = history();
+    history.last_seq += 1;
+    event.seq = history.last_seq;
+    if history.events.len() == HISTORY {
+        history.events.pop_front();
+    }
+    history.events.push_back(event.clone());
+    // Nobody listening is not an error
+    let _ = bus().sender.send(event);
+}
+
+/// The order as it is now, to be logged with a change to it
+fn order_of(conn: &Connection, order_id: i64) -> rusqlite::Result<OrderState> {
+    OrderState::header(conn, order_id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+/// What an event is about, besides its kind
+#[derive(Default)]
+struct Subject<'a> {
+    table_id: Option<i64>,
+    order: Option<&'a OrderState>,
+    item: Option<&'a OrderItemResponse>,
+    entity_id: Option<i64>,
+}
+
+/// Write an event to the outbox. Call it in the transaction of the change so the two are kept or lost together.
+/// An event made at a given time is written as published already
+fn record(conn: &Connection, kind: EventKind, subject: Subject, at: Option<&str>) -> rusqlite::Result<()> {
+    let Subject { table_id, order, item, entity_id } = subject;
+    let item_json = item.map(|item| serde_json::to_string(item).unwrap_or_default());
+    let order_json = order.map(|order| serde_json::to_string(order).unwrap_or_default());
+    conn.execute(
+        "INSERT INTO domain_events (kind, table_id, order_id, item_id, entity_id, station, item, order_state, created_at, published_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, COALESCE(?9, CURRENT_TIMESTAMP), ?9)",
+        params![
+            kind.as_str(), table_id, order.map(|order| order.id), item.map(|item| item.id), entity_id,
+            item.and_then(|item| item.station.clone()), item_json, order_json, at,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Record a change to a table itself
+pub fn record_table(conn: &Connection, kind: EventKind, table_id: i64) -> rusqlite::Result<()> {
+    record(conn, kind, Subject { table_id: Some(table_id), ..Subject::default() }, None)
+}
+
+/// Record a change to an order. Call it while the order still exists
+pub fn record_order(conn: &Connection, kind: EventKind, order_id: i64) -> rusqlite::Result<()> {
+    let order = order_of(conn, order_id)?;
+    record(conn, kind, Subject { table_id: Some(order.table_id), order: Some(&order), ..Subject::default() }, None)
+}
+
+/// Record a change to something on an order, a payment, coupon or discount, with the order as it is after it
+pub fn record_on_order(conn: &Connection, kind: EventKind, order_id: i64, entity_id: i64) -> rusqlite::Result<()> {
+    let order = order_of(conn, order_id)?;
+    record(conn, kind, Subject { table_id: Some(order.table_id), order: Some(&order), entity_id: Some(entity_id), ..Subject::default() }, None)
+}
+
+/// Record a change to an item of an order, with the item as it is now or as it was before its removal.
+/// Call it while the order still exists
+pub fn record_item(conn: &Connection, kind: EventKind, item: &OrderItemResponse) -> rusqlite::Result<()> {
+    let order = order_of(conn, item.order_id)?;
+    record(conn, kind, Subject { table_id: Some(order.table_id), order: Some(&order), item: Some(item), ..Subject::default() }, None)
+}
+
+/// Record a change to a menu, promotion or service charge rule, which are not on a table
+pub fn record_entity(conn: &Connection, kind: EventKind, entity_id: i64) -> rusqlite::Result<()> {
+    record(conn, kind, Subject { entity_id: Some(entity_id), ..Subject::default() }, None)
+}
+
+/// Record a change made at a time before changes were logged, with the order as it was then.
+/// It is not published, live subscribers only get what happens while they listen
+pub fn record_past(conn: &Connection, kind: EventKind, order: &OrderState, item: Option<&OrderItemResponse>, at: &str) -> rusqlite::Result<()> {
+    record(conn, kind, Subject { table_id: Some(order.table_id), order: Some(order), item, ..Subject::default() }, Some(at))
+}
+
+/// Read the kind of an event from a column of the log
+pub fn kind_at(row: &rusqlite::Row, index: usize) -> rusqlite::Result<EventKind> {
+    let kind: String = row.get(index)?;
+    EventKind::parse(&kind).ok_or(rusqlite::Error::InvalidColumnType(index, kind, rusqlite::types::Type::Text))
+}
+
+/// Tell the dispatcher events were committed to the outbox
+pub fn committed() {
+    bus().committed.notify_one();
+}
+
+/// An event of the outbox, the item is kept for removals too
+struct Recorded {
+    id: i64,
+    kind: EventKind,
+    table_id: Option<i64>,
+    order_id: Option<i64>,
+    item_id: Option<i64>,
+    entity_id: Option<i64>,
+    station: Option<String>,
+    item: Option<OrderItemResponse>,
+}
+
+impl Recorded {
+    fn into_event(self) -> Event {
+        let item = self.item.filter(|_| self.kind != EventKind::ItemRemoved);
+        Event { seq: 0, kind: self.kind, table_id: self.table_id, order_id: self.order_id, item_id: self.item_id, entity_id: self.entity_id, station: self.station, item }
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Recorded> {
+        let item: Option<String> = row.get(7)?;
+        Ok(Recorded {
+            id: row.get(0)?,
+            kind: kind_at(row, 1)?,
+            table_id: row.get(2)?,
+            order_id: row.get(3)?,
+            item_id: row.get(4)?,
+            entity_id: row.get(5)?,
+            station: row.get(6)?,
+            item: item.and_then(|item| serde_json::from_str(&item).ok()),
+        })
+    }
+}
+
+/// Publish the events of the outbox not published yet, in the order they were committed. Returns how many were published
+pub fn publish_pending(conn: &Connection) -> rusqlite::Result<usize> {
+    let _publishing = bus().publishing.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut stmt = conn.prepare("SELECT id, kind, table_id, order_id, item_id, entity_id, station, item FROM domain_events WHERE published_at IS NULL ORDER BY id")?;
+    let rows = stmt.query_map([], Recorded::from_row)?;
+    let pending: Vec<Recorded> = rows.collect::<Result<_, _>>()?;
+    let Some(last_id) = pending.last().map(|recorded| recorded.id) else {
+        return Ok(0);
+    };
+    let count = pending.len();
+    pending.into_iter().for_each(|recorded| publish(recorded.into_event()));
+    conn.execute("UPDATE domain_events SET published_at = CURRENT_TIMESTAMP WHERE published_at IS NULL AND id <= ?1", params![last_id])?;
+    Ok(count)
+}
+
+/// Events of the log after an id with their ids, oldest first and at most limit of them. For consumers that keep their
+/// own place in the log, the events are not published and have no seq
+pub fn logged_after(conn: &Connection, after_id: i64, limit: i64) -> rusqlite::Result<Vec<(i64, Event)>> {
+    let mut stmt = conn.prepare("SELECT id, kind, table_id, order_id, item_id, entity_id, station, item FROM domain_events WHERE id > ?1 ORDER BY id LIMIT ?2")?;
+    let rows = stmt.query_map(params![after_id, limit], Recorded::from_row)?;
+    rows.map(|recorded| recorded.map(|recorded| (recorded.id, recorded.into_event()))).collect()
+}
+
+/// Publish the events of the outbox as they are committed, until the server stops.
+/// Events left unpublished by an earlier run are published first
+pub async fn dispatch(connect: impl Fn() -> Connection) {
+    loop {
+        if let Err(err) = publish_pending(&connect()) {
+            eprintln!("Failed to publish events: {}", err);
+        }
+        tokio::select! {
+            _ = bus().committed.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
@@ -0,0 +1,414 @@
+// src/graphql.rs
+use crate::errors::ApiError;
+use crate::events::{self, Event, EventKind};
+use crate::handlers::{place_order, remove_order_item, set_item_quantity};
+use crate::models::{
+    BillResponse, Menu, MenuListQuery, MenuResponse, OrderItem, OrderItemPatch, OrderItemResponse, OrderLine, OrderListQuery,
+    OrderRequestBody, OrderSummary, Table, TableListQuery, TableResponse, MENU_SORTS, ORDER_SORTS, TABLE_SORTS,
+};
+use crate::pagination::{CursorValue, Page, PageRequest};
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{ComplexObject, Context, Data, Enum, InputObject, Object, OutputType, SimpleObject, Subscription};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use validator::Validate;
+
+pub type RestaurantSchema = async_graphql::Schema<Query, Mutation, Subscription>;
+
+pub fn schema() -> RestaurantSchema {
+    async_graphql::Schema::build(Query, Mutation, Subscription).finish()
+}
+
+/// Database connection of a request or subscription connection, shared by its resolvers and loaders
+#[derive(Clone)]
+struct Db(Arc<Mutex<Connection>>);
+
+impl Db {
+    fn run<T>(&self, run: impl FnOnce(&Connection) -> Result<T, ApiError>) -> async_graphql::Result<T> {
+        let conn = self.0.lock().map_err(|_| ApiError::Internal("Database connection lock is poisoned".to_string()))?;
+        Ok(run(&conn)?)
+    }
+}
+
+/// Context data of a request or subscription connection, loaders batch the reads of nested fields
+/// so a list of orders loads its items in one query and not one per order
+pub fn data(conn: Connection) -> Data {
+    let db = Db(Arc::new(Mutex::new(conn)));
+    let mut data = Data::default();
+    data.insert(DataLoader::new(TablesById(db.clone()), tokio::spawn));
+    data.insert(DataLoader::new(MenusById(db.clone()), tokio::spawn));
+    data.insert(DataLoader::new(OrdersByTable(db.clone()), tokio::spawn));
+    data.insert(DataLoader::new(ItemsByOrder(db.clone()), tokio::spawn));
+    data.insert(DataLoader::new(BillByOrder(db.clone()), tokio::spawn));
+    data.insert(db);
+    data
+}
+
+/// Run a request on its own database connection
+pub async fn execute(schema: &RestaurantSchema, conn: Connection, mut request: async_graphql::Request) -> async_graphql::Response {
+    request.data = data(conn);
+    schema.execute(request).await
+}
+
+fn db<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a Db> {
+    ctx.data::<Db>()
+}
+
+// Loaders
+
+struct TablesById(Db);
+struct MenusById(Db);
+struct OrdersByTable(Db);
+struct ItemsByOrder(Db);
+struct BillByOrder(Db);
+
+impl Loader<i64> for TablesById {
+    type Value = TableResponse;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, TableResponse>, async_graphql::Error> {
+        let tables = self.0.run(|conn| Ok(Table::get_many(conn, keys)?))?;
+        Ok(tables.into_iter().map(|table| (table.id, table)).collect())
+    }
+}
+
+impl Loader<i64> for MenusById {
+    type Value = MenuResponse;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, MenuResponse>, async_graphql::Error> {
+        let menus = self.0.run(|conn| Ok(Menu::get_many(conn, keys)?))?;
+        Ok(menus.into_iter().map(|menu| (menu.id, menu)).collect())
+    }
+}
+
+impl Loader<i64> for OrdersByTable {
+    type Value = Vec<OrderSummary>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, Vec<OrderSummary>>, async_graphql::Error> {
+        let orders = self.0.run(|conn| Ok(OrderSummary::for_tables(conn, keys)?))?;
+        let mut by_table: HashMap<i64, Vec<OrderSummary>> = HashMap::new();
+        for order in orders {
+            by_table.entry(order.table_id).or_default().push(order);
+        }
+        Ok(by_table)
+    }
+}
+
+impl Loader<i64> for ItemsByOrder {
+    type Value = Vec<OrderItemResponse>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, Vec<OrderItemResponse>>, async_graphql::Error> {
+        let items = self.0.run(|conn| Ok(OrderItem::list_for_orders(conn, keys)?))?;
+        let mut by_order: HashMap<i64, Vec<OrderItemResponse>> = HashMap::new();
+        for item in items {
+            by_order.entry(item.order_id).or_default().push(item);
+        }
+        Ok(by_order)
+    }
+}
+
+/// Bills apply promotions, discounts and service charges per order, so this still takes queries per order.
+/// It keeps them to the orders whose bill was asked for
+impl Loader<i64> for BillByOrder {
+    type Value = Bill;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, Bill>, async_graphql::Error> {
+        self.0.run(|conn| {
+            let mut bills = HashMap::new();
+            for order_id in keys {
+                if let Some(bill) = BillResponse::for_order(conn, *order_id)? {
+                    bills.insert(*order_id, Bill::from(bill));
+                }
+            }
+            Ok(bills)
+        })
+    }
+}
+
+// Types
+
+/// Bill figures of an order, amounts are in cents
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Bill {
+    subtotal: i64,
+    discounts: i64, // Promotions and manual discounts, negative
+    service_charge: i64,
+    total: i64,
+    tips: i64,
+}
+
+impl From<BillResponse> for Bill {
+    fn from(bill: BillResponse) -> Bill {
+        Bill {
+            subtotal: bill.subtotal,
+            discounts: bill.adjustments.iter().map(|adjustment| adjustment.amount).sum(),
+            service_charge: bill.service_charge,
+            total: bill.total,
+            tips: bill.tips,
+        }
+    }
+}
+
+/// A page of a listing, pass next_cursor as after to get the next one
+#[derive(SimpleObject)]
+#[graphql(concrete(name = "TablePage", params(TableResponse)))]
+#[graphql(concrete(name = "MenuPage", params(MenuResponse)))]
+#[graphql(concrete(name = "OrderPage", params(OrderSummary)))]
+pub struct Listing<T: OutputType> {
+    nodes: Vec<T>,
+    next_cursor: Option<String>,
+}
+
+impl<T: OutputType> Listing<T> {
+    fn new(rows: Vec<T>, page: &PageRequest, cursor_of: impl Fn(&T, &str) -> (CursorValue, i64)) -> Listing<T> {
+        let page = Page::new(rows, page, cursor_of, |_| String::new());
+        Listing { nodes: page.data, next_cursor: page.next_cursor }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum OrderStatus {
+    Open,
+    Closed,
+}
+
+impl OrderStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Open => "open",
+            OrderStatus::Closed => "closed",
+        }
+    }
+}
+
+#[ComplexObject]
+impl TableResponse {
+    /// Orders of the table, the newest first
+    async fn orders(&self, ctx: &Context<'_>, status: Option<OrderStatus>) -> async_graphql::Result<Vec<OrderSummary>> {
+        let orders = ctx.data::<DataLoader<OrdersByTable>>()?.load_one(self.id).await?.unwrap_or_default();
+        Ok(orders.into_iter().filter(|order| status.is_none_or(|status| order.status == status.as_str())).collect())
+    }
+
+    /// The order the table is being served on, if any
+    async fn open_order(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<OrderSummary>> {
+        let orders = ctx.data::<DataLoader<OrdersByTable>>()?.load_one(self.id).await?.unwrap_or_default();
+        Ok(orders.into_iter().find(|order| order.status == OrderStatus::Open.as_str()))
+    }
+}
+
+#[ComplexObject]
+impl OrderSummary {
+    async fn table(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<TableResponse>> {
+        ctx.data::<DataLoader<TablesById>>()?.load_one(self.table_id).await
+    }
+
+    async fn items(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<OrderItemResponse>> {
+        Ok(ctx.data::<DataLoader<ItemsByOrder>>()?.load_one(self.id).await?.unwrap_or_default())
+    }
+
+    async fn bill(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Bill>> {
+        ctx.data::<DataLoader<BillByOrder>>()?.load_one(self.id).await
+    }
+}
+
+#[ComplexObject]
+impl OrderItemResponse {
+    /// The menu as it is now, unit_price is what it cost when ordered
+    async fn menu(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<MenuResponse>> {
+        ctx.data::<DataLoader<MenusById>>()?.load_one(self.menu_id).await
+    }
+}
+
+// Queries
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// A page of the tables that are not archived, sort by id or code with - for descending
+    async fn tables(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        after: Option<String>,
+        sort: Option<String>,
+        code: Option<String>,
+        section: Option<String>,
+    ) -> async_graphql::Result<Listing<TableResponse>> {
+        let query = TableListQuery { limit, after, sort, code, section };
+        db(ctx)?.run(|conn| {
+            query.validate()?;
+            let page = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), TABLE_SORTS)?;
+            Ok(Listing::new(Table::list(conn, &query, &page)?, &page, TableResponse::cursor))
+        })
+    }
+
+    /// A table, archived or not
+    async fn table(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<TableResponse>> {
+        ctx.data::<DataLoader<TablesById>>()?.load_one(id).await
+    }
+
+    /// A page of the menus that are not archived, sort by id, name or price with - for descending
+    async fn menus(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        after: Option<String>,
+        sort: Option<String>,
+        name: Option<String>,
+        category: Option<String>,
+    ) -> async_graphql::Result<Listing<MenuResponse>> {
+        let query = MenuListQuery { limit, after, sort, name, category };
+        db(ctx)?.run(|conn| {
+            query.validate()?;
+            let page = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), MENU_SORTS)?;
+            Ok(Listing::new(Menu::list(conn, &query, &page)?, &page, MenuResponse::cursor))
+        })
+    }
+
+    /// A menu, archived or not
+    async fn menu(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<MenuResponse>> {
+        ctx.data::<DataLoader<MenusById>>()?.load_one(id).await
+    }
+
+    /// A page of orders, sort by id or created_at with - for descending
+    #[allow(clippy::too_many_arguments)]
+    async fn orders(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        after: Option<String>,
+        sort: Option<String>,
+        status: Option<OrderStatus>,
+        table_id: Option<i64>,
+        created_from: Option<String>,
+        created_to: Option<String>,
+    ) -> async_graphql::Result<Listing<OrderSummary>> {
+        let status = status.map(|status| status.as_str().to_string());
+        let query = OrderListQuery { limit, after, sort, status, table_id, created_from, created_to };
+        db(ctx)?.run(|conn| {
+            query.validate()?;
+            let page = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), ORDER_SORTS)?;
+            Ok(Listing::new(OrderSummary::list(conn, &query, &page)?, &page, OrderSummary::cursor))
+        })
+    }
+
+    async fn order(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<OrderSummary>> {
+        db(ctx)?.run(|conn| Ok(OrderSummary::get_many(conn, &[id])?.pop()))
+    }
+
+    /// Items of an order
+    async fn items(&self, ctx: &Context<'_>, order_id: i64) -> async_graphql::Result<Vec<OrderItemResponse>> {
+        Ok(ctx.data::<DataLoader<ItemsByOrder>>()?.load_one(order_id).await?.unwrap_or_default())
+    }
+}
+
+// Mutations
+
+/// A line of an order, the same menu for the same seat and note adds to the quantity
+#[derive(InputObject)]
+pub struct OrderLineInput {
+    menu_id: i64,
+    #[graphql(default = 1)]
+    quantity: i64,
+    note: Option<String>,
+    seat: Option<i64>,
+}
+
+#[derive(InputObject)]
+pub struct OrderInput {
+    table_id: i64,
+    items: Vec<OrderLineInput>,
+    party_size: Option<i64>,
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Order for a table, the items are added to its open order if it has one. Returns the order
+    async fn create_order(&self, ctx: &Context<'_>, input: OrderInput) -> async_graphql::Result<OrderSummary> {
+        let items = input.items.into_iter().map(|line| OrderLine { menu_id: line.menu_id, quantity: line.quantity, note: line.note, seat: line.seat });
+        let req_body = OrderRequestBody { table_id: input.table_id, menu_ids: vec![], items: items.collect(), party_size: input.party_size };
+        db(ctx)?.run(|conn| {
+            let (_, body) = place_order(conn, req_body)?;
+            let order_id = body["id"].as_i64().ok_or_else(|| ApiError::Internal("Placed order has no id".to_string()))?;
+            OrderSummary::get_many(conn, &[order_id])?.pop().ok_or_else(|| ApiError::NotFound("No Order Found".to_string()))
+        })
+    }
+
+    /// Set the quantity of an item of an open order. if_match is the ETag of the order the client last saw
+    async fn set_item_quantity(
+        &self,
+        ctx: &Context<'_>,
+        order_id: i64,
+        item_id: i64,
+        quantity: i64,
+        if_match: Option<String>,
+    ) -> async_graphql::Result<OrderItemResponse> {
+        let patch = OrderItemPatch { quantity };
+        db(ctx)?.run(|conn| Ok(set_item_quantity(conn, order_id, item_id, if_match.as_deref(), &patch)?.0))
+    }
+
+    /// Remove an item of an open order. Returns the order, null if it was deleted with its last item
+    async fn remove_item(&self, ctx: &Context<'_>, order_id: i64, item_id: i64, if_match: Option<String>) -> async_graphql::Result<Option<OrderSummary>> {
+        db(ctx)?.run(|conn| {
+            remove_order_item(conn, order_id, item_id, if_match.as_deref())?;
+            Ok(OrderSummary::get_many(conn, &[order_id])?.pop())
+        })
+    }
+}
+
+// Subscriptions
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum ItemChangeKind {
+    Added,
+    Updated,
+    Removed,
+}
+
+/// A change to an item of an order, made through GraphQL or REST
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ItemChange {
+    kind: ItemChangeKind,
+    table_id: i64,
+    order_id: i64,
+    item_id: i64,
+    item: Option<OrderItemResponse>, // Null once removed
+}
+
+impl ItemChange {
+    /// The change of an item event, None for changes to the order itself
+    fn from_event(event: Event) -> Option<ItemChange> {
+        let kind = match event.kind {
+            EventKind::ItemAdded => ItemChangeKind::Added,
+            EventKind::ItemUpdated => ItemChangeKind::Updated,
+            EventKind::ItemRemoved => ItemChangeKind::Removed,
+            _ => return None,
+        };
+        Some(ItemChange { kind, table_id: event.table_id?, order_id: event.order_id?, item_id: event.item_id?, item: event.item })
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Items added, changed or removed from now on, of one order or table or of all of them.
+    /// A subscriber that falls too far behind skips the changes it missed
+    async fn item_changes(&self, order_id: Option<i64>, table_id: Option<i64>) -> impl Stream<Item = ItemChange> {
+        BroadcastStream::new(events::subscribe()).filter_map(move |event| {
+            let event = event.ok()?;
+            let wanted = order_id.is_none_or(|id| Some(id) == event.order_id) && table_id.is_none_or(|id| Some(id) == event.table_id);
+            wanted.then_some(event).and_then(ItemChange::from_event)
+        })
+    }
+}
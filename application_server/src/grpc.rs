@@ -0,0 +1,338 @@
+// src/grpc.rs
+// tonic::Status is large but it is the error type of the generated service
+#![allow(clippy::result_large_err)]
+use crate::audit;
+use crate::db::get_db_conn;
+use crate::errors::{ApiError, REQUEST_ID_HEADER};
+use crate::events::{self, Event, EventKind};
+use crate::handlers::{create_menu, create_table, place_order, remove_order_item, set_item_quantity};
+use crate::models::{
+    Menu, MenuListQuery, MenuResponse, OrderItem, OrderItemPatch, OrderItemResponse, OrderLine, OrderListQuery, OrderRequestBody,
+    OrderResponse, OrderSummary, Table, TableListQuery, TableResponse, MENU_SORTS, ORDER_SORTS, TABLE_SORTS,
+};
+use crate::pagination::{CursorValue, Page, PageRequest};
+use rusqlite::Connection;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+use validator::Validate;
+use warp::http::{HeaderMap, HeaderValue};
+
+/// Messages and the service generated from proto/restaurant.proto
+pub mod proto {
+    tonic::include_proto!("restaurant");
+}
+
+use proto::order_update::Kind;
+use proto::restaurant_server::{Restaurant, RestaurantServer};
+
+/// Address of the gRPC service, localhost:50051 unless configured with e.g. GRPC_ADDR=0.0.0.0:50051
+pub fn address() -> SocketAddr {
+    std::env::var("GRPC_ADDR").map_or_else(
+        |_| ([127, 0, 0, 1], 50051).into(),
+        |addr| addr.parse().unwrap_or_else(|_| panic!("GRPC_ADDR must be host:port, not {}", addr)),
+    )
+}
+
+/// Serve the gRPC service on its own port, next to the REST API.
+/// Calls are not authenticated, x-staff-id and x-device-id are trusted as sent, so only bind it where the devices are trusted
+pub async fn serve(addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(RestaurantServer::new(RestaurantService::new(get_db_conn)))
+        .serve(addr)
+        .await
+}
+
+/// The gRPC service, every call opens its own database connection as the REST routes do
+pub struct RestaurantService {
+    connect: Box<dyn Fn() -> Connection + Send + Sync>,
+}
+
+impl RestaurantService {
+    pub fn new(connect: impl Fn() -> Connection + Send + Sync + 'static) -> RestaurantService {
+        RestaurantService { connect: Box::new(connect) }
+    }
+
+    fn run<T>(&self, run: impl FnOnce(&Connection) -> Result<T, ApiError>) -> Result<Response<T>, Status> {
+        let conn = (self.connect)();
+        Ok(Response::new(run(&conn)?))
+    }
+
+    /// Run a call that changes something in the audit context of its request, who made it is read from the
+    /// x-staff-id and x-device-id metadata as from the headers of the REST routes
+    fn change<T>(&self, context: audit::Context, run: impl FnOnce(&Connection) -> Result<T, ApiError>) -> Result<Response<T>, Status> {
+        let conn = (self.connect)();
+        let result = context.clone().sync_scope(|| run(&conn));
+        let status = match &result {
+            Ok(_) => 200,
+            Err(err) => err.status().as_u16(),
+        };
+        audit::finish(&conn, &context, status);
+        Ok(Response::new(result?))
+    }
+}
+
+/// The audit context of a call, its path is that of the gRPC method
+fn context<T>(request: &Request<T>, method: &str) -> audit::Context {
+    let mut headers = HeaderMap::new();
+    for name in [REQUEST_ID_HEADER, audit::ACTOR_HEADER, audit::DEVICE_HEADER, "user-agent"] {
+        if let Some(value) = request.metadata().get(name).and_then(|value| HeaderValue::from_bytes(value.as_bytes()).ok()) {
+            headers.insert(name, value);
+        }
+    }
+    audit::Context::new("POST", &format!("/restaurant.Restaurant/{}", method), &headers)
+}
+
+/// Rows of a page and the cursor of the next one
+fn page<T>(rows: Vec<T>, page: &PageRequest, cursor_of: impl Fn(&T, &str) -> (CursorValue, i64)) -> (Vec<T>, Option<String>) {
+    let page = Page::new(rows, page, cursor_of, |_| String::new());
+    (page.data, page.next_cursor)
+}
+
+#[tonic::async_trait]
+impl Restaurant for RestaurantService {
+    async fn list_tables(&self, request: Request<proto::ListTablesRequest>) -> Result<Response<proto::TablePage>, Status> {
+        let proto::ListTablesRequest { limit, after, sort, code, section } = request.into_inner();
+        let query = TableListQuery { limit, after, sort, code, section };
+        self.run(|conn| {
+            query.validate()?;
+            let request = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), TABLE_SORTS)?;
+            let (tables, next_cursor) = page(Table::list(conn, &query, &request)?, &request, TableResponse::cursor);
+            Ok(proto::TablePage { tables: tables.into_iter().map(proto::Table::from).collect(), next_cursor })
+        })
+    }
+
+    async fn get_table(&self, request: Request<proto::GetTableRequest>) -> Result<Response<proto::Table>, Status> {
+        let table_id = request.into_inner().id;
+        self.run(|conn| {
+            let table = Table::get(conn, table_id)?.ok_or_else(|| ApiError::NotFound("No Table Found".to_string()))?;
+            Ok(proto::Table::from(table))
+        })
+    }
+
+    async fn create_table(&self, request: Request<proto::CreateTableRequest>) -> Result<Response<proto::Table>, Status> {
+        let context = context(&request, "CreateTable");
+        let proto::CreateTableRequest { code, section, table_type, server } = request.into_inner();
+        let data = Table { id: 0, code, section, table_type, server };
+        self.change(context, |conn| {
+            let table_id = create_table(conn, &data)?;
+            let table = Table::get(conn, table_id)?.ok_or_else(|| ApiError::NotFound("No Table Found".to_string()))?;
+            Ok(proto::Table::from(table))
+        })
+    }
+
+    async fn list_menus(&self, request: Request<proto::ListMenusRequest>) -> Result<Response<proto::MenuPage>, Status> {
+        let proto::ListMenusRequest { limit, after, sort, name, category } = request.into_inner();
+        let query = MenuListQuery { limit, after, sort, name, category };
+        self.run(|conn| {
+            query.validate()?;
+            let request = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), MENU_SORTS)?;
+            let (menus, next_cursor) = page(Menu::list(conn, &query, &request)?, &request, MenuResponse::cursor);
+            Ok(proto::MenuPage { menus: menus.into_iter().map(proto::Menu::from).collect(), next_cursor })
+        })
+    }
+
+    async fn get_menu(&self, request: Request<proto::GetMenuRequest>) -> Result<Response<proto::Menu>, Status> {
+        let menu_id = request.into_inner().id;
+        self.run(|conn| {
+            let menu = Menu::get(conn, menu_id)?.ok_or_else(|| ApiError::NotFound("No Menu Found".to_string()))?;
+            Ok(proto::Menu::from(menu))
+        })
+    }
+
+    async fn create_menu(&self, request: Request<proto::CreateMenuRequest>) -> Result<Response<proto::Menu>, Status> {
+        let context = context(&request, "CreateMenu");
+        let proto::CreateMenuRequest { name, price, category, station } = request.into_inner();
+        let data = Menu { id: 0, name, price, category, station };
+        self.change(context, |conn| {
+            let menu_id = create_menu(conn, &data)?;
+            let menu = Menu::get(conn, menu_id)?.ok_or_else(|| ApiError::NotFound("No Menu Found".to_string()))?;
+            Ok(proto::Menu::from(menu))
+        })
+    }
+
+    async fn list_orders(&self, request: Request<proto::ListOrdersRequest>) -> Result<Response<proto::OrderPage>, Status> {
+        let proto::ListOrdersRequest { limit, after, sort, status, table_id, created_from, created_to } = request.into_inner();
+        let query = OrderListQuery { limit, after, sort, status, table_id, created_from, created_to };
+        self.run(|conn| {
+            query.validate()?;
+            let request = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), ORDER_SORTS)?;
+            let (orders, next_cursor) = page(OrderSummary::list(conn, &query, &request)?, &request, OrderSummary::cursor);
+            Ok(proto::OrderPage { orders: orders.into_iter().map(proto::Order::from).collect(), next_cursor })
+        })
+    }
+
+    async fn get_order(&self, request: Request<proto::GetOrderRequest>) -> Result<Response<proto::Order>, Status> {
+        let order_id = request.into_inner().id;
+        self.run(|conn| order(conn, order_id))
+    }
+
+    async fn create_order(&self, request: Request<proto::CreateOrderRequest>) -> Result<Response<proto::Order>, Status> {
+        let context = context(&request, "CreateOrder");
+        let proto::CreateOrderRequest { table_id, items, party_size } = request.into_inner();
+        let items = items.into_iter().map(|line| OrderLine { menu_id: line.menu_id, quantity: line.quantity.unwrap_or(1), note: line.note, seat: line.seat });
+        let req_body = OrderRequestBody { table_id, menu_ids: vec![], items: items.collect(), party_size };
+        self.change(context, |conn| {
+            let (_, body) = place_order(conn, req_body)?;
+            let order_id = body["id"].as_i64().ok_or_else(|| ApiError::Internal("Placed order has no id".to_string()))?;
+            order(conn, order_id)
+        })
+    }
+
+    async fn list_order_items(&self, request: Request<proto::ListOrderItemsRequest>) -> Result<Response<proto::OrderItems>, Status> {
+        let order_id = request.into_inner().order_id;
+        self.run(|conn| {
+            if OrderResponse::get_status(conn, order_id)?.is_none() {
+                return Err(ApiError::NotFound("No Order Found".to_string()));
+            }
+            let items = OrderItem::list_all_order_items(conn, order_id)?;
+            Ok(proto::OrderItems { items: items.into_iter().map(proto::OrderItem::from).collect() })
+        })
+    }
+
+    async fn set_item_quantity(&self, request: Request<proto::SetItemQuantityRequest>) -> Result<Response<proto::OrderItem>, Status> {
+        let context = context(&request, "SetItemQuantity");
+        let proto::SetItemQuantityRequest { order_id, item_id, quantity, if_match } = request.into_inner();
+        let patch = OrderItemPatch { quantity };
+        self.change(context, |conn| {
+            let (item, _) = set_item_quantity(conn, order_id, item_id, if_match.as_deref(), &patch)?;
+            Ok(proto::OrderItem::from(item))
+        })
+    }
+
+    async fn remove_item(&self, request: Request<proto::RemoveItemRequest>) -> Result<Response<proto::RemoveItemResponse>, Status> {
+        let context = context(&request, "RemoveItem");
+        let proto::RemoveItemRequest { order_id, item_id, if_match } = request.into_inner();
+        self.change(context, |conn| {
+            remove_order_item(conn, order_id, item_id, if_match.as_deref())?;
+            let order_deleted = OrderResponse::get_status(conn, order_id)?.is_none();
+            Ok(proto::RemoveItemResponse { order_deleted })
+        })
+    }
+
+    type WatchOrdersStream = Pin<Box<dyn Stream<Item = Result<proto::OrderUpdate, Status>> + Send>>;
+
+    async fn watch_orders(&self, request: Request<proto::WatchOrdersRequest>) -> Result<Response<Self::WatchOrdersStream>, Status> {
+        let proto::WatchOrdersRequest { order_id, table_id } = request.into_inner();
+        let updates = BroadcastStream::new(events::subscribe()).filter_map(move |event| {
+            let event = event.ok()?;
+            let wanted = order_id.is_none_or(|id| Some(id) == event.order_id) && table_id.is_none_or(|id| Some(id) == event.table_id);
+            wanted.then_some(event).and_then(proto::OrderUpdate::from_event).map(Ok)
+        });
+        Ok(Response::new(Box::pin(updates)))
+    }
+}
+
+/// An order with its items and bill figures
+fn order(conn: &Connection, order_id: i64) -> Result<proto::Order, ApiError> {
+    let order = OrderResponse::get(conn, order_id)?.ok_or_else(|| ApiError::NotFound("No Order Found".to_string()))?;
+    Ok(proto::Order {
+        id: order.id,
+        table_id: order.table_id,
+        table_name: order.table_name,
+        status: order.status,
+        party_size: order.party_size,
+        created_at: order.created_at,
+        version: order.version,
+        items: order.menus.into_iter().map(proto::OrderItem::from).collect(),
+        total_cooking_time: order.total_cooking_time.into(),
+        subtotal: order.subtotal,
+        discounts: order.adjustments.iter().map(|adjustment| adjustment.amount).sum(),
+        service_charge: order.service_charge,
+        total: order.total,
+        tips: order.tips,
+    })
+}
+
+impl From<TableResponse> for proto::Table {
+    fn from(table: TableResponse) -> proto::Table {
+        proto::Table {
+            id: table.id,
+            code: table.code,
+            section: table.section,
+            table_type: table.table_type,
+            server: table.server,
+            archived: table.archived,
+            version: table.version,
+        }
+    }
+}
+
+impl From<MenuResponse> for proto::Menu {
+    fn from(menu: MenuResponse) -> proto::Menu {
+        proto::Menu {
+            id: menu.id,
+            name: menu.name,
+            price: menu.price,
+            category: menu.category,
+            station: menu.station,
+            archived: menu.archived,
+            version: menu.version,
+        }
+    }
+}
+
+/// Listed orders come without their items and bill figures
+impl From<OrderSummary> for proto::Order {
+    fn from(order: OrderSummary) -> proto::Order {
+        proto::Order {
+            id: order.id,
+            table_id: order.table_id,
+            table_name: order.table_name,
+            status: order.status,
+            party_size: order.party_size,
+            created_at: order.created_at,
+            version: order.version,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<OrderItemResponse> for proto::OrderItem {
+    fn from(item: OrderItemResponse) -> proto::OrderItem {
+        proto::OrderItem {
+            id: item.id,
+            order_id: item.order_id,
+            menu_id: item.menu_id,
+            menu_name: item.menu_name,
+            quantity: item.quantity,
+            seat: item.seat,
+            unit_price: item.unit_price,
+            cooking_time: item.cooking_time,
+            unit_cooking_time: item.unit_cooking_time,
+            note: item.note,
+            station: item.station,
+        }
+    }
+}
+
+impl proto::OrderUpdate {
+    /// The update of an event, None for changes that are not to an order
+    fn from_event(event: Event) -> Option<proto::OrderUpdate> {
+        let kind = match event.kind {
+            EventKind::OrderCreated => Kind::OrderCreated,
+            EventKind::OrderClosed => Kind::OrderClosed,
+            EventKind::OrderDeleted => Kind::OrderDeleted,
+            EventKind::OrderMoved => Kind::OrderMoved,
+            EventKind::ItemAdded => Kind::ItemAdded,
+            EventKind::ItemUpdated => Kind::ItemUpdated,
+            EventKind::ItemRemoved => Kind::ItemRemoved,
+            EventKind::PaymentCaptured => Kind::PaymentCaptured,
+            EventKind::PaymentRefunded => Kind::PaymentRefunded,
+            EventKind::PaymentVoided => Kind::PaymentVoided,
+            EventKind::CouponRedeemed => Kind::CouponRedeemed,
+            EventKind::DiscountGiven => Kind::DiscountGiven,
+            _ => return None,
+        };
+        Some(proto::OrderUpdate {
+            kind: kind.into(),
+            table_id: event.table_id?,
+            order_id: event.order_id?,
+            item_id: event.item_id,
+            item: event.item.map(proto::OrderItem::from),
+            entity_id: event.entity_id,
+        })
+    }
+}
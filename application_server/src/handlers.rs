@@ -1,121 +1,333 @@
-use crate::models::{OrderResponse, OrderItem, OrderRequestBody, Table, Menu, MenuResponse, TableResponse, OrderItemResponse};
-use rusqlite::Connection;
-use warp;
+use crate::models::{OrderResponse, OrderItem, OrderItemPatch, OrderItemResponse, OrderRequestBody, OrderLine, Table, Menu, SeatQuery, TableItemsQuery, BillResponse, SplitBillRequest, SplitBillResponse, SubBillResponse, PaymentRequest, PaymentReasonRequest, PaymentResponse, CheckoutResponse, Settlement, Tender, Promotion, PromotionResponse, CouponRequest, ManualDiscountRequest, ManualDiscountResponse, TableServerRequest, ServiceChargeRule, ServiceChargeRuleResponse, ReportQuery, DailyReportResponse, TablePatch, MenuPatch, DeleteOutcome, TableResponse, TableListQuery, TABLE_SORTS, MenuResponse, MenuListQuery, MENU_SORTS, OrderListQuery, ORDER_SORTS, LiveUpdatesQuery, EventStreamQuery, Webhook, WebhookPatch, WebhookDeliveryResponse, EventLogQuery, LoggedEventResponse, TableHistoryQuery, TableHistoryResponse, AuditQuery, AuditEntryResponse, AUDIT_SORTS, BatchRequest, BatchMode, BatchOperation, BatchOutcome, BatchResultResponse, BatchResponse, MenuFormat, MenuImportQuery, MenuExportQuery, MAX_ITEM_QUANTITY};
+use crate::errors::{respond, ApiError};
+use crate::validation::FieldError;
+use crate::pagination::{link, Page, PageRequest};
+use crate::idempotency::{self, Claim};
+use crate::events::{self, EventKind, ResumePoint};
+use crate::websocket;
+use crate::sse;
+use crate::webhooks;
+use crate::history;
+use crate::menu_files;
+use crate::audit::{self, Entity};
+use crate::graphql::{self, RestaurantSchema};
+use async_graphql_warp::GraphQLResponse;
+use crate::preconditions::{self, check_if_match, current_version, etag, Versioned};
+use crate::payments::PaymentProcessor;
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use std::sync::Arc;
 use rand::Rng;
 use rusqlite::params;
 use serde_json::json;
+use validator::Validate;
+use warp::Reply;
 
 
 // Table Handlers
 
-/// List All Tables
-pub async fn list_table_handler(conn: Connection)-> Result<impl warp::Reply, warp::Rejection>{
-    match Table::list(&conn) {
-        Ok(tables) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&tables),
-                warp::http::StatusCode::OK
-            ))
-        }
-        Err(_err) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json::<Vec<TableResponse>>(&vec![]),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR
-            ))
-        }
-    }
+/// List a page of Tables
+pub async fn list_table_handler(conn: Connection, query: TableListQuery)-> Result<impl warp::Reply, warp::Rejection>{
+    respond(async move {
+        query.validate()?;
+        let page = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), TABLE_SORTS)?;
+        let tables = Table::list(&conn, &query, &page)?;
+        let tables = Page::new(tables, &page, TableResponse::cursor, |after| link("/tables", &query, after));
+        Ok(warp::reply::with_status(
+            warp::reply::json(&tables),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
 }
 /// Create a new Table
 pub async fn create_table_handler(conn: Connection, data: Table) -> Result<impl warp::Reply, warp::Rejection> {
-    match Table::get_existing_table_id(&conn, &data) {
-    Ok(Some(table_id))=>{
+    respond(async move {
+        let table_id = create_table(&conn, &data)?;
         Ok(warp::reply::with_status(
             warp::reply::json(&json!({ "id": table_id })),
             warp::http::StatusCode::CREATED,
         ))
+    }.await)
+}
+
+/// Create a table, or bring back the archived table with the same code. Returns its id
+pub fn create_table(conn: &Connection, data: &Table) -> Result<i64, ApiError> {
+    data.validate()?;
+    match Table::get_existing_table_id(conn, data)? {
+        Some(table_id) => audited(conn, Entity::Table, table_id, |tx| {
+            // Creating an archived table again brings it back
+            if Table::restore(tx, table_id)? {
+                events::record_table(tx, EventKind::TableCreated, table_id)?;
+            }
+            Ok(table_id)
+        }),
+        None => in_transaction(conn, |tx| {
+            let table_id = Table::create(tx, data)?;
+            events::record_table(tx, EventKind::TableCreated, table_id)?;
+            audit::record(tx, Entity::Table, table_id, None)?;
+            Ok(table_id)
+        }),
     }
-    Ok(None)=>{
-        match Table::create(&conn, &data) {
-            Ok(table_id) => {
-                Ok(warp::reply::with_status(
-                    warp::reply::json(&json!({ "id": table_id })),
-                    warp::http::StatusCode::CREATED,
-                ))
+}
+
+/// Run a change and record its events in one transaction, so the log never misses a change or has one
+/// that was rolled back. The dispatcher is woken once it is committed.
+/// Inside a transaction that is already open, as in a batch, the change joins it and its owner commits
+fn in_transaction<T>(conn: &Connection, run: impl FnOnce(&Connection) -> Result<T, ApiError>) -> Result<T, ApiError> {
+    if !conn.is_autocommit() {
+        return run(conn);
+    }
+    // The write lock is taken first, so nothing can change what the change reads before it writes
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let result = run(&tx)?;
+    tx.commit()?;
+    events::committed();
+    Ok(result)
+}
+
+/// Change an entity as in_transaction does, and write the change to the audit log in the same transaction
+/// with the entity as it was before and after it
+fn audited<T>(conn: &Connection, entity: Entity, id: i64, run: impl FnOnce(&Connection) -> Result<T, ApiError>) -> Result<T, ApiError> {
+    in_transaction(conn, |tx| {
+        let before = audit::snapshot(tx, entity, id)?;
+        let result = run(tx)?;
+        audit::record(tx, entity, id, before)?;
+        Ok(result)
+    })
+}
+
+/// Get a table, archived tables can still be fetched by id
+pub async fn get_table_handler(conn: Connection, table_id: i64, if_none_match: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let table = Table::get(&conn, table_id)?.ok_or_else(|| ApiError::NotFound("No Table Found".to_string()))?;
+        Ok(tagged(&table, table.version, if_none_match.as_deref()))
+    }.await)
+}
+
+/// Replace a table
+pub async fn update_table_handler(conn: Connection, table_id: i64, if_match: Option<String>, data: Table) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let version = check_if_match(&conn, if_match.as_deref(), Versioned::Table, table_id)?;
+        save_table(&conn, table_id, &data, version)
+    }.await)
+}
+
+/// Update some fields of a table
+pub async fn patch_table_handler(conn: Connection, table_id: i64, if_match: Option<String>, patch: TablePatch) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        patch.validate()?;
+        let version = check_if_match(&conn, if_match.as_deref(), Versioned::Table, table_id)?;
+        let table = Table::get(&conn, table_id)?.ok_or_else(|| ApiError::NotFound("No Table Found".to_string()))?;
+        // The patched fields are only written over the version they were read from
+        let version = version.or(Some(table.version));
+        save_table(&conn, table_id, &patch.apply(table), version)
+    }.await)
+}
+
+/// Delete a table. Tables with orders are archived instead, a table with an open order can't be deleted
+pub async fn delete_table_handler(conn: Connection, table_id: i64, if_match: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let outcome = audited(&conn, Entity::Table, table_id, |tx| {
+            check_if_match(tx, if_match.as_deref(), Versioned::Table, table_id)?;
+            if OrderResponse::get_existing_order_id(tx, table_id)?.is_some() {
+                return Err(ApiError::Conflict("Table has an open order".to_string()));
             }
-            Err(_err) => {
-                // Respond with an error
-                Ok(warp::reply::with_status(
-                    warp::reply::json(&json!({"error":"Error creating table"})),
-                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                ))
+            let outcome = Table::delete(tx, table_id)?;
+            match outcome {
+                DeleteOutcome::Archived => events::record_table(tx, EventKind::TableArchived, table_id)?,
+                DeleteOutcome::Deleted => events::record_table(tx, EventKind::TableDeleted, table_id)?,
+                DeleteOutcome::NotFound => {}
             }
-        }
+            Ok(outcome)
+        })?;
+        delete_reply(outcome, table_id, "Table")
+    }.await)
+}
+
+/// Replace a table, only if it is still at the version when one is given
+fn save_table(conn: &Connection, table_id: i64, table: &Table, version: Option<i64>) -> Result<warp::reply::Response, ApiError> {
+    table.validate()?;
+    if Table::get_existing_table_id(conn, table)?.is_some_and(|existing_id| existing_id != table_id) {
+        return Err(ApiError::Validation(vec![FieldError::new("code", "duplicate", "Table code already exists")]));
     }
-    Err(_err) => {
-        // Respond with an error
+    audited(conn, Entity::Table, table_id, |tx| {
+        if !Table::update(tx, table_id, table, version)? {
+            return Err(preconditions::not_written(tx, Versioned::Table, table_id, "No Table Found"));
+        }
+        Ok(events::record_table(tx, EventKind::TableUpdated, table_id)?)
+    })?;
+    let table = Table::get(conn, table_id)?.ok_or_else(|| ApiError::NotFound("No Table Found".to_string()))?;
+    Ok(tagged(&table, table.version, None))
+}
+
+/// Assign a server to a table, tips of payments taken on the table from then on go to them
+pub async fn assign_table_server_handler(conn: Connection, table_id: i64, if_match: Option<String>, req_body: TableServerRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        req_body.validate()?;
+        let server = req_body.server.as_deref().map(str::trim).filter(|server| !server.is_empty());
+        audited(&conn, Entity::Table, table_id, |tx| {
+            check_if_match(tx, if_match.as_deref(), Versioned::Table, table_id)?;
+            if !Table::assign_server(tx, table_id, server)? {
+                return Err(ApiError::NotFound("No Table Found".to_string()));
+            }
+            Ok(events::record_table(tx, EventKind::ServerAssigned, table_id)?)
+        })?;
         Ok(warp::reply::with_status(
-            warp::reply::json(&json!({"error":"Error creating table"})),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            warp::reply::json(&json!({"id": table_id, "server": server})),
+            warp::http::StatusCode::OK,
         ))
-    }
-}
-    
+    }.await)
 }
 
 // Menu Handler
 
-/// List All Menus
-pub async fn list_menu_handler(conn: Connection)-> Result<impl warp::Reply, warp::Rejection>{
-    match Menu::list(&conn) {
-        Ok(menus) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&menus),
-                warp::http::StatusCode::OK,
-            ))
-        }
-        Err(_err) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json::<Vec<MenuResponse>>(&vec![]),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            )
-            )
-        }
-    }
+/// List a page of Menus
+pub async fn list_menu_handler(conn: Connection, query: MenuListQuery)-> Result<impl warp::Reply, warp::Rejection>{
+    respond(async move {
+        query.validate()?;
+        let page = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), MENU_SORTS)?;
+        let menus = Menu::list(&conn, &query, &page)?;
+        let menus = Page::new(menus, &page, MenuResponse::cursor, |after| link("/menus", &query, after));
+        Ok(warp::reply::with_status(
+            warp::reply::json(&menus),
+            warp::http::StatusCode::OK,
+        ))
+    }.await)
 }
 // Create a new Menu
 pub async fn create_menu_handler(conn: Connection, data: Menu) -> Result<impl warp::Reply, warp::Rejection> {
-    match Menu::get_existing_menu_id(&conn, &data) {
-        Ok(Some(menu_id))=>{
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({ "id": menu_id })),
-                warp::http::StatusCode::CREATED,
-            ))
-        }
-        Ok(None)=>{
-            match Menu::create(&conn, &data) {
-                Ok(menu_id) => {
-                    Ok(warp::reply::with_status(
-                        warp::reply::json(&json!({ "id": menu_id })),
-                        warp::http::StatusCode::CREATED,
-                    ))
-                }
-                Err(_err) => {
-                    // Respond with an error
-                    Ok(warp::reply::with_status(
-                        warp::reply::json(&json!({ "error": "Error creating Menu" })),
-                        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    ))
-                }
+    respond(async move {
+        let menu_id = create_menu(&conn, &data)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({ "id": menu_id })),
+            warp::http::StatusCode::CREATED,
+        ))
+    }.await)
+}
+
+/// Create a menu, an existing menu with the same name is returned as is. Returns its id
+pub fn create_menu(conn: &Connection, data: &Menu) -> Result<i64, ApiError> {
+    data.validate()?;
+    if let Some(menu_id) = Menu::get_existing_menu_id(conn, data)? {
+        return Ok(menu_id);
+    }
+    match Menu::get_archived_menu_id(conn, data)? {
+        Some(menu_id) => audited(conn, Entity::Menu, menu_id, |tx| {
+            // Creating an archived menu again brings it back
+            if Menu::restore(tx, menu_id)? {
+                events::record_entity(tx, EventKind::MenuCreated, menu_id)?;
             }
+            Ok(menu_id)
+        }),
+        None => in_transaction(conn, |tx| {
+            let menu_id = Menu::create(tx, data)?;
+            events::record_entity(tx, EventKind::MenuCreated, menu_id)?;
+            audit::record(tx, Entity::Menu, menu_id, None)?;
+            Ok(menu_id)
+        }),
+    }
+}
+
+/// Import menus from a CSV or JSON file, updating those with the same name. With dry_run nothing is written
+pub async fn import_menus_handler(conn: Connection, query: MenuImportQuery, content_type: Option<String>, body: warp::hyper::body::Bytes) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let format = query.format.unwrap_or_else(|| MenuFormat::from_content_type(content_type.as_deref()));
+        let rows = menu_files::parse(format, &body)?;
+        let report = menu_files::import(&conn, rows, query.dry_run)?;
+        let status = match report.invalid > 0 && !report.dry_run {
+            true => warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+            false => warp::http::StatusCode::OK,
+        };
+        Ok(warp::reply::with_status(
+            warp::reply::json(&report),
+            status,
+        ))
+    }.await)
+}
+
+/// Export the menus that are not archived as a file that can be imported again
+pub async fn export_menus_handler(conn: Connection, query: MenuExportQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let format = query.format.unwrap_or(MenuFormat::Json);
+        let file = menu_files::export(&conn, format)?;
+        let reply = warp::reply::with_header(file, warp::http::header::CONTENT_TYPE, format.content_type());
+        Ok(warp::reply::with_header(reply, warp::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"menus.{}\"", format.extension())))
+    }.await)
+}
+
+/// Get a menu, archived menus can still be fetched by id
+pub async fn get_menu_handler(conn: Connection, menu_id: i64, if_none_match: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let menu = Menu::get(&conn, menu_id)?.ok_or_else(|| ApiError::NotFound("No Menu Found".to_string()))?;
+        Ok(tagged(&menu, menu.version, if_none_match.as_deref()))
+    }.await)
+}
+
+/// Replace a menu
+pub async fn update_menu_handler(conn: Connection, menu_id: i64, if_match: Option<String>, data: Menu) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let version = check_if_match(&conn, if_match.as_deref(), Versioned::Menu, menu_id)?;
+        save_menu(&conn, menu_id, &data, version)
+    }.await)
+}
+
+/// Update some fields of a menu
+pub async fn patch_menu_handler(conn: Connection, menu_id: i64, if_match: Option<String>, patch: MenuPatch) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        patch.validate()?;
+        let version = check_if_match(&conn, if_match.as_deref(), Versioned::Menu, menu_id)?;
+        let menu = Menu::get(&conn, menu_id)?.ok_or_else(|| ApiError::NotFound("No Menu Found".to_string()))?;
+        // The patched fields are only written over the version they were read from
+        let version = version.or(Some(menu.version));
+        save_menu(&conn, menu_id, &patch.apply(menu), version)
+    }.await)
+}
+
+/// Delete a menu. Menus that were ordered or have promotions are archived instead
+pub async fn delete_menu_handler(conn: Connection, menu_id: i64, if_match: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let outcome = audited(&conn, Entity::Menu, menu_id, |tx| {
+            check_if_match(tx, if_match.as_deref(), Versioned::Menu, menu_id)?;
+            let outcome = Menu::delete(tx, menu_id)?;
+            match outcome {
+                DeleteOutcome::Archived => events::record_entity(tx, EventKind::MenuArchived, menu_id)?,
+                DeleteOutcome::Deleted => events::record_entity(tx, EventKind::MenuDeleted, menu_id)?,
+                DeleteOutcome::NotFound => {}
+            }
+            Ok(outcome)
+        })?;
+        delete_reply(outcome, menu_id, "Menu")
+    }.await)
+}
+
+/// Replace a menu, only if it is still at the version when one is given
+fn save_menu(conn: &Connection, menu_id: i64, menu: &Menu, version: Option<i64>) -> Result<warp::reply::Response, ApiError> {
+    menu.validate()?;
+    audited(conn, Entity::Menu, menu_id, |tx| {
+        if !Menu::update(tx, menu_id, menu, version)? {
+            return Err(preconditions::not_written(tx, Versioned::Menu, menu_id, "No Menu Found"));
         }
-        Err(_err) => {
-            // Respond with an error
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({ "error": "Error creating Menu" })),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            ))
-        }
+        Ok(events::record_entity(tx, EventKind::MenuUpdated, menu_id)?)
+    })?;
+    let menu = Menu::get(conn, menu_id)?.ok_or_else(|| ApiError::NotFound("No Menu Found".to_string()))?;
+    Ok(tagged(&menu, menu.version, None))
+}
+
+/// Reply with a resource and the ETag of its version, or with 304 Not Modified if the client already has it
+fn tagged<T: serde::Serialize>(resource: &T, version: i64, if_none_match: Option<&str>) -> warp::reply::Response {
+    let reply = match preconditions::not_modified(if_none_match, version) {
+        true => warp::reply::with_status(warp::reply(), warp::http::StatusCode::NOT_MODIFIED).into_response(),
+        false => warp::reply::with_status(warp::reply::json(resource), warp::http::StatusCode::OK).into_response(),
+    };
+    warp::reply::with_header(reply, warp::http::header::ETAG, etag(version)).into_response()
 }
+
+fn delete_reply(outcome: DeleteOutcome, id: i64, resource: &str) -> Result<warp::reply::WithStatus<warp::reply::Json>, ApiError> {
+    let message = match outcome {
+        DeleteOutcome::Deleted => format!("{} deleted", resource),
+        DeleteOutcome::Archived => format!("{} archived, it is used by past orders", resource),
+        DeleteOutcome::NotFound => return Err(ApiError::NotFound(format!("No {} Found", resource))),
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&json!({"id": id, "success": message})), warp::http::StatusCode::OK))
 }
 
 
@@ -123,350 +335,1130 @@ pub async fn create_menu_handler(conn: Connection, data: Menu) -> Result<impl wa
 // Order Handlers
 
 /// Create a new order
-pub async fn create_order_handler(conn: Connection, req_body: OrderRequestBody) -> Result<impl warp::Reply, warp::Rejection> {
+pub async fn create_order_handler(conn: Connection, idempotency_key: Option<String>, req_body: OrderRequestBody) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let request = format!("POST /orders {}", json!(req_body));
+        idempotent(&conn, idempotency_key, request, || place_order(&conn, req_body))
+    }.await)
+}
+
+/// Create a new order for a table, or add to its open order. The table comes from the path
+pub async fn create_table_order_handler(conn: Connection, table_id: i64, idempotency_key: Option<String>, mut req_body: OrderRequestBody) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let request = format!("POST /tables/{}/orders {}", table_id, json!(req_body));
+        idempotent(&conn, idempotency_key, request, || {
+            if req_body.table_id != 0 && req_body.table_id != table_id {
+                return Err(ApiError::Validation(vec![FieldError::new("table_id", "mismatch", "Does not match the table of the path")]));
+            }
+            req_body.table_id = table_id;
+            place_order(&conn, req_body)
+        })
+    }.await)
+}
+
+/// Run a request at most once per Idempotency-Key. The first response is stored and replayed for repeats of
+/// the same request within the retention window, a key reused for another request is a conflict
+fn idempotent(
+    conn: &Connection,
+    key: Option<String>,
+    request: String,
+    run: impl FnOnce() -> Result<(warp::http::StatusCode, serde_json::Value), ApiError>,
+) -> Result<warp::reply::Response, ApiError> {
+    let key = match key {
+        None => return run().map(|(status, body)| warp::reply::with_status(warp::reply::json(&body), status).into_response()),
+        Some(key) if !idempotency::is_valid_key(&key) => return Err(ApiError::bad_request("Idempotency-Key must be 1 to 255 printable characters")),
+        Some(key) => key,
+    };
+    match idempotency::claim(conn, &key, &request)? {
+        Claim::Mismatch => Err(ApiError::Conflict("Idempotency-Key was already used for a different request".to_string())),
+        Claim::InProgress => Err(ApiError::Conflict("A request with this Idempotency-Key is still in progress".to_string())),
+        Claim::Replay(status, body) => {
+            let status = warp::http::StatusCode::from_u16(status).map_err(|err| ApiError::Internal(err.to_string()))?;
+            let body: serde_json::Value = serde_json::from_str(&body).map_err(|err| ApiError::Internal(err.to_string()))?;
+            let reply = warp::reply::with_status(warp::reply::json(&body), status);
+            Ok(warp::reply::with_header(reply, idempotency::REPLAYED_HEADER, "true").into_response())
+        }
+        // The response is stored with the request's writes, a crash leaves either both or neither
+        Claim::New(claim) => match in_transaction(conn, |conn| {
+            let (status, body) = run()?;
+            if !idempotency::complete(conn, &key, claim, status.as_u16(), &body.to_string())? {
+                return Err(ApiError::Conflict("A retry with this Idempotency-Key took the request over".to_string()));
+            }
+            Ok((status, body))
+        }) {
+            Ok((status, body)) => Ok(warp::reply::with_status(warp::reply::json(&body), status).into_response()),
+            Err(err) => {
+                idempotency::release(conn, &key, claim)?;
+                Err(err)
+            }
+        },
+    }
+}
+
+/// Add the lines of a request to the open order of the table, a new order is created if the table has none
+pub fn place_order(conn: &Connection, req_body: OrderRequestBody) -> Result<(warp::http::StatusCode, serde_json::Value), ApiError> {
     let table_id = req_body.table_id;
-    let menu_ids = req_body.menu_ids;
-    if menu_ids.len() == 0{
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&json!({"error":"Please Add Items"})),
-            warp::http::StatusCode::BAD_REQUEST,
-        ));
+    let lines = req_body.lines();
+    if lines.is_empty() {
+        return Err(ApiError::bad_request("Please Add Items"));
     }
+    req_body.validate()?;
+    check_order_references(conn, &req_body)?;
+    in_transaction(conn, |conn| add_order_lines(conn, table_id, lines, req_body.party_size))
+}
+
+fn add_order_lines(conn: &Connection, table_id: i64, lines: Vec<OrderLine>, party_size: Option<i64>) -> Result<(warp::http::StatusCode, serde_json::Value), ApiError> {
     // Check if there is an existing order with status 0 (running order) for the given table_id
-    match OrderResponse::get_existing_order_id(&conn, table_id) {
-        Ok(Some(order_id)) => {
+    match OrderResponse::get_existing_order_id(conn, table_id)? {
+        Some(order_id) => audited(conn, Entity::Order, order_id, |conn| {
             // Order exists for the given table_id, update the order items
-            for menu_id in menu_ids {
-                // Generate a random cooking time
-                let cooking_time = rand::thread_rng().gen_range(5..=15);
-                match OrderItem::get_existing_order_item_id(&conn, order_id, menu_id) {
-                    Ok(Some(order_item_id)) => {
-                         // Order item does exist, update quantity
-                         match OrderItem::add_quantity_of_existing_order_item(&conn, order_item_id){
-                            Ok(_)=>{
-                                continue;
-                            },
-                            Err(_)=>{
-                                return Ok(warp::reply::with_status(
-                                    warp::reply::json(&json!({"error":"Error updating order Item"})),
-                                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                ));
-                            }
-                         }
-                    }
-                    Ok(None) => {
-                        // Order item does not exist, create a new order item
-                        match OrderItem::create(&conn, order_id, menu_id, cooking_time) {
-                            Ok(_) => {
-                                // Continue to the next menu_id
-                                continue;
-                            }
-                            Err(_err) => {
-                                // Return an error response
-                                eprintln!("{}",_err);
-                                return Ok(warp::reply::with_status(
-                                    warp::reply::json(&json!({"error":"Error creating order Item"})),
-                                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                ));
-                            }
-                        }
-                    }
-                    Err(_err) => {
-                        // Return an error response
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&json!({"error":"Error creating for existing order Item"})),
-                            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        ));
-                    }
-                }
+            set_party_size(conn, order_id, party_size)?;
+            for line in lines {
+                add_order_line(conn, order_id, &line)?;
             }
+            SubBillResponse::rebalance(conn, order_id)?;
 
             // If you reach this point, it means all order items were successfully handled
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({"success":"All order items updated successfully"})),
-                warp::http::StatusCode::OK,
-            ))
-        }
-        Ok(None) => {
+            Ok((warp::http::StatusCode::OK, json!({"id":order_id, "success":"All order items updated successfully"})))
+        }),
+        None => {
             // No running order exists for the given table_id, create a new order and order items
-            match OrderResponse::create(&conn, table_id) {
-                Ok(last_inserted_id) => {
-                    for menu_id in menu_ids {
-                        // Generate a random cooking time
-                        let cooking_time = rand::thread_rng().gen_range(5..=15);
-                        match OrderItem::create(&conn, last_inserted_id, menu_id, cooking_time) {
-                            Ok(_) => {
-                                // Continue to the next menu_id
-                                continue;
-                            }
-                            Err(_err) => {
-                                // Return an error response
-                                eprintln!("{}",_err);
-                                return Ok(warp::reply::with_status(
-                                    warp::reply::json(&json!({"error":"Error creating order Item"})),
-                                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                ));
-                            }
-                        }
-                    }
-
-                    Ok(warp::reply::with_status(
-                        warp::reply::json(&json!({"id":last_inserted_id, "success":"Order and All Order Item Created Successfully"})),
-                        warp::http::StatusCode::CREATED,
-                    ))
-                }
-                Err(_err) => {
-                    // Return an error response
-                    Ok(warp::reply::with_status(
-                        warp::reply::json(&json!({"error":format!("Error creating order {}", _err)})),
-                        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    ))
-                }
+            let last_inserted_id = OrderResponse::create(conn, table_id)?;
+            set_party_size(conn, last_inserted_id, party_size)?;
+            events::record_order(conn, EventKind::OrderCreated, last_inserted_id)?;
+            // The same menu twice for a seat is one item with a higher quantity
+            for line in lines {
+                add_order_line(conn, last_inserted_id, &line)?;
             }
+            audit::record(conn, Entity::Order, last_inserted_id, None)?;
+
+            Ok((warp::http::StatusCode::CREATED, json!({"id":last_inserted_id, "success":"Order and All Order Item Created Successfully"})))
         }
-        Err(_err) => {
-            // Return an error response
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({"error":"Error checking for existing order"})),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            ))
+    }
+}
+
+/// Referenced tables and menus must exist and not be archived
+fn check_order_references(conn: &Connection, req_body: &OrderRequestBody) -> Result<(), ApiError> {
+    let mut errors = Vec::new();
+    match Table::get(conn, req_body.table_id)? {
+        None => errors.push(FieldError::new("table_id", "not_found", format!("Table {} does not exist", req_body.table_id))),
+        Some(table) if table.archived => errors.push(FieldError::new("table_id", "archived", "Table is archived")),
+        Some(_) => {}
+    }
+    let menu_ids = req_body.menu_ids.iter().enumerate().map(|(index, menu_id)| (format!("menu_ids[{}]", index), *menu_id));
+    let item_menu_ids = req_body.items.iter().enumerate().map(|(index, line)| (format!("items[{}].menu_id", index), line.menu_id));
+    for (field, menu_id) in menu_ids.chain(item_menu_ids) {
+        match Menu::get(conn, menu_id)? {
+            None => errors.push(FieldError::new(field, "not_found", format!("Menu {} does not exist", menu_id))),
+            Some(menu) if menu.archived => errors.push(FieldError::new(field, "archived", format!("Menu {} is archived", menu_id))),
+            Some(_) => {}
         }
     }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(ApiError::Validation(errors)),
+    }
 }
 
-/// List All Orders
-pub async fn list_order_handler(conn: Connection)-> Result<impl warp::Reply, warp::Rejection>{
-    match OrderResponse::list(&conn) {
-        Ok(menus) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&menus),
-                warp::http::StatusCode::OK,
-            ))
+/// Add a line to an order, same menu for another seat or with another note is kept as a separate item
+fn add_order_line(conn: &Connection, order_id: i64, line: &OrderLine) -> Result<(), ApiError> {
+    let (kind, order_item_id) = match OrderItem::get_existing_order_item_id(conn, order_id, line)? {
+        // Order item does exist, update quantity
+        Some(order_item_id) => {
+            if !OrderItem::add_quantity_of_existing_order_item(conn, order_item_id, line.quantity)? {
+                let message = format!("Menu {} would be on one item more than {} times", line.menu_id, MAX_ITEM_QUANTITY);
+                return Err(ApiError::Validation(vec![FieldError::new("quantity", "range", message)]));
+            }
+            (EventKind::ItemUpdated, order_item_id)
         }
-        Err(_err) => {
-            Ok(
-                warp::reply::with_status(
-                warp::reply::json::<Vec<OrderResponse>>(&vec![]),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            ))
+        // Order item does not exist, create a new order item with a random prep time per unit
+        None => {
+            let unit_cooking_time = rand::thread_rng().gen_range(5..=15);
+            (EventKind::ItemAdded, OrderItem::create(conn, order_id, line, unit_cooking_time)?)
         }
+    };
+    match OrderItem::get(conn, order_id, order_item_id)? {
+        Some(item) => Ok(events::record_item(conn, kind, &item)?),
+        None => Ok(()),
+    }
+}
+
+/// Keep the party size of an order if the request has one
+fn set_party_size(conn: &Connection, order_id: i64, party_size: Option<i64>) -> rusqlite::Result<()> {
+    match party_size {
+        Some(party_size) => OrderResponse::set_party_size(conn, order_id, party_size),
+        None => Ok(()),
     }
 }
 
+/// List a page of Orders
+pub async fn list_order_handler(conn: Connection, query: OrderListQuery)-> Result<impl warp::Reply, warp::Rejection>{
+    respond(async move {
+        query.validate()?;
+        let page = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref(), ORDER_SORTS)?;
+        let orders = OrderResponse::list(&conn, &query, &page)?;
+        let orders = Page::new(orders, &page, OrderResponse::cursor, |after| link("/orders", &query, after));
+        Ok(warp::reply::with_status(
+            warp::reply::json(&orders),
+            warp::http::StatusCode::OK,
+        ))
+    }.await)
+}
+
 /// Delete Specific Order Item from Order By Table
-pub async fn delete_order_item_handler(conn: Connection, table_id: i64, menu_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
-
-    // Decrease the item quantity if greater than 1
-    let result = conn.execute(
-        "UPDATE order_items 
-        SET cooking_time = cooking_time - (cooking_time/quantity), quantity = quantity - 1
-        WHERE order_items.order_id IN (
-            SELECT orders.id
-            FROM orders
-            JOIN tables ON orders.table_id = tables.id
-            WHERE tables.id = ?1
-        ) AND order_items.menu_id = ?2 AND order_items.quantity > 1",
-        params![table_id, menu_id],
-    );
-
-    match result {
-        Ok(updated) => {
-            if updated > 0 {
-                // If quantity was greater than 1, update and return success
-                Ok(warp::reply::with_status(
-                    warp::reply::json(&json!({"success": "Menu quantity updated successfully"})),
-                    warp::http::StatusCode::OK,
-                ))
-            } else {
-                // Quantity is 1, delete the order item
-                let delete_result = conn.execute(
-                    "DELETE FROM order_items 
-                    WHERE order_items.order_id IN (
-                        SELECT orders.id
-                        FROM orders
-                        JOIN tables ON orders.table_id = tables.id
-                        WHERE tables.id = ?1
-                    ) AND order_items.menu_id = ?2",
-                    params![table_id, menu_id],
-                );
-
-                match delete_result {
-                    Ok(_) => {
-                        let order_id_result = OrderResponse::get_existing_order_id(&conn, table_id);
-
-                        match order_id_result {
-                            Ok(Some(order_id)) => {
-                                let has_items = OrderResponse::has_items(&conn, order_id);
-
-                                match has_items {
-                                    Ok(false) => {
-                                        // If there are no more items, delete the order as well
-                                        let _ = conn.execute("DELETE from orders WHERE id = ?", params![order_id]);
-
-                                        Ok(warp::reply::with_status(
-                                            warp::reply::json(&json!({"success": "Menu deleted successfully and order deleted"})),
-                                            warp::http::StatusCode::OK,
-                                        ))
-                                    }
-                                    Ok(true)=>{
-                                        Ok(warp::reply::with_status(
-                                            warp::reply::json(&json!({"success": "Menu deleted successfully"})),
-                                            warp::http::StatusCode::OK,
-                                        )) 
-                                    }
-                                    Err(_err) => {
-                                        Ok(warp::reply::with_status(
-                                            warp::reply::json(&json!({"error": "Menu deleted failed"})),
-                                            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                        ))
-                                    }
-                                }
-                            }
-                            _ => Ok(warp::reply::with_status(
-                                warp::reply::json(&json!({"error": "Failed to retrieve order ID"})),
-                                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                            )),
-                        }
+/// If a seat is given only that seat's item is touched. A menu on several lines and no seat that tells them apart is refused
+/// Deprecated, items are removed by their own id with delete_order_item_handler
+pub async fn delete_order_item_for_table_handler(conn: Connection, table_id: i64, menu_id: i64, query: SeatQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let message = in_transaction(&conn, |conn| {
+            let item = menu_line(conn, table_id, menu_id, query.seat)?;
+            audited(conn, Entity::Order, item.order_id, |conn| {
+                // Decrease the item quantity if greater than 1
+                if item.quantity > 1 {
+                    OrderItem::set_quantity(conn, item.order_id, item.id, item.quantity - 1)?;
+                    if let Some(decreased) = OrderItem::get(conn, item.order_id, item.id)? {
+                        events::record_item(conn, EventKind::ItemUpdated, &decreased)?;
                     }
-                    Err(_) => {
-                        Ok(warp::reply::with_status(
-                            warp::reply::json(&json!({"error": "Menu delete failed"})),
-                            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        ))
+                    SubBillResponse::rebalance(conn, item.order_id)?;
+                    return Ok("Menu quantity updated successfully");
+                }
+
+                // Quantity is 1, delete the order item. The removal is recorded before, while the order is sure to still exist
+                events::record_item(conn, EventKind::ItemRemoved, &item)?;
+                OrderItem::delete(conn, item.order_id, item.id)?;
+                match remove_empty_order(conn, item.order_id)? {
+                    true => Ok("Menu deleted successfully and order deleted"),
+                    false => {
+                        SubBillResponse::rebalance(conn, item.order_id)?;
+                        Ok("Menu deleted successfully")
                     }
                 }
+            })
+        })?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"success": message})),
+            warp::http::StatusCode::OK,
+        ))
+    }.await)
+}
+
+/// Remove an item from an open order. If it was the last item the order is deleted too,
+/// unless payments were made on it
+pub async fn delete_order_item_handler(conn: Connection, order_id: i64, order_item_id: i64, if_match: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let message = remove_order_item(&conn, order_id, order_item_id, if_match.as_deref())?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"success": message})),
+            warp::http::StatusCode::OK,
+        ))
+    }.await)
+}
+
+/// Remove an item from an open order, and the order with it if nothing is left on it. Returns what was done
+pub fn remove_order_item(conn: &Connection, order_id: i64, order_item_id: i64, if_match: Option<&str>) -> Result<&'static str, ApiError> {
+    audited(conn, Entity::Order, order_id, |tx| {
+        require_open_order(tx, order_id)?;
+        check_if_match(tx, if_match, Versioned::Order, order_id)?;
+        let item = OrderItem::get(tx, order_id, order_item_id)?.ok_or_else(|| ApiError::NotFound("No Item Found".to_string()))?;
+        OrderItem::delete(tx, order_id, order_item_id)?;
+        events::record_item(tx, EventKind::ItemRemoved, &item)?;
+        match remove_empty_order(tx, order_id)? {
+            true => Ok("Item deleted successfully and order deleted"),
+            false => {
+                SubBillResponse::rebalance(tx, order_id)?;
+                Ok("Item deleted successfully")
             }
         }
-        Err(_err) => {
-            eprintln!("Failed to update quantity: {:?}", _err);
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({"error": "Failed to update quantity"})),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            ))
-        }
+    })
+}
+
+/// Delete an order that has no items left, together with its sub-bills. Returns true if it was deleted
+/// Orders with payments are kept for the payment records
+fn remove_empty_order(conn: &Connection, order_id: i64) -> rusqlite::Result<bool> {
+    if OrderResponse::has_items(conn, order_id)? || PaymentResponse::has_payments(conn, order_id)? {
+        return Ok(false);
     }
+    events::record_order(conn, EventKind::OrderDeleted, order_id)?;
+    SubBillResponse::delete_for_order(conn, order_id)?;
+    conn.execute("DELETE from orders WHERE id = ?", params![order_id])?;
+    Ok(true)
 }
 
-/// List All Orders for a specific table
-pub async fn list_order_items_for_table_handler(conn: Connection, table_id:i64)-> Result<impl warp::Reply, warp::Rejection>{
-    match OrderItem::list_order_items(&conn, table_id) {
-        Ok(items) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&items),
-                warp::http::StatusCode::OK
-            ))
-        }
-        Err(_err) => {
-            eprintln!("{}", _err);
-            Ok(warp::reply::with_status(
-                warp::reply::json::<Vec<OrderItemResponse>>(&vec![]),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR
-            ))
+/// Set the quantity of an item of an open order outright, the prep time follows the quantity
+/// The reply carries the ETag of the order
+pub async fn patch_order_item_handler(conn: Connection, order_id: i64, order_item_id: i64, if_match: Option<String>, patch: OrderItemPatch) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let (item, version) = set_item_quantity(&conn, order_id, order_item_id, if_match.as_deref(), &patch)?;
+        Ok(tagged(&item, version, None))
+    }.await)
+}
+
+/// Set the quantity of an item of an open order. Returns the item and the new version of the order
+pub fn set_item_quantity(conn: &Connection, order_id: i64, order_item_id: i64, if_match: Option<&str>, patch: &OrderItemPatch) -> Result<(OrderItemResponse, i64), ApiError> {
+    patch.validate()?;
+    audited(conn, Entity::Order, order_id, |tx| {
+        require_open_order(tx, order_id)?;
+        check_if_match(tx, if_match, Versioned::Order, order_id)?;
+        if !OrderItem::set_quantity(tx, order_id, order_item_id, patch.quantity)? {
+            return Err(ApiError::NotFound("No Item Found".to_string()));
         }
-    }
+        let item = OrderItem::get(tx, order_id, order_item_id)?.ok_or_else(|| ApiError::NotFound("No Item Found".to_string()))?;
+        events::record_item(tx, EventKind::ItemUpdated, &item)?;
+        SubBillResponse::rebalance(tx, order_id)?;
+        let version = current_version(tx, Versioned::Order, order_id)?.ok_or_else(|| ApiError::NotFound("No Order Found".to_string()))?;
+        Ok((item, version))
+    })
 }
 
-/// Retrieve a specific item from a specific table
-pub async fn get_order_item_for_table_handler(conn: Connection, table_id:i64, menu_id: i64)-> Result<impl warp::Reply, warp::Rejection>{
-    match OrderItem::get_item(&conn, table_id, menu_id) {
-        Ok(Some(item)) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&item),
-                warp::http::StatusCode::OK
-            ))
+/// Move an open order to another table that has no open order. Returns the order as it is now
+pub fn move_order(conn: &Connection, order_id: i64, table_id: i64, if_match: Option<&str>) -> Result<OrderResponse, ApiError> {
+    audited(conn, Entity::Order, order_id, |tx| {
+        require_open_order(tx, order_id)?;
+        check_if_match(tx, if_match, Versioned::Order, order_id)?;
+        match Table::get(tx, table_id)? {
+            None => return Err(ApiError::Validation(vec![FieldError::new("table_id", "not_found", format!("Table {} does not exist", table_id))])),
+            Some(table) if table.archived => return Err(ApiError::Validation(vec![FieldError::new("table_id", "archived", "Table is archived")])),
+            Some(_) => {}
         }
-        Ok(None) => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({"error": "No Item Found"})),
-                warp::http::StatusCode::NOT_FOUND,
-            ))
+        match OrderResponse::get_existing_order_id(tx, table_id)? {
+            // Already on that table
+            Some(open_order_id) if open_order_id == order_id => {}
+            Some(_) => return Err(ApiError::Conflict(format!("Table {} already has an open order", table_id))),
+            None => {
+                OrderResponse::move_to_table(tx, order_id, table_id)?;
+                events::record_order(tx, EventKind::OrderMoved, order_id)?;
+                // The service charge rule of the new table can differ
+                SubBillResponse::rebalance(tx, order_id)?;
+            }
         }
-        Err(_err) => {
-            eprintln!("{}", _err);
-            Ok(warp::reply::with_status(
-                warp::reply::json(&json!({"error": "Something Wrong!"})),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR
-            ))
+        OrderResponse::get(tx, order_id)?.ok_or_else(|| ApiError::NotFound("No Order Found".to_string()))
+    })
+}
+
+/// Get an order with its items and bill figures
+pub async fn get_order_handler(conn: Connection, order_id: i64, if_none_match: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let order = OrderResponse::get(&conn, order_id)?.ok_or_else(|| ApiError::NotFound("No Order Found".to_string()))?;
+        Ok(tagged(&order, order.version, if_none_match.as_deref()))
+    }.await)
+}
+
+/// List a page of the orders of a table, the newest first unless sorted otherwise
+pub async fn list_table_orders_handler(conn: Connection, table_id: i64, query: OrderListQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        query.validate()?;
+        let page = PageRequest::new(query.limit, query.after.as_deref(), query.sort.as_deref().or(Some("-id")), ORDER_SORTS)?;
+        if Table::get(&conn, table_id)?.is_none() {
+            return Err(ApiError::NotFound("No Table Found".to_string()));
         }
-    }
+        let filter = OrderListQuery { table_id: Some(table_id), ..query.clone() };
+        let orders = OrderResponse::list(&conn, &filter, &page)?;
+        let path = format!("/tables/{}/orders", table_id);
+        let orders = Page::new(orders, &page, OrderResponse::cursor, |after| link(&path, &query, after));
+        Ok(warp::reply::with_status(
+            warp::reply::json(&orders),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
 }
 
+/// List the items of an order, with group_by=seat they are grouped per seat
+pub async fn list_order_items_handler(conn: Connection, order_id: i64, query: TableItemsQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let group_by_seat = group_by_seat(&query)?;
+        if OrderResponse::get_status(&conn, order_id)?.is_none() {
+            return Err(ApiError::NotFound("No Order Found".to_string()));
+        }
+        let items = OrderItem::list_all_order_items(&conn, order_id)?;
+        let body = if group_by_seat {
+            warp::reply::json(&OrderItem::group_by_seat(items))
+        } else {
+            warp::reply::json(&items)
+        };
+        Ok(warp::reply::with_status(body, warp::http::StatusCode::OK))
+    }.await)
+}
 
-/// Unit Tests
-#[cfg(test)]
-mod tests {
-    use warp::{Reply, hyper::Body};
-    use super::*;
+/// Get an item of an order by its id
+pub async fn get_order_item_handler(conn: Connection, order_id: i64, order_item_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let item = OrderItem::get(&conn, order_id, order_item_id)?.ok_or_else(|| ApiError::NotFound("No Item Found".to_string()))?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&item),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
 
+fn group_by_seat(query: &TableItemsQuery) -> Result<bool, ApiError> {
+    match query.group_by.as_deref() {
+        None => Ok(false),
+        Some("seat") => Ok(true),
+        Some(_) => Err(ApiError::bad_request("Items can only be grouped by seat")),
+    }
+}
 
-    // Set up the test database
-    fn setup_test_db() -> Connection {
-        println!("Initializing the test database...");
-        let conn = Connection::open_in_memory().expect("Failed to create test database");
-        conn.execute("PRAGMA foreign_keys = ON;", []).expect("Failed to enable foreign key support");
-        conn.execute("CREATE TABLE IF NOT EXISTS tables (id INTEGER PRIMARY KEY,code TEXT NOT NULL UNIQUE)",[]).expect("Table table creation failed");
-        conn.execute("CREATE TABLE IF NOT EXISTS menus (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",[]).expect("Menu table creation failed");
-        conn.execute("CREATE TABLE IF NOT EXISTS orders (id INTEGER PRIMARY KEY, table_id INTEGER NOT NULL, FOREIGN KEY (table_id) REFERENCES tables(id), UNIQUE (table_id))",[]).expect("Order table creation failed");
-        conn.execute("CREATE TABLE IF NOT EXISTS order_items (id INTEGER PRIMARY KEY, order_id INTEGER NOT NULL, menu_id INTEGER NOT NULL, cooking_time INTEGER NOT NULL,  quantity INTEGER NOT NULL default 1, FOREIGN KEY (order_id) REFERENCES orders(id), FOREIGN KEY (menu_id) REFERENCES menus(id))",[]).expect("OrderItems table creation failed");
-        conn
+/// List All Orders for a specific table
+/// Deprecated, items are listed per order with list_order_items_handler
+/// With group_by=seat the items are returned grouped per seat
+pub async fn list_order_items_for_table_handler(conn: Connection, table_id:i64, query: TableItemsQuery)-> Result<impl warp::Reply, warp::Rejection>{
+    respond(async move {
+        let group_by_seat = group_by_seat(&query)?;
+        let items = OrderItem::list_order_items(&conn, table_id)?;
+        let body = if group_by_seat {
+            warp::reply::json(&OrderItem::group_by_seat(items))
+        } else {
+            warp::reply::json(&items)
+        };
+        Ok(warp::reply::with_status(body, warp::http::StatusCode::OK))
+    }.await)
+}
+
+/// Retrieve a specific item from a specific table
+/// A menu on several lines and no seat that tells them apart is refused
+/// Deprecated, items are read by their own id with get_order_item_handler
+pub async fn get_order_item_for_table_handler(conn: Connection, table_id:i64, menu_id: i64, query: SeatQuery)-> Result<impl warp::Reply, warp::Rejection>{
+    respond(async move {
+        let item = menu_line(&conn, table_id, menu_id, query.seat)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&item),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// The one line of a menu on the open order of a table, on a seat if given
+fn menu_line(conn: &Connection, table_id: i64, menu_id: i64, seat: Option<i64>) -> Result<OrderItemResponse, ApiError> {
+    let mut items = OrderItem::get_items(conn, table_id, menu_id, seat)?;
+    match items.len() {
+        0 => Err(ApiError::NotFound("No Item Found".to_string())),
+        1 => Ok(items.remove(0)),
+        lines => Err(ApiError::Conflict(format!("Menu is on {} lines of the order, give the seat or use the id of the item", lines))),
     }
+}
 
-    // Inserting static table and menu data
-    fn setup_static_data(conn: &Connection){
-        let values_to_insert = vec!["T-01", "T-02", "T-03"];
 
-        for value in values_to_insert {
-            conn.execute("INSERT INTO tables (code) VALUES (?1)", &[value]).expect("Insertion Failed");
-        }
-        let values_to_insert = vec!["M-01", "M-02", "M-03", "M-04", "M-05"];
+// Bill Handlers
 
-        for value in values_to_insert {
-            conn.execute("INSERT INTO menus (name) VALUES (?1)", &[value]).expect("Insertion Failed");
+/// Itemised bill of the open order of a table
+pub async fn get_bill_handler(conn: Connection, table_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let bill = open_bill(&conn, table_id)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&bill),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Split the bill of a table into sub-bills, replacing an earlier split
+/// Not allowed any more once one of the sub-bills is paid
+pub async fn split_bill_handler(conn: Connection, table_id: i64, req_body: SplitBillRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        req_body.validate()?;
+        let (bill, sub_bills) = in_transaction(&conn, |tx| {
+            let bill = open_bill(tx, table_id)?;
+            let mut sub_bills = bill.split(&req_body).map_err(ApiError::bad_request)?;
+            let replaced = SubBillResponse::list(tx, bill.order_id)?
+                .into_iter()
+                .map(|sub_bill| Ok((sub_bill.id, audit::snapshot(tx, Entity::SubBill, sub_bill.id)?)))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            if !SubBillResponse::replace(tx, bill.order_id, &mut sub_bills)? {
+                return Err(ApiError::Conflict("Bill is already partly paid".to_string()));
+            }
+            // The sub-bills replaced are audited as deleted, the new ones as made
+            for (sub_bill_id, before) in replaced {
+                audit::record(tx, Entity::SubBill, sub_bill_id, before)?;
+            }
+            for sub_bill in &sub_bills {
+                audit::record(tx, Entity::SubBill, sub_bill.id, None)?;
+            }
+            Ok((bill, sub_bills))
+        })?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&SplitBillResponse { order_id: bill.order_id, total: bill.total, sub_bills }),
+            warp::http::StatusCode::CREATED,
+        ))
+    }.await)
+}
+
+/// List the sub-bills of the open order of a table
+pub async fn list_sub_bills_handler(conn: Connection, table_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let bill = open_bill(&conn, table_id)?;
+        let sub_bills = SubBillResponse::list(&conn, bill.order_id)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&SplitBillResponse { order_id: bill.order_id, total: bill.total, sub_bills }),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+fn open_bill(conn: &Connection, table_id: i64) -> Result<BillResponse, ApiError> {
+    BillResponse::for_table(conn, table_id)?.ok_or_else(|| ApiError::NotFound("No open order for this table".to_string()))
+}
+
+/// Pay a single sub-bill. The payment counts towards the order as well
+pub async fn pay_sub_bill_handler(conn: Connection, processor: Arc<dyn PaymentProcessor>, sub_bill_id: i64, req_body: PaymentRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let sub_bill = SubBillResponse::get(&conn, sub_bill_id)?.ok_or_else(|| ApiError::NotFound("No sub-bill found".to_string()))?;
+        record_payment(&conn, processor.as_ref(), sub_bill.order_id, Some(sub_bill_id), None, &req_body)
+    }.await)
+}
+
+// Payment Handlers
+
+/// Pay towards an open order. Partial payments are allowed, the order closes once fully paid
+pub async fn create_payment_handler(conn: Connection, processor: Arc<dyn PaymentProcessor>, order_id: i64, if_match: Option<String>, req_body: PaymentRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        record_payment(&conn, processor.as_ref(), order_id, None, if_match.as_deref(), &req_body)
+    }.await)
+}
+
+/// List all payments of an order, including refunded and voided ones
+pub async fn list_payments_handler(conn: Connection, order_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        if OrderResponse::get_status(&conn, order_id)?.is_none() {
+            return Err(ApiError::NotFound("No Order Found".to_string()));
         }
+        let payments = PaymentResponse::list_for_order(&conn, order_id)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&payments),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Refund a captured payment, a reason is required
+pub async fn refund_payment_handler(conn: Connection, processor: Arc<dyn PaymentProcessor>, payment_id: i64, req_body: PaymentReasonRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(cancel_payment(&conn, processor.as_ref(), payment_id, &req_body, "refunded"))
+}
+
+/// Void a captured payment of an open order, a reason is required
+pub async fn void_payment_handler(conn: Connection, processor: Arc<dyn PaymentProcessor>, payment_id: i64, req_body: PaymentReasonRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(cancel_payment(&conn, processor.as_ref(), payment_id, &req_body, "voided"))
+}
+
+// Promotion Handlers
+
+/// List all promotions
+pub async fn list_promotion_handler(conn: Connection) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let promotions = PromotionResponse::list(&conn)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&promotions),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Create a promotion, it applies to every open order from then on
+pub async fn create_promotion_handler(conn: Connection, data: Promotion) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        data.validate()?;
+        let id = in_transaction(&conn, |tx| {
+            let id = Promotion::create(tx, &data).map_err(|err| match ApiError::from(err) {
+                ApiError::Conflict(_) => ApiError::Conflict("Coupon code already exists".to_string()),
+                err => err,
+            })?;
+            events::record_entity(tx, EventKind::PromotionCreated, id)?;
+            audit::record(tx, Entity::Promotion, id, None)?;
+            SubBillResponse::rebalance_open_orders(tx)?;
+            Ok(id)
+        })?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"id": id})),
+            warp::http::StatusCode::CREATED,
+        ))
+    }.await)
+}
+
+/// Redeem a coupon code on an open order. Returns the recalculated bill
+pub async fn redeem_coupon_handler(conn: Connection, order_id: i64, if_match: Option<String>, req_body: CouponRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        req_body.validate()?;
+        audited(&conn, Entity::Order, order_id, |tx| {
+            require_open_order(tx, order_id)?;
+            check_if_match(tx, if_match.as_deref(), Versioned::Order, order_id)?;
+            let promotion_id = PromotionResponse::get_by_coupon_code(tx, req_body.code.trim())?
+                .ok_or_else(|| ApiError::NotFound("Unknown coupon code".to_string()))?;
+            if !PromotionResponse::redeem_coupon(tx, order_id, promotion_id)? {
+                return Err(ApiError::Conflict("Coupon is used up or already applied to this order".to_string()));
+            }
+            SubBillResponse::rebalance(tx, order_id)?;
+            Ok(events::record_on_order(tx, EventKind::CouponRedeemed, order_id, promotion_id)?)
+        })?;
+        bill_reply(&conn, order_id)
+    }.await)
+}
+
+/// Give a manual discount on an open order. Only sent with X-Staff-Role: manager
+/// and a reason code is required. Returns the recalculated bill
+pub async fn create_manual_discount_handler(conn: Connection, order_id: i64, staff_role: Option<String>, if_match: Option<String>, req_body: ManualDiscountRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        require_manager_role(staff_role.as_deref(), "Only managers can give manual discounts")?;
+        req_body.validate()?;
+        audited(&conn, Entity::Order, order_id, |tx| {
+            require_open_order(tx, order_id)?;
+            check_if_match(tx, if_match.as_deref(), Versioned::Order, order_id)?;
+            let discount_id = ManualDiscountResponse::create(tx, order_id, &req_body)?;
+            SubBillResponse::rebalance(tx, order_id)?;
+            Ok(events::record_on_order(tx, EventKind::DiscountGiven, order_id, discount_id)?)
+        })?;
+        bill_reply(&conn, order_id)
+    }.await)
+}
 
+/// Check the X-Staff-Role header asks for the manager role.
+/// The header is taken as the client sends it: this is a stand-in until staff authenticate, it keeps
+/// waiters from giving discounts by mistake but does not stop a client that sets the header itself
+fn require_manager_role(staff_role: Option<&str>, message: &str) -> Result<(), ApiError> {
+    match staff_role {
+        Some(role) if role.eq_ignore_ascii_case("manager") => Ok(()),
+        _ => Err(ApiError::Forbidden(message.to_string())),
     }
+}
 
-    // Convert warp Response to serde Json Value
-    async fn convert_response_to_json(resp:  warp::http::Response<Body>)->serde_json::Value {
-        let body_bytes = warp::hyper::body::to_bytes(resp.into_body()).await.unwrap();
-        let body_vec = body_bytes.to_vec();
-        let body_string = String::from_utf8_lossy(&body_vec);
-        let json_value: serde_json::Value = serde_json::from_str(&body_string).unwrap();
-        return json_value;
+/// Reply with the bill of an order after its adjustments changed
+fn bill_reply(conn: &Connection, order_id: i64) -> Result<warp::reply::WithStatus<warp::reply::Json>, ApiError> {
+    let bill = BillResponse::for_order(conn, order_id)?.ok_or_else(|| ApiError::NotFound("No Order Found".to_string()))?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&bill),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+/// Only open orders can be changed or paid
+fn require_open_order(conn: &Connection, order_id: i64) -> Result<(), ApiError> {
+    match OrderResponse::get_status(conn, order_id)? {
+        Some(status) if status == "open" => Ok(()),
+        Some(_) => Err(ApiError::Conflict("Order is already closed".to_string())),
+        None => Err(ApiError::NotFound("No Order Found".to_string())),
     }
+}
 
-    // Test Case: 01 Menu Creation
-    #[tokio::test]
-    async fn test_create_menu_handler() {
-        let conn = setup_test_db();
-        let menu = Menu {
-            id: 0,
-            name: "Menu-01".to_string(),
-        };
-        let result = create_menu_handler(conn, menu).await;
-        match result {
-            Ok(rep)=>{
-                let resp = rep.into_response();
-                assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
-                let json_data = convert_response_to_json(resp).await;
-                assert_eq!(json_data["id"].as_i64(), Some(1));
+// Batch Handler
+
+/// Run several operations in one transaction, replying with the result of each
+pub async fn batch_handler(conn: Connection, req_body: BatchRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let (status, batch) = run_batch(&conn, req_body)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&batch),
+            status,
+        ))
+    }.await)
+}
+
+/// Run the operations of a batch in order in one transaction, each in a savepoint so a failed one is undone on its own.
+/// In all_or_nothing mode the first failure rolls the batch back, skips the rest and sets the status of the reply
+pub fn run_batch(conn: &Connection, batch: BatchRequest) -> Result<(warp::http::StatusCode, BatchResponse), ApiError> {
+    batch.validate()?;
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let mut results = Vec::new();
+    let mut failure = None;
+    for (index, operation) in batch.operations.into_iter().enumerate() {
+        let op = operation.name().to_string();
+        if failure.is_some() && batch.mode == BatchMode::AllOrNothing {
+            results.push(BatchResultResponse { index, op, outcome: BatchOutcome::Skipped, status: None, body: None, error: None });
+            continue;
+        }
+        tx.execute_batch("SAVEPOINT batch_operation")?;
+        match run_operation(&tx, operation) {
+            Ok((status, body)) => {
+                tx.execute_batch("RELEASE batch_operation")?;
+                results.push(BatchResultResponse { index, op, outcome: BatchOutcome::Applied, status: Some(status.as_u16()), body: Some(body), error: None });
             }
-            Err(_)=>{
-                panic!("Unhandled Error");
+            // The database failing is not the fault of the operation, the batch is given up
+            Err(err @ (ApiError::Database(_) | ApiError::Internal(_))) => return Err(err),
+            Err(err) => {
+                tx.execute_batch("ROLLBACK TO batch_operation; RELEASE batch_operation")?;
+                let status = err.status();
+                failure.get_or_insert(status);
+                results.push(BatchResultResponse { index, op, outcome: BatchOutcome::Failed, status: Some(status.as_u16()), body: None, error: Some(err.envelope()) });
+            }
+        }
+    }
+    match (batch.mode, failure) {
+        (BatchMode::AllOrNothing, Some(status)) => {
+            tx.rollback()?;
+            for result in results.iter_mut().filter(|result| result.outcome == BatchOutcome::Applied) {
+                result.outcome = BatchOutcome::RolledBack;
             }
+            Ok((status, BatchResponse { mode: batch.mode, committed: false, results }))
+        }
+        _ => {
+            tx.commit()?;
+            events::committed();
+            Ok((warp::http::StatusCode::OK, BatchResponse { mode: batch.mode, committed: true, results }))
         }
     }
+}
 
-    // Test Case: 02 Table Creation
-    #[tokio::test]
-    async fn test_create_table_handler() {
-        let conn = setup_test_db();
-        let table = Table {
-            id: 0,
-            code: "Table-01".to_string(),
-        };
-        let result = create_table_handler(conn, table).await;
-        match result {
+/// Run one operation of a batch like its route, returns the status and body the route would reply with
+fn run_operation(conn: &Connection, operation: BatchOperation) -> Result<(warp::http::StatusCode, serde_json::Value), ApiError> {
+    match operation {
+        BatchOperation::AddItems(req_body) => place_order(conn, req_body),
+        BatchOperation::RemoveItem { order_id, item_id, if_match } => {
+            let message = remove_order_item(conn, order_id, item_id, if_match.as_deref())?;
+            Ok((warp::http::StatusCode::OK, json!({"success": message})))
+        }
+        BatchOperation::SetQuantity { order_id, item_id, quantity, if_match } => {
+            let (item, _) = set_item_quantity(conn, order_id, item_id, if_match.as_deref(), &OrderItemPatch { quantity })?;
+            Ok((warp::http::StatusCode::OK, json!(item)))
+        }
+        BatchOperation::MoveOrder { order_id, table_id, if_match } => {
+            let order = move_order(conn, order_id, table_id, if_match.as_deref())?;
+            Ok((warp::http::StatusCode::OK, json!(order)))
+        }
+    }
+}
+
+// Service Charge Handlers
+
+/// List all service charge rules
+pub async fn list_service_charge_rules_handler(conn: Connection) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let rules = ServiceChargeRuleResponse::list(&conn)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&rules),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Create a service charge rule
+pub async fn create_service_charge_rule_handler(conn: Connection, data: ServiceChargeRule) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        data.validate()?;
+        let id = in_transaction(&conn, |tx| {
+            let id = ServiceChargeRule::create(tx, &data)?;
+            events::record_entity(tx, EventKind::ServiceChargeRuleCreated, id)?;
+            audit::record(tx, Entity::ServiceChargeRule, id, None)?;
+            SubBillResponse::rebalance_open_orders(tx)?;
+            Ok(id)
+        })?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"id": id})),
+            warp::http::StatusCode::CREATED,
+        ))
+    }.await)
+}
+
+// Event Log Handlers
+
+/// Read the log of the changes after an event id, for auditing and for consumers catching up
+pub async fn event_log_handler(conn: Connection, query: EventLogQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        query.validate()?;
+        let log = LoggedEventResponse::since(&conn, &query)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&log),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// The orders of a table as they were at a time, rebuilt from their events. Items removed since
+/// are shown as they were, to settle what was ordered
+pub async fn table_history_handler(conn: Connection, table_id: i64, query: TableHistoryQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        query.validate()?;
+        Table::get(&conn, table_id)?.ok_or_else(|| ApiError::NotFound("No Table Found".to_string()))?;
+        let as_of = history::resolve(&conn, query.as_of.as_deref())?;
+        let orders = history::table_as_of(&conn, table_id, &as_of)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&TableHistoryResponse { table_id, as_of, orders }),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+// Audit Handlers
+
+/// List the audit log, the newest entries first unless sorted by id. Only sent with X-Staff-Role: manager
+pub async fn audit_log_handler(conn: Connection, staff_role: Option<String>, query: AuditQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        require_manager_role(staff_role.as_deref(), "Only managers can read the audit log")?;
+        query.validate()?;
+        let page = PageRequest::new(query.limit, query.after.as_deref(), Some(query.sort.as_deref().unwrap_or("-id")), AUDIT_SORTS)?;
+        let entries = AuditEntryResponse::list(&conn, &query, &page)?;
+        let entries = Page::new(entries, &page, AuditEntryResponse::cursor, |after| link("/audit", &query, after));
+        Ok(warp::reply::with_status(
+            warp::reply::json(&entries),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+// Webhook Handlers
+
+/// List all webhooks, without their secrets
+pub async fn list_webhooks_handler(conn: Connection) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let webhooks = Webhook::list(&conn)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&webhooks),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Create a webhook, the reply is the only time its secret is shown
+pub async fn create_webhook_handler(conn: Connection, data: Webhook) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        data.validate()?;
+        let secret = data.secret.clone().unwrap_or_else(webhooks::generate_secret);
+        let id = in_transaction(&conn, |tx| {
+            let id = Webhook::create(tx, &data, &secret)?;
+            audit::record(tx, Entity::Webhook, id, None)?;
+            Ok(id)
+        })?;
+        let mut webhook = Webhook::get(&conn, id)?.ok_or_else(|| ApiError::Internal("Created webhook is missing".to_string()))?;
+        webhook.secret = Some(secret);
+        Ok(warp::reply::with_status(
+            warp::reply::json(&webhook),
+            warp::http::StatusCode::CREATED,
+        ))
+    }.await)
+}
+
+pub async fn get_webhook_handler(conn: Connection, webhook_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let webhook = Webhook::get(&conn, webhook_id)?.ok_or_else(|| ApiError::NotFound("No Webhook Found".to_string()))?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&webhook),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Update some fields of a webhook, a new secret is used for the deliveries sent from now on
+pub async fn patch_webhook_handler(conn: Connection, webhook_id: i64, patch: WebhookPatch) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        patch.validate()?;
+        audited(&conn, Entity::Webhook, webhook_id, |tx| {
+            let webhook = Webhook::get(tx, webhook_id)?.ok_or_else(|| ApiError::NotFound("No Webhook Found".to_string()))?;
+            let webhook = patch.apply(webhook);
+            webhook.validate()?;
+            Ok(Webhook::update(tx, webhook_id, &webhook)?)
+        })?;
+        let webhook = Webhook::get(&conn, webhook_id)?.ok_or_else(|| ApiError::NotFound("No Webhook Found".to_string()))?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&webhook),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Delete a webhook, its pending deliveries are dropped
+pub async fn delete_webhook_handler(conn: Connection, webhook_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let outcome = audited(&conn, Entity::Webhook, webhook_id, |tx| Ok(Webhook::delete(tx, webhook_id)?))?;
+        delete_reply(outcome, webhook_id, "Webhook")
+    }.await)
+}
+
+/// List the deliveries of a webhook that failed every attempt
+pub async fn list_dead_letters_handler(conn: Connection, webhook_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        Webhook::get(&conn, webhook_id)?.ok_or_else(|| ApiError::NotFound("No Webhook Found".to_string()))?;
+        let deliveries = WebhookDeliveryResponse::dead_letters(&conn, webhook_id)?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&deliveries),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Send a dead delivery again with a fresh set of attempts
+pub async fn redeliver_handler(conn: Connection, webhook_id: i64, delivery_id: i64) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        audited(&conn, Entity::Webhook, webhook_id, |tx| {
+            WebhookDeliveryResponse::get(tx, webhook_id, delivery_id)?.ok_or_else(|| ApiError::NotFound("No Delivery Found".to_string()))?;
+            if !WebhookDeliveryResponse::redeliver(tx, delivery_id)? {
+                return Err(ApiError::Conflict("Only dead deliveries can be redelivered".to_string()));
+            }
+            Ok(())
+        })?;
+        let delivery = WebhookDeliveryResponse::get(&conn, webhook_id, delivery_id)?.ok_or_else(|| ApiError::NotFound("No Delivery Found".to_string()))?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&delivery),
+            warp::http::StatusCode::ACCEPTED
+        ))
+    }.await)
+}
+
+// Report Handlers
+
+/// End-of-day report with sales, discounts, service charges, payments and tips per server
+pub async fn daily_report_handler(conn: Connection, query: ReportQuery) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let report = DailyReportResponse::for_date(&conn, query.date.as_deref())?
+            .ok_or_else(|| ApiError::bad_request("Date must be YYYY-MM-DD"))?;
+        Ok(warp::reply::with_status(
+            warp::reply::json(&report),
+            warp::http::StatusCode::OK
+        ))
+    }.await)
+}
+
+/// Record a payment on an order or one of its sub-bills and close the order once fully paid
+fn record_payment(conn: &Connection, processor: &dyn PaymentProcessor, order_id: i64, sub_bill_id: Option<i64>, if_match: Option<&str>, req_body: &PaymentRequest) -> Result<warp::reply::WithStatus<warp::reply::Json>, ApiError> {
+    req_body.validate()?;
+    // The write lock is held from reading what is due until the payment is stored,
+    // so two payments at once can't both pay what is left
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    require_open_order(&tx, order_id)?;
+    check_if_match(&tx, if_match, Versioned::Order, order_id)?;
+    let bill = BillResponse::for_order(&tx, order_id)?.ok_or_else(|| ApiError::NotFound("No Order Found".to_string()))?;
+    // A sub-bill is paid up to what it has left, but never more than the order still owes
+    let balance = bill.total - PaymentResponse::paid_for_order(&tx, order_id)?;
+    let due = match sub_bill_id {
+        Some(sub_bill_id) => {
+            let sub_bill = SubBillResponse::get(&tx, sub_bill_id)?
+                .filter(|sub_bill| sub_bill.order_id == order_id)
+                .ok_or_else(|| ApiError::NotFound("No sub-bill found".to_string()))?;
+            (sub_bill.amount - PaymentResponse::paid_for_sub_bill(&tx, sub_bill_id)?).min(balance)
+        }
+        None => balance,
+    };
+    if due <= 0 {
+        return Err(ApiError::Conflict("Nothing left to pay".to_string()));
+    }
+    let settlement = PaymentResponse::settle(req_body, due).map_err(ApiError::bad_request)?;
+
+    // Cards are charged before anything is stored
+    let reference = if req_body.tender == Tender::Card {
+        let token = req_body.card_token.as_deref().ok_or_else(|| ApiError::bad_request("Card payments need a card_token"))?;
+        Some(processor.charge(req_body.amount, token)?)
+    } else {
+        None
+    };
+
+    let result = store_payment(&tx, order_id, sub_bill_id, req_body, &settlement, reference.clone(), &bill)
+        .and_then(|stored| tx.commit().map(|_| stored));
+    let (payment_id, paid) = match (result, reference) {
+        (Ok(stored), _) => stored,
+        (Err(err), None) => return Err(err.into()),
+        // Don't keep the money of a payment we failed to record. The staff must hear of a charge
+        // that is left on the card, taking the payment again would charge the guest twice
+        (Err(err), Some(reference)) => return Err(match processor.void(&reference) {
+            Ok(_) => err.into(),
+            Err(void_err) => ApiError::Unavailable(format!(
+                "The payment could not be recorded and card charge {} could not be voided, void it at the processor before taking the payment again ({})",
+                reference, void_err
+            )),
+        }),
+    };
+
+    let payment = PaymentResponse::get(conn, payment_id)?.ok_or_else(|| ApiError::Internal(format!("Payment {} missing after insert", payment_id)))?;
+    let order_status = OrderResponse::get_status(conn, order_id)?.ok_or_else(|| ApiError::Internal(format!("Order {} missing after payment", order_id)))?;
+    events::committed();
+    let total = bill.total;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&CheckoutResponse { payment, order_id, total, paid, balance_due: total - paid, order_status }),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+/// Store a payment taken on an order, closing the order once fully paid. Returns the payment id and what is paid in all
+fn store_payment(tx: &Connection, order_id: i64, sub_bill_id: Option<i64>, req_body: &PaymentRequest, settlement: &Settlement, reference: Option<String>, bill: &BillResponse) -> rusqlite::Result<(i64, i64)> {
+    let payment_id = PaymentResponse::create(tx, order_id, sub_bill_id, req_body, settlement, reference)?;
+    events::record_on_order(tx, EventKind::PaymentCaptured, order_id, payment_id)?;
+    audit::record(tx, Entity::Payment, payment_id, None)?;
+    if let Some(sub_bill_id) = sub_bill_id {
+        SubBillResponse::refresh_status(tx, sub_bill_id)?;
+    }
+    let paid = PaymentResponse::paid_for_order(tx, order_id)?;
+    let closed = paid >= bill.total && OrderResponse::close(tx, order_id, bill)?;
+    if closed {
+        events::record_order(tx, EventKind::OrderClosed, order_id)?;
+    }
+    Ok((payment_id, paid))
+}
+
+/// Refund or void a captured payment. Card payments are refunded or voided at the processor too
+fn cancel_payment(conn: &Connection, processor: &dyn PaymentProcessor, payment_id: i64, req_body: &PaymentReasonRequest, status: &str) -> Result<warp::reply::WithStatus<warp::reply::Json>, ApiError> {
+    req_body.validate()?;
+    let reason = req_body.reason.trim();
+    // The write lock is taken before the processor is called, so a second refund or void
+    // of the same payment waits for this one and then finds the payment no longer captured
+    audited(conn, Entity::Payment, payment_id, |tx| {
+        let payment = PaymentResponse::get(tx, payment_id)?.ok_or_else(|| ApiError::NotFound("No Payment Found".to_string()))?;
+        if payment.status != "captured" {
+            return Err(ApiError::Conflict(format!("Payment is already {}", payment.status)));
+        }
+        if status == "voided" && OrderResponse::get_status(tx, payment.order_id)?.as_deref() != Some("open") {
+            return Err(ApiError::Conflict("Payments of a closed order can only be refunded".to_string()));
+        }
+
+        let cancel_reference = match (&payment.reference, payment.tender) {
+            (Some(reference), Tender::Card) if status == "voided" => Some(processor.void(reference)?),
+            (Some(reference), Tender::Card) => Some(processor.refund(reference, payment.tendered)?),
+            _ => None,
+        };
+
+        if !PaymentResponse::cancel(tx, payment_id, status, reason, cancel_reference)? {
+            return Err(ApiError::Conflict("Payment is not captured".to_string()));
+        }
+        if let Some(sub_bill_id) = payment.sub_bill_id {
+            SubBillResponse::refresh_status(tx, sub_bill_id)?;
+        }
+        let kind = match status {
+            "voided" => EventKind::PaymentVoided,
+            _ => EventKind::PaymentRefunded,
+        };
+        Ok(events::record_on_order(tx, kind, payment.order_id, payment_id)?)
+    })?;
+    let payment = PaymentResponse::get(conn, payment_id)?.ok_or_else(|| ApiError::NotFound("No Payment Found".to_string()))?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&payment),
+        warp::http::StatusCode::OK
+    ))
+}
+
+// Live Update Handlers
+
+/// Upgrade to a WebSocket pushing the changes of the topics asked for, replaying those after the resume token first
+pub async fn live_updates_handler(query: LiveUpdatesQuery, ws: warp::ws::Ws) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        let topics = websocket::parse_topics(query.topics.as_deref().unwrap_or_default().split(','))?;
+        let resume = match query.resume.as_deref() {
+            Some(token) => Some(ResumePoint::parse(token).ok_or_else(|| ApiError::bad_request("Invalid resume token"))?),
+            None => None,
+        };
+        Ok(ws.on_upgrade(move |socket| websocket::serve(socket, topics, resume)))
+    }.await)
+}
+
+/// Stream the changes to orders as Server-Sent Events, replaying those after the Last-Event-ID first
+pub async fn event_stream_handler(query: EventStreamQuery, last_event_id: Option<String>) -> Result<impl warp::Reply, warp::Rejection> {
+    respond(async move {
+        query.validate()?;
+        let resume = match last_event_id.as_deref() {
+            Some(id) => Some(ResumePoint::parse(id).ok_or_else(|| ApiError::bad_request("Invalid Last-Event-ID"))?),
+            None => None,
+        };
+        Ok(warp::sse::reply(warp::sse::keep_alive().stream(sse::feed(query.table_id, resume))))
+    }.await)
+}
+
+// GraphQL Handlers
+
+/// Run a GraphQL query or mutation on its own database connection
+pub async fn graphql_handler(conn: Connection, (schema, request): (RestaurantSchema, async_graphql::Request)) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(GraphQLResponse::from(graphql::execute(&schema, conn, request).await))
+}
+
+/// Unit Tests
+#[cfg(test)]
+mod tests {
+    use warp::{Reply, hyper::Body};
+    use super::*;
+    use crate::db::create_schema;
+    use crate::models::{allocate_amount, SplitMode, SubBillAllocation, ItemAllocation};
+    use crate::payments::FakeProcessor;
+    use crate::models::{BillLineResponse, DiscountKind, DiscountReason};
+    use crate::promotions::promotion_adjustments;
+    use crate::pagination::{Cursor, CursorValue, SortColumn};
+
+
+    // Set up the test database
+    fn setup_test_db() -> Connection {
+        println!("Initializing the test database...");
+        let conn = Connection::open_in_memory().expect("Failed to create test database");
+        conn.execute("PRAGMA foreign_keys = ON;", []).expect("Failed to enable foreign key support");
+        create_schema(&conn);
+        conn
+    }
+
+    // A single unit of a menu for a seat
+    fn order_line(menu_id: i64, seat: Option<i64>) -> OrderLine {
+        OrderLine { menu_id, quantity: 1, note: None, seat }
+    }
+
+    // Open a database shared by every connection opened with the same name, kept while one is open
+    fn shared_test_db(name: &str) -> Connection {
+        let flags = rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE | rusqlite::OpenFlags::SQLITE_OPEN_CREATE | rusqlite::OpenFlags::SQLITE_OPEN_URI;
+        Connection::open_with_flags(format!("file:{}?mode=memory&cache=shared", name), flags).expect("Failed to create test database")
+    }
+
+    /// Database the routes open their connections to, shared by the tests that go through restaurent_routes
+    fn routes_test_db() -> Connection {
+        std::env::set_var("DATABASE_PATH", "file:routes_test?mode=memory&cache=shared");
+        let conn = shared_test_db("routes_test");
+        create_schema(&conn);
+        conn
+    }
+
+    // Inserting static table and menu data
+    fn setup_static_data(conn: &Connection){
+        let values_to_insert = ["T-01", "T-02", "T-03"];
+
+        for value in values_to_insert {
+            conn.execute("INSERT INTO tables (code) VALUES (?1)", [value]).expect("Insertion Failed");
+        }
+        let values_to_insert = [("M-01", 1000), ("M-02", 1250), ("M-03", 899), ("M-04", 1500), ("M-05", 350)];
+
+        for (name, price) in values_to_insert {
+            conn.execute("INSERT INTO menus (name, price) VALUES (?1, ?2)", params![name, price]).expect("Insertion Failed");
+        }
+
+    }
+
+    // The first page of a listing with the default limit and sort
+    fn first_page(sorts: &'static [SortColumn]) -> PageRequest {
+        PageRequest::new(None, None, None, sorts).expect("Invalid Page")
+    }
+
+    // Convert warp Response to serde Json Value
+    async fn convert_response_to_json(resp:  warp::http::Response<Body>)->serde_json::Value {
+        let body_bytes = warp::hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body_vec = body_bytes.to_vec();
+        let body_string = String::from_utf8_lossy(&body_vec);
+        let json_value: serde_json::Value = serde_json::from_str(&body_string).unwrap();
+        json_value
+    }
+
+    // Test Case: 01 Menu Creation
+    #[tokio::test]
+    async fn test_create_menu_handler() {
+        let conn = setup_test_db();
+        let menu = Menu {
+            id: 0,
+            name: "Menu-01".to_string(),
+            price: 1000,
+            category: None,
+            station: None,
+        };
+        let result = create_menu_handler(conn, menu).await;
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+                let json_data = convert_response_to_json(resp).await;
+                assert_eq!(json_data["id"].as_i64(), Some(1));
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 02 Table Creation
+    #[tokio::test]
+    async fn test_create_table_handler() {
+        let conn = setup_test_db();
+        let table = Table {
+            id: 0,
+            code: "Table-01".to_string(),
+            section: None,
+            table_type: None,
+            server: None,
+        };
+        let result = create_table_handler(conn, table).await;
+        match result {
             Ok(rep)=>{
                 let resp = rep.into_response();
                 assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
@@ -486,15 +1478,22 @@ mod tests {
         let order = OrderRequestBody {
             table_id: 1,
             menu_ids: vec![1, 2],
+            items: vec![],
+            party_size: None,
         };
-        let result = create_order_handler(conn, order).await;
-        // Will raise error, since table and menu not found
+        let result = create_order_handler(conn, None, order).await;
+        // Will raise error, since table and menu not found. Every missing reference is listed
         match result {
             Ok(rep)=>{
                 let resp = rep.into_response();
-                assert_eq!(resp.status(), warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+                assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
                 let json_data = convert_response_to_json(resp).await;
-                assert_eq!(json_data["error"].as_str(), Some("Error creating order FOREIGN KEY constraint failed"));
+                assert_eq!(json_data["code"].as_str(), Some("validation_failed"));
+                assert_eq!(json_data["details"], json!([
+                    {"field": "table_id", "code": "not_found", "message": "Table 1 does not exist"},
+                    {"field": "menu_ids[0]", "code": "not_found", "message": "Menu 1 does not exist"},
+                    {"field": "menu_ids[1]", "code": "not_found", "message": "Menu 2 does not exist"},
+                ]));
             }
             Err(_)=>{
                 panic!("Unhandled Error");
@@ -503,20 +1502,22 @@ mod tests {
     }
     #[tokio::test]
     async fn test_create_order_handler_wrong_data2() {
-        let mut conn = setup_test_db();
-        setup_static_data(&mut conn);
+        let conn = setup_test_db();
+        setup_static_data(&conn);
         let order = OrderRequestBody {
             table_id: 1,
             menu_ids: vec![],
+            items: vec![],
+            party_size: None,
         };
-        let result = create_order_handler(conn, order).await;
+        let result = create_order_handler(conn, None, order).await;
         // Will fail, since menu_ids empty
         match result {
             Ok(rep)=>{
                 let resp = rep.into_response();
                 assert_eq!(resp.status(), warp::http::StatusCode::BAD_REQUEST);
                 let json_data = convert_response_to_json(resp).await;
-                assert_eq!(json_data["error"].as_str(), Some("Please Add Items"));
+                assert_eq!(json_data["message"].as_str(), Some("Please Add Items"));
             }
             Err(_)=>{
                 panic!("Unhandled Error");
@@ -532,9 +1533,11 @@ mod tests {
         let order = OrderRequestBody {
             table_id: 1,
             menu_ids: vec![1, 2],
+            items: vec![],
+            party_size: None,
         };
 
-        let result = create_order_handler(conn, order).await;
+        let result = create_order_handler(conn, None, order).await;
         // Will create a new order for table_id 1 and menu 1, 2
         match result {
             Ok(rep)=>{
@@ -580,7 +1583,7 @@ mod tests {
 
         // Commit the transaction
         tx.commit().expect("Commit Failed");
-        let result = delete_order_item_handler(conn, 1, 2).await;
+        let result = delete_order_item_for_table_handler(conn, 1, 2, SeatQuery::default()).await;
         // Will remove menu 2 from the order, menu 1 will be still there
         match result {
             Ok(rep)=>{
@@ -621,7 +1624,7 @@ mod tests {
 
         // Commit the transaction
         tx.commit().expect("Commit Failed");
-        let result = delete_order_item_handler(conn, 1, 1).await;
+        let result = delete_order_item_for_table_handler(conn, 1, 1, SeatQuery::default()).await;
         // Will remove menu 1 from the order, and since no item i order, order will be deleted
         match result {
             Ok(rep)=>{
@@ -662,7 +1665,7 @@ mod tests {
 
         // Commit the transaction
         tx.commit().expect("Commit Failed");
-        let result = delete_order_item_handler(conn, 1, 1).await;
+        let result = delete_order_item_for_table_handler(conn, 1, 1, SeatQuery::default()).await;
         // Will update the quantity of menu 1
         match result {
             Ok(rep)=>{
@@ -709,7 +1712,7 @@ mod tests {
         // Commit the transaction
         tx.commit().expect("Commit Failed");
 
-        let result = get_order_item_for_table_handler(conn, 1, 2).await;
+        let result = get_order_item_for_table_handler(conn, 1, 2, SeatQuery::default()).await;
         // Will retrieve menu 2 from the table
         match result {
             Ok(rep)=>{
@@ -723,7 +1726,7 @@ mod tests {
                     // If item not found raise NotFound
                     warp::http::StatusCode::NOT_FOUND=>{
                         let json_data = convert_response_to_json(resp).await;
-                        assert_eq!(json_data["error"].as_str(), Some("No Item Found"));
+                        assert_eq!(json_data["message"].as_str(), Some("No Item Found"));
                     },
                     _ => {}
                 }
@@ -734,4 +1737,2258 @@ mod tests {
         }
 
     }
-}
\ No newline at end of file
+
+    // Test Case: 09 Same menu on different seats stays on separate lines
+    #[tokio::test]
+    async fn test_same_menu_on_different_seats(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        OrderItem::create(&conn, order_id, &order_line(1, Some(1)), 6).expect("OrderItems creation failed");
+
+        // Seat 2 does not match the line of seat 1
+        assert_eq!(OrderItem::get_existing_order_item_id(&conn, order_id, &order_line(1, Some(2))).expect("Query Failed"), None);
+        let item_id = OrderItem::get_existing_order_item_id(&conn, order_id, &order_line(1, Some(1))).expect("Query Failed");
+        assert!(item_id.is_some());
+
+        let result = list_order_items_for_table_handler(conn, 1, TableItemsQuery::default()).await;
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::OK);
+                let json_data = convert_response_to_json(resp).await;
+                assert_eq!(json_data[0]["seat"].as_i64(), Some(1));
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 10 List the items of a table grouped by seat
+    #[tokio::test]
+    async fn test_list_items_grouped_by_seat_handler(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        OrderItem::create(&conn, order_id, &order_line(1, Some(2)), 6).expect("OrderItems creation failed");
+        OrderItem::create(&conn, order_id, &order_line(1, Some(1)), 6).expect("OrderItems creation failed");
+        OrderItem::create(&conn, order_id, &order_line(2, Some(1)), 7).expect("OrderItems creation failed");
+        OrderItem::create(&conn, order_id, &order_line(3, None), 8).expect("OrderItems creation failed");
+
+        let query = TableItemsQuery { group_by: Some("seat".to_string()) };
+        let result = list_order_items_for_table_handler(conn, 1, query).await;
+        // Seats are listed in order with the items without a seat at the end
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::OK);
+                let json_data = convert_response_to_json(resp).await;
+                assert_eq!(json_data[0]["seat"].as_i64(), Some(1));
+                assert_eq!(json_data[0]["items"].as_array().map(|items| items.len()), Some(2));
+                assert_eq!(json_data[1]["seat"].as_i64(), Some(2));
+                assert_eq!(json_data[1]["items"][0]["menu_name"].as_str(), Some("M-01"));
+                assert!(json_data[2]["seat"].is_null());
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 11 Bill of a table with menu prices
+    #[tokio::test]
+    async fn test_get_bill_handler(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        let item_id = OrderItem::create(&conn, order_id, &order_line(1, Some(1)), 6).expect("OrderItems creation failed");
+        OrderItem::add_quantity_of_existing_order_item(&conn, item_id, 1).expect("Quantity update failed");
+        OrderItem::create(&conn, order_id, &order_line(2, Some(2)), 7).expect("OrderItems creation failed");
+
+        let result = get_bill_handler(conn, 1).await;
+        // 2 x M-01 and 1 x M-02
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::OK);
+                let json_data = convert_response_to_json(resp).await;
+                assert_eq!(json_data["lines"][0]["amount"].as_i64(), Some(2000));
+                assert_eq!(json_data["lines"][1]["unit_price"].as_i64(), Some(1250));
+                assert_eq!(json_data["total"].as_i64(), Some(3250));
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 12 Rounding remainders are handed out deterministically
+    #[test]
+    fn test_allocate_amount(){
+        assert_eq!(allocate_amount(1000, &[1, 1, 1]), vec![334, 333, 333]);
+        assert_eq!(allocate_amount(1001, &[1, 1, 1]), vec![334, 334, 333]);
+        assert_eq!(allocate_amount(1000, &[333_300, 333_300, 333_400]), vec![333, 333, 334]);
+        assert_eq!(allocate_amount(0, &[1, 2]), vec![0, 0]);
+        assert_eq!(allocate_amount(899, &[0, 0]), vec![0, 0]);
+    }
+
+    // Test Case: 13 Even split adds up to the bill total
+    #[tokio::test]
+    async fn test_split_bill_even_handler(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        OrderItem::create(&conn, order_id, &order_line(3, None), 6).expect("OrderItems creation failed");
+
+        let request = SplitBillRequest { mode: SplitMode::Even, guests: Some(3), allocations: vec![] };
+        let result = split_bill_handler(conn, 1, request).await;
+        // 899 over 3 guests
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+                let json_data = convert_response_to_json(resp).await;
+                let amounts: Vec<i64> = json_data["sub_bills"].as_array().unwrap().iter().map(|sub_bill| sub_bill["amount"].as_i64().unwrap()).collect();
+                assert_eq!(amounts, vec![300, 300, 299]);
+                assert!(json_data["sub_bills"][0]["id"].as_i64().unwrap() > 0);
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 14 Custom split with fractional quantities, then pay one sub-bill
+    #[tokio::test]
+    async fn test_split_bill_custom(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        let pizza = OrderItem::create(&conn, order_id, &order_line(1, None), 6).expect("OrderItems creation failed");
+        let drink = OrderItem::create(&conn, order_id, &order_line(5, Some(2)), 7).expect("OrderItems creation failed");
+        let bill = BillResponse::for_table(&conn, 1).expect("Bill Failed").expect("No Bill");
+
+        // The drink is not allocated
+        let request = SplitBillRequest {
+            mode: SplitMode::Custom,
+            guests: None,
+            allocations: vec![SubBillAllocation { label: None, items: vec![ItemAllocation { order_item_id: pizza, quantity: 1.0 }] }],
+        };
+        assert!(bill.split(&request).is_err());
+
+        // A third of the pizza each and the drink for the last guest
+        let third = || ItemAllocation { order_item_id: pizza, quantity: 0.3333 };
+        let request = SplitBillRequest {
+            mode: SplitMode::Custom,
+            guests: None,
+            allocations: vec![
+                SubBillAllocation { label: Some("Ann".to_string()), items: vec![third()] },
+                SubBillAllocation { label: None, items: vec![third()] },
+                SubBillAllocation { label: None, items: vec![third(), ItemAllocation { order_item_id: drink, quantity: 1.0 }] },
+            ],
+        };
+        let mut sub_bills = bill.split(&request).expect("Split Failed");
+        let amounts: Vec<i64> = sub_bills.iter().map(|sub_bill| sub_bill.amount).collect();
+        assert_eq!(amounts, vec![334, 333, 683]);
+        assert_eq!(amounts.iter().sum::<i64>(), bill.total);
+        assert_eq!(sub_bills[0].label, "Ann");
+
+        assert!(SubBillResponse::replace(&conn, order_id, &mut sub_bills).expect("Saving Failed"));
+        let payment = PaymentRequest { tender: Tender::Cash, amount: 333, tip: 0, card_token: None };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, Some(sub_bills[1].id), None, &payment).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+        // Paid sub-bills can't be paid again and block splitting again
+        let saved = SubBillResponse::list(&conn, order_id).expect("Listing Failed");
+        assert_eq!(saved[1].status, "paid");
+        assert_eq!(saved[2].lines.len(), 2);
+        let resp = record_payment(&conn, &FakeProcessor, order_id, Some(saved[1].id), None, &payment).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+        assert!(!SubBillResponse::replace(&conn, order_id, &mut sub_bills).expect("Saving Failed"));
+    }
+
+    // Create an order on table 1 with M-01 and M-02, 2250 in total
+    fn setup_order(conn: &Connection) -> i64 {
+        let order_id = OrderResponse::create(conn, 1).expect("Order Creation Failed");
+        OrderItem::create(conn, order_id, &order_line(1, None), 6).expect("OrderItems creation failed");
+        OrderItem::create(conn, order_id, &order_line(2, None), 7).expect("OrderItems creation failed");
+        order_id
+    }
+
+    // Test Case: 15 Partial payments close the order once fully paid
+    #[tokio::test]
+    async fn test_partial_payments_close_order(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+
+        let cash = PaymentRequest { tender: Tender::Cash, amount: 1000, tip: 0, card_token: None };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &cash).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["balance_due"].as_i64(), Some(1250));
+        assert_eq!(json_data["order_status"].as_str(), Some("open"));
+
+        // The tip is not put towards the bill
+        let card = PaymentRequest { tender: Tender::Card, amount: 1450, tip: 200, card_token: Some("tok-visa".to_string()) };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &card).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["payment"]["amount"].as_i64(), Some(1250));
+        assert_eq!(json_data["payment"]["tip"].as_i64(), Some(200));
+        assert!(json_data["payment"]["reference"].as_str().unwrap().starts_with("fake-charge"));
+        assert_eq!(json_data["balance_due"].as_i64(), Some(0));
+        assert_eq!(json_data["order_status"].as_str(), Some("closed"));
+
+        // The table is free for a new order and the closed order takes no more payments
+        assert_eq!(OrderResponse::get_existing_order_id(&conn, 1).expect("Query Failed"), None);
+        OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &cash).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+    }
+
+    // Test Case: 16 Change is only given on cash, declined cards record nothing
+    #[tokio::test]
+    async fn test_payment_tenders(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+
+        let card = PaymentRequest { tender: Tender::Card, amount: 3000, tip: 0, card_token: Some("tok-visa".to_string()) };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &card).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::BAD_REQUEST);
+
+        let declined = PaymentRequest { tender: Tender::Card, amount: 1000, tip: 0, card_token: Some("decline-visa".to_string()) };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &declined).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::PAYMENT_REQUIRED);
+        assert!(PaymentResponse::list_for_order(&conn, order_id).expect("Listing Failed").is_empty());
+
+        let voucher = PaymentRequest { tender: Tender::Voucher, amount: 250, tip: 0, card_token: None };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &voucher).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+
+        let cash = PaymentRequest { tender: Tender::Cash, amount: 5000, tip: 500, card_token: None };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &cash).into_response();
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["payment"]["amount"].as_i64(), Some(2000));
+        assert_eq!(json_data["payment"]["change_due"].as_i64(), Some(2500));
+        assert_eq!(json_data["order_status"].as_str(), Some("closed"));
+    }
+
+    // Test Case: 17 Refunds and voids need a reason and are tracked on the payment
+    #[tokio::test]
+    async fn test_refund_and_void_payment(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+
+        let card = PaymentRequest { tender: Tender::Card, amount: 1000, tip: 0, card_token: Some("tok-visa".to_string()) };
+        record_payment(&conn, &FakeProcessor, order_id, None, None, &card).expect("Payment Failed");
+        let card_payment = PaymentResponse::list_for_order(&conn, order_id).expect("Listing Failed").remove(0);
+
+        let resp = cancel_payment(&conn, &FakeProcessor, card_payment.id, &reason(" "), "voided").into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let resp = cancel_payment(&conn, &FakeProcessor, card_payment.id, &reason("Wrong table"), "voided").into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["status"].as_str(), Some("voided"));
+        assert_eq!(json_data["reason"].as_str(), Some("Wrong table"));
+        assert!(json_data["cancel_reference"].as_str().unwrap().starts_with("fake-void"));
+        assert_eq!(PaymentResponse::paid_for_order(&conn, order_id).expect("Query Failed"), 0);
+
+        // Pay in full, the closed order's payment can be refunded but not voided
+        let cash = PaymentRequest { tender: Tender::Cash, amount: 2250, tip: 0, card_token: None };
+        record_payment(&conn, &FakeProcessor, order_id, None, None, &cash).expect("Payment Failed");
+        let cash_payment = PaymentResponse::list_for_order(&conn, order_id).expect("Listing Failed").remove(1);
+        let resp = cancel_payment(&conn, &FakeProcessor, cash_payment.id, &reason("Cold food"), "voided").into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+        let resp = cancel_payment(&conn, &FakeProcessor, cash_payment.id, &reason("Cold food"), "refunded").into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let resp = cancel_payment(&conn, &FakeProcessor, cash_payment.id, &reason("Cold food"), "refunded").into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+    }
+
+    fn reason(reason: &str) -> PaymentReasonRequest {
+        PaymentReasonRequest { reason: reason.to_string() }
+    }
+
+    fn create_promotion(conn: &Connection, promotion: serde_json::Value) -> i64 {
+        let promotion: Promotion = serde_json::from_value(promotion).expect("Invalid promotion");
+        promotion.validate().expect("Invalid promotion");
+        Promotion::create(conn, &promotion).expect("Promotion creation failed")
+    }
+
+    fn bill_line(menu_id: i64, quantity: i64, unit_price: i64, ordered_minute: i64) -> BillLineResponse {
+        BillLineResponse {
+            order_item_id: menu_id,
+            menu_id,
+            menu_name: format!("M-0{}", menu_id),
+            category: None,
+            seat: None,
+            quantity,
+            unit_price,
+            amount: quantity * unit_price,
+            ordered_minute: Some(ordered_minute),
+        }
+    }
+
+    // Test Case: 18 Each promotion is its own adjustment line on the bill
+    #[tokio::test]
+    async fn test_promotion_adjustments_on_bill(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        conn.execute("UPDATE menus SET category = 'drinks' WHERE id = 2", []).expect("Update Failed");
+        setup_order(&conn);
+        create_promotion(&conn, json!({"name": "M-01 10% off", "kind": "percent", "scope": "item", "menu_id": 1, "value": 10}));
+        create_promotion(&conn, json!({"name": "Drinks 20% off", "kind": "percent", "scope": "category", "category": "drinks", "value": 20}));
+        create_promotion(&conn, json!({"name": "Welcome", "kind": "fixed", "scope": "order", "value": 200}));
+        create_promotion(&conn, json!({"name": "M-03 deal", "kind": "fixed", "scope": "item", "menu_id": 3, "value": 100}));
+
+        let result = get_bill_handler(conn, 1).await;
+        // 2250 - 100 - 250 - 200, the M-03 deal has nothing to apply to
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::OK);
+                let json_data = convert_response_to_json(resp).await;
+                let amounts: Vec<i64> = json_data["adjustments"].as_array().unwrap().iter().map(|adjustment| adjustment["amount"].as_i64().unwrap()).collect();
+                assert_eq!(amounts, vec![-100, -250, -200]);
+                assert_eq!(json_data["adjustments"][1]["description"].as_str(), Some("Drinks 20% off"));
+                assert_eq!(json_data["subtotal"].as_i64(), Some(2250));
+                assert_eq!(json_data["total"].as_i64(), Some(1700));
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 19 Buy X get Y and happy hour windows
+    #[test]
+    fn test_buy_x_get_y_and_happy_hour(){
+        let buy_two_get_one: PromotionResponse = serde_json::from_value(json!({
+            "id": 1, "name": "3 for 2", "kind": "buy_x_get_y", "scope": "item", "menu_id": 5, "category": null, "value": 0,
+            "buy_quantity": 2, "get_quantity": 1, "starts_at": null, "ends_at": null, "coupon_code": null, "usage_limit": null,
+            "usage_count": 0, "active": true
+        })).unwrap();
+        // Only full groups of 3 count, the cheapest unit of each group is free
+        let lines = vec![bill_line(5, 4, 350, 600), bill_line(5, 3, 300, 600)];
+        let adjustments = promotion_adjustments(&lines, &[buy_two_get_one], &[]);
+        assert_eq!(adjustments[0].amount, -650);
+
+        let happy_hour: PromotionResponse = serde_json::from_value(json!({
+            "id": 2, "name": "Late night", "kind": "percent", "scope": "order", "menu_id": null, "category": null, "value": 50,
+            "buy_quantity": null, "get_quantity": null, "starts_at": "22:00", "ends_at": "02:00", "coupon_code": null, "usage_limit": null,
+            "usage_count": 0, "active": true
+        })).unwrap();
+        // Ordered at 23:30, 01:00 and 02:00, the window runs past midnight and ends at 02:00
+        let lines = vec![bill_line(1, 1, 1000, 23 * 60 + 30), bill_line(2, 1, 1250, 60), bill_line(3, 1, 899, 120)];
+        let adjustments = promotion_adjustments(&lines, std::slice::from_ref(&happy_hour), &[]);
+        assert_eq!(adjustments[0].amount, -1125);
+        // A coupon promotion only applies once redeemed
+        let coupon = PromotionResponse { coupon_code: Some("NIGHT".to_string()), ..happy_hour };
+        assert!(promotion_adjustments(&lines, std::slice::from_ref(&coupon), &[]).is_empty());
+        assert_eq!(promotion_adjustments(&lines, &[coupon], &[2]).len(), 1);
+    }
+
+    // Test Case: 20 Coupons can't be used more often than their usage limit
+    #[tokio::test]
+    async fn test_coupon_usage_limit(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let other_order_id = OrderResponse::create(&conn, 2).expect("Order Creation Failed");
+        let promotion_id = create_promotion(&conn, json!({"name": "Launch", "kind": "fixed", "scope": "order", "value": 500, "coupon_code": "LAUNCH", "usage_limit": 1}));
+
+        assert_eq!(PromotionResponse::get_by_coupon_code(&conn, "NOPE").expect("Query Failed"), None);
+        assert!(PromotionResponse::redeem_coupon(&conn, order_id, promotion_id).expect("Redeem Failed"));
+        // Not twice on the same order and not on a second order
+        assert!(!PromotionResponse::redeem_coupon(&conn, order_id, promotion_id).expect("Redeem Failed"));
+        assert!(!PromotionResponse::redeem_coupon(&conn, other_order_id, promotion_id).expect("Redeem Failed"));
+        let promotion = PromotionResponse::list(&conn).expect("Listing Failed").remove(0);
+        assert_eq!(promotion.usage_count, 1);
+        assert_eq!(PromotionResponse::coupon_promotion_ids(&conn, other_order_id).expect("Query Failed"), Vec::<i64>::new());
+
+        let bill = BillResponse::for_order(&conn, order_id).expect("Query Failed").unwrap();
+        assert_eq!(bill.total, 1750);
+        let result = redeem_coupon_handler(conn, other_order_id, None, CouponRequest { code: "LAUNCH".to_string() }).await;
+        match result {
+            Ok(rep)=>{
+                assert_eq!(rep.into_response().status(), warp::http::StatusCode::CONFLICT);
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 21 Manual discounts need a manager and a reason code
+    #[tokio::test]
+    async fn test_manual_discount_requires_manager(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let request = ManualDiscountRequest { kind: DiscountKind::Percent, value: 10, reason_code: DiscountReason::Complaint };
+        let result = create_manual_discount_handler(conn, order_id, Some("waiter".to_string()), None, request).await;
+        match result {
+            Ok(rep)=>{
+                assert_eq!(rep.into_response().status(), warp::http::StatusCode::FORBIDDEN);
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let request = ManualDiscountRequest { kind: DiscountKind::Percent, value: 10, reason_code: DiscountReason::Complaint };
+        let result = create_manual_discount_handler(conn, order_id, Some("Manager".to_string()), None, request).await;
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+                let json_data = convert_response_to_json(resp).await;
+                assert_eq!(json_data["adjustments"][0]["reason_code"].as_str(), Some("complaint"));
+                assert_eq!(json_data["adjustments"][0]["amount"].as_i64(), Some(-225));
+                assert_eq!(json_data["total"].as_i64(), Some(2025));
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 22 Discounts never take the total below zero
+    #[test]
+    fn test_discounts_are_capped(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        create_promotion(&conn, json!({"name": "Welcome", "kind": "fixed", "scope": "order", "value": 2000}));
+        let request = ManualDiscountRequest { kind: DiscountKind::Fixed, value: 1000, reason_code: DiscountReason::ManagerComp };
+        ManualDiscountResponse::create(&conn, order_id, &request).expect("Discount creation failed");
+
+        let bill = BillResponse::for_order(&conn, order_id).expect("Query Failed").unwrap();
+        let amounts: Vec<i64> = bill.adjustments.iter().map(|adjustment| adjustment.amount).collect();
+        assert_eq!(amounts, vec![-2000, -250]);
+        assert_eq!(bill.total, 0);
+    }
+
+    // Test Case: 23 Service charge for large parties, by the most specific rule of the table
+    #[test]
+    fn test_service_charge_rules(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        conn.execute("UPDATE tables SET section = 'terrace', table_type = 'booth' WHERE id = 1", []).expect("Update Failed");
+        let rules = [(None, None, 6, 10), (Some("terrace"), None, 4, 12), (Some("terrace"), Some("booth"), 4, 15), (Some("bar"), None, 0, 20)];
+        for (section, table_type, min_party_size, percent) in rules {
+            let rule = ServiceChargeRule { id: 0, section: section.map(str::to_string), table_type: table_type.map(str::to_string), min_party_size, percent };
+            ServiceChargeRule::create(&conn, &rule).expect("Rule creation failed");
+        }
+        assert_eq!(ServiceChargeRuleResponse::for_table(&conn, 1).expect("Query Failed").unwrap().percent, 15);
+        assert_eq!(ServiceChargeRuleResponse::for_table(&conn, 2).expect("Query Failed").unwrap().percent, 10);
+
+        // Two seats is not above the party size of the rule
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        OrderItem::create(&conn, order_id, &order_line(1, Some(1)), 6).expect("OrderItems creation failed");
+        OrderItem::create(&conn, order_id, &order_line(2, Some(2)), 7).expect("OrderItems creation failed");
+        let bill = BillResponse::for_order(&conn, order_id).expect("Query Failed").unwrap();
+        assert_eq!((bill.party_size, bill.service_charge, bill.total), (Some(2), 0, 2250));
+
+        // 15% of 2250 is 337.5, rounded up
+        OrderResponse::set_party_size(&conn, order_id, 5).expect("Update Failed");
+        let bill = BillResponse::for_order(&conn, order_id).expect("Query Failed").unwrap();
+        assert_eq!((bill.service_charge_percent, bill.service_charge, bill.total), (Some(15), 338, 2588));
+    }
+
+    // Test Case: 24 Tips go to the server of the table and show up in the end-of-day report
+    #[tokio::test]
+    async fn test_tips_by_server_in_daily_report(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        ServiceChargeRule::create(&conn, &ServiceChargeRule { id: 0, section: None, table_type: None, min_party_size: 2, percent: 10 }).expect("Rule creation failed");
+        Table::assign_server(&conn, 1, Some("Alex")).expect("Assign Failed");
+        let order_id = setup_order(&conn);
+        OrderResponse::set_party_size(&conn, order_id, 4).expect("Update Failed");
+
+        // 2250 + 225 service charge, paid with a 300 tip by card and then in cash
+        let card = PaymentRequest { tender: Tender::Card, amount: 1300, tip: 300, card_token: Some("tok".to_string()) };
+        record_payment(&conn, &FakeProcessor, order_id, None, None, &card).expect("Payment Failed");
+        Table::assign_server(&conn, 1, Some("Sam")).expect("Assign Failed");
+        let cash = PaymentRequest { tender: Tender::Cash, amount: 2000, tip: 100, card_token: None };
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &cash).into_response();
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["order_status"].as_str(), Some("closed"));
+        assert_eq!(json_data["payment"]["server"].as_str(), Some("Sam"));
+
+        let bill = BillResponse::for_order(&conn, order_id).expect("Query Failed").unwrap();
+        assert_eq!((bill.service_charge, bill.tips), (225, 400));
+
+        let report = DailyReportResponse::for_date(&conn, None).expect("Report Failed").unwrap();
+        assert_eq!((report.orders_closed, report.subtotal, report.service_charges, report.total), (1, 2250, 225, 2475));
+        assert_eq!(report.tips, 400);
+        let tips: Vec<(Option<String>, i64)> = report.tips_by_server.into_iter().map(|server| (server.server, server.tips)).collect();
+        assert_eq!(tips, vec![(Some("Alex".to_string()), 300), (Some("Sam".to_string()), 100)]);
+        assert!(DailyReportResponse::for_date(&conn, Some("2024-02-30")).expect("Report Failed").is_none());
+    }
+
+    // Test Case: 25 Menus and tables used by orders are archived instead of deleted
+    #[tokio::test]
+    async fn test_delete_archives_used_menus_and_tables(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+
+        assert_eq!(Menu::delete(&conn, 5).expect("Delete Failed"), DeleteOutcome::Deleted);
+        assert_eq!(Menu::delete(&conn, 1).expect("Delete Failed"), DeleteOutcome::Archived);
+        assert_eq!(Menu::delete(&conn, 9).expect("Delete Failed"), DeleteOutcome::NotFound);
+        let menu_ids: Vec<i64> = Menu::list(&conn, &MenuListQuery::default(), &first_page(MENU_SORTS)).expect("Listing Failed").iter().map(|menu| menu.id).collect();
+        assert_eq!(menu_ids, vec![2, 3, 4]);
+        assert!(Menu::get(&conn, 1).expect("Query Failed").unwrap().archived);
+        // The bill of the order still shows the archived menu
+        let bill = BillResponse::for_order(&conn, order_id).expect("Query Failed").unwrap();
+        assert_eq!(bill.lines[0].menu_name, "M-01");
+
+        // A table with an open order can't be deleted, once closed it is archived
+        let open_conn = setup_test_db();
+        setup_static_data(&open_conn);
+        setup_order(&open_conn);
+        let resp = delete_table_handler(open_conn, 1, None).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+        let bill = BillResponse::for_order(&conn, order_id).expect("Query Failed").unwrap();
+        OrderResponse::close(&conn, order_id, &bill).expect("Close Failed");
+        assert_eq!(Table::delete(&conn, 1).expect("Delete Failed"), DeleteOutcome::Archived);
+        assert_eq!(Table::delete(&conn, 2).expect("Delete Failed"), DeleteOutcome::Deleted);
+        assert_eq!(Table::list(&conn, &TableListQuery::default(), &first_page(TABLE_SORTS)).expect("Listing Failed").len(), 1);
+
+        let order = OrderRequestBody {
+            table_id: 3,
+            menu_ids: vec![2, 1],
+            items: vec![],
+            party_size: None,
+        };
+        let result = create_order_handler(conn, None, order).await;
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+                let json_data = convert_response_to_json(resp).await;
+                assert_eq!(json_data["details"], json!([{"field": "menu_ids[1]", "code": "archived", "message": "Menu 1 is archived"}]));
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+    }
+
+    // Test Case: 26 Patching a table keeps missing fields and clears null ones
+    #[tokio::test]
+    async fn test_patch_table_handler(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        conn.execute("UPDATE tables SET section = 'terrace', server = 'Alex' WHERE id = 1", []).expect("Update Failed");
+        let patch: TablePatch = serde_json::from_value(json!({"code": "T-10", "server": null})).unwrap();
+        let result = patch_table_handler(conn, 1, None, patch).await;
+        match result {
+            Ok(rep)=>{
+                let resp = rep.into_response();
+                assert_eq!(resp.status(), warp::http::StatusCode::OK);
+                let json_data = convert_response_to_json(resp).await;
+                assert_eq!(json_data["code"].as_str(), Some("T-10"));
+                assert_eq!(json_data["section"].as_str(), Some("terrace"));
+                assert!(json_data["server"].is_null());
+            }
+            Err(_)=>{
+                panic!("Unhandled Error");
+            }
+        }
+
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let patch = TablePatch { code: Some("T-02".to_string()), ..Default::default() };
+        let resp = patch_table_handler(conn, 1, None, patch).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["details"][0]["field"].as_str(), Some("code"));
+        assert_eq!(json_data["details"][0]["code"].as_str(), Some("duplicate"));
+    }
+
+    // Test Case: 27 Errors share one envelope, the request id is echoed and internals are not leaked
+    #[tokio::test]
+    async fn test_error_envelope_and_request_id(){
+        let routes = crate::routes::restaurent_routes();
+        let resp = warp::test::request()
+            .method("GET")
+            .path("/no-such-route")
+            .header("x-request-id", "req-123")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers()["x-request-id"], "req-123");
+        let json_data: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(json_data["code"].as_str(), Some("not_found"));
+        assert_eq!(json_data["message"].as_str(), Some("Route not found"));
+        assert_eq!(json_data["request_id"].as_str(), Some("req-123"));
+        assert!(json_data["details"].is_null());
+
+        // Unusable ids are replaced by a generated one
+        let resp = warp::test::request().path("/no-such-route").header("x-request-id", "bad id!").reply(&routes).await;
+        let generated = resp.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert_eq!(generated.len(), 16);
+        let json_data: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(json_data["request_id"].as_str(), Some(generated.as_str()));
+
+        let resp = crate::errors::stamp_request_id("req-456".to_string(), respond::<warp::reply::Json>(Err(ApiError::Internal("disk on fire".to_string()))).unwrap());
+        assert_eq!(resp.status(), warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["code"].as_str(), Some("internal"));
+        assert_eq!(json_data["message"].as_str(), Some("Internal server error"));
+        assert_eq!(json_data["request_id"].as_str(), Some("req-456"));
+        assert!(!json_data.to_string().contains("disk on fire"));
+    }
+
+    // Test Case: 28 Constraint violations of the database map to client errors
+    #[test]
+    fn test_constraint_errors_map_to_status(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let err = conn.execute("INSERT INTO tables (code) VALUES ('T-01')", []).unwrap_err();
+        assert_eq!(ApiError::from(err).status(), warp::http::StatusCode::CONFLICT);
+        let err = conn.execute("INSERT INTO orders (table_id) VALUES (99)", []).unwrap_err();
+        assert_eq!(ApiError::from(err).status(), warp::http::StatusCode::BAD_REQUEST);
+        let err = conn.execute("SELECT * FROM no_such_table", []).unwrap_err();
+        assert_eq!(ApiError::from(err).status(), warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    // Test Case: 29 Request rules are checked declaratively and every failed field is listed
+    #[tokio::test]
+    async fn test_request_validation_field_errors(){
+        let conn = setup_test_db();
+        let table = Table { id: 0, code: "T 01!".to_string(), section: Some(" ".to_string()), table_type: None, server: Some("x".repeat(51)) };
+        let resp = create_table_handler(conn, table).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let json_data = convert_response_to_json(resp).await;
+        let fields: Vec<(&str, &str)> = json_data["details"].as_array().unwrap().iter()
+            .map(|error| (error["field"].as_str().unwrap(), error["code"].as_str().unwrap()))
+            .collect();
+        assert_eq!(fields, vec![("code", "charset"), ("section", "blank"), ("server", "length")]);
+
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order = OrderRequestBody {
+            table_id: 1,
+            menu_ids: vec![],
+            items: vec![OrderLine { menu_id: 1, quantity: 1, note: None, seat: Some(0) }, OrderLine { menu_id: -2, quantity: 1, note: None, seat: None }],
+            party_size: Some(0),
+        };
+        let resp = create_order_handler(conn, None, order).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let json_data = convert_response_to_json(resp).await;
+        let fields: Vec<&str> = json_data["details"].as_array().unwrap().iter().map(|error| error["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, vec!["items[0].seat", "items[1].menu_id", "party_size"]);
+        assert_eq!(json_data["details"][2]["message"].as_str(), Some("Must be between 1 and 100"));
+
+        // Unknown fields are rejected instead of ignored
+        assert!(serde_json::from_value::<Menu>(json!({"name": "Soup", "price": 500, "colour": "red"})).is_err());
+        assert!(serde_json::from_value::<OrderRequestBody>(json!({"table_id": 1, "items": [{"menu_id": 1, "qty": 2}]})).is_err());
+    }
+
+    // Test Case: 30 The same menu twice for a seat is one item with a higher quantity
+    #[tokio::test]
+    async fn test_duplicate_order_lines_are_merged(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order = OrderRequestBody {
+            table_id: 1,
+            menu_ids: vec![2, 2],
+            items: vec![OrderLine { menu_id: 2, quantity: 1, note: None, seat: Some(1) }],
+            party_size: None,
+        };
+        check_order_references(&conn, &order).expect("References are valid");
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Failed");
+        for line in order.lines() {
+            add_order_line(&conn, order_id, &line).expect("Adding Failed");
+        }
+        let items = OrderItem::list_order_items(&conn, 1).expect("Listing Failed");
+        let quantities: Vec<(Option<i64>, i64)> = items.iter().map(|item| (item.seat, item.quantity)).collect();
+        assert_eq!(quantities, vec![(Some(1), 1), (None, 2)]);
+    }
+
+    // Test Case: 31 Lines carry a quantity and a note, PATCH sets the quantity and prep time stays exact
+    #[tokio::test]
+    async fn test_order_line_quantities_and_patch(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order: OrderRequestBody = serde_json::from_value(json!({"table_id": 1, "items": [
+            {"menu_id": 1, "quantity": 3, "note": "no onions"},
+            {"menu_id": 1, "quantity": 2},
+            {"menu_id": 1, "note": "no onions"},
+        ]})).unwrap();
+        order.validate().expect("Order is valid");
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Failed");
+        for line in order.lines() {
+            add_order_line(&conn, order_id, &line).expect("Adding Failed");
+        }
+        let items = OrderItem::list_all_order_items(&conn, order_id).expect("Listing Failed");
+        assert_eq!(items.len(), 2);
+        assert_eq!((items[0].quantity, items[0].note.as_deref()), (4, Some("no onions")));
+        assert_eq!(items[0].cooking_time, items[0].unit_cooking_time * 4);
+        assert_eq!((items[1].quantity, items[1].note.as_deref()), (2, None));
+        let item = &items[0];
+
+        let resp = patch_order_item_handler(conn, order_id, item.id, None, OrderItemPatch { quantity: 7 }).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["quantity"].as_i64(), Some(7));
+        assert_eq!(json_data["cooking_time"].as_i64(), Some(item.unit_cooking_time * 7));
+
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let resp = patch_order_item_handler(conn, order_id, 99, None, OrderItemPatch { quantity: 2 }).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+        let resp = patch_order_item_handler(setup_test_db(), 1, 1, None, OrderItemPatch { quantity: 0 }).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    // Test Case: 32 Items are removed by their own id, the last one takes the empty order with it
+    #[tokio::test]
+    async fn test_delete_order_item_by_id(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let items = OrderItem::list_all_order_items(&conn, order_id).expect("Listing Failed");
+        OrderItem::delete(&conn, order_id, items[0].id).expect("Delete Failed");
+        assert!(!remove_empty_order(&conn, order_id).expect("Cleanup Failed"));
+        let resp = delete_order_item_handler(conn, order_id, items[1].id, None).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["success"].as_str(), Some("Item deleted successfully and order deleted"));
+
+        // An item of another order is not found
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let other_order_id = OrderResponse::create(&conn, 2).expect("Order Failed");
+        let other_item_id = OrderItem::create(&conn, other_order_id, &order_line(3, None), 5).expect("OrderItems creation failed");
+        let resp = delete_order_item_handler(conn, order_id, other_item_id, None).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+    }
+
+    // Test Case: 33 Orders are placed and read under their table
+    #[tokio::test]
+    async fn test_table_orders(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order: OrderRequestBody = serde_json::from_value(json!({"items": [{"menu_id": 1, "quantity": 2}]})).unwrap();
+        let resp = create_table_order_handler(conn, 2, None, order).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order: OrderRequestBody = serde_json::from_value(json!({"table_id": 1, "menu_ids": [1]})).unwrap();
+        let resp = create_table_order_handler(conn, 2, None, order).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let order = OrderResponse::get(&conn, order_id).expect("Query Failed").expect("Order not found");
+        assert_eq!((order.table_id, order.menus.len()), (1, 2));
+        let query = |table_id| OrderListQuery { table_id: Some(table_id), ..Default::default() };
+        assert_eq!(OrderResponse::list(&conn, &query(1), &first_page(ORDER_SORTS)).expect("Listing Failed").len(), 1);
+        assert!(OrderResponse::list(&conn, &query(2), &first_page(ORDER_SORTS)).expect("Listing Failed").is_empty());
+        let resp = list_table_orders_handler(conn, 99, OrderListQuery::default()).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+    }
+
+    // Test Case: 34 Listings are paged with cursors, filtered and sorted
+    #[tokio::test]
+    async fn test_list_pagination(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let query: MenuListQuery = serde_urlencoded::from_str("limit=2&sort=-price").unwrap();
+        let resp = list_menu_handler(conn, query).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let page = convert_response_to_json(resp).await;
+        assert_eq!(page["data"].as_array().unwrap().iter().map(|menu| menu["id"].as_i64().unwrap()).collect::<Vec<_>>(), vec![4, 2]);
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+        assert_eq!(page["links"]["next"], json!(format!("/menus?limit=2&sort=-price&after={}", cursor)));
+
+        // Following the cursors walks every menu once, the last page has no next link
+        let mut ids = vec![4, 2];
+        let mut after = Some(cursor);
+        while let Some(cursor) = after {
+            let conn = setup_test_db();
+            setup_static_data(&conn);
+            let query = MenuListQuery { limit: Some(2), after: Some(cursor), sort: Some("-price".to_string()), ..Default::default() };
+            let page = convert_response_to_json(list_menu_handler(conn, query).await.unwrap().into_response()).await;
+            ids.extend(page["data"].as_array().unwrap().iter().map(|menu| menu["id"].as_i64().unwrap()));
+            after = page["next_cursor"].as_str().map(str::to_string);
+            assert_eq!(after.is_none(), page["links"]["next"].is_null());
+        }
+        assert_eq!(ids, vec![4, 2, 1, 3, 5]);
+
+        // Filters
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        conn.execute("UPDATE menus SET category = 'drinks' WHERE id IN (3, 5)", []).expect("Update Failed");
+        let query = MenuListQuery { name: Some("m-0".to_string()), category: Some("drinks".to_string()), sort: Some("name".to_string()), ..Default::default() };
+        let menus = Menu::list(&conn, &query, &first_page(MENU_SORTS)).expect("Listing Failed");
+        assert_eq!(menus.iter().map(|menu| menu.id).collect::<Vec<_>>(), vec![3, 5]);
+        let query = TableListQuery { code: Some("02".to_string()), ..Default::default() };
+        let tables = Table::list(&conn, &query, &first_page(TABLE_SORTS)).expect("Listing Failed");
+        assert_eq!(tables.iter().map(|table| table.code.as_str()).collect::<Vec<_>>(), vec!["T-02"]);
+
+        // Orders by state, table and creation time
+        let order_id = setup_order(&conn);
+        conn.execute("INSERT INTO orders (table_id, status, created_at) VALUES (2, 'closed', '2024-01-01 12:00:00')", []).expect("Insertion Failed");
+        let list = |query: OrderListQuery| {
+            let page = PageRequest::new(query.limit, None, query.sort.as_deref(), ORDER_SORTS).expect("Invalid Page");
+            OrderResponse::list(&conn, &query, &page).expect("Listing Failed").iter().map(|order| order.id).collect::<Vec<_>>()
+        };
+        assert_eq!(list(OrderListQuery { status: Some("open".to_string()), ..Default::default() }), vec![order_id]);
+        assert_eq!(list(OrderListQuery { table_id: Some(2), ..Default::default() }), vec![order_id + 1]);
+        let query = OrderListQuery { created_from: Some("2024-01-01".to_string()), created_to: Some("2024-01-02".to_string()), ..Default::default() };
+        assert_eq!(list(query), vec![order_id + 1]);
+        assert_eq!(list(OrderListQuery { sort: Some("created_at".to_string()), ..Default::default() }), vec![order_id + 1, order_id]);
+
+        // Unknown sorts, foreign cursors and bad filters are rejected with field errors
+        let cursor = Cursor { sort: "price".to_string(), value: CursorValue::Int(1000), id: 1 }.encode();
+        for (query, field) in [("sort=cooking_time", "sort"), ("after=zz", "after"), ("limit=0", "limit"), ("sort=name&after=", "after")] {
+            let conn = setup_test_db();
+            let query: MenuListQuery = serde_urlencoded::from_str(query).unwrap();
+            let resp = list_menu_handler(conn, query).await.unwrap().into_response();
+            assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(convert_response_to_json(resp).await["details"][0]["field"], json!(field));
+        }
+        let conn = setup_test_db();
+        let query = MenuListQuery { after: Some(cursor), sort: Some("name".to_string()), ..Default::default() };
+        let resp = list_menu_handler(conn, query).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let conn = setup_test_db();
+        let query: OrderListQuery = serde_urlencoded::from_str("status=paid&created_from=2024-13-01").unwrap();
+        let resp = list_order_handler(conn, query).await.unwrap().into_response();
+        let fields: Vec<_> = convert_response_to_json(resp).await["details"].as_array().unwrap().iter().map(|error| error["field"].clone()).collect();
+        assert_eq!(fields, vec![json!("created_from"), json!("status")]);
+    }
+
+    // Test Case: 35 Retried orders with an Idempotency-Key are replayed, not added again
+    #[tokio::test]
+    async fn test_idempotent_order_creation(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order = |body| -> OrderRequestBody { serde_json::from_value(body).unwrap() };
+        let key = || Some("tablet-7-0001".to_string());
+        let first = idempotent(&conn, key(), "POST /orders 1".to_string(), || place_order(&conn, order(json!({"table_id": 1, "menu_ids": [1]})))).expect("Order Failed");
+        assert_eq!(first.status(), warp::http::StatusCode::CREATED);
+        let first = convert_response_to_json(first).await;
+        let retry = idempotent(&conn, key(), "POST /orders 1".to_string(), || place_order(&conn, order(json!({"table_id": 1, "menu_ids": [1]})))).expect("Order Failed");
+        assert_eq!(retry.status(), warp::http::StatusCode::CREATED);
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        assert_eq!(convert_response_to_json(retry).await, first);
+        let order_id = first["id"].as_i64().unwrap();
+        let items = OrderItem::list_all_order_items(&conn, order_id).expect("Listing Failed");
+        assert_eq!(items.iter().map(|item| item.quantity).collect::<Vec<_>>(), vec![1]);
+
+        // The same key with another body is a conflict, so is a key whose first request still runs
+        let resp = idempotent(&conn, key(), "POST /orders 2".to_string(), || place_order(&conn, order(json!({"table_id": 1, "menu_ids": [2]})))).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+        conn.execute("INSERT INTO idempotency_keys (key, request, claim, created_at) VALUES ('running', 'POST /orders 1', 1, CURRENT_TIMESTAMP)", []).expect("Insertion Failed");
+        let resp = idempotent(&conn, Some("running".to_string()), "POST /orders 1".to_string(), || panic!("Ran twice")).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+
+        // Failed requests give their key up, expired keys are forgotten
+        let resp = idempotent(&conn, Some("failed".to_string()), "POST /orders 3".to_string(), || place_order(&conn, order(json!({"table_id": 9, "menu_ids": [1]})))).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(matches!(idempotency::claim(&conn, "failed", "POST /orders 3").expect("Claim Failed"), Claim::New(_)));
+        // A claim taken over while its request ran rolls the request's writes back
+        let resp = idempotent(&conn, Some("slow".to_string()), "POST /orders 4".to_string(), || {
+            let placed = place_order(&conn, order(json!({"table_id": 2, "menu_ids": [1]})));
+            conn.execute("UPDATE idempotency_keys SET created_at = datetime('now', '-2 minutes') WHERE key = 'slow'", []).expect("Update Failed");
+            assert!(matches!(idempotency::claim(&conn, "slow", "POST /orders 4").expect("Claim Failed"), Claim::New(_)));
+            placed
+        }).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+        assert_eq!(OrderResponse::get_existing_order_id(&conn, 2).expect("Query Failed"), None);
+        conn.execute("UPDATE idempotency_keys SET created_at = datetime('now', '-25 hours') WHERE key = 'tablet-7-0001'", []).expect("Update Failed");
+        assert!(matches!(idempotency::claim(&conn, "tablet-7-0001", "POST /orders 2").expect("Claim Failed"), Claim::New(_)));
+
+        // Handlers key on the path and body, without a key every request is placed
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let resp = create_table_order_handler(conn, 1, Some("bad key".to_string()), order(json!({"menu_ids": [1]}))).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::BAD_REQUEST);
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        place_order(&conn, order(json!({"table_id": 1, "menu_ids": [1]}))).expect("Order Failed");
+        place_order(&conn, order(json!({"table_id": 1, "menu_ids": [1]}))).expect("Order Failed");
+        let items = OrderItem::list_order_items(&conn, 1).expect("Listing Failed");
+        assert_eq!(items[0].quantity, 2);
+    }
+
+    // Test Case: 36 Versions are sent as ETags, stale writes fail and unchanged reads are not sent again
+    #[tokio::test]
+    async fn test_versions_and_etags(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let version = |conn: &Connection| current_version(conn, Versioned::Order, order_id).expect("Query Failed").unwrap();
+        let before = version(&conn);
+        OrderItem::set_quantity(&conn, order_id, 1, 3).expect("Update Failed");
+        assert_eq!(version(&conn), before + 1);
+        ManualDiscountResponse::create(&conn, order_id, &serde_json::from_value(json!({"kind": "fixed", "value": 100, "reason_code": "complaint"})).unwrap()).expect("Insertion Failed");
+        assert_eq!(version(&conn), before + 2);
+        conn.execute("UPDATE tables SET server = 'Ann' WHERE id = 2", []).expect("Update Failed");
+        assert_eq!(current_version(&conn, Versioned::Table, 2).expect("Query Failed"), Some(2));
+
+        // Reads carry the ETag and answer 304 when the client has the current version
+        let resp = get_order_handler(conn, order_id, None).await.unwrap().into_response();
+        let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+        assert_eq!(etag, format!("\"{}\"", before + 2));
+        assert_eq!(convert_response_to_json(resp).await["version"], json!(before + 2));
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let resp = get_menu_handler(conn, 1, Some("\"7\", W/\"1\"".to_string())).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers()["etag"], "\"1\"");
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let resp = get_menu_handler(conn, 1, Some("\"2\"".to_string())).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+
+        // Writes with a stale If-Match fail and change nothing, a current one goes through and returns the new ETag
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let patch: TablePatch = serde_json::from_value(json!({"section": "bar"})).unwrap();
+        let resp = patch_table_handler(conn, 1, Some("\"1\"".to_string()), patch).await.unwrap().into_response();
+        assert_eq!((resp.status(), resp.headers()["etag"].to_str().unwrap()), (warp::http::StatusCode::OK, "\"2\""));
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        conn.execute("UPDATE menus SET price = 1100 WHERE id = 1", []).expect("Update Failed");
+        let menu = Menu { id: 0, name: "M-01".to_string(), price: 900, category: None, station: None };
+        let resp = check_if_match(&conn, Some("\"1\""), Versioned::Menu, 1).and_then(|version| save_menu(&conn, 1, &menu, version)).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::PRECONDITION_FAILED);
+        assert_eq!(convert_response_to_json(resp).await["code"], json!("precondition_failed"));
+        assert_eq!(Menu::get(&conn, 1).expect("Query Failed").unwrap().price, 1100);
+        assert!(check_if_match(&conn, Some("*"), Versioned::Menu, 1).is_ok());
+        // A write that passed the check still fails if the menu changes before it is written
+        let checked = check_if_match(&conn, Some("\"2\""), Versioned::Menu, 1).expect("Check Failed");
+        assert_eq!(checked, Some(2));
+        conn.execute("UPDATE menus SET price = 1200 WHERE id = 1", []).expect("Update Failed");
+        let resp = save_menu(&conn, 1, &menu, checked).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::PRECONDITION_FAILED);
+        assert_eq!(Menu::get(&conn, 1).expect("Query Failed").unwrap().price, 1200);
+        assert_eq!(save_menu(&conn, 99, &menu, Some(1)).into_response().status(), warp::http::StatusCode::NOT_FOUND);
+        let order_id = setup_order(&conn);
+        let resp = delete_order_item_handler(conn, order_id, 1, Some("\"1\"".to_string())).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::PRECONDITION_FAILED);
+    }
+
+    // Test Case: 37 Every operation of the OpenAPI document is routed and the other methods on its path are not
+    #[tokio::test]
+    async fn test_openapi_covers_routes() {
+        // Every documented method and path is routed, any other method on the same path is not allowed.
+        // Ids that don't exist keep the handlers from changing anything
+        let _conn = routes_test_db();
+        let mut routes = warp::service(crate::routes::restaurent_routes());
+        let mut gen = schemars::gen::SchemaSettings::openapi3().into_generator();
+        let operations = crate::openapi::operations(&mut gen);
+        let documented: std::collections::BTreeSet<(&str, String)> = operations
+            .iter()
+            .map(|operation| {
+                let path: String = operation.path.split('/').skip(1).map(|segment| match segment.starts_with('{') {
+                    true => "/0".to_string(),
+                    false => format!("/{}", segment),
+                }).collect();
+                (operation.method, path)
+            })
+            .collect();
+        assert_eq!(documented.len(), operations.len(), "an operation is documented twice");
+        for path in documented.iter().map(|(_, path)| path).collect::<std::collections::BTreeSet<_>>() {
+            for method in ["get", "post", "put", "patch", "delete"] {
+                // Served as by the server so the streams are not read, only their status
+                let request = warp::http::Request::builder().method(method.to_uppercase().as_str()).uri(path).body(warp::hyper::Body::empty()).unwrap();
+                let resp = warp::hyper::service::Service::call(&mut routes, request).await.unwrap();
+                let routed = match resp.status() {
+                    warp::http::StatusCode::METHOD_NOT_ALLOWED => false,
+                    warp::http::StatusCode::NOT_FOUND => {
+                        let body = warp::hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["message"] != json!("Route not found")
+                    }
+                    _ => true,
+                };
+                assert_eq!(routed, documented.contains(&(method, path.clone())), "{} {}", method, path);
+            }
+        }
+
+        // The document is served and refers only to schemas it defines
+        let resp = warp::test::request().path("/openapi.json").reply(&crate::routes::openapi_route()).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let document: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(document["openapi"], json!("3.0.3"));
+        assert_eq!(document["paths"]["/tables/{table_id}"]["patch"]["parameters"][0]["name"], json!("table_id"));
+        let text = document.to_string();
+        for (start, _) in text.match_indices("#/components/schemas/") {
+            let name = text[start + "#/components/schemas/".len()..].split('"').next().unwrap();
+            assert!(document["components"]["schemas"].get(name).is_some(), "{} is not defined", name);
+        }
+        let resp = warp::test::request().path("/docs").reply(&crate::routes::docs_route()).await;
+        assert!(String::from_utf8_lossy(resp.body()).contains("/openapi.json"));
+    }
+
+    // Test Case: 38 Versions reshape the replies of the same handlers and announce their sunset
+    #[tokio::test]
+    async fn test_api_versions() {
+        use crate::versions::{render, sunset_headers, ApiVersion};
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let v1 = convert_response_to_json(get_order_handler(conn, order_id, None).await.unwrap().into_response()).await;
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let reply = get_order_handler(conn, order_id, None).await.unwrap();
+        assert_eq!(convert_response_to_json(render(ApiVersion::V1, true, reply).await.unwrap()).await, v1);
+
+        // v2 sends decimal money, nests the menu of each item and has a state
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let reply = get_order_handler(conn, order_id, None).await.unwrap();
+        let resp = render(ApiVersion::V2, true, reply).await.unwrap();
+        assert_eq!(resp.headers()["etag"], "\"3\"");
+        let v2 = convert_response_to_json(resp).await;
+        assert_eq!(v2["state"], json!("open"));
+        assert_eq!(v2["subtotal"], json!(22.5));
+        assert!(v2.get("menus").is_none() && v2.get("status").is_none());
+        assert_eq!(v2["items"][1]["menu"], json!({"id": 2, "name": "M-02"}));
+        assert_eq!(v2["items"][1]["unit_price"], json!(12.5));
+        assert_eq!(v2["items"][1]["quantity"], v1["menus"][1]["quantity"]);
+
+        // Listing links stay under the version, tables and menus have a state
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let query: TableListQuery = serde_json::from_value(json!({"limit": 1})).unwrap();
+        let reply = list_table_handler(conn, query).await.unwrap();
+        let page = convert_response_to_json(render(ApiVersion::V2, true, reply).await.unwrap()).await;
+        assert!(page["links"]["next"].as_str().unwrap().starts_with("/v2/tables?limit=1&after="));
+        assert_eq!(page["data"][0]["state"], json!("active"));
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let query: TableListQuery = serde_json::from_value(json!({"limit": 1})).unwrap();
+        let reply = list_table_handler(conn, query).await.unwrap();
+        let page = convert_response_to_json(render(ApiVersion::V1, false, reply).await.unwrap()).await;
+        assert!(page["links"]["next"].as_str().unwrap().starts_with("/tables?"));
+        assert_eq!(page["data"][0]["archived"], json!(false));
+
+        // Only a configured sunset is announced, and never on the latest version
+        let mut headers = warp::http::HeaderMap::new();
+        sunset_headers(ApiVersion::V1, None, &mut headers);
+        assert!(headers.is_empty());
+        sunset_headers(ApiVersion::V2, Some("Fri, 31 Dec 2027 23:59:59 GMT"), &mut headers);
+        assert!(headers.is_empty());
+        sunset_headers(ApiVersion::V1, Some("Fri, 31 Dec 2027 23:59:59 GMT"), &mut headers);
+        assert_eq!(headers["sunset"], "Fri, 31 Dec 2027 23:59:59 GMT");
+        assert_eq!(headers["deprecation"], "@1792368000");
+        assert_eq!(headers["link"], "</v2/>; rel=\"successor-version\"");
+    }
+
+    // Test Case: 39 GraphQL reads tables, orders, items and menus in one request, edits orders and streams item changes
+    #[tokio::test]
+    async fn test_graphql() {
+        use async_graphql::Request;
+        use tokio_stream::StreamExt;
+        let schema = graphql::schema();
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        setup_order(&conn);
+        let query = "{ tables(limit: 2) { nodes { code openOrder { items { quantity menu { name } } bill { subtotal total } } } nextCursor } }";
+        let resp = graphql::execute(&schema, conn, Request::new(query)).await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        let data = resp.data.into_json().unwrap();
+        let tables = &data["tables"]["nodes"];
+        assert_eq!(tables[0]["openOrder"]["items"][1]["menu"]["name"], json!("M-02"));
+        assert_eq!(tables[0]["openOrder"]["bill"]["subtotal"], json!(2250));
+        assert_eq!(tables[1]["openOrder"], json!(null));
+        assert!(data["tables"]["nextCursor"].is_string());
+
+        // Changes made by mutations are streamed to subscribers of the table, a table no other test orders on
+        let mut changes = schema.execute_stream(Request::new("subscription { itemChanges(tableId: 9001) { kind itemId item { quantity } } }"));
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(50), changes.next()).await.is_err());
+        // Kept open for the dispatcher publishing the events committed by the mutations
+        let conn = shared_test_db("graphql_test");
+        create_schema(&conn);
+        setup_static_data(&conn);
+        conn.execute("INSERT INTO tables (id, code) VALUES (9001, 'T-9001')", []).expect("Insertion Failed");
+        tokio::spawn(crate::events::dispatch(|| shared_test_db("graphql_test")));
+        let mutation = "mutation {
+            placed: createOrder(input: {tableId: 9001, items: [{menuId: 1, quantity: 2}, {menuId: 2}]}) { id status items { quantity } }
+            changed: setItemQuantity(orderId: 1, itemId: 1, quantity: 3) { quantity unitPrice }
+            removed: removeItem(orderId: 1, itemId: 2) { items { id } }
+        }";
+        let resp = graphql::execute(&schema, shared_test_db("graphql_test"), Request::new(mutation)).await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        let data = resp.data.into_json().unwrap();
+        assert_eq!(data["placed"], json!({"id": 1, "status": "open", "items": [{"quantity": 2}, {"quantity": 1}]}));
+        assert_eq!(data["changed"], json!({"quantity": 3, "unitPrice": 1000}));
+        assert_eq!(data["removed"], json!({"items": [{"id": 1}]}));
+        let mut kinds = Vec::new();
+        for _ in 0..4 {
+            let change = changes.next().await.unwrap().data.into_json().unwrap();
+            kinds.push((change["itemChanges"]["kind"].clone(), change["itemChanges"]["item"]["quantity"].clone()));
+        }
+        assert_eq!(kinds, vec![
+            (json!("ADDED"), json!(2)),
+            (json!("ADDED"), json!(1)),
+            (json!("UPDATED"), json!(3)),
+            (json!("REMOVED"), json!(null)),
+        ]);
+
+        // Errors carry the code and details of the REST error envelope
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let resp = graphql::execute(&schema, conn, Request::new("mutation { createOrder(input: {tableId: 1, items: [{menuId: 99}]}) { id } }")).await;
+        let error = json!(resp.errors[0]);
+        assert_eq!(error["extensions"]["code"], json!("validation_failed"));
+        assert_eq!(error["extensions"]["details"][0]["field"], json!("items[0].menu_id"));
+    }
+
+    // Test Case: 40 gRPC serves tables, menus and orders over the network and streams order updates
+    #[tokio::test]
+    async fn test_grpc() {
+        use crate::grpc::proto::order_update::Kind;
+        use crate::grpc::proto::restaurant_client::RestaurantClient;
+        use crate::grpc::proto::restaurant_server::RestaurantServer;
+        use crate::grpc::{proto, RestaurantService};
+        use tokio_stream::wrappers::TcpListenerStream;
+        use tokio_stream::StreamExt;
+        // Calls open their own connections, to one in-memory database kept alive by this one
+        let open = || shared_test_db("grpc_test");
+        let conn = open();
+        create_schema(&conn);
+        setup_static_data(&conn);
+        // Publish the events committed by the calls, as the server does
+        tokio::spawn(crate::events::dispatch(open));
+        conn.execute("INSERT INTO tables (id, code) VALUES (9002, 'T-9002')", []).expect("Insertion Failed");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tonic::transport::Server::builder().add_service(RestaurantServer::new(RestaurantService::new(open)));
+        tokio::spawn(server.serve_with_incoming(TcpListenerStream::new(listener)));
+        let mut client = RestaurantClient::connect(format!("http://{}", addr)).await.unwrap();
+
+        let mut request = tonic::Request::new(proto::CreateTableRequest { code: "T-04".to_string(), section: Some("bar".to_string()), ..Default::default() });
+        request.metadata_mut().insert("x-staff-id", "carol".parse().unwrap());
+        let table = client.create_table(request).await.unwrap().into_inner();
+        assert_eq!((table.code.as_str(), table.section.as_deref(), table.archived), ("T-04", Some("bar"), false));
+        let menu = client.get_menu(proto::GetMenuRequest { id: 2 }).await.unwrap().into_inner();
+        assert_eq!((menu.name.as_str(), menu.price), ("M-02", 1250));
+        let tables = client.list_tables(proto::ListTablesRequest { limit: Some(2), ..Default::default() }).await.unwrap().into_inner();
+        assert_eq!(tables.tables.iter().map(|table| table.code.as_str()).collect::<Vec<_>>(), vec!["T-01", "T-02"]);
+        let next = client.list_tables(proto::ListTablesRequest { limit: Some(2), after: tables.next_cursor, ..Default::default() }).await.unwrap().into_inner();
+        assert_eq!(next.tables[0].code, "T-03");
+
+        // Order changes of the table are streamed from the moment the call is answered
+        let mut updates = client.watch_orders(proto::WatchOrdersRequest { table_id: Some(9002), order_id: None }).await.unwrap().into_inner();
+        let line = |menu_id, quantity| proto::OrderLine { menu_id, quantity, ..Default::default() };
+        let order = client.create_order(proto::CreateOrderRequest { table_id: 9002, items: vec![line(1, Some(2)), line(2, None)], party_size: None }).await.unwrap().into_inner();
+        assert_eq!((order.status.as_str(), order.items.len(), order.subtotal), ("open", 2, 3250));
+        let item = client.set_item_quantity(proto::SetItemQuantityRequest { order_id: order.id, item_id: order.items[0].id, quantity: 3, if_match: None }).await.unwrap().into_inner();
+        assert_eq!(item.quantity, 3);
+        let removed = client.remove_item(proto::RemoveItemRequest { order_id: order.id, item_id: order.items[1].id, if_match: None }).await.unwrap().into_inner();
+        assert!(!removed.order_deleted);
+        let items = client.list_order_items(proto::ListOrderItemsRequest { order_id: order.id }).await.unwrap().into_inner();
+        assert_eq!(items.items.len(), 1);
+        let mut kinds = Vec::new();
+        for _ in 0..5 {
+            let update = updates.next().await.unwrap().unwrap();
+            kinds.push((update.kind(), update.item.map(|item| item.quantity)));
+        }
+        assert_eq!(kinds, vec![
+            (Kind::OrderCreated, None),
+            (Kind::ItemAdded, Some(2)),
+            (Kind::ItemAdded, Some(1)),
+            (Kind::ItemUpdated, Some(3)),
+            (Kind::ItemRemoved, None),
+        ]);
+
+        // Calls that change something are audited as the REST requests are
+        let mut stmt = conn.prepare("SELECT path, actor, status, entity_type || ' ' || entity_id FROM audit_log ORDER BY id").unwrap();
+        let entries: Vec<(String, Option<String>, Option<u16>, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let entry = |method: &str, actor: Option<&str>, entity: String| (format!("/restaurant.Restaurant/{}", method), actor.map(str::to_string), Some(200), entity);
+        assert_eq!(entries, vec![
+            entry("CreateTable", Some("carol"), format!("table {}", table.id)),
+            entry("CreateOrder", None, format!("order {}", order.id)),
+            entry("SetItemQuantity", None, format!("order {}", order.id)),
+            entry("RemoveItem", None, format!("order {}", order.id)),
+        ]);
+
+        // Errors map to status codes and carry the code and details of the REST error envelope
+        let status = client.get_order(proto::GetOrderRequest { id: 999 }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(status.metadata().get("error-code").unwrap(), "not_found");
+        let status = client.create_order(proto::CreateOrderRequest { table_id: 9002, items: vec![line(99, None)], party_size: None }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let details: serde_json::Value = serde_json::from_str(status.metadata().get("error-details").unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(details[0]["field"], json!("items[0].menu_id"));
+        let status = client.set_item_quantity(proto::SetItemQuantityRequest { order_id: order.id, item_id: item.id, quantity: 1, if_match: Some("\"0\"".to_string()) }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Aborted);
+    }
+
+    // Test Case: 41 The WebSocket pushes order changes to the topics subscribed to and replays them on resume
+    #[tokio::test]
+    async fn test_live_updates() {
+        use crate::routes::live_updates_route;
+        use warp::test::WsClient;
+        async fn recv(client: &mut WsClient) -> serde_json::Value {
+            serde_json::from_str(client.recv().await.unwrap().to_str().unwrap()).unwrap()
+        }
+        // The next message that isn't an event, other tests order on the kitchen too
+        async fn reply(client: &mut WsClient) -> serde_json::Value {
+            loop {
+                let message = recv(client).await;
+                if message["type"] != json!("event") {
+                    return message;
+                }
+            }
+        }
+        fn kinds(messages: &[serde_json::Value]) -> Vec<&str> {
+            messages.iter().map(|message| message["event"]["kind"].as_str().unwrap_or_else(|| message["type"].as_str().unwrap())).collect()
+        }
+        // Tables and stations no other test orders on
+        let conn = shared_test_db("live_updates_test");
+        create_schema(&conn);
+        tokio::spawn(crate::events::dispatch(|| shared_test_db("live_updates_test")));
+        conn.execute("INSERT INTO tables (id, code) VALUES (9003, 'T-9003')", []).expect("Insertion Failed");
+        conn.execute("INSERT INTO menus (name, price, station) VALUES ('M-01', 1200, 'wok-9003'), ('M-02', 400, NULL)", []).expect("Insertion Failed");
+
+        let mut table = warp::test::ws().path("/ws?topics=table:9003").handshake(live_updates_route()).await.expect("Handshake Failed");
+        assert_eq!(recv(&mut table).await["topics"], json!(["table:9003"]));
+        let mut station = warp::test::ws().path("/ws?topics=station:wok-9003").handshake(live_updates_route()).await.expect("Handshake Failed");
+        assert_eq!(recv(&mut station).await["type"], json!("subscribed"));
+
+        let body: OrderRequestBody = serde_json::from_value(json!({"table_id": 9003, "items": [{"menu_id": 1, "quantity": 2}, {"menu_id": 2}]})).unwrap();
+        let resp = create_order_handler(shared_test_db("live_updates_test"), None, body).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+        let order_id = convert_response_to_json(resp).await["id"].as_i64().unwrap();
+        let resp = delete_order_item_handler(shared_test_db("live_updates_test"), order_id, 2, None).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let payment = PaymentRequest { tender: Tender::Cash, amount: 2400, tip: 0, card_token: None };
+        let resp = record_payment(&shared_test_db("live_updates_test"), &FakeProcessor, order_id, None, None, &payment).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+
+        let mut messages = Vec::new();
+        for _ in 0..6 {
+            messages.push(recv(&mut table).await);
+        }
+        assert_eq!(kinds(&messages), vec!["order_created", "item_added", "item_added", "item_removed", "payment_captured", "order_closed"]);
+        assert_eq!(messages[4]["event"]["entity_id"], json!(1));
+        assert_eq!(messages[1]["topics"], json!(["table:9003"]));
+        assert_eq!(messages[1]["event"]["item"]["quantity"], json!(2));
+        assert_eq!(messages[3]["event"]["item"], json!(null));
+        // The station only hears of the items of its menus
+        let message = recv(&mut station).await;
+        assert_eq!((message["event"]["kind"].as_str(), message["event"]["item"]["menu_name"].as_str()), (Some("item_added"), Some("M-01")));
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(50), station.recv()).await.is_err());
+
+        // Reconnecting with the token of the first event replays the rest before the subscription
+        let token = messages[0]["resume_token"].as_str().unwrap().to_string();
+        let mut resumed = warp::test::ws().path(&format!("/ws?topics=table:9003&resume={}", token)).handshake(live_updates_route()).await.expect("Handshake Failed");
+        let mut replayed = Vec::new();
+        for _ in 0..6 {
+            replayed.push(recv(&mut resumed).await);
+        }
+        assert_eq!(kinds(&replayed), vec!["item_added", "item_added", "item_removed", "payment_captured", "order_closed", "subscribed"]);
+        assert_eq!(replayed[4]["resume_token"], messages[5]["resume_token"]);
+
+        // Topics can be changed on the open connection
+        table.send_text(r#"{"action": "subscribe", "topics": ["kitchen", "station:grill"]}"#).await;
+        assert_eq!(reply(&mut table).await["topics"], json!(["table:9003", "kitchen", "station:grill"]));
+        table.send_text(r#"{"action": "unsubscribe", "topics": ["table:9003"]}"#).await;
+        assert_eq!(reply(&mut table).await["topics"], json!(["kitchen", "station:grill"]));
+        table.send_text(r#"{"action": "subscribe", "topics": ["chef"]}"#).await;
+        assert_eq!(reply(&mut table).await["type"], json!("error"));
+
+        // Tokens of an earlier run of the server ask for a resync, unknown topics and broken tokens are refused
+        let (epoch, seq) = token.split_once('.').unwrap();
+        let earlier = format!("{:08x}.{}", u32::from_str_radix(epoch, 16).unwrap() ^ 1, seq);
+        let mut expired = warp::test::ws().path(&format!("/ws?topics=table:9003&resume={}", earlier)).handshake(live_updates_route()).await.expect("Handshake Failed");
+        assert_eq!(recv(&mut expired).await["type"], json!("resync"));
+        assert!(warp::test::ws().path("/ws?topics=chef").handshake(live_updates_route()).await.is_err());
+        assert!(warp::test::ws().path("/ws?topics=kitchen&resume=later").handshake(live_updates_route()).await.is_err());
+    }
+
+    // Test Case: 42 The event stream sends the changes to the orders of a table and replays them after the Last-Event-ID
+    #[tokio::test]
+    async fn test_event_stream() {
+        use warp::hyper::body::HttpBody;
+        // The id, event and data of the next count events on the stream
+        async fn read(body: &mut warp::hyper::Body, count: usize) -> Vec<(String, String, String)> {
+            let mut text = String::new();
+            while text.matches("\n\n").count() < count {
+                let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.data()).await.expect("No event").unwrap().unwrap();
+                text.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+            text.split("\n\n").filter(|block| !block.is_empty()).map(|block| {
+                let field = |name: &str| block.lines().find_map(|line| line.strip_prefix(name)).unwrap_or_default().to_string();
+                (field("id:"), field("event:"), field("data:"))
+            }).collect()
+        }
+        async fn open(table_id: Option<i64>, last_event_id: Option<&str>) -> warp::http::Response<warp::hyper::Body> {
+            event_stream_handler(EventStreamQuery { table_id }, last_event_id.map(str::to_string)).await.unwrap().into_response()
+        }
+        // Tables no other test orders on
+        let conn = shared_test_db("event_stream_test");
+        create_schema(&conn);
+        tokio::spawn(crate::events::dispatch(|| shared_test_db("event_stream_test")));
+        conn.execute("INSERT INTO tables (id, code) VALUES (9004, 'T-9004'), (9005, 'T-9005')", []).expect("Insertion Failed");
+        conn.execute("INSERT INTO menus (name, price) VALUES ('M-01', 1200)", []).expect("Insertion Failed");
+
+        let resp = open(Some(9004), None).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/event-stream");
+        let mut stream = resp.into_body();
+
+        for table_id in [9005, 9004] {
+            let body: OrderRequestBody = serde_json::from_value(json!({"table_id": table_id, "items": [{"menu_id": 1}]})).unwrap();
+            let resp = create_order_handler(shared_test_db("event_stream_test"), None, body).await.unwrap().into_response();
+            assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+        }
+        // Only the changes to the orders of table 9004 are sent
+        let events = read(&mut stream, 2).await;
+        assert_eq!(events.iter().map(|(_, event, _)| event.as_str()).collect::<Vec<_>>(), vec!["order_created", "item_added"]);
+        let data: serde_json::Value = serde_json::from_str(&events[1].2).unwrap();
+        assert_eq!((data["table_id"].as_i64(), data["item"]["menu_name"].as_str()), (Some(9004), Some("M-01")));
+        let order_id = data["order_id"].as_i64().unwrap();
+        let resp = delete_order_item_handler(shared_test_db("event_stream_test"), order_id, data["item_id"].as_i64().unwrap(), None).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        let events = [events, read(&mut stream, 2).await].concat();
+        assert_eq!((events[2].1.as_str(), events[3].1.as_str()), ("item_removed", "order_deleted"));
+
+        // Reconnecting with the id of the first event replays the rest
+        let mut resumed = open(Some(9004), Some(&events[0].0)).await.into_body();
+        assert_eq!(read(&mut resumed, 3).await, events[1..].to_vec());
+
+        // Ids of an earlier run of the server ask for a resync, broken ones and bad tables are refused
+        let (epoch, seq) = events[0].0.split_once('.').unwrap();
+        let earlier = format!("{:08x}.{}", u32::from_str_radix(epoch, 16).unwrap() ^ 1, seq);
+        let mut expired = open(None, Some(&earlier)).await.into_body();
+        assert_eq!(read(&mut expired, 1).await[0].1, "resync");
+        assert_eq!(open(None, Some("later")).await.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(open(Some(0), None).await.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    // Test Case: 43 Webhooks get the events of their kinds signed, failed deliveries are retried and end up as dead letters
+    #[tokio::test]
+    async fn test_webhooks() {
+        use crate::webhooks::{self, Dispatcher, RetryPolicy};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+        use warp::Filter;
+        // Receiver answering 500 to its first request, /down answers 503 until it is up
+        let received: Arc<Mutex<Vec<(String, warp::http::HeaderMap, String)>>> = Arc::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let up = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let receiver = {
+            let (received, calls, up) = (received.clone(), calls.clone(), up.clone());
+            warp::post().and(warp::path::full()).and(warp::header::headers_cloned()).and(warp::body::bytes()).map(move |path: warp::path::FullPath, headers, body: warp::hyper::body::Bytes| {
+                received.lock().unwrap().push((path.as_str().to_string(), headers, String::from_utf8(body.to_vec()).unwrap()));
+                let accepted = match path.as_str() {
+                    "/down" => up.load(Ordering::SeqCst),
+                    _ => calls.fetch_add(1, Ordering::SeqCst) > 0,
+                };
+                match accepted {
+                    true => warp::http::StatusCode::NO_CONTENT,
+                    false if path.as_str() == "/down" => warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                    false => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                }
+            })
+        };
+        let (addr, server) = warp::serve(receiver).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let conn = shared_test_db("webhooks_test");
+        create_schema(&conn);
+        let log = |kind: &str| {
+            conn.execute("INSERT INTO domain_events (kind, table_id, order_id) VALUES (?1, 1, 7)", params![kind]).expect("Insertion Failed");
+            conn.last_insert_rowid()
+        };
+        // Webhooks get the events logged from when they were made on
+        log("order_created");
+        let create = |body: serde_json::Value| async move {
+            let webhook: Webhook = serde_json::from_value(body).unwrap();
+            create_webhook_handler(shared_test_db("webhooks_test"), webhook).await.unwrap().into_response()
+        };
+        let resp = create(json!({"url": format!("http://{}/hook", addr), "events": ["order_created", "order_closed"], "secret": "loyalty-secret-0001"})).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+        let hook = convert_response_to_json(resp).await;
+        assert_eq!(hook["secret"], json!("loyalty-secret-0001"));
+        let resp = create(json!({"url": format!("http://{}/down", addr), "events": ["order_created"]})).await;
+        let down = convert_response_to_json(resp).await;
+        assert_eq!(down["secret"].as_str().unwrap().len(), 64);
+        let (hook_id, down_id) = (hook["id"].as_i64().unwrap(), down["id"].as_i64().unwrap());
+        // Listings don't show the secrets
+        let resp = list_webhooks_handler(shared_test_db("webhooks_test")).await.unwrap().into_response();
+        assert!(convert_response_to_json(resp).await.as_array().unwrap().iter().all(|webhook| webhook.get("secret").is_none()));
+        for body in [json!({"url": "ftp://example.com", "events": ["order_created"]}), json!({"url": "http://example.com", "events": ["pizza_baked"]}), json!({"url": "http://example.com", "events": []})] {
+            assert_eq!(create(body).await.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        // The kinds are listed from the kinds there are
+        let details = convert_response_to_json(create(json!({"url": "http://example.com", "events": ["pizza_baked"]})).await).await["details"].clone();
+        let message = details[0]["message"].as_str().unwrap();
+        assert!(message.starts_with("Must be table_created, table_updated,") && message.ends_with(", promotion_created or service_charge_rule_created"), "{}", message);
+        assert_eq!(message.matches('_').count(), crate::events::EventKind::ALL.iter().map(|kind| kind.as_str().matches('_').count()).sum::<usize>());
+
+        // Only the webhooks with the kind of an event get it, each event once even after a restart
+        let dispatcher = Dispatcher::new(|| shared_test_db("webhooks_test"), RetryPolicy { max_attempts: 3, base_delay: 0, max_delay: 0 });
+        assert_eq!(dispatcher.enqueue().unwrap(), 0);
+        let event_id = log("order_created");
+        log("item_added");
+        assert_eq!(dispatcher.enqueue().unwrap(), 2);
+        let restarted = Dispatcher::new(|| shared_test_db("webhooks_test"), RetryPolicy::default());
+        assert_eq!(restarted.enqueue().unwrap(), 0);
+
+        // The first attempts fail, the hook accepts the retry and the dead receiver runs out of attempts
+        let delivered: Vec<usize> = [dispatcher.deliver_due().await, dispatcher.deliver_due().await, dispatcher.deliver_due().await, dispatcher.deliver_due().await]
+            .into_iter().map(Result::unwrap).collect();
+        assert_eq!(delivered, vec![0, 1, 0, 0]);
+        let requests = received.lock().unwrap().clone();
+        let paths: Vec<&str> = requests.iter().map(|(path, _, _)| path.as_str()).collect();
+        assert_eq!(paths.iter().filter(|path| **path == "/hook").count(), 2);
+        assert_eq!(paths.iter().filter(|path| **path == "/down").count(), 3);
+        for (path, headers, body) in &requests {
+            let secret = if path == "/hook" { hook["secret"].as_str().unwrap() } else { down["secret"].as_str().unwrap() };
+            let timestamp: i64 = headers["x-webhook-timestamp"].to_str().unwrap().parse().unwrap();
+            assert_eq!(headers["x-webhook-signature"], webhooks::sign(secret, timestamp, body).as_str());
+            // Retries are sent as the same delivery
+            let first = requests.iter().find(|(first, _, _)| first == path).unwrap();
+            assert_eq!(headers["x-webhook-delivery"], first.1["x-webhook-delivery"]);
+            assert_eq!(headers["x-webhook-event"], "order_created");
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!((body["id"].as_i64(), body["event"].as_str(), body["data"]["order_id"].as_i64()), (Some(event_id), Some("order_created"), Some(7)));
+        }
+
+        // The dead delivery is listed and can be sent again once the receiver is back
+        let resp = list_dead_letters_handler(shared_test_db("webhooks_test"), down_id).await.unwrap().into_response();
+        let dead = convert_response_to_json(resp).await;
+        assert_eq!(dead.as_array().unwrap().len(), 1);
+        assert_eq!((dead[0]["attempts"].as_i64(), dead[0]["last_status"].as_i64(), dead[0]["status"].as_str()), (Some(3), Some(503), Some("dead")));
+        let delivery_id = dead[0]["id"].as_i64().unwrap();
+        assert_eq!(convert_response_to_json(list_dead_letters_handler(shared_test_db("webhooks_test"), hook_id).await.unwrap().into_response()).await, json!([]));
+        up.store(true, Ordering::SeqCst);
+        let resp = redeliver_handler(shared_test_db("webhooks_test"), down_id, delivery_id).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::ACCEPTED);
+        assert_eq!(dispatcher.deliver_due().await.unwrap(), 1);
+        assert_eq!(convert_response_to_json(list_dead_letters_handler(shared_test_db("webhooks_test"), down_id).await.unwrap().into_response()).await, json!([]));
+        let resp = redeliver_handler(shared_test_db("webhooks_test"), down_id, delivery_id).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+        let resp = redeliver_handler(shared_test_db("webhooks_test"), hook_id, delivery_id).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+
+        // Paused and deleted webhooks get nothing
+        let patch: WebhookPatch = serde_json::from_value(json!({"active": false})).unwrap();
+        let resp = patch_webhook_handler(shared_test_db("webhooks_test"), hook_id, patch).await.unwrap().into_response();
+        assert_eq!(convert_response_to_json(resp).await["active"], json!(false));
+        let resp = delete_webhook_handler(shared_test_db("webhooks_test"), down_id).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        log("order_closed");
+        assert_eq!(dispatcher.enqueue().unwrap(), 0);
+        // A webhook active again does not get what was logged while it was paused
+        let patch: WebhookPatch = serde_json::from_value(json!({"active": true})).unwrap();
+        patch_webhook_handler(shared_test_db("webhooks_test"), hook_id, patch).await.unwrap();
+        assert_eq!(dispatcher.enqueue().unwrap(), 0);
+        log("order_closed");
+        assert_eq!(dispatcher.enqueue().unwrap(), 1);
+        let resp = get_webhook_handler(shared_test_db("webhooks_test"), down_id).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+
+        // Waits double after every failed attempt, up to the maximum
+        let policy = RetryPolicy::default();
+        assert_eq!((1..=4).map(|attempts| policy.delay(attempts)).collect::<Vec<_>>(), vec![10, 20, 40, 80]);
+        assert_eq!(policy.delay(20), 3600);
+    }
+
+    // Test Case: 44 Changes are logged in their own transaction and published from the log, which can be read since an id
+    #[tokio::test]
+    async fn test_event_log() {
+        async fn read(query: serde_json::Value) -> serde_json::Value {
+            let query: EventLogQuery = serde_json::from_value(query).unwrap();
+            convert_response_to_json(event_log_handler(shared_test_db("event_log_test"), query).await.unwrap().into_response()).await
+        }
+        fn kinds(log: &serde_json::Value) -> Vec<&str> {
+            log["events"].as_array().unwrap().iter().map(|event| event["kind"].as_str().unwrap()).collect()
+        }
+        let conn = shared_test_db("event_log_test");
+        create_schema(&conn);
+        conn.execute("INSERT INTO menus (name, price) VALUES ('M-01', 1200)", []).expect("Insertion Failed");
+        let table = Table { id: 0, code: "T-9006".to_string(), section: None, table_type: None, server: None };
+        let table_id = create_table(&conn, &table).unwrap();
+        let body: OrderRequestBody = serde_json::from_value(json!({"table_id": table_id, "items": [{"menu_id": 1, "quantity": 2}]})).unwrap();
+        let (_, created) = place_order(&conn, body).unwrap();
+        let order_id = created["id"].as_i64().unwrap();
+        let item_id: i64 = conn.query_row("SELECT id FROM order_items WHERE order_id = ?1", params![order_id], |row| row.get(0)).unwrap();
+        set_item_quantity(&conn, order_id, item_id, None, &OrderItemPatch { quantity: 3 }).unwrap();
+
+        // A change that fails leaves neither its writes nor its events behind
+        let failed: Result<(), ApiError> = in_transaction(&conn, |tx| {
+            let table_id = Table::create(tx, &Table { code: "T-9007".to_string(), ..table })?;
+            events::record_table(tx, EventKind::TableCreated, table_id)?;
+            Err(ApiError::Conflict("Changed my mind".to_string()))
+        });
+        assert!(failed.is_err());
+        assert!(Table::get_existing_table_id(&conn, &Table { id: 0, code: "T-9007".to_string(), section: None, table_type: None, server: None }).unwrap().is_none());
+
+        let log = read(json!({})).await;
+        assert_eq!(kinds(&log), vec!["table_created", "order_created", "item_added", "item_updated"]);
+        assert_eq!((log["events"][3]["item"]["quantity"].as_i64(), log["events"][3]["published_at"].as_str()), (Some(3), None));
+        assert_eq!(log["events"][0]["order_id"], json!(null));
+
+        // The dispatcher publishes each event once, in the order they were committed
+        let mut receiver = events::subscribe();
+        assert_eq!(events::publish_pending(&conn).unwrap(), 4);
+        assert_eq!(events::publish_pending(&conn).unwrap(), 0);
+        let mut published = Vec::new();
+        while published.len() < 4 {
+            let event = receiver.recv().await.unwrap();
+            if event.table_id == Some(table_id) {
+                published.push(event.kind.as_str());
+            }
+        }
+        assert_eq!(published, kinds(&log));
+        assert!(read(json!({})).await["events"].as_array().unwrap().iter().all(|event| event["published_at"].is_string()));
+
+        // Read a page at a time from the last id read, or only the events of an order
+        let page = read(json!({"limit": 3})).await;
+        assert_eq!((kinds(&page).len(), page["has_more"].as_bool()), (3, Some(true)));
+        let rest = read(json!({"since": page["last_id"]})).await;
+        assert_eq!((kinds(&rest), rest["has_more"].as_bool()), (vec!["item_updated"], Some(false)));
+        assert_eq!(read(json!({"since": rest["last_id"]})).await["last_id"], rest["last_id"]);
+        assert_eq!(kinds(&read(json!({"order_id": order_id})).await), vec!["order_created", "item_added", "item_updated"]);
+        let query = EventLogQuery { limit: Some(0), ..Default::default() };
+        let resp = event_log_handler(shared_test_db("event_log_test"), query).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    // Test Case: 45 Orders are rebuilt from their events as they were at a time, and the projections can be regenerated
+    #[tokio::test]
+    async fn test_order_history() {
+        async fn history(table_id: i64, as_of: &str) -> serde_json::Value {
+            let query = TableHistoryQuery { as_of: Some(as_of.to_string()) };
+            convert_response_to_json(table_history_handler(shared_test_db("history_test"), table_id, query).await.unwrap().into_response()).await
+        }
+        fn items(order: &serde_json::Value) -> Vec<(&str, i64)> {
+            order["items"].as_array().unwrap().iter().map(|item| (item["menu_name"].as_str().unwrap(), item["quantity"].as_i64().unwrap())).collect()
+        }
+        let conn = shared_test_db("history_test");
+        create_schema(&conn);
+        conn.execute("INSERT INTO menus (name, price) VALUES ('M-01', 1200), ('M-02', 800)", []).expect("Insertion Failed");
+        let table = |code: &str| Table { id: 0, code: code.to_string(), section: None, table_type: None, server: None };
+        let table_id = create_table(&conn, &table("T-01")).unwrap();
+
+        // Orders made before changes were logged are logged as they happened, once
+        conn.execute("INSERT INTO orders (table_id, status, created_at, closed_at, total) VALUES (?1, 'closed', '2024-01-01 12:00:00', '2024-01-01 13:00:00', 1200)", params![table_id]).expect("Insertion Failed");
+        let old_order_id = conn.last_insert_rowid();
+        conn.execute("INSERT INTO order_items (order_id, menu_id, cooking_time, unit_cooking_time, quantity, unit_price, ordered_at) VALUES (?1, 1, 10, 10, 1, 1200, '2024-01-01 12:05:00')", params![old_order_id]).expect("Insertion Failed");
+        assert_eq!(history::record_unlogged_orders(&conn).unwrap(), 1);
+        assert_eq!(history::record_unlogged_orders(&conn).unwrap(), 0);
+        assert_eq!(history(table_id, "2024-01-01 11:00:00").await["orders"], json!([]));
+        let lunch = history(table_id, "2024-01-01 12:30:00").await;
+        assert_eq!((lunch["orders"][0]["status"].as_str(), items(&lunch["orders"][0])), (Some("open"), vec![("M-01", 1)]));
+        assert_eq!(lunch["orders"][0]["items"][0]["ordered_at"], json!("2024-01-01 12:05:00"));
+        let paid = history(table_id, "2024-01-01T14:00:00").await;
+        assert_eq!((paid["as_of"].as_str(), paid["orders"][0]["status"].as_str(), paid["orders"][0]["total"].as_i64()), (Some("2024-01-01 14:00:00"), Some("closed"), Some(1200)));
+
+        // An item removed later still shows on the order as it was before
+        let body: OrderRequestBody = serde_json::from_value(json!({"table_id": table_id, "party_size": 2, "items": [{"menu_id": 1, "quantity": 2}, {"menu_id": 2}]})).unwrap();
+        let order_id = place_order(&conn, body).unwrap().1["id"].as_i64().unwrap();
+        conn.execute("UPDATE domain_events SET created_at = '2025-06-01 19:00:00' WHERE order_id = ?1", params![order_id]).expect("Update Failed");
+        let removed: i64 = conn.query_row("SELECT id FROM order_items WHERE order_id = ?1 AND menu_id = 2", params![order_id], |row| row.get(0)).unwrap();
+        remove_order_item(&conn, order_id, removed, None).unwrap();
+        conn.execute("UPDATE domain_events SET created_at = '2025-06-01 19:30:00' WHERE order_id = ?1 AND kind = 'item_removed'", params![order_id]).expect("Update Failed");
+        let before = history(table_id, "2025-06-01 19:15:00").await;
+        assert_eq!(before["orders"].as_array().unwrap().iter().map(|order| order["id"].as_i64().unwrap()).collect::<Vec<_>>(), vec![order_id, old_order_id]);
+        assert_eq!((items(&before["orders"][0]), before["orders"][0]["party_size"].as_i64()), (vec![("M-01", 2), ("M-02", 1)], Some(2)));
+        assert_eq!(items(&history(table_id, "2025-06-01 19:45:00").await["orders"][0]), vec![("M-01", 2)]);
+
+        // Long histories are snapshotted, reading from a snapshot gives the same order
+        let kept: i64 = conn.query_row("SELECT id FROM order_items WHERE order_id = ?1", params![order_id], |row| row.get(0)).unwrap();
+        for quantity in 1..=20 {
+            set_item_quantity(&conn, order_id, kept, None, &OrderItemPatch { quantity }).unwrap();
+        }
+        let now = history::resolve(&conn, None).unwrap();
+        let replayed = history::order_as_of(&conn, order_id, &now).unwrap().unwrap();
+        let snapshots: i64 = conn.query_row("SELECT COUNT(*) FROM order_snapshots WHERE order_id = ?1", params![order_id], |row| row.get(0)).unwrap();
+        assert_eq!(snapshots, 1);
+        let from_snapshot = history::order_as_of(&conn, order_id, &now).unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&from_snapshot).unwrap(), serde_json::to_value(&replayed).unwrap());
+        assert_eq!(from_snapshot.items[0].item.quantity, 20);
+        assert_eq!(items(&history(table_id, "2025-06-01 19:15:00").await["orders"][0]), vec![("M-01", 2), ("M-02", 1)]);
+
+        // A rebuild brings back the orders and items from their events, and removes deleted orders
+        let other_table_id = create_table(&conn, &table("T-02")).unwrap();
+        let body: OrderRequestBody = serde_json::from_value(json!({"table_id": other_table_id, "items": [{"menu_id": 2}]})).unwrap();
+        let deleted_order_id = place_order(&conn, body).unwrap().1["id"].as_i64().unwrap();
+        let deleted_item: i64 = conn.query_row("SELECT id FROM order_items WHERE order_id = ?1", params![deleted_order_id], |row| row.get(0)).unwrap();
+        remove_order_item(&conn, deleted_order_id, deleted_item, None).unwrap();
+        conn.execute("DELETE FROM order_items", []).expect("Delete Failed");
+        conn.execute("UPDATE orders SET party_size = 9", []).expect("Update Failed");
+        let rebuilt = history::rebuild(&conn).unwrap();
+        assert_eq!(rebuilt, history::Rebuilt { orders: 2, items: 2, deleted: 1 });
+        let order = OrderResponse::get(&conn, order_id).unwrap().unwrap();
+        assert_eq!((order.party_size, order.menus.len(), order.menus[0].id, order.menus[0].quantity, order.menus[0].unit_price), (Some(2), 1, kept, 20, 1200));
+        let old_order = OrderResponse::get(&conn, old_order_id).unwrap().unwrap();
+        assert_eq!((old_order.status.as_str(), old_order.party_size, old_order.menus.len()), ("closed", None, 1));
+        assert!(OrderResponse::get(&conn, deleted_order_id).unwrap().is_none());
+        assert_eq!(history(other_table_id, &now).await["orders"], json!([]));
+
+        let query = TableHistoryQuery { as_of: Some("yesterday".to_string()) };
+        let resp = table_history_handler(shared_test_db("history_test"), table_id, query).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let resp = table_history_handler(shared_test_db("history_test"), 999, TableHistoryQuery::default()).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+    }
+
+    // Test Case: 46 Changes are audited in their transaction with who made them and what changed, managers read the log
+    #[tokio::test]
+    async fn test_audit_log() {
+        use crate::audit;
+        use std::future::Future;
+        use warp::http::{HeaderMap, Method};
+        let connect = || shared_test_db("audit_test");
+        async fn list(staff_role: Option<&str>, query: serde_json::Value) -> (warp::http::StatusCode, serde_json::Value) {
+            let query: AuditQuery = serde_json::from_value(query).unwrap();
+            let resp = audit_log_handler(shared_test_db("audit_test"), staff_role.map(str::to_string), query).await.unwrap().into_response();
+            (resp.status(), convert_response_to_json(resp).await)
+        }
+        // Serve a request in its audit context, as the server does
+        async fn serve<R: Reply>(staff: &HeaderMap, request_id: &str, method: &str, path: &str, handler: impl Future<Output = Result<R, warp::Rejection>>) -> warp::reply::Response {
+            let mut headers = staff.clone();
+            headers.insert("x-request-id", request_id.parse().unwrap());
+            let context = audit::Context::new(method, path, &headers);
+            let resp = context.clone().scope(handler).await.unwrap().into_response();
+            audit::finish(&shared_test_db("audit_test"), &context, resp.status().as_u16());
+            resp
+        }
+        let conn = connect();
+        create_schema(&conn);
+        conn.execute("INSERT INTO menus (name, price) VALUES ('M-01', 1200), ('M-02', 800)", []).expect("Insertion Failed");
+        let mut alice = HeaderMap::new();
+        alice.insert(audit::ACTOR_HEADER, "alice".parse().unwrap());
+        alice.insert(audit::DEVICE_HEADER, "pos-1".parse().unwrap());
+        let mut bob = HeaderMap::new();
+        bob.insert(audit::ACTOR_HEADER, "bob".parse().unwrap());
+        bob.insert("user-agent", "tablet/2".parse().unwrap());
+
+        let table = Table { id: 0, code: "T-01".to_string(), section: None, table_type: None, server: None };
+        let resp = serve(&alice, "req-1", "POST", "/v1/tables", create_table_handler(connect(), table)).await;
+        let table_id = convert_response_to_json(resp).await["id"].as_i64().unwrap();
+
+        let patch = TablePatch { code: Some("T-02".to_string()), ..Default::default() };
+        serve(&bob, "req-2", "PATCH", &format!("/tables/{}", table_id), patch_table_handler(connect(), table_id, None, patch)).await;
+
+        let body: OrderRequestBody = serde_json::from_value(json!({"items": [{"menu_id": 1}, {"menu_id": 2}]})).unwrap();
+        let path = format!("/v2/tables/{}/orders", table_id);
+        let resp = serve(&alice, "req-3", "POST", &path, create_table_order_handler(connect(), table_id, None, body)).await;
+        let order_id = convert_response_to_json(resp).await["id"].as_i64().unwrap();
+
+        let item_id: i64 = conn.query_row("SELECT id FROM order_items WHERE order_id = ?1 AND menu_id = 2", params![order_id], |row| row.get(0)).unwrap();
+        let path = format!("/orders/{}/items/{}", order_id, item_id);
+        serve(&alice, "req-4", "DELETE", &path, delete_order_item_handler(connect(), order_id, item_id, None)).await;
+
+        // Failed requests are audited too, reads are not. Changes made outside of a request are not audited
+        let resp = serve(&bob, "req-5", "PATCH", "/menus/99", patch_menu_handler(connect(), 99, None, MenuPatch::default())).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+        assert!(!audit::changes(&Method::GET));
+        create_table(&conn, &Table { id: 0, code: "T-03".to_string(), section: None, table_type: None, server: None }).expect("Creation Failed");
+
+        let (status, _) = list(None, json!({})).await;
+        assert_eq!(status, warp::http::StatusCode::FORBIDDEN);
+        let (_, table_log) = list(Some("manager"), json!({"entity_type": "table", "entity_id": table_id})).await;
+        let entries = table_log["data"].as_array().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry["request_id"].as_str().unwrap()).collect::<Vec<_>>(), vec!["req-2", "req-1"]);
+        assert_eq!((entries[0]["actor"].as_str(), entries[0]["device"].as_str(), entries[0]["method"].as_str()), (Some("bob"), Some("tablet/2"), Some("PATCH")));
+        assert_eq!(entries[0]["diff"]["/code"], json!({"before": "T-01", "after": "T-02"}));
+        assert_eq!((entries[1]["before"].is_null(), entries[1]["diff"][""]["after"]["code"].as_str()), (true, Some("T-01")));
+
+        let (_, order_log) = list(Some("manager"), json!({"entity_type": "order", "entity_id": order_id, "sort": "id"})).await;
+        let entries = order_log["data"].as_array().unwrap();
+        assert_eq!((entries[0]["path"].as_str(), entries[0]["status"].as_i64()), (Some(format!("/v2/tables/{}/orders", table_id).as_str()), Some(201)));
+        assert_eq!(entries[1]["before"]["menus"].as_array().unwrap().len(), 2);
+        assert_eq!(entries[1]["after"]["menus"].as_array().unwrap().len(), 1);
+        assert_eq!((entries[1]["diff"]["/menus/1"]["after"].is_null(), entries[1]["after"]["menus"][0]["menu_name"].as_str()), (true, Some("M-01")));
+
+        let (_, bob_log) = list(Some("manager"), json!({"actor": "bob", "limit": 1})).await;
+        assert_eq!((bob_log["data"][0]["request_id"].as_str(), bob_log["data"][0]["status"].as_i64()), (Some("req-5"), Some(404)));
+        let (_, next) = list(Some("manager"), json!({"actor": "bob", "after": bob_log["next_cursor"]})).await;
+        assert_eq!((next["data"][0]["request_id"].as_str(), next["next_cursor"].is_null()), (Some("req-2"), true));
+
+        // Entries past the retention are deleted
+        conn.execute("UPDATE audit_log SET created_at = datetime('now', '-100 days') WHERE request_id = 'req-1'", []).expect("Update Failed");
+        assert_eq!(audit::prune(&conn, 90).unwrap(), 1);
+        assert_eq!(list(Some("manager"), json!({})).await.1["data"].as_array().unwrap().len(), 4);
+        assert_eq!(audit::diff(&json!({"a": 1, "b": [1, 2]}), &json!({"a": 1, "b": [1]})), json!({"/b/1": {"before": 2, "after": null}}).as_object().unwrap().clone());
+
+        // Operations of a batch are audited as they are applied, those rolled back are not
+        let item_id: i64 = conn.query_row("SELECT id FROM order_items WHERE order_id = ?1", params![order_id], |row| row.get(0)).unwrap();
+        let batch: BatchRequest = serde_json::from_value(json!({"mode": "continue_on_error", "operations": [
+            {"op": "set_quantity", "order_id": order_id, "item_id": item_id, "quantity": 4},
+            {"op": "remove_item", "order_id": order_id, "item_id": 999},
+        ]})).unwrap();
+        serve(&alice, "req-6", "POST", "/batch", batch_handler(connect(), batch)).await;
+        let (_, batch_log) = list(Some("manager"), json!({"entity_type": "order", "entity_id": order_id, "limit": 1})).await;
+        let entry = &batch_log["data"][0];
+        assert_eq!((entry["request_id"].as_str(), entry["path"].as_str(), entry["status"].as_i64()), (Some("req-6"), Some("/batch"), Some(200)));
+        assert_eq!(entry["diff"]["/menus/0/quantity"], json!({"before": 1, "after": 4}));
+        assert_eq!(list(Some("manager"), json!({})).await.1["data"].as_array().unwrap().len(), 5);
+    }
+
+    // Test Case: 47 Batches run their operations in one transaction, all or nothing or going on after a failed one
+    #[tokio::test]
+    async fn test_batch() {
+        async fn batch(body: serde_json::Value) -> (warp::http::StatusCode, serde_json::Value) {
+            let req_body: BatchRequest = serde_json::from_value(body).unwrap();
+            let resp = batch_handler(shared_test_db("batch_test"), req_body).await.unwrap().into_response();
+            (resp.status(), convert_response_to_json(resp).await)
+        }
+        fn outcomes(reply: &serde_json::Value) -> Vec<&str> {
+            reply["results"].as_array().unwrap().iter().map(|result| result["outcome"].as_str().unwrap()).collect()
+        }
+        let conn = shared_test_db("batch_test");
+        create_schema(&conn);
+        conn.execute("INSERT INTO menus (name, price) VALUES ('M-01', 1200), ('M-02', 800), ('M-03', 500)", []).expect("Insertion Failed");
+        let t1 = create_table(&conn, &Table { id: 0, code: "T-01".to_string(), section: None, table_type: None, server: None }).unwrap();
+        let t2 = create_table(&conn, &Table { id: 0, code: "T-02".to_string(), section: None, table_type: None, server: None }).unwrap();
+        let order = |table_id: i64, menu_ids: serde_json::Value| {
+            let body: OrderRequestBody = serde_json::from_value(json!({"table_id": table_id, "menu_ids": menu_ids})).unwrap();
+            place_order(&conn, body).unwrap().1["id"].as_i64().unwrap()
+        };
+        let item_of = |order_id: i64, menu_id: i64| -> i64 {
+            conn.query_row("SELECT id FROM order_items WHERE order_id = ?1 AND menu_id = ?2", params![order_id, menu_id], |row| row.get(0)).unwrap()
+        };
+        let order_id = order(t1, json!([1, 2]));
+
+        // Items are removed, added and changed and the order is moved, each replying as its route would
+        let (status, reply) = batch(json!({"operations": [
+            {"op": "remove_item", "order_id": order_id, "item_id": item_of(order_id, 2)},
+            {"op": "add_items", "table_id": t1, "items": [{"menu_id": 3, "quantity": 2}]},
+            {"op": "set_quantity", "order_id": order_id, "item_id": item_of(order_id, 1), "quantity": 3},
+            {"op": "move_order", "order_id": order_id, "table_id": t2},
+        ]})).await;
+        assert_eq!((status, reply["mode"].as_str(), reply["committed"].as_bool()), (warp::http::StatusCode::OK, Some("all_or_nothing"), Some(true)));
+        assert_eq!(outcomes(&reply), vec!["applied"; 4]);
+        assert_eq!((reply["results"][1]["status"].as_i64(), reply["results"][1]["body"]["id"].as_i64()), (Some(200), Some(order_id)));
+        assert_eq!((reply["results"][2]["body"]["quantity"].as_i64(), reply["results"][3]["body"]["table_id"].as_i64()), (Some(3), Some(t2)));
+        let moved = OrderResponse::get(&conn, order_id).unwrap().unwrap();
+        assert_eq!((moved.table_id, moved.menus.len()), (t2, 2));
+        let moves: i64 = conn.query_row("SELECT COUNT(*) FROM domain_events WHERE kind = 'order_moved' AND order_id = ?1 AND table_id = ?2", params![order_id, t2], |row| row.get(0)).unwrap();
+        assert_eq!(moves, 1);
+        let now = history::resolve(&conn, None).unwrap();
+        assert!(history::table_as_of(&conn, t1, &now).unwrap().is_empty());
+        assert_eq!(history::table_as_of(&conn, t2, &now).unwrap()[0].items.len(), 2);
+
+        // A failure undoes the whole batch, the rest is skipped and its status is the status of the reply
+        let second = order(t1, json!([1]));
+        let operations = json!([
+            {"op": "set_quantity", "order_id": second, "item_id": item_of(second, 1), "quantity": 2},
+            {"op": "move_order", "order_id": second, "table_id": t2},
+            {"op": "add_items", "table_id": t1, "menu_ids": [2]},
+        ]);
+        let (status, reply) = batch(json!({"mode": "all_or_nothing", "operations": operations})).await;
+        assert_eq!((status, reply["committed"].as_bool()), (warp::http::StatusCode::CONFLICT, Some(false)));
+        assert_eq!(outcomes(&reply), vec!["rolled_back", "failed", "skipped"]);
+        assert_eq!((reply["results"][1]["status"].as_i64(), reply["results"][1]["error"]["code"].as_str()), (Some(409), Some("conflict")));
+        assert_eq!(OrderItem::get(&conn, second, item_of(second, 1)).unwrap().unwrap().quantity, 1);
+
+        // Going on after a failure keeps the operations that succeeded
+        let (status, reply) = batch(json!({"mode": "continue_on_error", "operations": operations})).await;
+        assert_eq!((status, reply["committed"].as_bool()), (warp::http::StatusCode::OK, Some(true)));
+        assert_eq!(outcomes(&reply), vec!["applied", "failed", "applied"]);
+        let kept = OrderResponse::get(&conn, second).unwrap().unwrap();
+        assert_eq!((kept.table_id, kept.menus.len()), (t1, 2));
+        assert_eq!(OrderItem::get(&conn, second, item_of(second, 1)).unwrap().unwrap().quantity, 2);
+
+        // Operations are checked like their routes, unknown ones and empty batches are refused
+        let (_, reply) = batch(json!({"mode": "continue_on_error", "operations": [{"op": "move_order", "order_id": second, "table_id": 99}]})).await;
+        assert_eq!(reply["results"][0]["error"]["details"][0]["field"].as_str(), Some("table_id"));
+        assert!(serde_json::from_value::<BatchRequest>(json!({"operations": [{"op": "pay", "order_id": second}]})).is_err());
+        assert!(serde_json::from_value::<BatchRequest>(json!({"operations": [{"op": "remove_item", "order_id": second, "item_id": 1, "seat": 2}]})).is_err());
+        let (status, _) = batch(json!({"operations": []})).await;
+        assert_eq!(status, warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    // Test Case: 48 Menus are imported from CSV and JSON files matched by name, with a dry run and errors by row, and exported again
+    #[tokio::test]
+    async fn test_menu_import_export() {
+        use crate::versions::{render, ApiVersion};
+        async fn import(query: serde_json::Value, content_type: Option<&str>, body: &str) -> (warp::http::StatusCode, serde_json::Value) {
+            let query: MenuImportQuery = serde_json::from_value(query).unwrap();
+            let body = warp::hyper::body::Bytes::from(body.to_string());
+            let resp = import_menus_handler(shared_test_db("menu_files_test"), query, content_type.map(str::to_string), body).await.unwrap().into_response();
+            (resp.status(), convert_response_to_json(resp).await)
+        }
+        async fn export(format: &str) -> warp::reply::Response {
+            let query: MenuExportQuery = serde_json::from_value(json!({"format": format})).unwrap();
+            export_menus_handler(shared_test_db("menu_files_test"), query).await.unwrap().into_response()
+        }
+        async fn text(resp: warp::reply::Response) -> String {
+            String::from_utf8(warp::hyper::body::to_bytes(resp.into_body()).await.unwrap().to_vec()).unwrap()
+        }
+        let conn = shared_test_db("menu_files_test");
+        create_schema(&conn);
+        conn.execute("INSERT INTO menus (name, price, category) VALUES ('Soup', 500, 'starter')", []).expect("Insertion Failed");
+        conn.execute("INSERT INTO menus (name, price, archived_at) VALUES ('Old', 300, CURRENT_TIMESTAMP)", []).expect("Insertion Failed");
+        let count = || -> i64 { conn.query_row("SELECT COUNT(*) FROM menus", [], |row| row.get(0)).unwrap() };
+
+        // A dry run reports what would change without writing, archived menus are not matched
+        let csv = "name,price,category,station\nSoup,550,starter,\nSalad,700,starter,cold\nOld,300,,\n";
+        let (status, report) = import(json!({"dry_run": true}), Some("text/csv"), csv).await;
+        assert_eq!((status, report["applied"].as_bool(), report["created"].as_i64(), report["updated"].as_i64()), (warp::http::StatusCode::OK, Some(false), Some(2), Some(1)));
+        assert_eq!(report["rows"][0]["changes"], json!({"/price": {"before": 500, "after": 550}}));
+        assert_eq!((report["rows"][0]["menu_id"].as_i64(), report["rows"][1]["action"].as_str(), report["rows"][1]["menu_id"].is_null()), (Some(1), Some("create"), true));
+        assert_eq!(count(), 2);
+
+        // An invalid row stops the whole import, errors are listed by row and field
+        let csv = "name,price\nSalad,abc\nSoup,600\nSoup,650\n,100\n";
+        let (status, report) = import(json!({"format": "csv"}), None, csv).await;
+        assert_eq!((status, report["applied"].as_bool(), report["invalid"].as_i64()), (warp::http::StatusCode::UNPROCESSABLE_ENTITY, Some(false), Some(3)));
+        let errors: Vec<(i64, &str, &str)> = report["rows"].as_array().unwrap().iter()
+            .flat_map(|row| row["errors"].as_array().unwrap().iter().map(move |error| (row["row"].as_i64().unwrap(), error["field"].as_str().unwrap(), error["code"].as_str().unwrap())))
+            .collect();
+        assert_eq!(errors, vec![(1, "price", "type"), (3, "name", "duplicate"), (4, "name", "length"), (4, "name", "blank")]);
+        assert_eq!(Menu::get(&conn, 1).unwrap().unwrap().price, 500);
+
+        // JSON is imported when the upload is not CSV, the same name updates the menu
+        let json = r#"[{"name": "Soup", "price": 550, "category": "starter"}, {"name": "Salad", "price": 700, "station": "cold"}]"#;
+        let (status, report) = import(json!({}), Some("application/json"), json).await;
+        assert_eq!((status, report["applied"].as_bool(), report["created"].as_i64(), report["updated"].as_i64()), (warp::http::StatusCode::OK, Some(true), Some(1), Some(1)));
+        let salad_id = report["rows"][1]["menu_id"].as_i64().unwrap();
+        assert_eq!(Menu::get(&conn, salad_id).unwrap().unwrap().station.as_deref(), Some("cold"));
+        assert_eq!((Menu::get(&conn, 1).unwrap().unwrap().price, count()), (550, 3));
+
+        // Exports can be imported again as they are, in every version prices stay in cents
+        let resp = export("csv").await;
+        assert_eq!(resp.headers()["content-type"], "text/csv; charset=utf-8");
+        assert_eq!(resp.headers()["content-disposition"], "attachment; filename=\"menus.csv\"");
+        let csv = text(resp).await;
+        assert_eq!(csv, "name,price,category,station\nSoup,550,starter,\nSalad,700,,cold\n");
+        let (_, report) = import(json!({"dry_run": true}), Some("text/csv"), &csv).await;
+        assert_eq!((report["unchanged"].as_i64(), report["created"].as_i64()), (Some(2), Some(0)));
+        let json = text(render(ApiVersion::V2, true, export("json").await).await.unwrap()).await;
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap()[0], json!({"name": "Soup", "price": 550, "category": "starter", "station": null}));
+        let (_, report) = import(json!({}), None, &json).await;
+        assert_eq!((report["unchanged"].as_i64(), report["applied"].as_bool()), (Some(2), Some(true)));
+
+        // Files that can't be read at all are refused
+        let (status, _) = import(json!({"format": "csv"}), None, "name,colour\nSoup,red\n").await;
+        assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+        let (status, _) = import(json!({}), None, r#"{"name": "Soup"}"#).await;
+        assert_eq!(status, warp::http::StatusCode::BAD_REQUEST);
+    }
+
+    // Test Case: 49 Without a version prefix DELETE /orders/{table_id}/items/{menu_id} still takes a unit of a menu off the open order of the table
+    #[tokio::test]
+    async fn test_deprecated_delete_item_path() {
+        let conn = routes_test_db();
+        setup_static_data(&conn);
+        // The order of table 2 is order 1, so the ids can't be taken the other way round
+        let body: OrderRequestBody = serde_json::from_value(json!({"table_id": 2, "items": [{"menu_id": 1, "quantity": 2}, {"menu_id": 3}]})).unwrap();
+        let order_id = place_order(&conn, body).unwrap().1["id"].as_i64().unwrap();
+        assert_eq!(order_id, 1);
+        let routes = crate::routes::restaurent_routes();
+
+        let resp = warp::test::request().method("DELETE").path("/orders/2/items/1").reply(&routes).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        assert_eq!(resp.headers()["deprecation"], "@1792368000");
+        let items = OrderItem::list_all_order_items(&conn, order_id).unwrap();
+        assert_eq!(items.iter().map(|item| (item.menu_id, item.quantity)).collect::<Vec<_>>(), vec![(1, 1), (3, 1)]);
+
+        // Under a version prefix the same path addresses an item of an order by its id
+        let resp = warp::test::request().method("DELETE").path(&format!("/v1/orders/{}/items/{}", order_id, items[1].id)).reply(&routes).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        assert!(!resp.headers().contains_key("deprecation"));
+        assert_eq!(OrderItem::list_all_order_items(&conn, order_id).unwrap().len(), 1);
+        let resp = warp::test::request().method("DELETE").path("/v1/orders/2/items/1").reply(&routes).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND); // There is no order 2
+        let resp = warp::test::request().method("DELETE").path("/orders/1/items/1").reply(&routes).await;
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND); // Table 1 has no open order
+    }
+
+    // Test Case: 50 Split requests are bounded and the lines of a sub-bill add up to its amount after discounts
+    #[tokio::test]
+    async fn test_split_bill_limits() {
+        let open = || shared_test_db("split_limits_test");
+        let conn = open();
+        create_schema(&conn);
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let items = OrderItem::list_all_order_items(&conn, order_id).unwrap();
+
+        let request = SplitBillRequest { mode: SplitMode::Even, guests: Some(0), allocations: vec![] };
+        let resp = split_bill_handler(open(), 1, request).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let request: SplitBillRequest = serde_json::from_value(json!({"mode": "even", "guests": 1_000_000_000_000_i64})).unwrap();
+        assert!(request.validate().is_err());
+        let request: SplitBillRequest = serde_json::from_value(json!({"mode": "custom", "allocations": [{"items": [{"order_item_id": items[0].id, "quantity": 1e300}]}]})).unwrap();
+        let resp = split_bill_handler(open(), 1, request).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+
+        // 2250 less 225 shared by two guests having half of every item each
+        let request = ManualDiscountRequest { kind: DiscountKind::Fixed, value: 225, reason_code: DiscountReason::Complaint };
+        ManualDiscountResponse::create(&conn, order_id, &request).expect("Discount creation failed");
+        let half = |item: &OrderItemResponse| ItemAllocation { order_item_id: item.id, quantity: 0.5 };
+        let request = SplitBillRequest {
+            mode: SplitMode::Custom,
+            guests: None,
+            allocations: (0..2).map(|_| SubBillAllocation { label: None, items: items.iter().map(half).collect() }).collect(),
+        };
+        let bill = BillResponse::for_table(&conn, 1).expect("Bill Failed").expect("No Bill");
+        let sub_bills = bill.split(&request).expect("Split Failed");
+        assert_eq!(sub_bills.iter().map(|sub_bill| sub_bill.amount).sum::<i64>(), 2025);
+        for sub_bill in &sub_bills {
+            assert_eq!(sub_bill.lines.iter().map(|line| line.amount).sum::<i64>(), sub_bill.amount);
+        }
+    }
+
+    // Test Case: 51 Changes to tables, menus, payments, promotions, discounts and service charge rules are logged as events
+    #[tokio::test]
+    async fn test_domain_events_of_every_change() {
+        let open = || shared_test_db("every_change_test");
+        let conn = open();
+        create_schema(&conn);
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let since = conn.query_row("SELECT COALESCE(MAX(id), 0) FROM domain_events", [], |row| row.get::<_, i64>(0)).unwrap();
+
+        let patch: TablePatch = serde_json::from_value(json!({"section": "bar"})).unwrap();
+        patch_table_handler(open(), 2, None, patch).await.unwrap();
+        assign_table_server_handler(open(), 2, None, TableServerRequest { server: Some("Ann".to_string()) }).await.unwrap();
+        delete_table_handler(open(), 3, None).await.unwrap();
+        let menu_id = create_menu(&conn, &Menu { id: 0, name: "M-99".to_string(), price: 500, category: None, station: None }).unwrap();
+        let patch: MenuPatch = serde_json::from_value(json!({"price": 550})).unwrap();
+        patch_menu_handler(open(), menu_id, None, patch).await.unwrap();
+        delete_menu_handler(open(), menu_id, None).await.unwrap();
+        delete_menu_handler(open(), 1, None).await.unwrap();
+        let promotion: Promotion = serde_json::from_value(json!({"name": "Launch", "kind": "fixed", "scope": "order", "value": 100, "coupon_code": "LAUNCH"})).unwrap();
+        create_promotion_handler(open(), promotion).await.unwrap();
+        redeem_coupon_handler(open(), order_id, None, CouponRequest { code: "LAUNCH".to_string() }).await.unwrap();
+        let discount = ManualDiscountRequest { kind: DiscountKind::Fixed, value: 50, reason_code: DiscountReason::Complaint };
+        create_manual_discount_handler(open(), order_id, Some("manager".to_string()), None, discount).await.unwrap();
+        let rule = ServiceChargeRule { id: 0, section: None, table_type: None, min_party_size: 8, percent: 10 };
+        create_service_charge_rule_handler(open(), rule).await.unwrap();
+        let payment = PaymentRequest { tender: Tender::Cash, amount: 500, tip: 0, card_token: None };
+        let payment_id = convert_response_to_json(record_payment(&conn, &FakeProcessor, order_id, None, None, &payment).into_response()).await["payment"]["id"].as_i64().unwrap();
+        cancel_payment(&conn, &FakeProcessor, payment_id, &reason("Wrong table"), "voided").unwrap();
+
+        type Logged = (String, Option<i64>, Option<i64>, Option<i64>); // kind, table_id, order_id, entity_id
+        let mut stmt = conn.prepare("SELECT kind, table_id, order_id, entity_id FROM domain_events WHERE id > ?1 ORDER BY id").unwrap();
+        let logged: Vec<Logged> = stmt
+            .query_map(params![since], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let kinds: Vec<&str> = logged.iter().map(|(kind, ..)| kind.as_str()).collect();
+        assert_eq!(kinds, vec![
+            "table_updated", "server_assigned", "table_deleted", "menu_created", "menu_updated", "menu_deleted", "menu_archived",
+            "promotion_created", "coupon_redeemed", "discount_given", "service_charge_rule_created", "payment_captured", "payment_voided",
+        ]);
+        // Menus are not on a table, changes on an order carry it and the id of what changed
+        assert_eq!(logged[3], ("menu_created".to_string(), None, None, Some(menu_id)));
+        assert_eq!(logged[12], ("payment_voided".to_string(), Some(1), Some(order_id), Some(payment_id)));
+        let log = LoggedEventResponse::since(&conn, &EventLogQuery { since: Some(since), ..EventLogQuery::default() }).unwrap();
+        assert_eq!(log.events[6].entity_id, Some(1));
+        assert_eq!(log.events[6].table_id, None);
+    }
+
+    // Test Case: 52 Removing a menu by table without a seat is refused when the menu is on several seats
+    #[tokio::test]
+    async fn test_delete_menu_on_two_seats() {
+        let open = || shared_test_db("two_seats_test");
+        let conn = open();
+        create_schema(&conn);
+        setup_static_data(&conn);
+        let order_id = OrderResponse::create(&conn, 1).expect("Order Creation Failed");
+        OrderItem::create(&conn, order_id, &order_line(1, Some(1)), 6).expect("OrderItems creation failed");
+        OrderItem::create(&conn, order_id, &order_line(1, Some(2)), 6).expect("OrderItems creation failed");
+        let seats = |conn: &Connection| OrderItem::list_all_order_items(conn, order_id).unwrap().iter().map(|item| (item.seat, item.quantity)).collect::<Vec<_>>();
+
+        let resp = delete_order_item_for_table_handler(open(), 1, 1, SeatQuery::default()).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+        assert_eq!(seats(&conn), vec![(Some(1), 1), (Some(2), 1)]);
+        let resp = get_order_item_for_table_handler(open(), 1, 1, SeatQuery::default()).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CONFLICT);
+
+        // The seat tells the lines apart, the other seat keeps its line
+        let resp = delete_order_item_for_table_handler(open(), 1, 1, SeatQuery { seat: Some(2) }).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::OK);
+        assert_eq!(seats(&conn), vec![(Some(1), 1)]);
+        // With one line left no seat is needed
+        let resp = delete_order_item_for_table_handler(open(), 1, 1, SeatQuery::default()).await.unwrap().into_response();
+        assert_eq!(convert_response_to_json(resp).await["success"], json!("Menu deleted successfully and order deleted"));
+    }
+
+    // Test Case: 53 Promotions, discounts, service charge rules, payments and patches are validated with a 422 naming the field
+    #[tokio::test]
+    async fn test_adjustment_and_patch_validation() {
+        let open = || shared_test_db("adjustment_validation_test");
+        let conn = open();
+        create_schema(&conn);
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+
+        async fn fields(resp: warp::reply::Response) -> Vec<String> {
+            assert_eq!(resp.status(), warp::http::StatusCode::UNPROCESSABLE_ENTITY);
+            let json_data = convert_response_to_json(resp).await;
+            json_data["details"].as_array().unwrap().iter().map(|detail| detail["field"].as_str().unwrap().to_string()).collect()
+        }
+
+        let promotion = |value: serde_json::Value| serde_json::from_value::<Promotion>(value).unwrap();
+        let resp = create_promotion_handler(open(), promotion(json!({"name": "Half off", "kind": "percent", "scope": "order", "value": 150}))).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["value"]);
+        let resp = create_promotion_handler(open(), promotion(json!({"name": "Burgers", "kind": "fixed", "scope": "item", "value": 100}))).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["menu_id"]);
+        let resp = create_promotion_handler(open(), promotion(json!({"name": "Two for one", "kind": "buy_x_get_y", "scope": "item", "menu_id": 1, "buy_quantity": 1_000_000_000_000_i64, "get_quantity": 1}))).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["buy_quantity"]);
+        let resp = create_promotion_handler(open(), promotion(json!({"name": "Happy hour", "kind": "percent", "scope": "order", "value": 10, "starts_at": "25:00", "ends_at": "18:00"}))).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["starts_at"]);
+        let resp = create_promotion_handler(open(), promotion(json!({"name": "Limited", "kind": "fixed", "scope": "order", "value": 100, "usage_limit": 5}))).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["usage_limit"]);
+
+        let rule = ServiceChargeRule { id: 0, section: None, table_type: None, min_party_size: -1, percent: 0 };
+        let resp = create_service_charge_rule_handler(open(), rule).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["min_party_size", "percent"]);
+
+        let discount = ManualDiscountRequest { kind: DiscountKind::Percent, value: 200, reason_code: DiscountReason::Complaint };
+        let resp = create_manual_discount_handler(open(), order_id, Some("manager".to_string()), None, discount).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["value"]);
+        let discount = ManualDiscountRequest { kind: DiscountKind::Fixed, value: 0, reason_code: DiscountReason::Complaint };
+        let resp = create_manual_discount_handler(open(), order_id, Some("manager".to_string()), None, discount).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["value"]);
+
+        let payment = PaymentRequest { tender: Tender::Cash, amount: 0, tip: -1, card_token: None };
+        let resp = create_payment_handler(open(), Arc::new(FakeProcessor), order_id, None, payment).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["amount", "tip"]);
+
+        let patch: TablePatch = serde_json::from_value(json!({"code": "T 01 ", "section": " "})).unwrap();
+        let resp = patch_table_handler(open(), 1, None, patch).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["code", "section"]);
+        let patch: MenuPatch = serde_json::from_value(json!({"price": -5, "category": null})).unwrap();
+        let resp = patch_menu_handler(open(), 1, None, patch).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["price"]);
+        let patch: WebhookPatch = serde_json::from_value(json!({"url": "ftp://example.com", "events": ["no_such_event"]})).unwrap();
+        let resp = patch_webhook_handler(open(), 1, patch).await.unwrap().into_response();
+        assert_eq!(fields(resp).await, ["events", "url"]);
+    }
+
+    // Test Case: 54 Promotions with values past their limits saturate instead of overflowing
+    #[test]
+    fn test_promotion_discount_saturates(){
+        let promotion = |kind: &str, value: i64, buy: Option<i64>, get: Option<i64>| -> PromotionResponse {
+            serde_json::from_value(json!({
+                "id": 1, "name": "Huge", "kind": kind, "scope": "item", "menu_id": 5, "category": null, "value": value,
+                "buy_quantity": buy, "get_quantity": get, "starts_at": null, "ends_at": null, "coupon_code": null, "usage_limit": null,
+                "usage_count": 0, "active": true
+            })).unwrap()
+        };
+        let lines = vec![bill_line(5, 4, 350, 600), bill_line(5, 3, 300, 600)];
+        // A fixed amount off every unit is capped at what the units cost
+        let adjustments = promotion_adjustments(&lines, &[promotion("fixed", i64::MAX, None, None)], &[]);
+        assert_eq!(adjustments[0].amount, -2300);
+        let adjustments = promotion_adjustments(&lines, &[promotion("percent", i64::MAX, None, None)], &[]);
+        assert_eq!(adjustments[0].amount, -2300);
+        // Groups too large to fill give nothing off
+        assert!(promotion_adjustments(&lines, &[promotion("buy_x_get_y", 0, Some(i64::MAX), Some(i64::MAX))], &[]).is_empty());
+        assert!(promotion_adjustments(&lines, &[promotion("buy_x_get_y", 0, Some(-1), Some(1))], &[]).is_empty());
+    }
+
+    // Test Case: 55 Repeating a line can't take the item past the most units of a menu on one line
+    #[test]
+    fn test_merged_quantity_limit(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order = |value: serde_json::Value| serde_json::from_value::<OrderRequestBody>(value).unwrap();
+
+        let (_, created) = place_order(&conn, order(json!({"table_id": 1, "items": [{"menu_id": 1, "quantity": 60}]}))).unwrap();
+        let order_id = created["id"].as_i64().unwrap();
+        let result = place_order(&conn, order(json!({"table_id": 1, "items": [{"menu_id": 1, "quantity": 41}]})));
+        assert!(matches!(result, Err(ApiError::Validation(_))));
+        // Lines of one request are merged too, and nothing of a refused request is kept
+        let result = place_order(&conn, order(json!({"table_id": 1, "items": [{"menu_id": 2, "quantity": 1}, {"menu_id": 1, "quantity": 30}, {"menu_id": 1, "quantity": 30}]})));
+        assert!(matches!(result, Err(ApiError::Validation(_))));
+        let items = OrderItem::list_all_order_items(&conn, order_id).unwrap();
+        assert_eq!(items.iter().map(|item| (item.menu_id, item.quantity)).collect::<Vec<_>>(), [(1, 60)]);
+
+        place_order(&conn, order(json!({"table_id": 1, "items": [{"menu_id": 1, "quantity": 40}]}))).expect("Order Failed");
+        assert_eq!(OrderItem::list_all_order_items(&conn, order_id).unwrap()[0].quantity, MAX_ITEM_QUANTITY);
+    }
+
+    // Test Case: 56 Payments check If-Match in their transaction, a charge left on the card is reported and unknown tenders are errors
+    #[tokio::test]
+    async fn test_payment_failures(){
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let card = PaymentRequest { tender: Tender::Card, amount: 1000, tip: 0, card_token: Some("tok-visa".to_string()) };
+
+        // Approves every charge but can't void them
+        struct NoVoidProcessor;
+        impl PaymentProcessor for NoVoidProcessor {
+            fn charge(&self, _amount: i64, _token: &str) -> Result<String, crate::payments::PaymentError> {
+                Ok("charge-1".to_string())
+            }
+            fn refund(&self, reference: &str, _amount: i64) -> Result<String, crate::payments::PaymentError> {
+                Ok(format!("refund-{}", reference))
+            }
+            fn void(&self, _reference: &str) -> Result<String, crate::payments::PaymentError> {
+                Err(crate::payments::PaymentError::Unavailable("Terminal is offline".to_string()))
+            }
+        }
+
+        let resp = record_payment(&conn, &NoVoidProcessor, order_id, None, Some("\"99\""), &card).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::PRECONDITION_FAILED);
+
+        // Storing the payment fails after the card was charged
+        conn.execute_batch("CREATE TRIGGER payments_fail BEFORE INSERT ON payments BEGIN SELECT RAISE(ABORT, 'disk full'); END;").unwrap();
+        let resp = record_payment(&conn, &FakeProcessor, order_id, None, None, &card).into_response();
+        assert!(resp.status().is_client_error());
+        let resp = record_payment(&conn, &NoVoidProcessor, order_id, None, None, &card).into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::SERVICE_UNAVAILABLE);
+        let json_data = convert_response_to_json(resp).await;
+        assert!(json_data["message"].as_str().unwrap().contains("charge-1 could not be voided"));
+        assert!(conn.is_autocommit());
+        assert!(PaymentResponse::list_for_order(&conn, order_id).unwrap().is_empty());
+
+        conn.execute_batch("DROP TRIGGER payments_fail;").unwrap();
+        record_payment(&conn, &FakeProcessor, order_id, None, None, &card).expect("Payment Failed");
+        conn.execute("UPDATE payments SET tender = 'cheque'", []).unwrap();
+        assert!(PaymentResponse::list_for_order(&conn, order_id).is_err());
+    }
+
+    // Test Case: 57 Stamping the request id on an error keeps every value of a header sent more than once
+    #[tokio::test]
+    async fn test_request_id_keeps_repeated_headers(){
+        let mut resp = ApiError::NotFound("No Order Found".to_string()).into_response();
+        resp.headers_mut().append("link", "</v1/orders>; rel=\"successor-version\"".parse().unwrap());
+        resp.headers_mut().append("link", "</docs>; rel=\"help\"".parse().unwrap());
+        let resp = crate::errors::stamp_request_id("req-1".to_string(), resp);
+        assert_eq!(resp.status(), warp::http::StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get_all("link").iter().count(), 2);
+        assert_eq!(resp.headers()["x-request-id"], "req-1");
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!((json_data["code"].as_str(), json_data["request_id"].as_str()), (Some("not_found"), Some("req-1")));
+    }
+
+    // Test Case: 58 Sub-bills follow the total of the order after a split and never take more than the order owes
+    #[tokio::test]
+    async fn test_sub_bills_follow_order_changes(){
+        let open = || shared_test_db("sub_bill_changes_test");
+        let conn = open();
+        create_schema(&conn);
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+
+        let request = SplitBillRequest { mode: SplitMode::Even, guests: Some(2), allocations: vec![] };
+        let resp = split_bill_handler(open(), 1, request).await.unwrap().into_response();
+        assert_eq!(resp.status(), warp::http::StatusCode::CREATED);
+
+        // Another dish after the split is shared by the sub-bills
+        let order: OrderRequestBody = serde_json::from_value(json!({"table_id": 1, "menu_ids": [1]})).unwrap();
+        place_order(&conn, order).expect("Order Failed");
+        let total = BillResponse::for_order(&conn, order_id).unwrap().unwrap().total;
+        assert!(total > 2250);
+        let sub_bills = SubBillResponse::list(&conn, order_id).unwrap();
+        assert_eq!(sub_bills.iter().map(|sub_bill| sub_bill.amount).sum::<i64>(), total);
+
+        // Some is paid on the order itself, the last sub-bill only takes what is left
+        let cash = |amount: i64| PaymentRequest { tender: Tender::Cash, amount, tip: 0, card_token: None };
+        record_payment(&conn, &FakeProcessor, order_id, Some(sub_bills[0].id), None, &cash(sub_bills[0].amount)).expect("Payment Failed");
+        record_payment(&conn, &FakeProcessor, order_id, None, None, &cash(100)).expect("Payment Failed");
+        let resp = record_payment(&conn, &FakeProcessor, order_id, Some(sub_bills[1].id), None, &cash(sub_bills[1].amount)).expect("Payment Failed").into_response();
+        let json_data = convert_response_to_json(resp).await;
+        assert_eq!(json_data["payment"]["amount"].as_i64(), Some(sub_bills[1].amount - 100));
+        assert_eq!(json_data["payment"]["change_due"].as_i64(), Some(100));
+        assert_eq!(json_data["order_status"].as_str(), Some("closed"));
+        assert_eq!(PaymentResponse::paid_for_order(&conn, order_id).unwrap(), total);
+    }
+
+    // Test Case: 59 The same payment refunded twice at once is refunded once at the processor
+    #[test]
+    fn test_cancel_payment_twice(){
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Counts the refunds and takes a while over each, like a real processor
+        struct SlowProcessor(AtomicUsize);
+        impl PaymentProcessor for SlowProcessor {
+            fn charge(&self, _amount: i64, _token: &str) -> Result<String, crate::payments::PaymentError> {
+                Ok("charge-1".to_string())
+            }
+            fn refund(&self, reference: &str, _amount: i64) -> Result<String, crate::payments::PaymentError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                Ok(format!("refund-{}", reference))
+            }
+            fn void(&self, reference: &str) -> Result<String, crate::payments::PaymentError> {
+                Ok(format!("void-{}", reference))
+            }
+        }
+
+        // Two connections to a file, as two requests would have
+        let path = std::env::temp_dir().join(format!("cancel_twice_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connect = || {
+            let conn = Connection::open(&path).expect("Failed to open test database");
+            conn.busy_timeout(std::time::Duration::from_secs(5)).expect("Failed to set busy timeout");
+            conn
+        };
+        let conn = connect();
+        create_schema(&conn);
+        setup_static_data(&conn);
+        let order_id = setup_order(&conn);
+        let processor = Arc::new(SlowProcessor(AtomicUsize::new(0)));
+        let card = PaymentRequest { tender: Tender::Card, amount: 1000, tip: 0, card_token: Some("tok".to_string()) };
+        record_payment(&conn, processor.as_ref(), order_id, None, None, &card).expect("Payment Failed");
+        let payment_id = PaymentResponse::list_for_order(&conn, order_id).unwrap()[0].id;
+
+        let refunds: Vec<_> = (0..2)
+            .map(|_| {
+                let (conn, processor) = (connect(), processor.clone());
+                std::thread::spawn(move || cancel_payment(&conn, processor.as_ref(), payment_id, &reason("Cold food"), "refunded").map(|_| ()).map_err(|err| err.status()))
+            })
+            .collect();
+        let mut results: Vec<_> = refunds.into_iter().map(|refund| refund.join().unwrap()).collect();
+        results.sort_by_key(|result| result.is_err());
+        assert_eq!(results, [Ok(()), Err(warp::http::StatusCode::CONFLICT)]);
+        assert_eq!(processor.0.load(Ordering::SeqCst), 1);
+        drop(conn);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // Test Case: 60 Creating an archived menu again brings it back, as for tables
+    #[tokio::test]
+    async fn test_create_restores_archived_menu() {
+        let conn = setup_test_db();
+        setup_static_data(&conn);
+        setup_order(&conn);
+        assert_eq!(Menu::delete(&conn, 1).expect("Delete Failed"), DeleteOutcome::Archived);
+        let logged = |kind: &str| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM domain_events WHERE kind = ?1 AND entity_id = 1", [kind], |row| row.get(0)).unwrap()
+        };
+        let before = logged("menu_created");
+
+        let menu = Menu { id: 0, name: "M-01".to_string(), price: 1500, category: None, station: None };
+        assert_eq!(create_menu(&conn, &menu).unwrap(), 1);
+        assert!(!Menu::get(&conn, 1).unwrap().unwrap().archived);
+        assert_eq!(logged("menu_created"), before + 1);
+        // It is the menu as it was, and creating it once more neither restores nor logs anything
+        assert_eq!(Menu::get(&conn, 1).unwrap().unwrap().price, 1000);
+        assert_eq!(create_menu(&conn, &menu).unwrap(), 1);
+        assert_eq!(logged("menu_created"), before + 1);
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM menus WHERE name = 'M-01'", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,186 @@
+// src/history.rs
+use crate::events::{self, EventKind};
+use crate::models::{OrderItem, OrderItemResponse, OrderItemState, OrderState, PaymentResponse, SubBillResponse};
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+
+/// Events replayed to rebuild an order before a snapshot of it is kept, later rebuilds start from there
+const SNAPSHOT_EVERY: usize = 20;
+
+/// A logged change of an order with the order as it was right after it
+struct Change {
+    id: i64,
+    kind: EventKind,
+    order: Option<OrderState>, // Not logged by versions before orders were rebuilt from their events
+    item: Option<OrderItemResponse>,
+    created_at: String,
+}
+
+impl Change {
+    /// The order after the change from the order before it, None if it does not exist
+    fn apply(self, before: Option<OrderState>) -> Option<OrderState> {
+        let (header, items) = match (self.kind, before) {
+            (EventKind::OrderDeleted, _) => return None,
+            // The id of a deleted order can be used again by a new one
+            (EventKind::OrderCreated, _) => (None, Vec::new()),
+            (_, Some(mut before)) => {
+                let items = std::mem::take(&mut before.items);
+                (Some(before), items)
+            }
+            (_, None) => (None, Vec::new()),
+        };
+        let mut order = self.order.or(header)?;
+        order.items = items;
+        if let Some(item) = self.item {
+            match self.kind {
+                EventKind::ItemAdded | EventKind::ItemUpdated => match order.items.iter_mut().find(|known| known.item.id == item.id) {
+                    Some(known) => known.item = item,
+                    None => order.items.push(OrderItemState { item, ordered_at: self.created_at }),
+                },
+                EventKind::ItemRemoved => order.items.retain(|known| known.item.id != item.id),
+                _ => {}
+            }
+        }
+        Some(order)
+    }
+}
+
+/// Changes of an order after an event up to a time, oldest first
+fn changes(conn: &Connection, order_id: i64, after: i64, as_of: &str) -> rusqlite::Result<Vec<Change>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, kind, order_state, item, created_at FROM domain_events
+        WHERE order_id = ?1 AND id > ?2 AND created_at <= datetime(?3) ORDER BY id",
+    )?;
+    let rows = stmt.query_map(params![order_id, after, as_of], |row| {
+        let order: Option<String> = row.get(2)?;
+        let item: Option<String> = row.get(3)?;
+        Ok(Change {
+            id: row.get(0)?,
+            kind: events::kind_at(row, 1)?,
+            order: order.and_then(|order| serde_json::from_str(&order).ok()),
+            item: item.and_then(|item| serde_json::from_str(&item).ok()),
+            created_at: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// The newest snapshot of an order up to a time and the event it was taken at, 0 if there is none
+fn snapshot(conn: &Connection, order_id: i64, as_of: &str) -> rusqlite::Result<(i64, Option<OrderState>)> {
+    let result = conn.query_row(
+        "SELECT event_id, state FROM order_snapshots WHERE order_id = ?1 AND event_at <= datetime(?2) ORDER BY event_id DESC LIMIT 1",
+        params![order_id, as_of],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+    );
+    match result {
+        // A snapshot that can't be read is replayed from the start
+        Ok((event_id, state)) => Ok(serde_json::from_str(&state).map_or((0, None), |state| (event_id, Some(state)))),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok((0, None)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Resolve a time given as YYYY-MM-DD HH:MM:SS, or now if none is given, to the format times are logged in
+pub fn resolve(conn: &Connection, as_of: Option<&str>) -> rusqlite::Result<String> {
+    conn.query_row("SELECT datetime(COALESCE(?1, 'now'))", params![as_of], |row| row.get(0))
+}
+
+/// An order as it was at a time, from its newest snapshot up to then and the changes after it.
+/// None if it did not exist at that time or had been deleted
+pub fn order_as_of(conn: &Connection, order_id: i64, as_of: &str) -> rusqlite::Result<Option<OrderState>> {
+    let (after, snapshot) = snapshot(conn, order_id, as_of)?;
+    let changes = changes(conn, order_id, after, as_of)?;
+    let replayed = changes.len();
+    let last = changes.last().map(|change| (change.id, change.created_at.clone()));
+    let order = changes.into_iter().fold(snapshot, |order, change| change.apply(order));
+    if let (Some(order), Some((event_id, event_at))) = (&order, last) {
+        if replayed >= SNAPSHOT_EVERY {
+            conn.execute(
+                "INSERT OR IGNORE INTO order_snapshots (order_id, event_id, event_at, state) VALUES (?1, ?2, ?3, ?4)",
+                params![order_id, event_id, event_at, serde_json::to_string(order).unwrap_or_default()],
+            )?;
+        }
+    }
+    Ok(order)
+}
+
+/// The orders of a table as they were at a time, the newest first. Orders moved away from it by then are left out
+pub fn table_as_of(conn: &Connection, table_id: i64, as_of: &str) -> rusqlite::Result<Vec<OrderState>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT order_id FROM domain_events WHERE table_id = ?1 AND order_id IS NOT NULL AND created_at <= datetime(?2) ORDER BY order_id DESC",
+    )?;
+    let order_ids: Vec<i64> = stmt.query_map(params![table_id, as_of], |row| row.get(0))?.collect::<Result<_, _>>()?;
+    let mut orders = Vec::new();
+    for order_id in order_ids {
+        orders.extend(order_as_of(conn, order_id, as_of)?.filter(|order| order.table_id == table_id));
+    }
+    Ok(orders)
+}
+
+/// What a rebuild of the orders from their events did
+#[derive(Debug, Default, PartialEq)]
+pub struct Rebuilt {
+    pub orders: usize,
+    pub items: usize,
+    pub deleted: usize, // Orders whose events end with their deletion
+}
+
+/// Regenerate the orders and order_items tables from the events, in one transaction.
+/// Orders without any event are left as they are
+pub fn rebuild(conn: &Connection) -> rusqlite::Result<Rebuilt> {
+    let tx = conn.unchecked_transaction()?;
+    let now = resolve(&tx, None)?;
+    let mut stmt = tx.prepare("SELECT DISTINCT order_id FROM domain_events WHERE order_id IS NOT NULL ORDER BY order_id")?;
+    let order_ids: Vec<i64> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+    drop(stmt);
+    let mut rebuilt = Rebuilt::default();
+    for order_id in order_ids {
+        match order_as_of(&tx, order_id, &now)? {
+            Some(order) => {
+                order.project(&tx)?;
+                rebuilt.orders += 1;
+                rebuilt.items += order.items.len();
+            }
+            None => {
+                tx.execute("DELETE FROM order_items WHERE order_id = ?1", params![order_id])?;
+                // Orders with payments are kept for the payment records, as when they are deleted
+                if !PaymentResponse::has_payments(&tx, order_id)? {
+                    SubBillResponse::delete_for_order(&tx, order_id)?;
+                    tx.execute("DELETE FROM orders WHERE id = ?1", params![order_id])?;
+                }
+                rebuilt.deleted += 1;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(rebuilt)
+}
+
+/// Log the orders made before changes were logged as if they had been, so they can be rebuilt like the others.
+/// Returns how many were logged
+pub fn record_unlogged_orders(conn: &Connection) -> rusqlite::Result<usize> {
+    let mut stmt = conn.prepare("SELECT id FROM orders WHERE id NOT IN (SELECT order_id FROM domain_events WHERE order_id IS NOT NULL) ORDER BY id")?;
+    let order_ids: Vec<i64> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+    if order_ids.is_empty() {
+        return Ok(0);
+    }
+    let tx = conn.unchecked_transaction()?;
+    for order_id in &order_ids {
+        let Some(order) = OrderState::header(&tx, *order_id)? else {
+            continue;
+        };
+        let opened = OrderState { status: "open".to_string(), closed_at: None, subtotal: None, discounts: None, service_charge: None, total: None, ..order.clone() };
+        events::record_past(&tx, EventKind::OrderCreated, &opened, None, &order.created_at)?;
+        let mut stmt = tx.prepare("SELECT id, COALESCE(ordered_at, ?2) FROM order_items WHERE order_id = ?1")?;
+        let ordered_at: HashMap<i64, String> = stmt.query_map(params![order_id, order.created_at], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<_, _>>()?;
+        for item in OrderItem::list_all_order_items(&tx, *order_id)? {
+            let at = ordered_at.get(&item.id).unwrap_or(&order.created_at);
+            events::record_past(&tx, EventKind::ItemAdded, &opened, Some(&item), at)?;
+        }
+        if order.status == "closed" {
+            events::record_past(&tx, EventKind::OrderClosed, &order, None, order.closed_at.as_ref().unwrap_or(&order.created_at))?;
+        }
+    }
+    tx.commit()?;
+    Ok(order_ids.len())
+}
@@ -0,0 +1,74 @@
+// src/idempotency.rs
+use rand::Rng;
+use rusqlite::{params, Connection};
+
+/// Header clients send to make a retried request safe, the same key on a retry replays the first response
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header set on a replayed response
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// How long a stored response is replayed for
+const RETENTION: &str = "-24 hours";
+
+/// How long a key may stay claimed without a response before a retry may take it over, e.g. after a crash.
+/// The response is stored in the transaction of the request's writes, so a key without one has changed nothing
+const ABANDONED_AFTER: &str = "-1 minutes";
+
+/// What to do with a request carrying a key
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    New(i64), // First use of the key, run the request and complete the claim with its response
+    Replay(u16, String), // Status and body of the first response
+    InProgress, // The first request with the key has not finished yet
+    Mismatch, // The key was used for another request
+}
+
+/// Keys are chosen by clients, e.g. a UUID, and kept short and printable
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= 255 && key.chars().all(|c| c.is_ascii_graphic())
+}
+
+/// Claim a key for a request, request identifies what is done e.g. the method, path and body
+pub fn claim(conn: &Connection, key: &str, request: &str) -> rusqlite::Result<Claim> {
+    conn.execute(
+        "DELETE FROM idempotency_keys WHERE created_at < datetime('now', ?1) OR (status IS NULL AND created_at < datetime('now', ?2))",
+        params![RETENTION, ABANDONED_AFTER],
+    )?;
+    let claim: i64 = rand::thread_rng().gen();
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO idempotency_keys (key, request, claim, created_at) VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)",
+        params![key, request, claim],
+    )?;
+    if inserted > 0 {
+        return Ok(Claim::New(claim));
+    }
+    let result = conn.query_row(
+        "SELECT request, status, body FROM idempotency_keys WHERE key = ?1",
+        params![key],
+        |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?)),
+    );
+    match result {
+        Ok((stored, _, _)) if stored != request => Ok(Claim::Mismatch),
+        Ok((_, Some(status), Some(body))) => Ok(Claim::Replay(status, body)),
+        Ok(_) => Ok(Claim::InProgress),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(Claim::InProgress), // Released between the insert and the select
+        Err(err) => Err(err),
+    }
+}
+
+/// Store the response of a claim so repeats replay it, run it in the transaction of the request's writes.
+/// False when the claim was taken over by a retry in the meantime, the writes must then be rolled back
+pub fn complete(conn: &Connection, key: &str, claim: i64, status: u16, body: &str) -> rusqlite::Result<bool> {
+    let updated = conn.execute(
+        "UPDATE idempotency_keys SET status = ?1, body = ?2 WHERE key = ?3 AND claim = ?4 AND status IS NULL",
+        params![status, body, key, claim],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Give a claimed key up when its request failed, so a retry runs the request again
+pub fn release(conn: &Connection, key: &str, claim: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM idempotency_keys WHERE key = ?1 AND claim = ?2 AND status IS NULL", params![key, claim])?;
+    Ok(())
+}
@@ -1,21 +1,97 @@
 // src/main.rs
+#![recursion_limit = "256"]
 mod models;
 mod handlers;
 mod db;
 mod routes;
+mod payments;
+mod promotions;
+mod errors;
+mod validation;
+mod pagination;
+mod idempotency;
+mod preconditions;
+mod openapi;
+mod versions;
+mod events;
+mod graphql;
+mod grpc;
+mod websocket;
+mod sse;
+mod webhooks;
+mod history;
+mod audit;
+mod menu_files;
+use std::convert::Infallible;
+use warp::hyper::service::{make_service_fn, service_fn};
+use warp::hyper::Server;
 use warp::Filter;
 
 #[tokio::main]
 async fn main() {
     // Initialize DB
     db::initialize_db();
-    
+
+    // `rebuild` regenerates the orders and their items from the events instead of serving
+    if std::env::args().nth(1).as_deref() == Some("rebuild") {
+        let rebuilt = history::rebuild(&db::get_db_conn()).expect("Failed to rebuild the orders");
+        println!("Rebuilt {} orders with {} items, removed {} deleted orders", rebuilt.orders, rebuilt.items, rebuilt.deleted);
+        return;
+    }
+
+    // `import-menus <file> [--dry-run]` imports menus from a CSV or JSON file instead of serving
+    if std::env::args().nth(1).as_deref() == Some("import-menus") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let dry_run = args.iter().any(|arg| arg == "--dry-run");
+        let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) else {
+            eprintln!("Usage: import-menus <file.csv|file.json> [--dry-run]");
+            std::process::exit(2);
+        };
+        let bytes = std::fs::read(path).unwrap_or_else(|err| panic!("Failed to read {}: {}", path, err));
+        let report = menu_files::parse(models::MenuFormat::from_path(path), &bytes)
+            .and_then(|rows| menu_files::import(&db::get_db_conn(), rows, dry_run))
+            .unwrap_or_else(|err| panic!("Failed to import {}: {}", path, err.envelope().message));
+        for row in report.rows.iter().filter(|row| !row.errors.is_empty()) {
+            for error in &row.errors {
+                println!("Row {}: {}: {}", row.row, error.field, error.message);
+            }
+        }
+        let outcome = match (report.applied, report.dry_run) {
+            (true, _) => "Imported",
+            (false, true) => "Dry run, would import",
+            (false, false) => "Nothing imported, the rows above are invalid. Would import",
+        };
+        println!("{}: {} created, {} updated, {} unchanged, {} invalid", outcome, report.created, report.updated, report.unchanged, report.invalid);
+        return;
+    }
+
     // Combine all routes
     let routes = routes::restaurent_routes();
 
-    // Start the warp server
+    // Start the gRPC server for in-store devices next to it
+    tokio::spawn(async {
+        if let Err(err) = grpc::serve(grpc::address()).await {
+            eprintln!("gRPC server stopped: {}", err);
+        }
+    });
+
+    // Publish the events committed to the outbox to the live subscribers
+    tokio::spawn(events::dispatch(db::get_db_conn));
+
+    // Delete the audit log entries past their retention
+    tokio::spawn(audit::prune_periodically(db::get_db_conn));
+
+    // Send the order events to the webhooks in the background
+    tokio::spawn(webhooks::run(webhooks::Dispatcher::new(db::get_db_conn, webhooks::RetryPolicy::default())));
+
+    // Start the warp server, requests that change something are served in their audit context
     println!("Running the server");
-    warp::serve(routes.with(warp::trace::request()))
-        .run(([127, 0, 0, 1], 3030))
-        .await;
+    let service = warp::service(routes.with(warp::trace::request()));
+    let make_service = make_service_fn(move |_| {
+        let service = service.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| audit::serve(service.clone(), db::get_db_conn, req))) }
+    });
+    if let Err(err) = Server::bind(&([127, 0, 0, 1], 3030).into()).serve(make_service).await {
+        eprintln!("Server stopped: {}", err);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,158 @@
+// src/menu_files.rs
+use crate::audit::{self, Entity};
+use crate::errors::ApiError;
+use crate::events::{self, EventKind};
+use crate::models::{Menu, MenuFormat, MenuImportAction, MenuImportResponse, MenuImportRowResponse, MenuResponse};
+use crate::validation::{field_errors, FieldError};
+use rusqlite::Connection;
+use serde_json::Value;
+use std::collections::HashMap;
+use validator::Validate;
+
+/// Columns of a CSV file, in the order they are exported
+const COLUMNS: [&str; 4] = ["name", "price", "category", "station"];
+
+/// A row of a file, or why it could not be read
+pub type Row = Result<Menu, Vec<FieldError>>;
+
+/// Read the menus of a file row by row. A file that can't be read at all is refused
+pub fn parse(format: MenuFormat, bytes: &[u8]) -> Result<Vec<Row>, ApiError> {
+    match format {
+        MenuFormat::Csv => parse_csv(bytes),
+        MenuFormat::Json => parse_json(bytes),
+    }
+}
+
+fn parse_csv(bytes: &[u8]) -> Result<Vec<Row>, ApiError> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(bytes);
+    let headers = reader.headers().map_err(|err| ApiError::bad_request(format!("Unreadable CSV header: {}", err)))?.clone();
+    if let Some(unknown) = headers.iter().find(|column| !COLUMNS.contains(column)) {
+        return Err(ApiError::bad_request(format!("Unknown column {}, the columns are name, price, category and station", unknown)));
+    }
+    if !headers.iter().any(|column| column == "name") {
+        return Err(ApiError::bad_request("The name column is missing"));
+    }
+    let rows = reader.records().map(|record| {
+        let record = record.map_err(|err| vec![FieldError::new("row", "unreadable", err.to_string())])?;
+        record.deserialize::<Menu>(Some(&headers)).map_err(|err| {
+            let error = match err.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => {
+                    let column = err.field().and_then(|index| headers.get(index as usize)).unwrap_or("row");
+                    FieldError::new(column, "type", err.kind().to_string())
+                }
+                _ => FieldError::new("row", "unreadable", err.to_string()),
+            };
+            vec![error]
+        })
+    });
+    Ok(rows.collect())
+}
+
+fn parse_json(bytes: &[u8]) -> Result<Vec<Row>, ApiError> {
+    let values: Vec<Value> = serde_json::from_slice(bytes).map_err(|err| ApiError::bad_request(format!("Expected a JSON array of menus: {}", err)))?;
+    let rows = values.into_iter().map(|value| serde_json::from_value::<Menu>(value).map_err(|err| vec![FieldError::new("row", "invalid", err.to_string())]));
+    Ok(rows.collect())
+}
+
+/// Import the rows of a file in one transaction. Each is matched by name to a menu that is not archived, as when
+/// creating one, which is updated if it differs, otherwise a menu is created. Nothing is written on a dry run
+/// or if any row is invalid, the report tells what the import does to every row either way
+pub fn import(conn: &Connection, rows: Vec<Row>, dry_run: bool) -> Result<MenuImportResponse, ApiError> {
+    let tx = conn.unchecked_transaction()?;
+    let mut report = MenuImportResponse { dry_run, applied: false, created: 0, updated: 0, unchanged: 0, invalid: 0, rows: Vec::new() };
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        let number = index + 1;
+        let name = row.as_ref().ok().map(|menu| menu.name.clone());
+        let checked = row.and_then(|menu| {
+            menu.validate().map_err(|errors| field_errors(&errors))?;
+            match names.insert(menu.name.clone(), number) {
+                Some(first) => Err(vec![FieldError::new("name", "duplicate", format!("Same name as row {}", first))]),
+                None => Ok(menu),
+            }
+        });
+        let menu = match checked {
+            Ok(menu) => menu,
+            Err(errors) => {
+                report.invalid += 1;
+                report.rows.push(MenuImportRowResponse { row: number, name, action: MenuImportAction::Invalid, menu_id: None, changes: Value::Null, errors });
+                continue;
+            }
+        };
+        let after = serde_json::to_value(&menu).unwrap_or_default();
+        let (action, menu_id, changes) = match Menu::get_existing_menu_id(&tx, &menu)? {
+            Some(menu_id) => {
+                let before = Menu::get(&tx, menu_id)?.map(as_imported).and_then(|before| serde_json::to_value(before).ok()).unwrap_or_default();
+                let changes = audit::diff(&before, &after);
+                match changes.is_empty() {
+                    true => (MenuImportAction::Unchanged, Some(menu_id), changes),
+                    false => {
+                        if !dry_run {
+                            let snapshot = audit::snapshot(&tx, Entity::Menu, menu_id)?;
+                            Menu::update(&tx, menu_id, &menu, None)?;
+                            events::record_entity(&tx, EventKind::MenuUpdated, menu_id)?;
+                            audit::record(&tx, Entity::Menu, menu_id, snapshot)?;
+                        }
+                        (MenuImportAction::Update, Some(menu_id), changes)
+                    }
+                }
+            }
+            None => {
+                let menu_id = match dry_run {
+                    true => None,
+                    false => {
+                        let menu_id = Menu::create(&tx, &menu)?;
+                        events::record_entity(&tx, EventKind::MenuCreated, menu_id)?;
+                        audit::record(&tx, Entity::Menu, menu_id, None)?;
+                        Some(menu_id)
+                    }
+                };
+                (MenuImportAction::Create, menu_id, audit::diff(&Value::Null, &after))
+            }
+        };
+        match action {
+            MenuImportAction::Create => report.created += 1,
+            MenuImportAction::Update => report.updated += 1,
+            _ => report.unchanged += 1,
+        }
+        report.rows.push(MenuImportRowResponse { row: number, name, action, menu_id, changes: Value::Object(changes), errors: Vec::new() });
+    }
+    if dry_run || report.invalid > 0 {
+        // Menus made before an invalid row were rolled back with it
+        for row in report.rows.iter_mut().filter(|row| row.action == MenuImportAction::Create) {
+            row.menu_id = None;
+        }
+        return Ok(report);
+    }
+    tx.commit()?;
+    events::committed();
+    report.applied = true;
+    Ok(report)
+}
+
+/// The menus that are not archived as a file of a format, which can be imported again
+pub fn export(conn: &Connection, format: MenuFormat) -> Result<Vec<u8>, ApiError> {
+    let menus: Vec<Menu> = Menu::list_active(conn)?.into_iter().map(as_imported).collect();
+    match format {
+        MenuFormat::Json => serde_json::to_vec_pretty(&menus).map_err(|err| ApiError::Internal(err.to_string())),
+        MenuFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            let write = |writer: &mut csv::Writer<Vec<u8>>| -> csv::Result<()> {
+                writer.write_record(COLUMNS)?;
+                for menu in &menus {
+                    let price = menu.price.to_string();
+                    writer.write_record([menu.name.as_str(), &price, menu.category.as_deref().unwrap_or(""), menu.station.as_deref().unwrap_or("")])?;
+                }
+                writer.flush()?;
+                Ok(())
+            };
+            write(&mut writer).map_err(|err| ApiError::Internal(err.to_string()))?;
+            writer.into_inner().map_err(|err| ApiError::Internal(err.to_string()))
+        }
+    }
+}
+
+/// A menu with the fields a file has
+fn as_imported(menu: MenuResponse) -> Menu {
+    Menu { id: menu.id, name: menu.name, price: menu.price, category: menu.category, station: menu.station }
+}
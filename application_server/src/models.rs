@@ -1,60 +1,387 @@
 // src/models.rs
-use rusqlite::params;
-use serde;
+use rusqlite::{params, params_from_iter};
 use rusqlite::Connection;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use crate::promotions;
+use crate::errors::ErrorEnvelope;
+use crate::pagination::{CursorValue, PageRequest, SortColumn};
+use crate::validation::{FieldError, code_chars, printable, positive_ids, order_status, timestamp, time_of_day, webhook_url, event_kinds, invalid_field};
+use validator::ValidationError;
+use validator::Validate;
+use schemars::JsonSchema;
+use async_graphql::SimpleObject;
+
+/// Tell a missing field (None) apart from an explicit null (Some(None)) in PATCH bodies
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
 
 /// For Creating a Table from Request
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Table {
     #[serde(skip)]
+    #[allow(dead_code)]
     pub id: i64,
+    #[validate(length(min = 1, max = 16), custom(function = code_chars))]
     pub code: String,
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub section: Option<String>, // e.g. terrace or bar, service charge rules can be set per section
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub table_type: Option<String>, // e.g. booth or high-top, service charge rules can be set per type
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub server: Option<String>, // Staff member serving the table, tips are attributed to them
 }
 
 /// For Table Response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, SimpleObject)]
+#[graphql(name = "Table", complex)]
 pub struct TableResponse {
     pub id: i64,
     pub code: String,
+    pub section: Option<String>,
+    pub table_type: Option<String>,
+    pub server: Option<String>,
+    pub archived: bool, // Archived tables are kept for their orders but hidden and can't be ordered on
+    pub version: i64, // Goes up on every change, sent as the ETag
+}
+
+/// For Updating part of a Table from Request, missing fields are kept and null clears a field
+#[derive(Debug, Default, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TablePatch {
+    #[serde(default)]
+    #[validate(length(min = 1, max = 16), custom(function = code_chars))]
+    pub code: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub section: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub table_type: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub server: Option<Option<String>>,
+}
+
+/// What happened to a table or menu on delete
+#[derive(Debug, PartialEq)]
+pub enum DeleteOutcome {
+    Deleted,
+    Archived, // Still referenced by orders, so kept but hidden
+    NotFound,
+}
+
+/// For Assigning a Server to a Table from Request, null unassigns
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TableServerRequest {
+    #[validate(length(max = 50), custom(function = printable))]
+    pub server: Option<String>,
 }
 
 /// For Creating a Menu from Request
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Menu {
     #[serde(skip)]
+    #[allow(dead_code)]
     pub id: i64,
+    #[validate(length(min = 1, max = 100), custom(function = printable))]
     pub name: String,
+    #[serde(default)]
+    #[validate(range(min = 0, max = 1_000_000))]
+    pub price: i64, // Price in cents
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub category: Option<String>,
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub station: Option<String>, // Kitchen station preparing it, e.g. grill or bar
+}
+
+/// Formats menus are imported from and exported to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MenuFormat {
+    Csv, // With a header of name, price, category and station
+    Json, // An array of menus as POST /menus takes them
+}
+
+impl MenuFormat {
+    /// The format of an upload without a format parameter, CSV if it is sent as text/csv
+    pub fn from_content_type(content_type: Option<&str>) -> MenuFormat {
+        match content_type.is_some_and(|content_type| content_type.trim_start().starts_with("text/csv")) {
+            true => MenuFormat::Csv,
+            false => MenuFormat::Json,
+        }
+    }
+
+    /// The format of a file by its extension, JSON unless it ends in .csv
+    pub fn from_path(path: &str) -> MenuFormat {
+        match path.to_ascii_lowercase().ends_with(".csv") {
+            true => MenuFormat::Csv,
+            false => MenuFormat::Json,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            MenuFormat::Csv => "text/csv; charset=utf-8",
+            MenuFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            MenuFormat::Csv => "csv",
+            MenuFormat::Json => "json",
+        }
+    }
+}
+
+/// Query parameters for importing menus
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MenuImportQuery {
+    pub format: Option<MenuFormat>, // From the Content-Type if not given
+    #[serde(default)]
+    pub dry_run: bool, // Only report what the import would do
+}
+
+/// Query parameters for exporting menus
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MenuExportQuery {
+    pub format: Option<MenuFormat>, // json if not given
+}
+
+/// What importing a row does to the menus
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MenuImportAction {
+    Create,
+    Update,
+    Unchanged,
+    Invalid,
+}
+
+/// For Menu Import Row Response
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct MenuImportRowResponse {
+    pub row: usize, // From 1, the header of a CSV file is not counted
+    pub name: Option<String>,
+    pub action: MenuImportAction,
+    pub menu_id: Option<i64>, // The menu matched by name, or made once the import is applied
+    pub changes: serde_json::Value, // By JSON pointer, e.g. {"/price": {"before": 1200, "after": 1300}}. A new menu is one change at ""
+    pub errors: Vec<FieldError>,
+}
+
+/// For Menu Import Response
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct MenuImportResponse {
+    pub dry_run: bool,
+    pub applied: bool, // False on a dry run or if a row is invalid, then nothing is written
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub invalid: usize,
+    pub rows: Vec<MenuImportRowResponse>,
 }
 
 /// For Menu Response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, SimpleObject)]
+#[graphql(name = "Menu")]
 pub struct MenuResponse {
     pub id: i64,
     pub name: String,
+    pub price: i64,
+    pub category: Option<String>,
+    pub station: Option<String>,
+    pub archived: bool, // Archived menus are kept for their order items but hidden and can't be ordered
+    pub version: i64, // Goes up on every change, sent as the ETag
+}
+
+/// For Updating part of a Menu from Request, missing fields are kept and null clears the category or station
+#[derive(Debug, Default, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MenuPatch {
+    #[serde(default)]
+    #[validate(length(min = 1, max = 100), custom(function = printable))]
+    pub name: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 0, max = 1_000_000))]
+    pub price: Option<i64>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub category: Option<Option<String>>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub station: Option<Option<String>>,
 }
 
 /// For Creating a Order from Request
-#[derive(Debug, Serialize, Deserialize)]
+/// Items can be sent as plain menu_ids, one unit each, or as lines carrying a quantity, a note and a seat number
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct OrderRequestBody {
+    #[serde(default)] // Not needed when the table is part of the path
+    #[validate(range(min = 1))]
     pub table_id: i64,
+    #[serde(default)]
+    #[validate(length(max = 100), custom(function = positive_ids))]
     pub menu_ids: Vec<i64>,
+    #[serde(default)]
+    #[validate(length(max = 100), nested)]
+    pub items: Vec<OrderLine>,
+    #[serde(default)]
+    #[validate(range(min = 1, max = 100))]
+    pub party_size: Option<i64>, // Number of guests, defaults to the number of seats ordered for
+}
+
+/// A single line of an Order Request, optionally tagged with the seat it belongs to
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OrderLine {
+    #[validate(range(min = 1))]
+    pub menu_id: i64,
+    #[serde(default = "default_quantity")]
+    #[validate(range(min = 1, max = 100))]
+    pub quantity: i64,
+    #[serde(default)]
+    #[validate(length(max = 200), custom(function = printable))]
+    pub note: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 1, max = 100))]
+    pub seat: Option<i64>,
+}
+
+fn default_quantity() -> i64 {
+    1
+}
+
+/// Most units of a menu on one line of an order, also when repeated lines are merged
+pub const MAX_ITEM_QUANTITY: i64 = 100;
+
+/// For Setting the Quantity of an Order Item from Request
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OrderItemPatch {
+    #[validate(range(min = 1, max = 100))]
+    pub quantity: i64,
+}
+
+/// How a batch goes on after an operation fails
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    #[default]
+    AllOrNothing, // Nothing is kept, the operations after it are skipped
+    ContinueOnError, // Only the failed operation is undone
+}
+
+/// One operation of a batch, with the semantics of the route it is named after
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BatchOperation {
+    AddItems(OrderRequestBody), // POST /orders
+    RemoveItem { order_id: i64, item_id: i64, #[serde(default)] if_match: Option<String> }, // DELETE /orders/{order_id}/items/{item_id}
+    SetQuantity { order_id: i64, item_id: i64, quantity: i64, #[serde(default)] if_match: Option<String> }, // PATCH /orders/{order_id}/items/{item_id}
+    MoveOrder { order_id: i64, table_id: i64, #[serde(default)] if_match: Option<String> },
+}
+
+impl BatchOperation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchOperation::AddItems(_) => "add_items",
+            BatchOperation::RemoveItem { .. } => "remove_item",
+            BatchOperation::SetQuantity { .. } => "set_quantity",
+            BatchOperation::MoveOrder { .. } => "move_order",
+        }
+    }
+}
+
+/// For Running Several Operations in One Transaction from Request
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub mode: BatchMode,
+    #[validate(length(min = 1, max = 50))]
+    pub operations: Vec<BatchOperation>,
+}
+
+/// What became of an operation of a batch
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOutcome {
+    Applied,
+    Failed,
+    RolledBack, // It succeeded but another operation failed in all_or_nothing mode
+    Skipped, // It was not run because an earlier operation failed in all_or_nothing mode
+}
+
+/// For Batch Operation Result Response, in the order of the operations
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct BatchResultResponse {
+    pub index: usize,
+    pub op: String,
+    pub outcome: BatchOutcome,
+    pub status: Option<u16>, // The status the route would have answered with, not set for skipped operations
+    pub body: Option<serde_json::Value>, // What the route would have answered on success
+    pub error: Option<ErrorEnvelope>,
+}
+
+/// For Batch Response
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct BatchResponse {
+    pub mode: BatchMode,
+    pub committed: bool,
+    pub results: Vec<BatchResultResponse>,
 }
 
 /// For Order Response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct OrderResponse {
     pub id: i64,
     pub table_id: i64,
     pub table_name: String,
+    pub status: String, // open until fully paid, then closed
+    pub party_size: Option<i64>,
+    pub created_at: String,
+    pub version: i64, // Goes up on every change to the order, its items, coupons, discounts and payments. Sent as the ETag
     pub total_cooking_time: i32, // Property calculated based on order_items
     pub menus: Vec<OrderItemResponse>, 
+    pub subtotal: i64,
+    pub adjustments: Vec<AdjustmentResponse>, // Promotions and discounts applied to the order
+    pub service_charge: i64,
+    pub total: i64,
+    pub tips: i64,
+}
+
+/// An order without its items and bill figures, for readers that load those in batches
+#[derive(Debug, Clone, SimpleObject)]
+#[graphql(name = "Order", complex)]
+pub struct OrderSummary {
+    pub id: i64,
+    pub table_id: i64,
+    pub table_name: String,
+    pub status: String,
+    pub party_size: Option<i64>,
+    pub created_at: String,
+    pub version: i64,
 }
 
 /// For OrderItem creation from Request
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct OrderItem {
     #[serde(skip)]
+    #[allow(dead_code)]
     pub id: i64,
     pub order_id: i64,
     pub menu_id: i64,
@@ -62,41 +389,559 @@ pub struct OrderItem {
 }
 
 /// For OrderItem Response
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, SimpleObject)]
+#[graphql(name = "Item", complex)]
 pub struct OrderItemResponse{
     pub id: i64,
     pub order_id: i64,
     pub menu_id: i64,
     pub menu_name: String,
-    pub cooking_time: i64,
+    pub cooking_time: i64, // unit_cooking_time times the quantity
     pub quantity: i64,
+    pub seat: Option<i64>,
+    pub unit_price: i64,
+    pub unit_cooking_time: i64,
+    pub note: Option<String>, // e.g. no onions, the same menu with another note is a separate item
+    pub station: Option<String>, // Kitchen station of the menu
+}
+
+/// For Order Items of a Table grouped by seat
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SeatItemsResponse {
+    pub seat: Option<i64>,
+    pub items: Vec<OrderItemResponse>,
+}
+
+/// Query parameters for listing the items of a Table
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TableItemsQuery {
+    pub group_by: Option<String>,
+}
+
+/// Query parameters to narrow an item lookup down to a single seat
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SeatQuery {
+    pub seat: Option<i64>,
+}
+
+/// Query parameters for the live updates WebSocket
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct LiveUpdatesQuery {
+    pub topics: Option<String>, // Comma separated, table:{id}, kitchen or station:{name}
+    pub resume: Option<String>, // Resume token of the last event received before reconnecting
+}
+
+/// Query parameters for the Server-Sent Events feed
+#[derive(Debug, Default, Serialize, Deserialize, Validate, JsonSchema)]
+pub struct EventStreamQuery {
+    #[validate(range(min = 1))]
+    pub table_id: Option<i64>, // Only the events of this table
+}
+
+/// Query parameters for reading the event log, the events after the id since
+#[derive(Debug, Default, Serialize, Deserialize, Validate, JsonSchema)]
+pub struct EventLogQuery {
+    #[validate(range(min = 0))]
+    pub since: Option<i64>, // 0 if not given, the id of the last event already read
+    #[validate(range(min = 1, max = 1000))]
+    pub limit: Option<i64>, // 100 if not given
+    #[validate(range(min = 1))]
+    pub table_id: Option<i64>,
+    #[validate(range(min = 1))]
+    pub order_id: Option<i64>,
+}
+
+/// Events read per page of the log if no limit is given
+pub const EVENT_LOG_LIMIT: i64 = 100;
+
+/// For Logged Event Response, a change as it was recorded with the change
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct LoggedEventResponse {
+    pub id: i64,
+    pub kind: String,
+    pub table_id: Option<i64>, // Null for changes to menus, promotions and service charge rules
+    pub order_id: Option<i64>,
+    pub item_id: Option<i64>,
+    pub entity_id: Option<i64>, // The menu, payment, coupon promotion, discount, promotion or rule that changed
+    pub station: Option<String>,
+    pub item: Option<OrderItemResponse>, // For removals the item as it was before
+    pub created_at: String,
+    pub published_at: Option<String>, // Null until it was published to live subscribers
+}
+
+/// For Event Log Response, read the next page with since set to last_id
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EventLogResponse {
+    pub events: Vec<LoggedEventResponse>,
+    pub last_id: i64,
+    pub has_more: bool,
+}
+
+/// Functions for Logged Event Response
+impl LoggedEventResponse {
+
+    /// Events after an id, oldest first. One row more than the limit is read to tell if there are more
+    pub fn since(conn: &Connection, query: &EventLogQuery) -> rusqlite::Result<EventLogResponse> {
+        let since = query.since.unwrap_or(0);
+        let limit = query.limit.unwrap_or(EVENT_LOG_LIMIT);
+        let mut stmt = conn.prepare(
+            "SELECT id, kind, table_id, order_id, item_id, entity_id, station, item, created_at, published_at FROM domain_events
+            WHERE id > ?1 AND (?2 IS NULL OR table_id = ?2) AND (?3 IS NULL OR order_id = ?3)
+            ORDER BY id LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(params![since, query.table_id, query.order_id, limit + 1], |row| {
+            let item: Option<String> = row.get(7)?;
+            Ok(LoggedEventResponse {
+                id: row.get(0)?,
+                kind: row.get(1)?,
+                table_id: row.get(2)?,
+                order_id: row.get(3)?,
+                item_id: row.get(4)?,
+                entity_id: row.get(5)?,
+                station: row.get(6)?,
+                item: item.and_then(|item| serde_json::from_str(&item).ok()),
+                created_at: row.get(8)?,
+                published_at: row.get(9)?,
+            })
+        })?;
+        let mut events: Vec<LoggedEventResponse> = rows.collect::<Result<_, _>>()?;
+        let has_more = events.len() as i64 > limit;
+        events.truncate(limit as usize);
+        let last_id = events.last().map_or(since, |event| event.id);
+        Ok(EventLogResponse { events, last_id, has_more })
+    }
+}
+
+/// An order as it was at a point in time, rebuilt from its events. Every event of an order is logged
+/// with the order as it was right after the change, without the items
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OrderState {
+    pub id: i64,
+    pub table_id: i64,
+    pub status: String,
+    pub party_size: Option<i64>,
+    pub created_at: String,
+    pub closed_at: Option<String>,
+    // Figures of the final bill, set once the order is closed
+    pub subtotal: Option<i64>,
+    pub discounts: Option<i64>,
+    pub service_charge: Option<i64>,
+    pub total: Option<i64>,
+    #[serde(default)]
+    pub items: Vec<OrderItemState>,
+}
+
+/// An item of an order as it was, with when it was first ordered
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OrderItemState {
+    #[serde(flatten)]
+    pub item: OrderItemResponse,
+    pub ordered_at: String,
+}
+
+/// Query parameters for the orders of a table at a point in time
+#[derive(Debug, Default, Serialize, Deserialize, Validate, JsonSchema)]
+pub struct TableHistoryQuery {
+    #[validate(custom(function = timestamp))]
+    pub as_of: Option<String>, // YYYY-MM-DD HH:MM:SS in UTC, now if not given
+}
+
+/// For Table History Response
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TableHistoryResponse {
+    pub table_id: i64,
+    pub as_of: String,
+    pub orders: Vec<OrderState>, // The orders of the table at that time, the newest first. Deleted ones are left out
+}
+
+/// Functions for Order State
+impl OrderState {
+
+    /// The order as it is now without its items, None if the order does not exist
+    pub fn header(conn: &Connection, order_id: i64) -> rusqlite::Result<Option<OrderState>> {
+        let result = conn.query_row(
+            "SELECT id, table_id, status, party_size, created_at, closed_at, subtotal, discounts, service_charge, total FROM orders WHERE id = ?1",
+            params![order_id],
+            |row| Ok(OrderState {
+                id: row.get(0)?,
+                table_id: row.get(1)?,
+                status: row.get(2)?,
+                party_size: row.get(3)?,
+                created_at: row.get(4)?,
+                closed_at: row.get(5)?,
+                subtotal: row.get(6)?,
+                discounts: row.get(7)?,
+                service_charge: row.get(8)?,
+                total: row.get(9)?,
+                items: Vec::new(),
+            }),
+        );
+        match result {
+            Ok(order) => Ok(Some(order)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Write the order and its items to the orders and order_items tables, keeping their ids.
+    /// Items of the order that are not in the state are deleted
+    pub fn project(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT INTO orders (id, table_id, status, party_size, created_at, closed_at, subtotal, discounts, service_charge, total)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            ON CONFLICT (id) DO UPDATE SET table_id = excluded.table_id, status = excluded.status, party_size = excluded.party_size,
+            created_at = excluded.created_at, closed_at = excluded.closed_at, subtotal = excluded.subtotal, discounts = excluded.discounts,
+            service_charge = excluded.service_charge, total = excluded.total",
+            params![self.id, self.table_id, self.status, self.party_size, self.created_at, self.closed_at, self.subtotal, self.discounts, self.service_charge, self.total],
+        )?;
+        conn.execute("DELETE FROM order_items WHERE order_id = ?1", params![self.id])?;
+        for OrderItemState { item, ordered_at } in &self.items {
+            conn.execute(
+                "INSERT INTO order_items (id, order_id, menu_id, unit_cooking_time, cooking_time, quantity, seat, note, unit_price, ordered_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![item.id, self.id, item.menu_id, item.unit_cooking_time, item.cooking_time, item.quantity, item.seat, item.note, item.unit_price, ordered_at],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Query parameters for reading the audit log, a page at a time
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Validate, JsonSchema)]
+pub struct AuditQuery {
+    pub limit: Option<i64>,
+    #[serde(skip_serializing)]
+    pub after: Option<String>,
+    pub sort: Option<String>, // -id, the newest first, if not given or id
+    #[validate(length(min = 1, max = 50))]
+    pub entity_type: Option<String>, // table, menu, order, payment, sub_bill or webhook
+    #[validate(range(min = 1))]
+    pub entity_id: Option<i64>,
+    #[validate(length(min = 1, max = 100))]
+    pub actor: Option<String>,
+    #[validate(custom(function = timestamp))]
+    pub from: Option<String>, // Inclusive, YYYY-MM-DD or YYYY-MM-DD HH:MM:SS in UTC
+    #[validate(custom(function = timestamp))]
+    pub to: Option<String>, // Exclusive
+}
+
+/// Columns the audit log can be sorted by
+pub const AUDIT_SORTS: &[SortColumn] = &[SortColumn { name: "id", expr: "id" }];
+
+/// For Audit Entry Response, a request that changed something or tried to
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AuditEntryResponse {
+    pub id: i64,
+    pub request_id: String,
+    pub actor: Option<String>, // X-Staff-Id of the request
+    pub device: Option<String>, // X-Device-Id of the request, or its User-Agent
+    pub method: String,
+    pub path: String,
+    pub status: Option<i64>, // None until the request that made the change was answered
+    pub entity_type: Option<String>,
+    pub entity_id: Option<i64>,
+    pub before: Option<serde_json::Value>, // The entity as the API showed it before and after the request
+    pub after: Option<serde_json::Value>,
+    pub diff: serde_json::Value, // What changed by JSON pointer, e.g. {"/code": {"before": "T-01", "after": "T-02"}}
+    pub created_at: String,
+}
+
+/// Functions for Audit Entry Response
+impl AuditEntryResponse {
+
+    /// List a page of the audit log, one row more than the limit if there is a next page
+    pub fn list(conn: &Connection, query: &AuditQuery, page: &PageRequest) -> rusqlite::Result<Vec<AuditEntryResponse>> {
+        let sql = format!(
+            "SELECT id, request_id, actor, device, method, path, status, entity_type, entity_id, before, after, diff, created_at FROM audit_log
+            WHERE (?1 IS NULL OR entity_type = ?1)
+            AND (?2 IS NULL OR entity_id = ?2)
+            AND (?3 IS NULL OR actor = ?3)
+            AND (?4 IS NULL OR created_at >= datetime(?4))
+            AND (?5 IS NULL OR created_at < datetime(?5))
+            AND {}
+            ORDER BY {} LIMIT ?8",
+            page.sort.after("id", 6, 7),
+            page.sort.order_by("id"),
+        );
+        let (after_value, after_id) = page.after_params();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            params![query.entity_type, query.entity_id, query.actor, query.from, query.to, after_value, after_id, page.fetch()],
+            |row| {
+                let json = |index: usize| -> rusqlite::Result<Option<serde_json::Value>> {
+                    let value: Option<String> = row.get(index)?;
+                    Ok(value.and_then(|value| serde_json::from_str(&value).ok()))
+                };
+                Ok(AuditEntryResponse {
+                    id: row.get(0)?,
+                    request_id: row.get(1)?,
+                    actor: row.get(2)?,
+                    device: row.get(3)?,
+                    method: row.get(4)?,
+                    path: row.get(5)?,
+                    status: row.get(6)?,
+                    entity_type: row.get(7)?,
+                    entity_id: row.get(8)?,
+                    before: json(9)?,
+                    after: json(10)?,
+                    diff: json(11)?.unwrap_or_default(),
+                    created_at: row.get(12)?,
+                })
+            },
+        )?;
+        rows.collect()
+    }
+
+    /// Value of the sort column and id to continue a listing after this entry
+    pub fn cursor(&self, _sort: &str) -> (CursorValue, i64) {
+        (CursorValue::Int(self.id), self.id)
+    }
+}
+
+/// Query parameters for listing Tables, a page at a time
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Validate, JsonSchema)]
+pub struct TableListQuery {
+    pub limit: Option<i64>,
+    #[serde(skip_serializing)] // Set per page by the next link
+    pub after: Option<String>,
+    pub sort: Option<String>, // id or code, prefixed with - for descending
+    #[validate(length(min = 1, max = 16))]
+    pub code: Option<String>, // Part of the code, case insensitive
+    #[validate(length(min = 1, max = 50))]
+    pub section: Option<String>,
+}
+
+/// Query parameters for listing Menus, a page at a time
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Validate, JsonSchema)]
+pub struct MenuListQuery {
+    pub limit: Option<i64>,
+    #[serde(skip_serializing)]
+    pub after: Option<String>,
+    pub sort: Option<String>, // id, name or price, prefixed with - for descending
+    #[validate(length(min = 1, max = 100))]
+    pub name: Option<String>, // Part of the name, case insensitive
+    #[validate(length(min = 1, max = 50))]
+    pub category: Option<String>,
+}
+
+/// Query parameters for listing Orders, a page at a time
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Validate, JsonSchema)]
+pub struct OrderListQuery {
+    pub limit: Option<i64>,
+    #[serde(skip_serializing)]
+    pub after: Option<String>,
+    pub sort: Option<String>, // id or created_at, prefixed with - for descending
+    #[validate(custom(function = order_status))]
+    pub status: Option<String>,
+    #[validate(range(min = 1))]
+    pub table_id: Option<i64>,
+    #[validate(custom(function = timestamp))]
+    pub created_from: Option<String>, // Inclusive, YYYY-MM-DD or YYYY-MM-DD HH:MM:SS in UTC
+    #[validate(custom(function = timestamp))]
+    pub created_to: Option<String>, // Exclusive
+}
+
+/// Columns Tables can be sorted by, the first is the default
+pub const TABLE_SORTS: &[SortColumn] = &[
+    SortColumn { name: "id", expr: "id" },
+    SortColumn { name: "code", expr: "code" },
+];
+
+/// Columns Menus can be sorted by, the first is the default
+pub const MENU_SORTS: &[SortColumn] = &[
+    SortColumn { name: "id", expr: "id" },
+    SortColumn { name: "name", expr: "name" },
+    SortColumn { name: "price", expr: "price" },
+];
+
+/// Columns Orders can be sorted by, the first is the default
+pub const ORDER_SORTS: &[SortColumn] = &[
+    SortColumn { name: "id", expr: "orders.id" },
+    SortColumn { name: "created_at", expr: "orders.created_at" },
+];
+
+impl TableResponse {
+    /// Value of the sort column and id to continue a listing after this table
+    pub fn cursor(&self, sort: &str) -> (CursorValue, i64) {
+        match sort {
+            "code" => (CursorValue::Text(self.code.clone()), self.id),
+            _ => (CursorValue::Int(self.id), self.id),
+        }
+    }
+}
+
+impl MenuResponse {
+    /// Value of the sort column and id to continue a listing after this menu
+    pub fn cursor(&self, sort: &str) -> (CursorValue, i64) {
+        match sort {
+            "name" => (CursorValue::Text(self.name.clone()), self.id),
+            "price" => (CursorValue::Int(self.price), self.id),
+            _ => (CursorValue::Int(self.id), self.id),
+        }
+    }
+}
+
+impl OrderResponse {
+    /// Value of the sort column and id to continue a listing after this order
+    pub fn cursor(&self, sort: &str) -> (CursorValue, i64) {
+        match sort {
+            "created_at" => (CursorValue::Text(self.created_at.clone()), self.id),
+            _ => (CursorValue::Int(self.id), self.id),
+        }
+    }
+}
+
+impl OrderSummary {
+    /// Value of the sort column and id to continue a listing after this order
+    pub fn cursor(&self, sort: &str) -> (CursorValue, i64) {
+        match sort {
+            "created_at" => (CursorValue::Text(self.created_at.clone()), self.id),
+            _ => (CursorValue::Int(self.id), self.id),
+        }
+    }
+}
+
+impl OrderRequestBody {
+    /// All requested lines, menu_ids are treated as lines without a seat
+    pub fn lines(&self) -> Vec<OrderLine> {
+        self.menu_ids
+            .iter()
+            .map(|menu_id| OrderLine { menu_id: *menu_id, quantity: 1, note: None, seat: None })
+            .chain(self.items.iter().cloned())
+            .collect()
+    }
 }
 
+impl OrderItemResponse {
+    /// Build the response from a row selected with ORDER_ITEM_COLUMNS
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<OrderItemResponse> {
+        Ok(OrderItemResponse {
+            id: row.get(0)?,
+            order_id: row.get(1)?,
+            menu_id: row.get(2)?,
+            menu_name: row.get(3)?,
+            quantity: row.get(4)?,
+            cooking_time: row.get(5)?,
+            seat: row.get(6)?,
+            unit_price: row.get(7)?,
+            unit_cooking_time: row.get(8)?,
+            note: row.get(9)?,
+            station: row.get(10)?,
+        })
+    }
+}
+
+/// Columns selected for an OrderItemResponse, in the order expected by OrderItemResponse::from_row
+const ORDER_ITEM_COLUMNS: &str = "order_items.id, order_items.order_id, order_items.menu_id, m.name, order_items.quantity, order_items.cooking_time, order_items.seat, order_items.unit_price, COALESCE(order_items.unit_cooking_time, order_items.cooking_time / order_items.quantity), order_items.note, m.station";
+
+const ORDER_COLUMNS: &str = "orders.id, orders.table_id, t.code, orders.status, orders.party_size, orders.created_at, orders.version";
+
+/// Placeholders of an IN list of count values, e.g. ?,?,?
+fn in_list(count: usize) -> String {
+    vec!["?"; count].join(",")
+}
+
+/// Prep time of a single unit of an order item. Items stored before it was kept separately fall back to the average
+pub const UNIT_COOKING_TIME: &str = "COALESCE(unit_cooking_time, cooking_time / quantity)";
+
 /// Functions for Table Model
 impl Table {
 
     // Function to create the table
     pub fn create(conn: &rusqlite::Connection, table: &Table) -> rusqlite::Result<i64> {
         conn.execute(
-            "INSERT INTO tables (code) VALUES (?1)",
-            params![table.code],
+            "INSERT INTO tables (code, section, table_type, server) VALUES (?1, ?2, ?3, ?4)",
+            params![table.code, table.section, table.table_type, table.server],
         )?;
         // Get the last inserted row's ID
         let last_inserted_id = conn.last_insert_rowid();
         Ok(last_inserted_id)
     }
 
-    // Function to list all the tables
-    pub fn list(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<TableResponse>> {
-        let mut stmt = conn.prepare("SELECT * FROM tables")?;
-        let rows = stmt.query_map(params![], |row| {
-            Ok(TableResponse {
-                id: row.get(0)?,
-                code: row.get(1)?,
-            })
-        })?;
+    /// List a page of the tables that are not archived, one row more than the limit if there is a next page
+    pub fn list(conn: &rusqlite::Connection, query: &TableListQuery, page: &PageRequest) -> rusqlite::Result<Vec<TableResponse>> {
+        let sql = format!(
+            "SELECT id, code, section, table_type, server, archived_at IS NOT NULL, version FROM tables
+            WHERE archived_at IS NULL
+            AND (?1 IS NULL OR instr(lower(code), lower(?1)) > 0)
+            AND (?2 IS NULL OR section = ?2)
+            AND {}
+            ORDER BY {} LIMIT ?5",
+            page.sort.after("id", 3, 4),
+            page.sort.order_by("id"),
+        );
+        let (after_value, after_id) = page.after_params();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params![query.code, query.section, after_value, after_id, page.fetch()], Table::response_from_row)?;
+
+        rows.collect()
+    }
+
+    /// Get the tables of a batch of ids in one query, archived or not. Missing ids are left out
+    pub fn get_many(conn: &Connection, table_ids: &[i64]) -> rusqlite::Result<Vec<TableResponse>> {
+        let query = format!(
+            "SELECT id, code, section, table_type, server, archived_at IS NOT NULL, version FROM tables WHERE id IN ({})",
+            in_list(table_ids.len()),
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(table_ids), Table::response_from_row)?;
+        rows.collect()
+    }
+
+    /// Get a table, archived or not
+    pub fn get(conn: &Connection, table_id: i64) -> rusqlite::Result<Option<TableResponse>> {
+        let result = conn.query_row(
+            "SELECT id, code, section, table_type, server, archived_at IS NOT NULL, version FROM tables WHERE id = ?1",
+            params![table_id],
+            Table::response_from_row,
+        );
+        match result {
+            Ok(table) => Ok(Some(table)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn response_from_row(row: &rusqlite::Row) -> rusqlite::Result<TableResponse> {
+        Ok(TableResponse {
+            id: row.get(0)?,
+            code: row.get(1)?,
+            section: row.get(2)?,
+            table_type: row.get(3)?,
+            server: row.get(4)?,
+            archived: row.get(5)?,
+            version: row.get(6)?,
+        })
+    }
+
+    /// Replace all fields of a table, only if it is still at the version when one is given.
+    /// Returns false if the table does not exist or is at another version
+    pub fn update(conn: &Connection, table_id: i64, table: &Table, version: Option<i64>) -> rusqlite::Result<bool> {
+        let updated = conn.execute(
+            "UPDATE tables SET code = ?1, section = ?2, table_type = ?3, server = ?4 WHERE id = ?5 AND version = COALESCE(?6, version)",
+            params![table.code, table.section, table.table_type, table.server, table_id, version],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Delete a table, or archive it if orders were taken on it
+    pub fn delete(conn: &Connection, table_id: i64) -> rusqlite::Result<DeleteOutcome> {
+        let referenced: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM orders WHERE table_id = ?1)", params![table_id], |row| row.get(0))?;
+        let (query, outcome) = if referenced {
+            ("UPDATE tables SET archived_at = COALESCE(archived_at, CURRENT_TIMESTAMP) WHERE id = ?1", DeleteOutcome::Archived)
+        } else {
+            ("DELETE FROM tables WHERE id = ?1", DeleteOutcome::Deleted)
+        };
+        match conn.execute(query, params![table_id])? {
+            0 => Ok(DeleteOutcome::NotFound),
+            _ => Ok(outcome),
+        }
+    }
 
-        Ok(rows.map(|result| result.unwrap()).collect())
+    /// Bring back an archived table
+    pub fn restore(conn: &Connection, table_id: i64) -> rusqlite::Result<bool> {
+        let restored = conn.execute("UPDATE tables SET archived_at = NULL WHERE id = ?1 AND archived_at IS NOT NULL", params![table_id])?;
+        Ok(restored > 0)
     }
 
     // Utility Function for Table
@@ -111,7 +956,11 @@ impl Table {
         }
     }
 
-
+    /// Assign a server to a table. Returns false if the table does not exist
+    pub fn assign_server(conn: &Connection, table_id: i64, server: Option<&str>) -> rusqlite::Result<bool> {
+        let updated = conn.execute("UPDATE tables SET server = ?1 WHERE id = ?2", params![server, table_id])?;
+        Ok(updated > 0)
+    }
 }
 
 /// Functions for Menu Model
@@ -119,30 +968,108 @@ impl Menu {
     // Function to create menu item
     pub fn create(conn: &rusqlite::Connection, menu: &Menu) -> rusqlite::Result<i64> {
         conn.execute(
-            "INSERT INTO menus (name) VALUES (?1)",
-            params![menu.name],
+            "INSERT INTO menus (name, price, category, station) VALUES (?1, ?2, ?3, ?4)",
+            params![menu.name, menu.price, menu.category, menu.station],
         )?;
         // Get the last inserted row's ID
         let last_inserted_id = conn.last_insert_rowid();
         Ok(last_inserted_id)
     }
 
-    // Function to list all the menu items
-    pub fn list(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<MenuResponse>> {
-        let mut stmt = conn.prepare("SELECT * FROM menus")?;
-        let rows = stmt.query_map(params![], |row| {
-            Ok(MenuResponse {
-                id: row.get(0)?,
-                name: row.get(1)?,
-            })
-        })?;
+    /// List a page of the menus that are not archived, one row more than the limit if there is a next page
+    pub fn list(conn: &rusqlite::Connection, query: &MenuListQuery, page: &PageRequest) -> rusqlite::Result<Vec<MenuResponse>> {
+        let sql = format!(
+            "SELECT id, name, price, category, station, archived_at IS NOT NULL, version FROM menus
+            WHERE archived_at IS NULL
+            AND (?1 IS NULL OR instr(lower(name), lower(?1)) > 0)
+            AND (?2 IS NULL OR category = ?2)
+            AND {}
+            ORDER BY {} LIMIT ?5",
+            page.sort.after("id", 3, 4),
+            page.sort.order_by("id"),
+        );
+        let (after_value, after_id) = page.after_params();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params![query.name, query.category, after_value, after_id, page.fetch()], Menu::response_from_row)?;
+
+        rows.collect()
+    }
+
+    /// All the menus that are not archived, by id
+    pub fn list_active(conn: &Connection) -> rusqlite::Result<Vec<MenuResponse>> {
+        let mut stmt = conn.prepare("SELECT id, name, price, category, station, archived_at IS NOT NULL, version FROM menus WHERE archived_at IS NULL ORDER BY id")?;
+        let rows = stmt.query_map([], Menu::response_from_row)?;
+        rows.collect()
+    }
+
+    /// Get the menus of a batch of ids in one query, archived or not. Missing ids are left out
+    pub fn get_many(conn: &Connection, menu_ids: &[i64]) -> rusqlite::Result<Vec<MenuResponse>> {
+        let query = format!(
+            "SELECT id, name, price, category, station, archived_at IS NOT NULL, version FROM menus WHERE id IN ({})",
+            in_list(menu_ids.len()),
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(menu_ids), Menu::response_from_row)?;
+        rows.collect()
+    }
+
+    /// Get a menu, archived or not
+    pub fn get(conn: &Connection, menu_id: i64) -> rusqlite::Result<Option<MenuResponse>> {
+        let result = conn.query_row(
+            "SELECT id, name, price, category, station, archived_at IS NOT NULL, version FROM menus WHERE id = ?1",
+            params![menu_id],
+            Menu::response_from_row,
+        );
+        match result {
+            Ok(menu) => Ok(Some(menu)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn response_from_row(row: &rusqlite::Row) -> rusqlite::Result<MenuResponse> {
+        Ok(MenuResponse {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            price: row.get(2)?,
+            category: row.get(3)?,
+            station: row.get(4)?,
+            archived: row.get(5)?,
+            version: row.get(6)?,
+        })
+    }
+
+    /// Replace all fields of a menu, only if it is still at the version when one is given. Items already ordered
+    /// keep the price they were ordered at. Returns false if the menu does not exist or is at another version
+    pub fn update(conn: &Connection, menu_id: i64, menu: &Menu, version: Option<i64>) -> rusqlite::Result<bool> {
+        let updated = conn.execute(
+            "UPDATE menus SET name = ?1, price = ?2, category = ?3, station = ?4 WHERE id = ?5 AND version = COALESCE(?6, version)",
+            params![menu.name, menu.price, menu.category, menu.station, menu_id, version],
+        )?;
+        Ok(updated > 0)
+    }
 
-        Ok(rows.map(|result| result.unwrap()).collect())
+    /// Delete a menu, or archive it if it was ordered or a promotion refers to it
+    pub fn delete(conn: &Connection, menu_id: i64) -> rusqlite::Result<DeleteOutcome> {
+        let referenced: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM order_items WHERE menu_id = ?1) OR EXISTS (SELECT 1 FROM promotions WHERE menu_id = ?1)",
+            params![menu_id],
+            |row| row.get(0),
+        )?;
+        let (query, outcome) = if referenced {
+            ("UPDATE menus SET archived_at = COALESCE(archived_at, CURRENT_TIMESTAMP) WHERE id = ?1", DeleteOutcome::Archived)
+        } else {
+            ("DELETE FROM menus WHERE id = ?1", DeleteOutcome::Deleted)
+        };
+        match conn.execute(query, params![menu_id])? {
+            0 => Ok(DeleteOutcome::NotFound),
+            _ => Ok(outcome),
+        }
     }
 
     // Utility Function for Table
     pub fn get_existing_menu_id(conn: &Connection, menu: &Menu) -> Result<Option<i64>, rusqlite::Error> {
-        let query = "SELECT id FROM menus WHERE name = ?1";
+        let query = "SELECT id FROM menus WHERE name = ?1 AND archived_at IS NULL";
         let mut stmt = conn.prepare(query)?;
         let mut rows = stmt.query(params![menu.name])?;
         if let Some(row) = rows.next()? {
@@ -151,6 +1078,23 @@ impl Menu {
             Ok(None)
         }
     }
+
+    /// The archived menu of this name, the last archived if there are several
+    pub fn get_archived_menu_id(conn: &Connection, menu: &Menu) -> rusqlite::Result<Option<i64>> {
+        let query = "SELECT id FROM menus WHERE name = ?1 AND archived_at IS NOT NULL ORDER BY archived_at DESC, id DESC LIMIT 1";
+        let mut stmt = conn.prepare(query)?;
+        let mut rows = stmt.query(params![menu.name])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Bring back an archived menu
+    pub fn restore(conn: &Connection, menu_id: i64) -> rusqlite::Result<bool> {
+        let restored = conn.execute("UPDATE menus SET archived_at = NULL WHERE id = ?1 AND archived_at IS NOT NULL", params![menu_id])?;
+        Ok(restored > 0)
+    }
 }
 
 /// Functions for Order Model
@@ -161,7 +1105,7 @@ impl OrderResponse {
     // Create Function for Order Model
     pub fn create(conn: &rusqlite::Connection, table_id: i64) -> rusqlite::Result<i64> {
         conn.execute(
-            "INSERT INTO orders (table_id) VALUES (?1)",
+            "INSERT INTO orders (table_id, created_at) VALUES (?1, CURRENT_TIMESTAMP)",
             params![table_id],
         )?;
         // Get the last inserted row's ID
@@ -169,28 +1113,112 @@ impl OrderResponse {
         Ok(last_inserted_id)
     }
     
-    /// List all orders
-    pub fn list(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<OrderResponse>> {
-        let mut stmt = conn.prepare("SELECT orders.id, orders.table_id, t.code FROM orders JOIN tables as t on orders.table_id=t.id")?;
-        let rows = stmt.query_map(params![], |row| {
-            let order_response = OrderResponse {
-                id: row.get(0)?,
-                table_id: row.get(1)?,
-                table_name: row.get(3)?,
-                total_cooking_time: OrderResponse::calculate_total_cooking_time(conn, row.get(0)?)?, // Calculate total_cooking_time
-                menus: OrderItem::list_all_order_items(conn, row.get(0)?)?
-            };
-            Ok(order_response)
-        })?;
+    /// List a page of orders, one row more than the limit if there is a next page
+    pub fn list(conn: &rusqlite::Connection, query: &OrderListQuery, page: &PageRequest) -> rusqlite::Result<Vec<OrderResponse>> {
+        let orders = OrderSummary::list(conn, query, page)?;
+        orders.into_iter().map(|order| OrderResponse::from_summary(conn, order)).collect()
+    }
+
+    /// Get an order with its items and bill figures
+    pub fn get(conn: &Connection, order_id: i64) -> rusqlite::Result<Option<OrderResponse>> {
+        match OrderSummary::get_many(conn, &[order_id])?.pop() {
+            Some(order) => Ok(Some(OrderResponse::from_summary(conn, order)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Add the items and bill figures to an order
+    fn from_summary(conn: &Connection, order: OrderSummary) -> rusqlite::Result<OrderResponse> {
+        let bill = BillResponse::for_order(conn, order.id)?;
+        Ok(OrderResponse {
+            total_cooking_time: OrderResponse::calculate_total_cooking_time(conn, order.id)?, // Calculate total_cooking_time
+            menus: OrderItem::list_all_order_items(conn, order.id)?,
+            subtotal: bill.as_ref().map_or(0, |bill| bill.subtotal),
+            adjustments: bill.as_ref().map_or(vec![], |bill| bill.adjustments.clone()),
+            service_charge: bill.as_ref().map_or(0, |bill| bill.service_charge),
+            total: bill.as_ref().map_or(0, |bill| bill.total),
+            tips: bill.as_ref().map_or(0, |bill| bill.tips),
+            id: order.id,
+            table_id: order.table_id,
+            table_name: order.table_name,
+            status: order.status,
+            party_size: order.party_size,
+            created_at: order.created_at,
+            version: order.version,
+        })
+    }
+}
+
+/// Functions for reading Orders without their items and bill figures
+impl OrderSummary {
+
+    /// List a page of orders, one row more than the limit if there is a next page
+    pub fn list(conn: &rusqlite::Connection, query: &OrderListQuery, page: &PageRequest) -> rusqlite::Result<Vec<OrderSummary>> {
+        let sql = format!(
+            "SELECT {} FROM orders JOIN tables as t on orders.table_id=t.id
+            WHERE (?1 IS NULL OR orders.status = ?1)
+            AND (?2 IS NULL OR orders.table_id = ?2)
+            AND (?3 IS NULL OR orders.created_at >= datetime(?3))
+            AND (?4 IS NULL OR orders.created_at < datetime(?4))
+            AND {}
+            ORDER BY {} LIMIT ?7",
+            ORDER_COLUMNS,
+            page.sort.after("orders.id", 5, 6),
+            page.sort.order_by("orders.id"),
+        );
+        let (after_value, after_id) = page.after_params();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            params![query.status, query.table_id, query.created_from, query.created_to, after_value, after_id, page.fetch()],
+            OrderSummary::from_row,
+        )?;
+
+        rows.collect()
+    }
+
+    /// Get the orders of a batch of ids in one query. Missing ids are left out
+    pub fn get_many(conn: &Connection, order_ids: &[i64]) -> rusqlite::Result<Vec<OrderSummary>> {
+        let query = format!(
+            "SELECT {} FROM orders JOIN tables as t on orders.table_id=t.id WHERE orders.id IN ({})",
+            ORDER_COLUMNS, in_list(order_ids.len()),
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(order_ids), OrderSummary::from_row)?;
+        rows.collect()
+    }
+
+    /// List the orders of a batch of tables in one query, the newest first
+    pub fn for_tables(conn: &Connection, table_ids: &[i64]) -> rusqlite::Result<Vec<OrderSummary>> {
+        let query = format!(
+            "SELECT {} FROM orders JOIN tables as t on orders.table_id=t.id WHERE orders.table_id IN ({}) ORDER BY orders.id DESC",
+            ORDER_COLUMNS, in_list(table_ids.len()),
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(table_ids), OrderSummary::from_row)?;
+        rows.collect()
+    }
 
-        Ok(rows.map(|result| result.unwrap()).collect())
+    /// Build the summary from a row selected with ORDER_COLUMNS
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<OrderSummary> {
+        Ok(OrderSummary {
+            id: row.get(0)?,
+            table_id: row.get(1)?,
+            table_name: row.get(2)?,
+            status: row.get(3)?,
+            party_size: row.get(4)?,
+            created_at: row.get(5)?,
+            version: row.get(6)?,
+        })
     }
+}
+
+impl OrderResponse {
 
     /* Utility Functions for Order Model. This block will contain some utility function to call on Order Model */
 
     /// Get order_id from table_id, check if already there is order running for this table or not
     pub fn get_existing_order_id(conn: &Connection, table_id: i64) -> Result<Option<i64>, rusqlite::Error> {
-        let query = "SELECT id FROM orders WHERE table_id = ?1";
+        let query = "SELECT id FROM orders WHERE table_id = ?1 AND status = 'open'";
         let mut stmt = conn.prepare(query)?;
         let mut rows = stmt.query(params![table_id])?;
         if let Some(row) = rows.next()? {
@@ -200,13 +1228,46 @@ impl OrderResponse {
         }
     }
 
-    /// Calculate the total cooking time dynamically from current order_items
-    pub fn calculate_total_cooking_time(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<i32> {
-        let query = "
-        SELECT SUM(oi.cooking_time)
-        FROM orders
-        JOIN order_items oi ON oi.order_id = orders.id
-        WHERE orders.id = ?1
+    /// Status of an order, None if the order does not exist
+    pub fn get_status(conn: &Connection, order_id: i64) -> rusqlite::Result<Option<String>> {
+        let result = conn.query_row("SELECT status FROM orders WHERE id = ?1", params![order_id], |row| row.get(0));
+        match result {
+            Ok(status) => Ok(Some(status)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Close a fully paid order, the table is free for a new order afterwards.
+    /// The figures of the final bill are kept on the order for the end-of-day report
+    pub fn close(conn: &Connection, order_id: i64, bill: &BillResponse) -> rusqlite::Result<bool> {
+        let discounts: i64 = bill.adjustments.iter().map(|adjustment| adjustment.amount).sum();
+        let updated = conn.execute(
+            "UPDATE orders SET status = 'closed', closed_at = CURRENT_TIMESTAMP, subtotal = ?2, discounts = ?3, service_charge = ?4, total = ?5
+            WHERE id = ?1 AND status = 'open'",
+            params![order_id, bill.subtotal, discounts, bill.service_charge, bill.total],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Set the number of guests of an order
+    pub fn set_party_size(conn: &Connection, order_id: i64, party_size: i64) -> rusqlite::Result<()> {
+        conn.execute("UPDATE orders SET party_size = ?1 WHERE id = ?2", params![party_size, order_id])?;
+        Ok(())
+    }
+
+    pub fn move_to_table(conn: &Connection, order_id: i64, table_id: i64) -> rusqlite::Result<()> {
+        conn.execute("UPDATE orders SET table_id = ?1 WHERE id = ?2", params![table_id, order_id])?;
+        Ok(())
+    }
+
+    /// Calculate the total cooking time dynamically from current order_items
+    pub fn calculate_total_cooking_time(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<i32> {
+        let query = "
+        SELECT COALESCE(SUM(oi.cooking_time), 0)
+        FROM orders
+        JOIN order_items oi ON oi.order_id = orders.id
+        WHERE orders.id = ?1
     ";
 
         conn.query_row(query, params![order_id], |row| row.get(0))
@@ -224,10 +1285,13 @@ impl OrderResponse {
 impl OrderItem {
 
     /// Create orders items
-    pub fn create(conn: &rusqlite::Connection, order_id: i64, menu_id: i64, cooking_time:i64) -> rusqlite::Result<i64> {
+    /// The menu price is copied to the item so later price changes don't alter running bills
+    /// cooking_time is kept as unit_cooking_time times the quantity
+    pub fn create(conn: &rusqlite::Connection, order_id: i64, line: &OrderLine, unit_cooking_time: i64) -> rusqlite::Result<i64> {
         conn.execute(
-            "INSERT INTO order_items (order_id, menu_id, cooking_time, quantity) VALUES (?1, ?2, ?3, ?4)",
-            params![order_id, menu_id, cooking_time, 1],
+            "INSERT INTO order_items (order_id, menu_id, unit_cooking_time, cooking_time, quantity, seat, note, unit_price, ordered_at)
+            VALUES (?1, ?2, ?3, ?3 * ?4, ?4, ?5, ?6, COALESCE((SELECT price FROM menus WHERE id = ?2), 0), CURRENT_TIMESTAMP)",
+            params![order_id, line.menu_id, unit_cooking_time, line.quantity, line.seat, line.note],
         )?;
         // Get the last inserted row's ID
         let last_inserted_id = conn.last_insert_rowid();
@@ -247,97 +1311,1490 @@ impl OrderItem {
             })
         })?;
 
-        Ok(rows.map(|result| result.unwrap()).collect())
+        rows.collect()
     }
     */
     /// List all orders items for a specific order
     pub fn list_all_order_items(conn: &rusqlite::Connection, order_id:i64) -> rusqlite::Result<Vec<OrderItemResponse>> {
-        let mut stmt = conn.prepare("SELECT order_items.id, order_items.order_id, order_items.menu_id, m.name, order_items.quantity, order_items.cooking_time FROM order_items JOIN menus as m on order_items.menu_id=m.id WHERE order_id= ?1")?;
-        let rows = stmt.query_map(params![order_id], |row| {
-            Ok(OrderItemResponse {
-                id: row.get(0)?,
-                order_id: row.get(1)?,
-                menu_id: row.get(2)?,
-                menu_name: row.get(3)?,
-                quantity: row.get(4)?,
-                cooking_time: row.get(5)?,
-            })
-        })?;
-        let result: Result<Vec<_>, _> = rows.collect();
-        result
+        let query = format!("SELECT {} FROM order_items JOIN menus as m on order_items.menu_id=m.id WHERE order_id= ?1", ORDER_ITEM_COLUMNS);
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params![order_id], OrderItemResponse::from_row)?;
+        rows.collect()
+    }
+
+    /// List the items of a batch of orders in one query
+    pub fn list_for_orders(conn: &rusqlite::Connection, order_ids: &[i64]) -> rusqlite::Result<Vec<OrderItemResponse>> {
+        let query = format!(
+            "SELECT {} FROM order_items JOIN menus as m on order_items.menu_id=m.id WHERE order_id IN ({}) ORDER BY order_items.id",
+            ORDER_ITEM_COLUMNS, in_list(order_ids.len()),
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params_from_iter(order_ids), OrderItemResponse::from_row)?;
+        rows.collect()
     }
 
     /// List all orders items for a specific table
     pub fn list_order_items(conn: &rusqlite::Connection, table_id:i64) -> rusqlite::Result<Vec<OrderItemResponse>> {
-        let query = "SELECT order_items.id, order_items.order_id, order_items.menu_id, m.name, order_items.quantity, order_items.cooking_time
+        let query = format!("SELECT {}
         FROM order_items
         JOIN orders ON orders.id = order_items.order_id
         JOIN menus as m on order_items.menu_id=m.id
-        WHERE orders.table_id = ?1";
+        WHERE orders.table_id = ?1 AND orders.status = 'open'
+        ORDER BY order_items.seat IS NULL, order_items.seat, order_items.id", ORDER_ITEM_COLUMNS);
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params![table_id], OrderItemResponse::from_row)?;
+        rows.collect()
+    }
+
+    /// Group items by seat, keeping the order in which the seats first appear
+    pub fn group_by_seat(items: Vec<OrderItemResponse>) -> Vec<SeatItemsResponse> {
+        let mut groups: Vec<SeatItemsResponse> = Vec::new();
+        for item in items {
+            match groups.iter_mut().find(|group| group.seat == item.seat) {
+                Some(group) => group.items.push(item),
+                None => groups.push(SeatItemsResponse { seat: item.seat, items: vec![item] }),
+            }
+        }
+        groups
+    }
+
+    /// Get the items of a menu on the open order of a table. If seat is given only that seat's lines are considered.
+    /// The same menu is on several lines when it was ordered for several seats or with different notes
+    pub fn get_items(conn: &rusqlite::Connection, table_id:i64, menu_id: i64, seat: Option<i64>)->rusqlite::Result<Vec<OrderItemResponse>>{
+        let query = format!("
+        SELECT {}
+        FROM order_items
+        JOIN orders ON orders.id = order_items.order_id
+        JOIN menus as m on order_items.menu_id=m.id
+        WHERE orders.table_id = ?1 AND orders.status = 'open' AND order_items.menu_id = ?2 AND (?3 IS NULL OR order_items.seat = ?3)
+        ORDER BY order_items.id", ORDER_ITEM_COLUMNS);
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(params![table_id, menu_id, seat], OrderItemResponse::from_row)?;
+        rows.collect()
+    }
+
+    /* Utility Functions for OrderItem Model. This block will contain some utility function to call on OrderItem Model */
+
+    /// Number of distinct seats ordered for, None if no item has a seat
+    pub fn count_seats(conn: &Connection, order_id: i64) -> rusqlite::Result<Option<i64>> {
+        let count: i64 = conn.query_row("SELECT COUNT(DISTINCT seat) FROM order_items WHERE order_id = ?1", params![order_id], |row| row.get(0))?;
+        Ok(if count > 0 { Some(count) } else { None })
+    }
+
+    /// Get the exisiting order item for a order, a menu, a seat and a note
+    /// Same menu on different seats or with different notes are separate items
+    pub fn get_existing_order_item_id(conn: &Connection, order_id: i64, line: &OrderLine) -> Result<Option<i64>, rusqlite::Error> {
+        let query = "SELECT id FROM order_items WHERE order_id = ?1 AND menu_id = ?2 AND seat IS ?3 AND note IS ?4";
         let mut stmt = conn.prepare(query)?;
-        let rows = stmt.query_map(params![table_id], |row| {
-            Ok(OrderItemResponse {
+        let mut rows = stmt.query(params![order_id, line.menu_id, line.seat, line.note])?;
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Add units to an item. Returns false if there is no such item or it would have more than MAX_ITEM_QUANTITY
+    pub fn add_quantity_of_existing_order_item(conn: &Connection, order_item_id: i64, quantity: i64) -> Result<bool, rusqlite::Error> {
+        let query = format!("UPDATE order_items
+        SET cooking_time = {} * (quantity + ?2),
+        quantity = quantity + ?2
+        WHERE id = ?1 AND quantity + ?2 <= ?3", UNIT_COOKING_TIME);
+        let result = conn.execute(&query, params![order_item_id, quantity, MAX_ITEM_QUANTITY])?;
+        Ok(result > 0)
+    }
+
+    /// Set the quantity of an item of an order. Returns false if the order has no such item
+    pub fn set_quantity(conn: &Connection, order_id: i64, order_item_id: i64, quantity: i64) -> rusqlite::Result<bool> {
+        let query = format!("UPDATE order_items
+        SET cooking_time = {} * ?3,
+        quantity = ?3
+        WHERE id = ?2 AND order_id = ?1", UNIT_COOKING_TIME);
+        let result = conn.execute(&query, params![order_id, order_item_id, quantity])?;
+        Ok(result > 0)
+    }
+
+    /// Delete an item of an order. Returns false if the order has no such item
+    pub fn delete(conn: &Connection, order_id: i64, order_item_id: i64) -> rusqlite::Result<bool> {
+        let deleted = conn.execute("DELETE FROM order_items WHERE id = ?2 AND order_id = ?1", params![order_id, order_item_id])?;
+        Ok(deleted > 0)
+    }
+
+    /// Get an item of an order
+    pub fn get(conn: &Connection, order_id: i64, order_item_id: i64) -> rusqlite::Result<Option<OrderItemResponse>> {
+        let query = format!("SELECT {} FROM order_items JOIN menus as m on order_items.menu_id=m.id WHERE order_items.order_id = ?1 AND order_items.id = ?2", ORDER_ITEM_COLUMNS);
+        let result = conn.query_row(&query, params![order_id, order_item_id], OrderItemResponse::from_row);
+        match result {
+            Ok(item) => Ok(Some(item)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// For Bill Line Response
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BillLineResponse {
+    pub order_item_id: i64,
+    pub menu_id: i64,
+    pub menu_name: String,
+    pub category: Option<String>,
+    pub seat: Option<i64>,
+    pub quantity: i64,
+    pub unit_price: i64,
+    pub amount: i64,
+    #[serde(skip)]
+    pub ordered_minute: Option<i64>, // Local minute of the day the item was ordered, for happy hours
+}
+
+/// For Bill Response, an itemised bill of the open order of a table. Amounts are in cents
+/// total is the subtotal of the lines plus the adjustments and the service charge.
+/// Tips are paid on top of the total and listed separately
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BillResponse {
+    pub order_id: i64,
+    pub table_id: i64,
+    pub table_name: String,
+    pub server: Option<String>,
+    pub party_size: Option<i64>,
+    pub lines: Vec<BillLineResponse>,
+    pub subtotal: i64,
+    pub adjustments: Vec<AdjustmentResponse>,
+    pub service_charge_percent: Option<i64>,
+    pub service_charge: i64,
+    pub total: i64,
+    pub tips: i64,
+}
+
+/// For Adjustment Response, a promotion or discount on an order. Discounts have a negative amount
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AdjustmentResponse {
+    pub kind: String, // promotion or manual_discount
+    pub promotion_id: Option<i64>,
+    pub discount_id: Option<i64>,
+    pub description: String,
+    pub reason_code: Option<String>,
+    pub amount: i64,
+}
+
+/// How a bill is split into sub-bills
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitMode {
+    Even,
+    Seat,
+    Custom,
+}
+
+/// For Splitting a Bill from Request
+/// guests is required for even split, allocations for custom split
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SplitBillRequest {
+    pub mode: SplitMode,
+    #[serde(default)]
+    #[validate(range(min = 1, max = 100))]
+    pub guests: Option<i64>,
+    #[serde(default)]
+    #[validate(length(max = 100), nested)]
+    pub allocations: Vec<SubBillAllocation>,
+}
+
+/// Items, or fractions of them, put on one sub-bill of a custom split
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubBillAllocation {
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub label: Option<String>,
+    #[validate(length(max = 100), nested)]
+    pub items: Vec<ItemAllocation>,
+}
+
+/// Quantity of an order item put on a sub-bill, can be fractional
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ItemAllocation {
+    #[validate(range(min = 1))]
+    pub order_item_id: i64,
+    #[validate(range(exclusive_min = 0.0, max = 100.0))]
+    pub quantity: f64,
+}
+
+/// For Sub-Bill Line Response
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SubBillLineResponse {
+    pub order_item_id: i64,
+    pub menu_name: String,
+    pub quantity: f64,
+    pub amount: i64,
+}
+
+/// For Sub-Bill Response
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SubBillResponse {
+    pub id: i64,
+    pub order_id: i64,
+    pub label: String,
+    pub seat: Option<i64>,
+    pub amount: i64,
+    pub status: String,
+    pub lines: Vec<SubBillLineResponse>,
+}
+
+/// For Split Bill Response
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SplitBillResponse {
+    pub order_id: i64,
+    pub total: i64,
+    pub sub_bills: Vec<SubBillResponse>,
+}
+
+/// Quantities of a custom split are compared in millionths
+const QUANTITY_SCALE: f64 = 1_000_000.0;
+/// Allowed difference between the allocated and the ordered quantity of an item, in millionths
+const QUANTITY_TOLERANCE: i64 = 1_000;
+/// Largest quantity of an item put on one sub-bill, as many as an order line can have
+const MAX_ALLOCATED_QUANTITY: f64 = 100.0;
+
+/// Distribute an amount in cents proportionally to the weights.
+/// The shares always add up to the amount. Cents left over after rounding down go to the
+/// shares with the largest remainder, ties go to the earlier share so the result is deterministic
+pub fn allocate_amount(amount: i64, weights: &[i64]) -> Vec<i64> {
+    let total_weight: i128 = weights.iter().map(|weight| *weight as i128).sum();
+    if total_weight == 0 {
+        return vec![0; weights.len()];
+    }
+    let mut shares = Vec::with_capacity(weights.len());
+    let mut remainders = Vec::with_capacity(weights.len());
+    for (index, weight) in weights.iter().enumerate() {
+        let exact = amount as i128 * *weight as i128;
+        shares.push((exact / total_weight) as i64);
+        remainders.push((exact % total_weight, index));
+    }
+    let left_over = amount - shares.iter().sum::<i64>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, index) in remainders.into_iter().take(left_over as usize) {
+        shares[index] += 1;
+    }
+    shares
+}
+
+/// Functions for Bill Model
+impl BillResponse {
+
+    /// Build the bill of the open order of a table. None if the table has no open order
+    pub fn for_table(conn: &rusqlite::Connection, table_id: i64) -> rusqlite::Result<Option<BillResponse>> {
+        match OrderResponse::get_existing_order_id(conn, table_id)? {
+            Some(order_id) => BillResponse::for_order(conn, order_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Build the bill of an order, open or closed. None if the order does not exist
+    pub fn for_order(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<Option<BillResponse>> {
+        let result = conn.query_row(
+            "SELECT orders.table_id, t.code, t.server, orders.party_size FROM orders JOIN tables as t on orders.table_id=t.id WHERE orders.id = ?1",
+            params![order_id],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?, row.get::<_, Option<i64>>(3)?)),
+        );
+        let (table_id, table_name, server, party_size) = match result {
+            Ok(table) => table,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let query = "SELECT order_items.id, order_items.menu_id, m.name, m.category, order_items.seat, order_items.quantity, order_items.unit_price,
+        CAST(strftime('%H', order_items.ordered_at, 'localtime') AS INTEGER) * 60 + CAST(strftime('%M', order_items.ordered_at, 'localtime') AS INTEGER)
+        FROM order_items
+        JOIN menus as m on order_items.menu_id=m.id
+        WHERE order_items.order_id = ?1
+        ORDER BY order_items.seat IS NULL, order_items.seat, order_items.id";
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map(params![order_id], |row| {
+            let quantity: i64 = row.get(5)?;
+            let unit_price: i64 = row.get(6)?;
+            Ok(BillLineResponse {
+                order_item_id: row.get(0)?,
+                menu_id: row.get(1)?,
+                menu_name: row.get(2)?,
+                category: row.get(3)?,
+                seat: row.get(4)?,
+                quantity,
+                unit_price,
+                amount: quantity * unit_price,
+                ordered_minute: row.get(7)?,
+            })
+        })?;
+        let lines: Vec<BillLineResponse> = rows.collect::<Result<_, _>>()?;
+        let subtotal = lines.iter().map(|line| line.amount).sum();
+
+        // Promotions and manual discounts
+        let promotions = PromotionResponse::list(conn)?;
+        let coupon_promotion_ids = PromotionResponse::coupon_promotion_ids(conn, order_id)?;
+        let mut adjustments = promotions::promotion_adjustments(&lines, &promotions, &coupon_promotion_ids);
+        for discount in ManualDiscountResponse::list_for_order(conn, order_id)? {
+            adjustments.push(promotions::manual_discount_adjustment(&discount, subtotal));
+        }
+        promotions::cap_adjustments(subtotal, &mut adjustments);
+        let discounted = subtotal + adjustments.iter().map(|adjustment| adjustment.amount).sum::<i64>();
+
+        // Service charge on the discounted amount for parties above the size of the table's rule
+        let party_size = match party_size {
+            Some(party_size) => Some(party_size),
+            None => OrderItem::count_seats(conn, order_id)?,
+        };
+        let service_charge_percent = match (ServiceChargeRuleResponse::for_table(conn, table_id)?, party_size) {
+            (Some(rule), Some(party_size)) if party_size > rule.min_party_size => Some(rule.percent),
+            _ => None,
+        };
+        let service_charge = service_charge_percent.map_or(0, |percent| (discounted * percent + 50) / 100);
+        let tips = PaymentResponse::tips_for_order(conn, order_id)?;
+        Ok(Some(BillResponse {
+            order_id, table_id, table_name, server, party_size, lines, subtotal, adjustments,
+            service_charge_percent, service_charge, total: discounted + service_charge, tips,
+        }))
+    }
+
+    /// Split the bill into sub-bills. The sub-bills are not saved and have id 0.
+    /// Adjustments are shared over the sub-bills in proportion to their lines.
+    /// Returns a message if the request does not describe a valid split
+    pub fn split(&self, request: &SplitBillRequest) -> Result<Vec<SubBillResponse>, String> {
+        let mut sub_bills = match request.mode {
+            SplitMode::Even => return self.split_even(request.guests),
+            SplitMode::Seat => self.split_by_seat(),
+            SplitMode::Custom => self.split_custom(&request.allocations)?,
+        };
+        if self.total != self.subtotal {
+            // Promotions and service charges are shared like the items, and so are the lines of every sub-bill
+            let weights: Vec<i64> = sub_bills.iter().map(|sub_bill| sub_bill.amount).collect();
+            for (sub_bill, amount) in sub_bills.iter_mut().zip(allocate_amount(self.total, &weights)) {
+                let weights: Vec<i64> = sub_bill.lines.iter().map(|line| line.amount).collect();
+                for (line, amount) in sub_bill.lines.iter_mut().zip(allocate_amount(amount, &weights)) {
+                    line.amount = amount;
+                }
+                sub_bill.amount = amount;
+            }
+        }
+        Ok(sub_bills)
+    }
+
+    fn sub_bill(&self, label: String, seat: Option<i64>, lines: Vec<SubBillLineResponse>, amount: i64) -> SubBillResponse {
+        SubBillResponse { id: 0, order_id: self.order_id, label, seat, amount, status: "open".to_string(), lines }
+    }
+
+    /// Every guest pays an equal share of the total
+    fn split_even(&self, guests: Option<i64>) -> Result<Vec<SubBillResponse>, String> {
+        let guests = match guests {
+            Some(guests) if guests >= 1 => guests,
+            _ => return Err("Even split needs guests of at least 1".to_string()),
+        };
+        let amounts = allocate_amount(self.total, &vec![1; guests as usize]);
+        Ok(amounts
+            .into_iter()
+            .enumerate()
+            .map(|(index, amount)| self.sub_bill(format!("Guest {}", index + 1), None, vec![], amount))
+            .collect())
+    }
+
+    /// Every seat pays its own items, items without a seat go to a shared sub-bill
+    fn split_by_seat(&self) -> Vec<SubBillResponse> {
+        let mut sub_bills: Vec<SubBillResponse> = Vec::new();
+        for line in &self.lines {
+            let sub_bill_line = SubBillLineResponse {
+                order_item_id: line.order_item_id,
+                menu_name: line.menu_name.clone(),
+                quantity: line.quantity as f64,
+                amount: line.amount,
+            };
+            match sub_bills.iter_mut().find(|sub_bill| sub_bill.seat == line.seat) {
+                Some(sub_bill) => {
+                    sub_bill.amount += line.amount;
+                    sub_bill.lines.push(sub_bill_line);
+                }
+                None => {
+                    let label = match line.seat {
+                        Some(seat) => format!("Seat {}", seat),
+                        None => "Shared".to_string(),
+                    };
+                    sub_bills.push(self.sub_bill(label, line.seat, vec![sub_bill_line], line.amount));
+                }
+            }
+        }
+        sub_bills
+    }
+
+    /// Every item has to be fully allocated over the sub-bills. The amount of an item is shared
+    /// proportionally to the allocated quantities
+    fn split_custom(&self, allocations: &[SubBillAllocation]) -> Result<Vec<SubBillResponse>, String> {
+        if allocations.is_empty() {
+            return Err("Custom split needs allocations".to_string());
+        }
+        for allocation in allocations {
+            for item in &allocation.items {
+                if !self.lines.iter().any(|line| line.order_item_id == item.order_item_id) {
+                    return Err(format!("Item {} is not on this bill", item.order_item_id));
+                }
+                if !item.quantity.is_finite() || item.quantity <= 0.0 || item.quantity > MAX_ALLOCATED_QUANTITY {
+                    return Err(format!("Quantity of item {} must be positive and at most {}", item.order_item_id, MAX_ALLOCATED_QUANTITY));
+                }
+            }
+        }
+
+        let mut sub_bills: Vec<SubBillResponse> = allocations
+            .iter()
+            .enumerate()
+            .map(|(index, allocation)| {
+                let label = allocation.label.clone().unwrap_or_else(|| format!("Bill {}", index + 1));
+                self.sub_bill(label, None, vec![], 0)
+            })
+            .collect();
+        for line in &self.lines {
+            // Weight of every sub-bill for this item, in millionths of the quantity
+            let weights: Vec<i64> = allocations
+                .iter()
+                .map(|allocation| {
+                    allocation.items
+                        .iter()
+                        .filter(|item| item.order_item_id == line.order_item_id)
+                        .map(|item| (item.quantity * QUANTITY_SCALE).round() as i64)
+                        .fold(0, i64::saturating_add)
+                })
+                .collect();
+            let allocated = weights.iter().copied().fold(0, i64::saturating_add);
+            let ordered = line.quantity.saturating_mul(QUANTITY_SCALE as i64);
+            if allocated.saturating_sub(ordered).abs() > QUANTITY_TOLERANCE {
+                return Err(format!("Item {} must be fully allocated, {} ordered", line.order_item_id, line.quantity));
+            }
+            let amounts = allocate_amount(line.amount, &weights);
+            for (index, sub_bill) in sub_bills.iter_mut().enumerate() {
+                if weights[index] == 0 {
+                    continue;
+                }
+                sub_bill.amount += amounts[index];
+                sub_bill.lines.push(SubBillLineResponse {
+                    order_item_id: line.order_item_id,
+                    menu_name: line.menu_name.clone(),
+                    quantity: weights[index] as f64 / QUANTITY_SCALE,
+                    amount: amounts[index],
+                });
+            }
+        }
+        Ok(sub_bills)
+    }
+}
+
+/// Functions for SubBill Model
+impl SubBillResponse {
+
+    /// Replace the sub-bills of an order. Fails with false if a payment was made on a sub-bill of the order.
+    /// Run it in the transaction the bill was read in, so no payment can come between
+    pub fn replace(conn: &rusqlite::Connection, order_id: i64, sub_bills: &mut [SubBillResponse]) -> rusqlite::Result<bool> {
+        let paid: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM payments WHERE order_id = ?1 AND sub_bill_id IS NOT NULL AND status = 'captured'",
+            params![order_id],
+            |row| row.get(0),
+        )?;
+        if paid > 0 {
+            return Ok(false);
+        }
+        SubBillResponse::delete_for_order(conn, order_id)?;
+        for sub_bill in sub_bills.iter_mut() {
+            conn.execute(
+                "INSERT INTO sub_bills (order_id, label, seat, amount, status) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![order_id, sub_bill.label, sub_bill.seat, sub_bill.amount, sub_bill.status],
+            )?;
+            sub_bill.id = conn.last_insert_rowid();
+            for line in &sub_bill.lines {
+                conn.execute(
+                    "INSERT INTO sub_bill_lines (sub_bill_id, order_item_id, menu_name, quantity, amount) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![sub_bill.id, line.order_item_id, line.menu_name, line.quantity, line.amount],
+                )?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Share the total of a split order over its unpaid sub-bills again after the total changed,
+    /// in proportion to their amounts. Paid sub-bills keep their amount, lines of removed items are
+    /// dropped and the other lines follow the amount of their sub-bill. Run it in the transaction of the change
+    pub fn rebalance(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<()> {
+        if SubBillResponse::list(conn, order_id)?.is_empty() {
+            return Ok(());
+        }
+        let total = match BillResponse::for_order(conn, order_id)? {
+            Some(bill) => bill.total,
+            None => return Ok(()),
+        };
+        conn.execute(
+            "DELETE FROM sub_bill_lines WHERE sub_bill_id IN (SELECT id FROM sub_bills WHERE order_id = ?1)
+            AND order_item_id NOT IN (SELECT id FROM order_items WHERE order_id = ?1)",
+            params![order_id],
+        )?;
+        let (paid, unpaid): (Vec<SubBillResponse>, Vec<SubBillResponse>) = SubBillResponse::list(conn, order_id)?
+            .into_iter()
+            .partition(|sub_bill| sub_bill.status == "paid");
+        let remaining = (total - paid.iter().map(|sub_bill| sub_bill.amount).sum::<i64>()).max(0);
+        let mut weights: Vec<i64> = unpaid.iter().map(|sub_bill| sub_bill.amount).collect();
+        if weights.iter().all(|weight| *weight == 0) {
+            weights = vec![1; unpaid.len()];
+        }
+        for (sub_bill, amount) in unpaid.iter().zip(allocate_amount(remaining, &weights)) {
+            conn.execute("UPDATE sub_bills SET amount = ?2 WHERE id = ?1", params![sub_bill.id, amount])?;
+            let mut stmt = conn.prepare("SELECT id, amount FROM sub_bill_lines WHERE sub_bill_id = ?1 ORDER BY id")?;
+            let lines = stmt.query_map(params![sub_bill.id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let weights: Vec<i64> = lines.iter().map(|(_, amount)| *amount).collect();
+            for ((line_id, _), amount) in lines.iter().zip(allocate_amount(amount, &weights)) {
+                conn.execute("UPDATE sub_bill_lines SET amount = ?2 WHERE id = ?1", params![line_id, amount])?;
+            }
+            SubBillResponse::refresh_status(conn, sub_bill.id)?;
+        }
+        Ok(())
+    }
+
+    /// Rebalance the sub-bills of every open order, after a change that can touch the total of any of them
+    pub fn rebalance_open_orders(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+        let mut stmt = conn.prepare("SELECT DISTINCT sub_bills.order_id FROM sub_bills JOIN orders ON sub_bills.order_id = orders.id WHERE orders.status = 'open'")?;
+        let order_ids = stmt.query_map(params![], |row| row.get::<_, i64>(0))?.collect::<rusqlite::Result<Vec<i64>>>()?;
+        for order_id in order_ids {
+            SubBillResponse::rebalance(conn, order_id)?;
+        }
+        Ok(())
+    }
+
+    /// List the sub-bills of an order
+    pub fn list(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<Vec<SubBillResponse>> {
+        SubBillResponse::query(conn, "WHERE order_id = ?1", order_id)
+    }
+
+    /// Get a single sub-bill
+    pub fn get(conn: &rusqlite::Connection, sub_bill_id: i64) -> rusqlite::Result<Option<SubBillResponse>> {
+        Ok(SubBillResponse::query(conn, "WHERE id = ?1", sub_bill_id)?.pop())
+    }
+
+    fn query(conn: &rusqlite::Connection, filter: &str, id: i64) -> rusqlite::Result<Vec<SubBillResponse>> {
+        let mut stmt = conn.prepare(&format!("SELECT id, order_id, label, seat, amount, status FROM sub_bills {} ORDER BY id", filter))?;
+        let rows = stmt.query_map(params![id], |row| {
+            Ok(SubBillResponse {
                 id: row.get(0)?,
                 order_id: row.get(1)?,
-                menu_id: row.get(2)?,
-                menu_name: row.get(3)?,
-                quantity: row.get(4)?,
-                cooking_time: row.get(5)?,
+                label: row.get(2)?,
+                seat: row.get(3)?,
+                amount: row.get(4)?,
+                status: row.get(5)?,
+                lines: vec![],
             })
         })?;
-        let result: Result<Vec<_>, _> = rows.collect();
-        result
+        let mut sub_bills: Vec<SubBillResponse> = rows.collect::<Result<_, _>>()?;
+        let mut stmt = conn.prepare("SELECT order_item_id, menu_name, quantity, amount FROM sub_bill_lines WHERE sub_bill_id = ?1 ORDER BY id")?;
+        for sub_bill in sub_bills.iter_mut() {
+            let rows = stmt.query_map(params![sub_bill.id], |row| {
+                Ok(SubBillLineResponse {
+                    order_item_id: row.get(0)?,
+                    menu_name: row.get(1)?,
+                    quantity: row.get(2)?,
+                    amount: row.get(3)?,
+                })
+            })?;
+            sub_bill.lines = rows.collect::<Result<_, _>>()?;
+        }
+        Ok(sub_bills)
     }
 
-    pub fn get_item(conn: &rusqlite::Connection, table_id:i64, menu_id: i64)->rusqlite::Result<Option<OrderItemResponse>>{
-        let query = "
-        SELECT order_items.id, order_items.order_id, order_items.menu_id, m.name, order_items.quantity, order_items.cooking_time
-        FROM order_items
-        JOIN orders ON orders.id = order_items.order_id
-        JOIN menus as m on order_items.menu_id=m.id
-        WHERE orders.table_id = ?1 AND order_items.menu_id = ?2";
-        let mut stmt = conn.prepare(query)?;
-        let result = stmt.query_row(params![table_id, menu_id], |row| {
-            Ok(OrderItemResponse {
+    /// Mark a sub-bill paid once its payments cover the amount, and open again after a refund or void
+    pub fn refresh_status(conn: &rusqlite::Connection, sub_bill_id: i64) -> rusqlite::Result<()> {
+        conn.execute(
+            "UPDATE sub_bills SET status = CASE WHEN amount <= (
+                SELECT COALESCE(SUM(amount), 0) FROM payments WHERE sub_bill_id = sub_bills.id AND status = 'captured'
+            ) THEN 'paid' ELSE 'open' END
+            WHERE id = ?1",
+            params![sub_bill_id],
+        )?;
+        Ok(())
+    }
+
+    /// Remove all sub-bills of an order
+    pub fn delete_for_order(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<()> {
+        conn.execute("DELETE FROM sub_bill_lines WHERE sub_bill_id IN (SELECT id FROM sub_bills WHERE order_id = ?1)", params![order_id])?;
+        conn.execute("DELETE FROM sub_bills WHERE order_id = ?1", params![order_id])?;
+        Ok(())
+    }
+}
+
+/// How a payment is made
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Tender {
+    Cash,
+    Card,
+    Voucher,
+}
+
+impl Tender {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tender::Cash => "cash",
+            Tender::Card => "card",
+            Tender::Voucher => "voucher",
+        }
+    }
+}
+
+/// For Creating a Payment from Request. Amounts are in cents
+/// amount is what the guest hands over or is charged, including the tip
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PaymentRequest {
+    pub tender: Tender,
+    #[validate(range(min = 1, max = 100_000_000))]
+    pub amount: i64,
+    #[serde(default)]
+    #[validate(range(min = 0, max = 100_000_000))]
+    pub tip: i64,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 200), custom(function = printable))]
+    pub card_token: Option<String>,
+}
+
+/// For Refunding or Voiding a Payment from Request
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PaymentReasonRequest {
+    #[validate(length(min = 1, max = 200), custom(function = printable))]
+    pub reason: String,
+}
+
+/// For Payment Response
+/// amount is the part put towards the bill, tendered what was handed over or charged
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PaymentResponse {
+    pub id: i64,
+    pub order_id: i64,
+    pub sub_bill_id: Option<i64>,
+    pub tender: Tender,
+    pub amount: i64,
+    pub tendered: i64,
+    pub tip: i64,
+    pub change_due: i64,
+    pub status: String, // captured, refunded or voided
+    pub reason: Option<String>,
+    pub reference: Option<String>,
+    pub cancel_reference: Option<String>, // Processor reference of the refund or void
+    pub server: Option<String>, // Server of the table when the payment was taken, the tip goes to them
+    pub created_at: String,
+    pub cancelled_at: Option<String>,
+}
+
+/// For Checkout Response, the payment and what is left to pay on the order
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CheckoutResponse {
+    pub payment: PaymentResponse,
+    pub order_id: i64,
+    pub total: i64,
+    pub paid: i64,
+    pub balance_due: i64,
+    pub order_status: String,
+}
+
+/// A payment split into the part for the bill and the change
+#[derive(Debug, PartialEq)]
+pub struct Settlement {
+    pub amount: i64,
+    pub change_due: i64,
+}
+
+/// Functions for Payment Model
+impl PaymentResponse {
+
+    /// Work out how much of a payment goes towards the amount due.
+    /// Cash gives change, a card can't be charged more than is due and vouchers give no change
+    pub fn settle(request: &PaymentRequest, due: i64) -> Result<Settlement, String> {
+        let towards_bill = request.amount - request.tip;
+        if towards_bill <= 0 {
+            return Err("Amount must be more than the tip".to_string());
+        }
+        let amount = towards_bill.min(due);
+        let over = towards_bill - amount;
+        match request.tender {
+            Tender::Cash => Ok(Settlement { amount, change_due: over }),
+            Tender::Card if over > 0 => Err(format!("Card amount is {} more than the amount due", over)),
+            Tender::Card | Tender::Voucher => Ok(Settlement { amount, change_due: 0 }),
+        }
+    }
+
+    /// Record a captured payment
+    pub fn create(conn: &rusqlite::Connection, order_id: i64, sub_bill_id: Option<i64>, request: &PaymentRequest, settlement: &Settlement, reference: Option<String>) -> rusqlite::Result<i64> {
+        conn.execute(
+            "INSERT INTO payments (order_id, sub_bill_id, tender, amount, tendered, tip, change_due, reference, server)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, (SELECT t.server FROM orders JOIN tables as t on orders.table_id=t.id WHERE orders.id = ?1))",
+            params![order_id, sub_bill_id, request.tender.as_str(), settlement.amount, request.amount, request.tip, settlement.change_due, reference],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Get a single payment
+    pub fn get(conn: &rusqlite::Connection, payment_id: i64) -> rusqlite::Result<Option<PaymentResponse>> {
+        Ok(PaymentResponse::query(conn, "WHERE id = ?1", payment_id)?.pop())
+    }
+
+    /// List the payments of an order, including refunded and voided ones
+    pub fn list_for_order(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<Vec<PaymentResponse>> {
+        PaymentResponse::query(conn, "WHERE order_id = ?1", order_id)
+    }
+
+    fn query(conn: &rusqlite::Connection, filter: &str, id: i64) -> rusqlite::Result<Vec<PaymentResponse>> {
+        let mut stmt = conn.prepare(&format!("SELECT id, order_id, sub_bill_id, tender, amount, tendered, tip, change_due, status, reason, reference, cancel_reference, server, created_at, cancelled_at FROM payments {} ORDER BY id", filter))?;
+        let rows = stmt.query_map(params![id], |row| {
+            Ok(PaymentResponse {
                 id: row.get(0)?,
                 order_id: row.get(1)?,
-                menu_id: row.get(2)?,
-                menu_name: row.get(3)?,
-                quantity: row.get(4)?,
-                cooking_time: row.get(5)?,
+                sub_bill_id: row.get(2)?,
+                tender: enum_column(row, 3)?,
+                amount: row.get(4)?,
+                tendered: row.get(5)?,
+                tip: row.get(6)?,
+                change_due: row.get(7)?,
+                status: row.get(8)?,
+                reason: row.get(9)?,
+                reference: row.get(10)?,
+                cancel_reference: row.get(11)?,
+                server: row.get(12)?,
+                created_at: row.get(13)?,
+                cancelled_at: row.get(14)?,
             })
-        });
+        })?;
+        rows.collect()
+    }
+
+    /// Tips of the captured payments of an order
+    pub fn tips_for_order(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<i64> {
+        conn.query_row(
+            "SELECT COALESCE(SUM(tip), 0) FROM payments WHERE order_id = ?1 AND status = 'captured'",
+            params![order_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Amount of captured payments put towards an order
+    pub fn paid_for_order(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<i64> {
+        conn.query_row(
+            "SELECT COALESCE(SUM(amount), 0) FROM payments WHERE order_id = ?1 AND status = 'captured'",
+            params![order_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Amount of captured payments put towards a sub-bill
+    pub fn paid_for_sub_bill(conn: &rusqlite::Connection, sub_bill_id: i64) -> rusqlite::Result<i64> {
+        conn.query_row(
+            "SELECT COALESCE(SUM(amount), 0) FROM payments WHERE sub_bill_id = ?1 AND status = 'captured'",
+            params![sub_bill_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Check if any payments were made on an order
+    pub fn has_payments(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<bool> {
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM payments WHERE order_id = ?1", params![order_id], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+
+    /// Refund or void a captured payment with a reason. Returns false if the payment is not captured
+    pub fn cancel(conn: &rusqlite::Connection, payment_id: i64, status: &str, reason: &str, reference: Option<String>) -> rusqlite::Result<bool> {
+        let updated = conn.execute(
+            "UPDATE payments SET status = ?2, reason = ?3, cancel_reference = ?4, cancelled_at = CURRENT_TIMESTAMP WHERE id = ?1 AND status = 'captured'",
+            params![payment_id, status, reason, reference],
+        )?;
+        Ok(updated > 0)
+    }
+}
+
+/// Kind of a promotion. percent takes value in percent, fixed in cents per unit
+/// (or once for the whole order) and buy_x_get_y makes the cheapest get_quantity of every
+/// buy_quantity + get_quantity units free
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PromotionKind {
+    Percent,
+    Fixed,
+    BuyXGetY,
+}
+
+/// What a promotion applies to, a menu (item), a menu category or the whole order
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PromotionScope {
+    Item,
+    Category,
+    Order,
+}
+
+/// For Creating a Promotion from Request
+/// starts_at and ends_at ("HH:MM" local time) limit it to items ordered in that window every day.
+/// With a coupon_code it only applies to orders the coupon is redeemed on
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[validate(schema(function = promotion_rules))]
+pub struct Promotion {
+    #[serde(skip)]
+    #[allow(dead_code)]
+    pub id: i64,
+    #[validate(length(min = 1, max = 100), custom(function = printable))]
+    pub name: String,
+    pub kind: PromotionKind,
+    pub scope: PromotionScope,
+    #[serde(default)]
+    #[validate(range(min = 1))]
+    pub menu_id: Option<i64>,
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub category: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 0, max = 1_000_000))]
+    pub value: i64, // Percent, or cents off each unit or the order
+    #[serde(default)]
+    #[validate(range(min = 1, max = 100))]
+    pub buy_quantity: Option<i64>,
+    #[serde(default)]
+    #[validate(range(min = 1, max = 100))]
+    pub get_quantity: Option<i64>,
+    #[serde(default)]
+    #[validate(custom(function = time_of_day))]
+    pub starts_at: Option<String>,
+    #[serde(default)]
+    #[validate(custom(function = time_of_day))]
+    pub ends_at: Option<String>,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 32), custom(function = code_chars))]
+    pub coupon_code: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 1, max = 1_000_000))]
+    pub usage_limit: Option<i64>,
+}
+
+/// Check that the fields of a promotion describe a rule that can be applied
+fn promotion_rules(promotion: &Promotion) -> Result<(), ValidationError> {
+    match promotion.scope {
+        PromotionScope::Item if promotion.menu_id.is_none() => return Err(invalid_field("menu_id", "required", "Item promotions need a menu_id")),
+        PromotionScope::Category if promotion.category.is_none() => return Err(invalid_field("category", "required", "Category promotions need a category")),
+        _ => {}
+    }
+    match promotion.kind {
+        PromotionKind::Percent if !(1..=100).contains(&promotion.value) => return Err(invalid_field("value", "range", "Percent must be between 1 and 100")),
+        PromotionKind::Fixed if promotion.value < 1 => return Err(invalid_field("value", "range", "Fixed discount must be positive")),
+        PromotionKind::BuyXGetY if promotion.buy_quantity.is_none() => return Err(invalid_field("buy_quantity", "required", "Buy X get Y needs a buy_quantity")),
+        PromotionKind::BuyXGetY if promotion.get_quantity.is_none() => return Err(invalid_field("get_quantity", "required", "Buy X get Y needs a get_quantity")),
+        _ => {}
+    }
+    match (&promotion.starts_at, &promotion.ends_at) {
+        (Some(_), None) => return Err(invalid_field("ends_at", "required", "Happy hour needs starts_at and ends_at")),
+        (None, Some(_)) => return Err(invalid_field("starts_at", "required", "Happy hour needs starts_at and ends_at")),
+        _ => {}
+    }
+    if promotion.usage_limit.is_some() && promotion.coupon_code.is_none() {
+        return Err(invalid_field("usage_limit", "coupon", "Only coupons can have a usage_limit"));
+    }
+    Ok(())
+}
+
+/// For Promotion Response
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PromotionResponse {
+    pub id: i64,
+    pub name: String,
+    pub kind: PromotionKind,
+    pub scope: PromotionScope,
+    pub menu_id: Option<i64>,
+    pub category: Option<String>,
+    pub value: i64,
+    pub buy_quantity: Option<i64>,
+    pub get_quantity: Option<i64>,
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    pub coupon_code: Option<String>,
+    pub usage_limit: Option<i64>,
+    pub usage_count: i64,
+    pub active: bool,
+}
+
+/// For Redeeming a Coupon on an Order from Request
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CouponRequest {
+    #[validate(length(min = 1, max = 32), custom(function = code_chars))]
+    pub code: String,
+}
+
+/// Kind of a manual discount, value in percent of the subtotal or in cents
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountKind {
+    Percent,
+    Fixed,
+}
+
+/// Why a manager gave a manual discount
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountReason {
+    ServiceRecovery,
+    Complaint,
+    StaffMeal,
+    Loyalty,
+    ManagerComp,
+}
+
+impl DiscountReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiscountReason::ServiceRecovery => "service_recovery",
+            DiscountReason::Complaint => "complaint",
+            DiscountReason::StaffMeal => "staff_meal",
+            DiscountReason::Loyalty => "loyalty",
+            DiscountReason::ManagerComp => "manager_comp",
+        }
+    }
+}
+
+/// For Creating a Manual Discount from Request
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[validate(schema(function = manual_discount_rules))]
+pub struct ManualDiscountRequest {
+    pub kind: DiscountKind,
+    #[validate(range(min = 1, max = 1_000_000))]
+    pub value: i64,
+    pub reason_code: DiscountReason,
+}
+
+/// A percent discount can't be more than the whole bill
+fn manual_discount_rules(discount: &ManualDiscountRequest) -> Result<(), ValidationError> {
+    match discount.kind {
+        DiscountKind::Percent if discount.value > 100 => Err(invalid_field("value", "range", "Percent must be between 1 and 100")),
+        _ => Ok(()),
+    }
+}
+
+/// For Manual Discount Response
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ManualDiscountResponse {
+    pub id: i64,
+    pub order_id: i64,
+    pub kind: DiscountKind,
+    pub value: i64,
+    pub reason_code: DiscountReason,
+    pub created_at: String,
+}
+
+/// Convert a value stored as snake_case text back into one of the serde enums
+fn enum_column<T: serde::de::DeserializeOwned>(row: &rusqlite::Row, index: usize) -> rusqlite::Result<T> {
+    let value: String = row.get(index)?;
+    serde_json::from_value(serde_json::Value::String(value))
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+/// Store one of the serde enums as snake_case text
+fn enum_text<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(text)) => text,
+        _ => String::new(),
+    }
+}
+
+/// Functions for Promotion Model
+impl Promotion {
+
+    /// Create a promotion
+    pub fn create(conn: &rusqlite::Connection, promotion: &Promotion) -> rusqlite::Result<i64> {
+        conn.execute(
+            "INSERT INTO promotions (name, kind, scope, menu_id, category, value, buy_quantity, get_quantity, starts_at, ends_at, coupon_code, usage_limit)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                promotion.name, enum_text(&promotion.kind), enum_text(&promotion.scope), promotion.menu_id, promotion.category,
+                promotion.value, promotion.buy_quantity, promotion.get_quantity, promotion.starts_at, promotion.ends_at,
+                promotion.coupon_code, promotion.usage_limit
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+}
+
+/// Functions for Promotion Response
+impl PromotionResponse {
+
+    /// List all promotions
+    pub fn list(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<PromotionResponse>> {
+        let mut stmt = conn.prepare("SELECT id, name, kind, scope, menu_id, category, value, buy_quantity, get_quantity, starts_at, ends_at, coupon_code, usage_limit, usage_count, active FROM promotions ORDER BY id")?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok(PromotionResponse {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                kind: enum_column(row, 2)?,
+                scope: enum_column(row, 3)?,
+                menu_id: row.get(4)?,
+                category: row.get(5)?,
+                value: row.get(6)?,
+                buy_quantity: row.get(7)?,
+                get_quantity: row.get(8)?,
+                starts_at: row.get(9)?,
+                ends_at: row.get(10)?,
+                coupon_code: row.get(11)?,
+                usage_limit: row.get(12)?,
+                usage_count: row.get(13)?,
+                active: row.get(14)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Ids of the coupon promotions redeemed on an order
+    pub fn coupon_promotion_ids(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<Vec<i64>> {
+        let mut stmt = conn.prepare("SELECT promotion_id FROM order_coupons WHERE order_id = ?1")?;
+        let rows = stmt.query_map(params![order_id], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Get the active coupon promotion for a code
+    pub fn get_by_coupon_code(conn: &rusqlite::Connection, code: &str) -> rusqlite::Result<Option<i64>> {
+        let result = conn.query_row("SELECT id FROM promotions WHERE coupon_code = ?1 AND active = 1", params![code], |row| row.get(0));
         match result {
-            Ok(item) => Ok(Some(item)),
+            Ok(id) => Ok(Some(id)),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(err) => Err(err),
         }
     }
 
-    /* Utility Functions for OrderItem Model. This block will contain some utility function to call on OrderItem Model */
+    /// Redeem a coupon on an order, counting towards its usage limit.
+    /// Returns false, changing nothing, if the limit is reached or the coupon is already redeemed on the order
+    pub fn redeem_coupon(conn: &rusqlite::Connection, order_id: i64, promotion_id: i64) -> rusqlite::Result<bool> {
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO order_coupons (order_id, promotion_id)
+            SELECT ?1, id FROM promotions WHERE id = ?2 AND (usage_limit IS NULL OR usage_count < usage_limit)",
+            params![order_id, promotion_id],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+        conn.execute("UPDATE promotions SET usage_count = usage_count + 1 WHERE id = ?1", params![promotion_id])?;
+        Ok(true)
+    }
+}
 
-    /// Get the exisiting order item for a order and a menu
-    pub fn get_existing_order_item_id(conn: &Connection, order_id: i64, menu_id: i64) -> Result<Option<i64>, rusqlite::Error> {
-        let query = "SELECT id FROM order_items WHERE order_id = ?1 AND menu_id = ?2";
-        let mut stmt = conn.prepare(query)?;
-        let mut rows = stmt.query(params![order_id, menu_id])?;
-        if let Some(row) = rows.next()? {
-            Ok(Some(row.get(0)?))
-        } else {
-            Ok(None)
+/// Functions for Manual Discount Model
+impl ManualDiscountResponse {
+
+    /// Create a manual discount on an order
+    pub fn create(conn: &rusqlite::Connection, order_id: i64, discount: &ManualDiscountRequest) -> rusqlite::Result<i64> {
+        conn.execute(
+            "INSERT INTO order_discounts (order_id, kind, value, reason_code) VALUES (?1, ?2, ?3, ?4)",
+            params![order_id, enum_text(&discount.kind), discount.value, discount.reason_code.as_str()],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// List the manual discounts of an order
+    pub fn list_for_order(conn: &rusqlite::Connection, order_id: i64) -> rusqlite::Result<Vec<ManualDiscountResponse>> {
+        let mut stmt = conn.prepare("SELECT id, order_id, kind, value, reason_code, created_at FROM order_discounts WHERE order_id = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![order_id], |row| {
+            Ok(ManualDiscountResponse {
+                id: row.get(0)?,
+                order_id: row.get(1)?,
+                kind: enum_column(row, 2)?,
+                value: row.get(3)?,
+                reason_code: enum_column(row, 4)?,
+                created_at: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+}
+
+/// For Creating a Service Charge Rule from Request
+/// Parties larger than min_party_size pay percent service charge. A rule can be limited to a
+/// section and/or table type, the most specific rule matching a table is used
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceChargeRule {
+    #[serde(skip)]
+    #[allow(dead_code)]
+    pub id: i64,
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub section: Option<String>,
+    #[serde(default)]
+    #[validate(length(max = 50), custom(function = printable))]
+    pub table_type: Option<String>,
+    #[validate(range(min = 0, max = 100))]
+    pub min_party_size: i64,
+    #[validate(range(min = 1, max = 100))]
+    pub percent: i64,
+}
+
+/// For Service Charge Rule Response
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ServiceChargeRuleResponse {
+    pub id: i64,
+    pub section: Option<String>,
+    pub table_type: Option<String>,
+    pub min_party_size: i64,
+    pub percent: i64,
+}
+
+/// Functions for Service Charge Rule Model
+impl ServiceChargeRule {
+
+    /// Create a service charge rule
+    pub fn create(conn: &rusqlite::Connection, rule: &ServiceChargeRule) -> rusqlite::Result<i64> {
+        conn.execute(
+            "INSERT INTO service_charge_rules (section, table_type, min_party_size, percent) VALUES (?1, ?2, ?3, ?4)",
+            params![rule.section, rule.table_type, rule.min_party_size, rule.percent],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+}
+
+/// Functions for Service Charge Rule Response
+impl ServiceChargeRuleResponse {
+
+    /// List all service charge rules
+    pub fn list(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<ServiceChargeRuleResponse>> {
+        let mut stmt = conn.prepare("SELECT id, section, table_type, min_party_size, percent FROM service_charge_rules ORDER BY id")?;
+        let rows = stmt.query_map(params![], ServiceChargeRuleResponse::from_row)?;
+        rows.collect()
+    }
+
+    /// The rule for a table, one for its section and type wins over one for its section or type,
+    /// which wins over a rule for every table. The newest rule wins a tie
+    pub fn for_table(conn: &rusqlite::Connection, table_id: i64) -> rusqlite::Result<Option<ServiceChargeRuleResponse>> {
+        let query = "SELECT r.id, r.section, r.table_type, r.min_party_size, r.percent
+        FROM service_charge_rules as r
+        JOIN tables as t on t.id = ?1
+        WHERE (r.section IS NULL OR r.section = t.section) AND (r.table_type IS NULL OR r.table_type = t.table_type)
+        ORDER BY (r.section IS NOT NULL) + (r.table_type IS NOT NULL) DESC, r.id DESC
+        LIMIT 1";
+        let result = conn.query_row(query, params![table_id], ServiceChargeRuleResponse::from_row);
+        match result {
+            Ok(rule) => Ok(Some(rule)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
         }
     }
 
-    pub fn add_quantity_of_existing_order_item(conn: &Connection, order_item_id: i64) -> Result<bool, rusqlite::Error> {
-        let query = "UPDATE order_items
-        SET cooking_time = (cooking_time / quantity) * (quantity + 1),
-        quantity = quantity + 1
-        WHERE id = ?1";
-        let result = conn.execute(query, params![order_item_id])?;
-        if result > 0 {
-            Ok(true)
-        } else {
-            Ok(false)
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<ServiceChargeRuleResponse> {
+        Ok(ServiceChargeRuleResponse {
+            id: row.get(0)?,
+            section: row.get(1)?,
+            table_type: row.get(2)?,
+            min_party_size: row.get(3)?,
+            percent: row.get(4)?,
+        })
+    }
+}
+
+/// For Creating a Webhook from Request
+/// Events of the kinds listed are POSTed to the url as JSON, signed with the secret
+#[derive(Debug, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    #[validate(length(max = 500), custom(function = webhook_url))]
+    pub url: String,
+    #[validate(length(min = 1, max = 23), custom(function = event_kinds))]
+    pub events: Vec<String>, // Kinds of events, e.g. order_created, item_added or payment_captured
+    #[serde(default)]
+    #[validate(length(min = 16, max = 100), custom(function = printable))]
+    pub secret: Option<String>, // Generated if not given, kept if not given on update
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+/// For Webhook Response, the secret is only shown when it is set
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookResponse {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// For Updating part of a Webhook from Request, missing fields are kept
+#[derive(Debug, Default, Serialize, Deserialize, Validate, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookPatch {
+    #[serde(default)]
+    #[validate(length(max = 500), custom(function = webhook_url))]
+    pub url: Option<String>,
+    #[serde(default)]
+    #[validate(length(min = 1, max = 23), custom(function = event_kinds))]
+    pub events: Option<Vec<String>>,
+    #[serde(default)]
+    #[validate(length(min = 16, max = 100), custom(function = printable))]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+/// For Webhook Delivery Response, an event sent or to be sent to a webhook
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookDeliveryResponse {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: serde_json::Value, // The body sent
+    pub status: String, // pending, delivered or dead
+    pub attempts: i64,
+    pub last_status: Option<i64>, // HTTP status of the last answer of the receiver
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub delivered_at: Option<String>,
+}
+
+/// Functions for Webhook Model
+impl Webhook {
+
+    /// Create a webhook with its secret, it gets the events logged from now on
+    pub fn create(conn: &Connection, webhook: &Webhook, secret: &str) -> rusqlite::Result<i64> {
+        conn.execute(
+            "INSERT INTO webhooks (url, events, secret, active, last_event_id) VALUES (?1, ?2, ?3, ?4, (SELECT COALESCE(MAX(id), 0) FROM domain_events))",
+            params![webhook.url, webhook.events.join(","), secret, webhook.active],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// List all webhooks, without their secrets
+    pub fn list(conn: &Connection) -> rusqlite::Result<Vec<WebhookResponse>> {
+        let mut stmt = conn.prepare("SELECT id, url, events, active, created_at FROM webhooks ORDER BY id")?;
+        let rows = stmt.query_map(params![], Webhook::response_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get(conn: &Connection, webhook_id: i64) -> rusqlite::Result<Option<WebhookResponse>> {
+        let result = conn.query_row(
+            "SELECT id, url, events, active, created_at FROM webhooks WHERE id = ?1",
+            params![webhook_id],
+            Webhook::response_from_row,
+        );
+        match result {
+            Ok(webhook) => Ok(Some(webhook)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Update a webhook, its secret is kept if none is given. Returns false if it doesn't exist
+    pub fn update(conn: &Connection, webhook_id: i64, webhook: &Webhook) -> rusqlite::Result<bool> {
+        let updated = conn.execute(
+            "UPDATE webhooks SET url = ?1, events = ?2, secret = COALESCE(?3, secret), active = ?4 WHERE id = ?5",
+            params![webhook.url, webhook.events.join(","), webhook.secret, webhook.active, webhook_id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Delete a webhook with its deliveries, pending ones are not sent anymore
+    pub fn delete(conn: &Connection, webhook_id: i64) -> rusqlite::Result<DeleteOutcome> {
+        conn.execute("DELETE FROM webhook_deliveries WHERE webhook_id = ?1", params![webhook_id])?;
+        match conn.execute("DELETE FROM webhooks WHERE id = ?1", params![webhook_id])? {
+            0 => Ok(DeleteOutcome::NotFound),
+            _ => Ok(DeleteOutcome::Deleted),
         }
+    }
+
+    fn response_from_row(row: &rusqlite::Row) -> rusqlite::Result<WebhookResponse> {
+        let events: String = row.get(2)?;
+        Ok(WebhookResponse {
+            id: row.get(0)?,
+            url: row.get(1)?,
+            events: events.split(',').map(str::to_string).collect(),
+            active: row.get(3)?,
+            created_at: row.get(4)?,
+            secret: None,
+        })
+    }
+}
+
+impl WebhookPatch {
+    /// Apply the patch on the current webhook
+    pub fn apply(self, webhook: WebhookResponse) -> Webhook {
+        Webhook {
+            url: self.url.unwrap_or(webhook.url),
+            events: self.events.unwrap_or(webhook.events),
+            secret: self.secret,
+            active: self.active.unwrap_or(webhook.active),
+        }
+    }
+}
+
+/// Functions for Webhook Delivery Response
+impl WebhookDeliveryResponse {
+
+    /// Deliveries of a webhook that failed every attempt, oldest first
+    pub fn dead_letters(conn: &Connection, webhook_id: i64) -> rusqlite::Result<Vec<WebhookDeliveryResponse>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, webhook_id, event, payload, status, attempts, last_status, last_error, created_at, delivered_at
+            FROM webhook_deliveries WHERE webhook_id = ?1 AND status = 'dead' ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![webhook_id], WebhookDeliveryResponse::from_row)?;
+        rows.collect()
+    }
+
+    pub fn get(conn: &Connection, webhook_id: i64, delivery_id: i64) -> rusqlite::Result<Option<WebhookDeliveryResponse>> {
+        let result = conn.query_row(
+            "SELECT id, webhook_id, event, payload, status, attempts, last_status, last_error, created_at, delivered_at
+            FROM webhook_deliveries WHERE id = ?1 AND webhook_id = ?2",
+            params![delivery_id, webhook_id],
+            WebhookDeliveryResponse::from_row,
+        );
+        match result {
+            Ok(delivery) => Ok(Some(delivery)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Send a dead delivery again, its attempts start over. Returns false if it isn't dead
+    pub fn redeliver(conn: &Connection, delivery_id: i64) -> rusqlite::Result<bool> {
+        let updated = conn.execute(
+            "UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = CURRENT_TIMESTAMP WHERE id = ?1 AND status = 'dead'",
+            params![delivery_id],
+        )?;
+        Ok(updated > 0)
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<WebhookDeliveryResponse> {
+        let payload: String = row.get(3)?;
+        Ok(WebhookDeliveryResponse {
+            id: row.get(0)?,
+            webhook_id: row.get(1)?,
+            event: row.get(2)?,
+            payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::Null),
+            status: row.get(4)?,
+            attempts: row.get(5)?,
+            last_status: row.get(6)?,
+            last_error: row.get(7)?,
+            created_at: row.get(8)?,
+            delivered_at: row.get(9)?,
+        })
+    }
+}
+
+/// For Daily Report Query, date as YYYY-MM-DD local time. Defaults to today
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReportQuery {
+    pub date: Option<String>,
+}
+
+/// For Tender Total Response, captured payments of one tender
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TenderTotalResponse {
+    pub tender: Tender,
+    pub payments: i64,
+    pub amount: i64,
+}
+
+/// For Server Tips Response, the tips of one server. server is null for tables without a server
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ServerTipsResponse {
+    pub server: Option<String>,
+    pub payments: i64,
+    pub tips: i64,
+}
+
+/// For Daily Report Response, the end-of-day figures of a day. Amounts are in cents
+/// Order figures are for the orders closed that day, payments and tips for the payments taken that day
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DailyReportResponse {
+    pub date: String,
+    pub orders_closed: i64,
+    pub subtotal: i64,
+    pub discounts: i64,
+    pub service_charges: i64,
+    pub total: i64,
+    pub payments: Vec<TenderTotalResponse>,
+    pub tips: i64,
+    pub tips_by_server: Vec<ServerTipsResponse>,
+}
+
+/// Functions for Daily Report
+impl DailyReportResponse {
+
+    /// Build the report of a day. None if the date is not a valid YYYY-MM-DD date
+    pub fn for_date(conn: &rusqlite::Connection, date: Option<&str>) -> rusqlite::Result<Option<DailyReportResponse>> {
+        let date: Option<String> = match date {
+            Some(date) => conn.query_row("SELECT CASE WHEN date(?1, '+0 days') = ?1 THEN ?1 END", params![date], |row| row.get(0))?,
+            None => conn.query_row("SELECT date('now', 'localtime')", params![], |row| row.get(0))?,
+        };
+        let date = match date {
+            Some(date) => date,
+            None => return Ok(None),
+        };
+        let (orders_closed, subtotal, discounts, service_charges, total) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(subtotal), 0), COALESCE(SUM(discounts), 0), COALESCE(SUM(service_charge), 0), COALESCE(SUM(total), 0)
+            FROM orders WHERE status = 'closed' AND date(closed_at, 'localtime') = ?1",
+            params![date],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+        )?;
+
+        let mut stmt = conn.prepare(
+            "SELECT tender, COUNT(*), SUM(amount) FROM payments
+            WHERE status = 'captured' AND date(created_at, 'localtime') = ?1
+            GROUP BY tender ORDER BY tender",
+        )?;
+        let payments = stmt.query_map(params![date], |row| {
+            Ok(TenderTotalResponse {
+                tender: enum_column(row, 0)?,
+                payments: row.get(1)?,
+                amount: row.get(2)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT server, COUNT(*), SUM(tip) FROM payments
+            WHERE status = 'captured' AND tip > 0 AND date(created_at, 'localtime') = ?1
+            GROUP BY server ORDER BY server IS NULL, server",
+        )?;
+        let tips_by_server = stmt.query_map(params![date], |row| {
+            Ok(ServerTipsResponse {
+                server: row.get(0)?,
+                payments: row.get(1)?,
+                tips: row.get(2)?,
+            })
+        })?.collect::<Result<Vec<_>, _>>()?;
+        let tips = tips_by_server.iter().map(|server| server.tips).sum();
+
+        Ok(Some(DailyReportResponse { date, orders_closed, subtotal, discounts, service_charges, total, payments, tips, tips_by_server }))
+    }
+}
+
+/// Functions for Table Patch
+impl TablePatch {
+    /// Apply the patch on the current table
+    pub fn apply(self, table: TableResponse) -> Table {
+        Table {
+            id: table.id,
+            code: self.code.unwrap_or(table.code),
+            section: self.section.unwrap_or(table.section),
+            table_type: self.table_type.unwrap_or(table.table_type),
+            server: self.server.unwrap_or(table.server),
+        }
+    }
+}
+
+/// Functions for Menu Patch
+impl MenuPatch {
+    /// Apply the patch on the current menu
+    pub fn apply(self, menu: MenuResponse) -> Menu {
+        Menu {
+            id: menu.id,
+            name: self.name.unwrap_or(menu.name),
+            price: self.price.unwrap_or(menu.price),
+            category: self.category.unwrap_or(menu.category),
+            station: self.station.unwrap_or(menu.station),
         }
+    }
 }
@@ -0,0 +1,385 @@
+// src/openapi.rs
+use crate::errors::ErrorEnvelope;
+use crate::models::{
+    BillResponse, CheckoutResponse, MenuExportQuery, MenuImportQuery, MenuImportResponse, CouponRequest, DailyReportResponse, EventLogQuery, EventLogResponse, EventStreamQuery, LiveUpdatesQuery, ManualDiscountRequest, Menu, MenuListQuery, MenuPatch, MenuResponse,
+    OrderItemPatch, OrderItemResponse, OrderListQuery, OrderRequestBody, OrderResponse, PaymentReasonRequest, PaymentRequest, PaymentResponse,
+    Promotion, PromotionResponse, ReportQuery, SeatItemsResponse, SeatQuery, ServiceChargeRule, ServiceChargeRuleResponse, SplitBillRequest,
+    SplitBillResponse, AuditEntryResponse, AuditQuery, BatchRequest, BatchResponse, Table, TableHistoryQuery, TableHistoryResponse, TableItemsQuery, TableListQuery, TablePatch, TableResponse, TableServerRequest, Webhook,
+    WebhookDeliveryResponse, WebhookPatch, WebhookResponse,
+};
+use crate::pagination::Page;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+/// Reply of creates, e.g. {"id": 3}
+#[derive(Serialize, JsonSchema)]
+#[allow(dead_code)]
+struct CreatedResponse {
+    id: i64,
+}
+
+/// Reply of deletes and order placement, e.g. {"id": 3, "success": "Table deleted"}
+#[derive(Serialize, JsonSchema)]
+#[allow(dead_code)]
+struct SuccessResponse {
+    id: Option<i64>, // Left out by deletes of order items
+    success: String,
+}
+
+/// Reply of assigning a server to a table
+#[derive(Serialize, JsonSchema)]
+#[allow(dead_code)]
+struct TableServerResponse {
+    id: i64,
+    server: Option<String>,
+}
+
+/// Body of a GraphQL request
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+struct GraphQLRequest {
+    query: String,
+    operation_name: Option<String>,
+    variables: Option<Value>,
+}
+
+/// A single operation of the document, the path is written as in the spec e.g. /orders/{order_id}
+pub struct Operation {
+    pub method: &'static str,
+    pub path: &'static str,
+    spec: Value,
+}
+
+impl Operation {
+    /// Path parameters are taken from the path, all ids are integers
+    fn new(method: &'static str, path: &'static str, summary: &str) -> Operation {
+        let parameters: Vec<Value> = path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "integer", "format": "int64"}}))
+            .collect();
+        let error = json!({"description": "Error", "content": {"application/json": {"schema": {"$ref": "#/components/schemas/ErrorEnvelope"}}}});
+        let spec = json!({"summary": summary, "parameters": parameters, "responses": {"default": error}});
+        Operation { method, path, spec }
+    }
+
+    fn body<T: JsonSchema>(mut self, gen: &mut SchemaGenerator) -> Operation {
+        self.spec["requestBody"] = json!({"required": true, "content": {"application/json": {"schema": gen.subschema_for::<T>()}}});
+        self
+    }
+
+    /// Every field of the query struct is an optional query parameter
+    fn query<T: JsonSchema>(mut self, gen: &mut SchemaGenerator) -> Operation {
+        let schema = json!(gen.root_schema_for::<T>().schema);
+        let properties = schema["properties"].as_object().cloned().unwrap_or_default();
+        for (name, schema) in properties {
+            self.parameter(json!({"name": name, "in": "query", "required": false, "schema": schema}));
+        }
+        self
+    }
+
+    fn header(mut self, name: &str, description: &str) -> Operation {
+        self.parameter(json!({"name": name, "in": "header", "required": false, "description": description, "schema": {"type": "string"}}));
+        self
+    }
+
+    fn response<T: JsonSchema>(mut self, gen: &mut SchemaGenerator, status: u16, description: &str) -> Operation {
+        let content = json!({"application/json": {"schema": gen.subschema_for::<T>()}});
+        self.spec["responses"][status.to_string()] = json!({"description": description, "content": content});
+        self
+    }
+
+    /// The body can be sent as a CSV file too
+    fn csv_body(mut self) -> Operation {
+        self.spec["requestBody"]["content"]["text/csv"] = json!({"schema": {"type": "string"}});
+        self
+    }
+
+    /// The response can be a CSV file too
+    fn csv_response(mut self, status: u16) -> Operation {
+        self.spec["responses"][status.to_string()]["content"]["text/csv"] = json!({"schema": {"type": "string"}});
+        self
+    }
+
+    fn empty_response(mut self, status: u16, description: &str) -> Operation {
+        self.spec["responses"][status.to_string()] = json!({"description": description});
+        self
+    }
+
+    fn deprecated(mut self) -> Operation {
+        self.spec["deprecated"] = json!(true);
+        self
+    }
+
+    fn parameter(&mut self, parameter: Value) {
+        if let Some(parameters) = self.spec["parameters"].as_array_mut() {
+            parameters.push(parameter);
+        }
+    }
+}
+
+/// Describes the v1 shapes, v2 reshapes the responses
+const DESCRIPTION: &str = "Amounts are in cents. Under /v2 responses carry amounts as decimals e.g. 12.5, \
+order items nest their menu as menu: {id, name}, the items of an order are listed as items \
+and state takes the place of archived and status. Versions due to go away send a Sunset header";
+
+/// The OpenAPI document of every route, built once
+pub fn document() -> &'static Value {
+    static DOCUMENT: OnceLock<Value> = OnceLock::new();
+    DOCUMENT.get_or_init(|| {
+        let mut gen = SchemaSettings::openapi3().into_generator();
+        gen.subschema_for::<ErrorEnvelope>();
+        let mut paths = Map::new();
+        for operation in operations(&mut gen) {
+            paths.entry(operation.path).or_insert_with(|| json!({}))[operation.method] = operation.spec;
+        }
+        json!({
+            "openapi": "3.0.3",
+            "info": {"title": "Simple Restaurant API", "version": env!("CARGO_PKG_VERSION"), "description": DESCRIPTION},
+            "servers": [
+                {"url": "/v1"},
+                {"url": "/v2", "description": "Same requests, responses in the v2 shape"},
+                {"url": "/", "description": "Same as /v1 but for DELETE /orders/{table_id}/items/{menu_id}, for tablets already deployed"},
+            ],
+            "paths": paths,
+            "components": {"schemas": gen.definitions()},
+        })
+    })
+}
+
+/// Every operation of restaurent_routes. A test requests each of them and the other methods on their paths
+pub fn operations(gen: &mut SchemaGenerator) -> Vec<Operation> {
+    const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+    const IDEMPOTENCY: &str = "Retries with the same key replay the first response, the key with another body is a conflict";
+    const IF_MATCH: &str = "If-Match";
+    const IF_MATCH_DESCRIPTION: &str = "ETag the client last saw, PRECONDITION FAILED if it changed since";
+    const IF_NONE_MATCH: &str = "If-None-Match";
+    const IF_NONE_MATCH_DESCRIPTION: &str = "ETag the client has, NOT MODIFIED if it is still current";
+    vec![
+        // Orders
+        Operation::new("get", "/orders", "List orders a page at a time")
+            .query::<OrderListQuery>(gen)
+            .response::<Page<OrderResponse>>(gen, 200, "A page of orders"),
+        Operation::new("post", "/orders", "Order for a table, items are added to its open order if it has one")
+            .header(IDEMPOTENCY_KEY, IDEMPOTENCY)
+            .body::<OrderRequestBody>(gen)
+            .response::<SuccessResponse>(gen, 201, "Order created")
+            .response::<SuccessResponse>(gen, 200, "Items added to the open order"),
+        Operation::new("post", "/orders/create", "Deprecated alias of POST /orders")
+            .header(IDEMPOTENCY_KEY, IDEMPOTENCY)
+            .body::<OrderRequestBody>(gen)
+            .response::<SuccessResponse>(gen, 201, "Order created")
+            .response::<SuccessResponse>(gen, 200, "Items added to the open order")
+            .deprecated(),
+        Operation::new("get", "/orders/{order_id}", "Get an order with its items and bill figures")
+            .header(IF_NONE_MATCH, IF_NONE_MATCH_DESCRIPTION)
+            .response::<OrderResponse>(gen, 200, "The order, its version is sent as ETag")
+            .empty_response(304, "The order did not change"),
+        Operation::new("get", "/orders/{order_id}/items", "List the items of an order, group_by=seat groups them per seat")
+            .query::<TableItemsQuery>(gen)
+            .response::<Vec<OrderItemResponse>>(gen, 200, "The items, or with group_by=seat a list of seats with their items"),
+        Operation::new("get", "/orders/{order_id}/items/{item_id}", "Get an item of an order")
+            .response::<OrderItemResponse>(gen, 200, "The item"),
+        Operation::new("patch", "/orders/{order_id}/items/{item_id}", "Set the quantity of an item of an open order")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<OrderItemPatch>(gen)
+            .response::<OrderItemResponse>(gen, 200, "The item, the version of the order is sent as ETag"),
+        Operation::new("delete", "/orders/{order_id}/items/{item_id}", "Remove an item, an order left without items or payments is deleted. \
+Without a version prefix the path keeps the meaning it had before the versions, deprecated: the ids are of the table and the menu, \
+one unit of the menu is taken off the open order of the table and ?seat= is needed if the menu is on several lines")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .response::<SuccessResponse>(gen, 200, "Item deleted"),
+        Operation::new("post", "/batch", "Remove, add and change items and move orders in one transaction, each operation as its route would")
+            .body::<BatchRequest>(gen)
+            .response::<BatchResponse>(gen, 200, "The result of each operation, in all_or_nothing mode the status of the first failure if one failed"),
+        Operation::new("post", "/orders/{order_id}/payments", "Pay towards an open order, it closes once fully paid")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<PaymentRequest>(gen)
+            .response::<CheckoutResponse>(gen, 201, "Payment recorded"),
+        Operation::new("get", "/orders/{order_id}/payments", "List the payments of an order, refunded and voided ones included")
+            .response::<Vec<PaymentResponse>>(gen, 200, "The payments"),
+        Operation::new("post", "/orders/{order_id}/coupons", "Redeem a coupon on an open order")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<CouponRequest>(gen)
+            .response::<BillResponse>(gen, 201, "The recalculated bill"),
+        Operation::new("post", "/orders/{order_id}/discounts", "Give a manual discount on an open order, sent with the manager role")
+            .header("X-Staff-Role", "Role of the staff member as the client states it, manager to give discounts. Not authenticated")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<ManualDiscountRequest>(gen)
+            .response::<BillResponse>(gen, 201, "The recalculated bill"),
+        // Tables
+        Operation::new("get", "/tables", "List tables a page at a time")
+            .query::<TableListQuery>(gen)
+            .response::<Page<TableResponse>>(gen, 200, "A page of tables"),
+        Operation::new("post", "/tables", "Create a table, an existing code returns the existing table and restores it if archived")
+            .body::<Table>(gen)
+            .response::<CreatedResponse>(gen, 201, "Table created"),
+        Operation::new("post", "/tables/create", "Deprecated alias of POST /tables")
+            .body::<Table>(gen)
+            .response::<CreatedResponse>(gen, 201, "Table created")
+            .deprecated(),
+        Operation::new("get", "/tables/{table_id}", "Get a table, archived tables included")
+            .header(IF_NONE_MATCH, IF_NONE_MATCH_DESCRIPTION)
+            .response::<TableResponse>(gen, 200, "The table, its version is sent as ETag")
+            .empty_response(304, "The table did not change"),
+        Operation::new("put", "/tables/{table_id}", "Replace a table, fields left out are cleared")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<Table>(gen)
+            .response::<TableResponse>(gen, 200, "The table, its version is sent as ETag"),
+        Operation::new("patch", "/tables/{table_id}", "Update some fields of a table, null clears a field")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<TablePatch>(gen)
+            .response::<TableResponse>(gen, 200, "The table, its version is sent as ETag"),
+        Operation::new("delete", "/tables/{table_id}", "Delete a table, tables with past orders are archived")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .response::<SuccessResponse>(gen, 200, "Table deleted or archived"),
+        Operation::new("put", "/tables/{table_id}/server", "Assign a server to a table, null unassigns it")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<TableServerRequest>(gen)
+            .response::<TableServerResponse>(gen, 200, "Server assigned"),
+        Operation::new("get", "/tables/{table_id}/orders", "List the orders of a table a page at a time, the newest first")
+            .query::<OrderListQuery>(gen)
+            .response::<Page<OrderResponse>>(gen, 200, "A page of orders"),
+        Operation::new("post", "/tables/{table_id}/orders", "Order for the table, items are added to its open order if it has one")
+            .header(IDEMPOTENCY_KEY, IDEMPOTENCY)
+            .body::<OrderRequestBody>(gen)
+            .response::<SuccessResponse>(gen, 201, "Order created")
+            .response::<SuccessResponse>(gen, 200, "Items added to the open order"),
+        Operation::new("get", "/tables/{table_id}/items", "Deprecated, list the items of the open order of a table")
+            .query::<TableItemsQuery>(gen)
+            .response::<Vec<SeatItemsResponse>>(gen, 200, "The items, with group_by=seat grouped per seat")
+            .deprecated(),
+        Operation::new("get", "/tables/{table_id}/items/{menu_id}", "Deprecated, get the item of a menu on the open order of a table, the seat is needed if the menu is on several lines")
+            .query::<SeatQuery>(gen)
+            .response::<OrderItemResponse>(gen, 200, "The item")
+            .deprecated(),
+        Operation::new("get", "/tables/{table_id}/bill", "Get the itemised bill of the open order of a table")
+            .response::<BillResponse>(gen, 200, "The bill"),
+        Operation::new("post", "/tables/{table_id}/bill/split", "Split the bill of a table evenly, per seat or by custom allocations")
+            .body::<SplitBillRequest>(gen)
+            .response::<SplitBillResponse>(gen, 201, "The sub-bills"),
+        Operation::new("get", "/tables/{table_id}/bill/split", "List the sub-bills of a table")
+            .response::<SplitBillResponse>(gen, 200, "The sub-bills"),
+        // Payments
+        Operation::new("post", "/bills/{sub_bill_id}/pay", "Pay a sub-bill")
+            .body::<PaymentRequest>(gen)
+            .response::<CheckoutResponse>(gen, 201, "Payment recorded"),
+        Operation::new("post", "/payments/{payment_id}/refund", "Refund a captured payment")
+            .body::<PaymentReasonRequest>(gen)
+            .response::<PaymentResponse>(gen, 200, "The refunded payment"),
+        Operation::new("post", "/payments/{payment_id}/void", "Void a captured payment of an open order")
+            .body::<PaymentReasonRequest>(gen)
+            .response::<PaymentResponse>(gen, 200, "The voided payment"),
+        // Promotions and service charges
+        Operation::new("get", "/promotions", "List all promotions")
+            .response::<Vec<PromotionResponse>>(gen, 200, "The promotions"),
+        Operation::new("post", "/promotions", "Create a promotion, happy hour or coupon")
+            .body::<Promotion>(gen)
+            .response::<CreatedResponse>(gen, 201, "Promotion created"),
+        Operation::new("post", "/promotions/create", "Deprecated alias of POST /promotions")
+            .body::<Promotion>(gen)
+            .response::<CreatedResponse>(gen, 201, "Promotion created")
+            .deprecated(),
+        Operation::new("get", "/service-charges", "List all service charge rules")
+            .response::<Vec<ServiceChargeRuleResponse>>(gen, 200, "The rules"),
+        Operation::new("post", "/service-charges", "Create a service charge rule")
+            .body::<ServiceChargeRule>(gen)
+            .response::<CreatedResponse>(gen, 201, "Rule created"),
+        Operation::new("post", "/service-charges/create", "Deprecated alias of POST /service-charges")
+            .body::<ServiceChargeRule>(gen)
+            .response::<CreatedResponse>(gen, 201, "Rule created")
+            .deprecated(),
+        Operation::new("get", "/reports/daily", "End-of-day report, today without a date")
+            .query::<ReportQuery>(gen)
+            .response::<DailyReportResponse>(gen, 200, "The report"),
+        // Menus
+        Operation::new("get", "/menus", "List menus a page at a time")
+            .query::<MenuListQuery>(gen)
+            .response::<Page<MenuResponse>>(gen, 200, "A page of menus"),
+        Operation::new("post", "/menus", "Create a menu, an existing name returns the existing menu and restores it if archived")
+            .body::<Menu>(gen)
+            .response::<CreatedResponse>(gen, 201, "Menu created"),
+        Operation::new("post", "/menus/create", "Deprecated alias of POST /menus")
+            .body::<Menu>(gen)
+            .response::<CreatedResponse>(gen, 201, "Menu created")
+            .deprecated(),
+        Operation::new("post", "/menus/import", "Create menus from a CSV or JSON file, or update those with the same name. Nothing is written if a row is invalid")
+            .query::<MenuImportQuery>(gen)
+            .body::<Vec<Menu>>(gen)
+            .csv_body()
+            .response::<MenuImportResponse>(gen, 200, "What the import did to every row, or would do on a dry run")
+            .response::<MenuImportResponse>(gen, 422, "Nothing was imported, the errors are listed by row"),
+        Operation::new("get", "/menus/export", "The menus that are not archived as a CSV or JSON file that can be imported again")
+            .query::<MenuExportQuery>(gen)
+            .response::<Vec<Menu>>(gen, 200, "The menus as an attachment, prices in cents in every version")
+            .csv_response(200),
+        Operation::new("get", "/menus/{menu_id}", "Get a menu, archived menus included")
+            .header(IF_NONE_MATCH, IF_NONE_MATCH_DESCRIPTION)
+            .response::<MenuResponse>(gen, 200, "The menu, its version is sent as ETag")
+            .empty_response(304, "The menu did not change"),
+        Operation::new("put", "/menus/{menu_id}", "Replace a menu, items already ordered keep their price")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<Menu>(gen)
+            .response::<MenuResponse>(gen, 200, "The menu, its version is sent as ETag"),
+        Operation::new("patch", "/menus/{menu_id}", "Update some fields of a menu, null clears the category or station")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .body::<MenuPatch>(gen)
+            .response::<MenuResponse>(gen, 200, "The menu, its version is sent as ETag"),
+        Operation::new("delete", "/menus/{menu_id}", "Delete a menu, menus that were ordered or have promotions are archived")
+            .header(IF_MATCH, IF_MATCH_DESCRIPTION)
+            .response::<SuccessResponse>(gen, 200, "Menu deleted or archived"),
+        // Event log
+        Operation::new("get", "/events/log", "Changes to tables, orders and their items after an event id, oldest first")
+            .query::<EventLogQuery>(gen)
+            .response::<EventLogResponse>(gen, 200, "The events, ask for the next ones since last_id"),
+        Operation::new("get", "/tables/{table_id}/history", "The orders of a table as they were at a time, rebuilt from their events")
+            .query::<TableHistoryQuery>(gen)
+            .response::<TableHistoryResponse>(gen, 200, "The orders then with their items at that time, the newest first"),
+        // Audit log
+        Operation::new("get", "/audit", "Changes made by requests, an entry per entity changed with who made it and the entity before and after, and requests that failed. Sent with the manager role")
+            .header("X-Staff-Role", "Role of the staff member as the client states it, manager to read the log. Not authenticated")
+            .query::<AuditQuery>(gen)
+            .response::<Page<AuditEntryResponse>>(gen, 200, "A page of entries, the newest first"),
+        // Webhooks
+        Operation::new("get", "/webhooks", "List all webhooks, without their secrets")
+            .response::<Vec<WebhookResponse>>(gen, 200, "The webhooks"),
+        Operation::new("post", "/webhooks", "Create a webhook, events of its kinds are POSTed to its url signed with HMAC-SHA256")
+            .body::<Webhook>(gen)
+            .response::<WebhookResponse>(gen, 201, "Webhook created, the only time its secret is shown"),
+        Operation::new("get", "/webhooks/{webhook_id}", "Get a webhook")
+            .response::<WebhookResponse>(gen, 200, "The webhook"),
+        Operation::new("patch", "/webhooks/{webhook_id}", "Update some fields of a webhook, inactive webhooks keep their deliveries until active again")
+            .body::<WebhookPatch>(gen)
+            .response::<WebhookResponse>(gen, 200, "The webhook"),
+        Operation::new("delete", "/webhooks/{webhook_id}", "Delete a webhook and its deliveries")
+            .response::<SuccessResponse>(gen, 200, "Webhook deleted"),
+        Operation::new("get", "/webhooks/{webhook_id}/dead-letters", "Deliveries of a webhook that failed every attempt")
+            .response::<Vec<WebhookDeliveryResponse>>(gen, 200, "The deliveries, oldest first"),
+        Operation::new("post", "/webhooks/{webhook_id}/deliveries/{delivery_id}/redeliver", "Send a dead delivery again with a fresh set of attempts")
+            .response::<WebhookDeliveryResponse>(gen, 202, "The delivery, pending again"),
+        // Documentation
+        Operation::new("get", "/openapi.json", "This document")
+            .empty_response(200, "The OpenAPI document"),
+        Operation::new("get", "/docs", "Swagger UI for this document")
+            .empty_response(200, "HTML page"),
+        // GraphQL
+        Operation::new("post", "/graphql", "Run a GraphQL query or mutation over tables, menus, orders and items")
+            .body::<GraphQLRequest>(gen)
+            .empty_response(200, "GraphQL response, errors are listed in it with the code of the error envelope"),
+        Operation::new("get", "/graphql", "GraphiQL to try out GraphQL queries")
+            .empty_response(200, "HTML page"),
+        Operation::new("get", "/graphql/ws", "GraphQL subscriptions over a WebSocket, graphql-transport-ws or graphql-ws")
+            .empty_response(101, "Switching to the WebSocket"),
+        Operation::new("get", "/ws", "Changes to orders and their items over a WebSocket, for a table, the kitchen or a station")
+            .query::<LiveUpdatesQuery>(gen)
+            .empty_response(101, "Switching to the WebSocket"),
+        Operation::new("get", "/events", "Changes to orders and their items as Server-Sent Events, of one table or all of them")
+            .query::<EventStreamQuery>(gen)
+            .header("Last-Event-ID", "Id of the last event received, the events after it are sent first")
+            .empty_response(200, "text/event-stream, the event name is the kind of change and the data the change as JSON"),
+    ]
+}
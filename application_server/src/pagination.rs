@@ -0,0 +1,201 @@
+// src/pagination.rs
+use crate::errors::ApiError;
+use crate::validation::FieldError;
+use rusqlite::types::{ToSql, ToSqlOutput};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Page size when the request has no limit
+pub const DEFAULT_LIMIT: i64 = 50;
+
+/// A column clients may sort a listing by, name is what they send and expr the SQL it sorts on
+pub struct SortColumn {
+    pub name: &'static str,
+    pub expr: &'static str,
+}
+
+/// Sort of a listing, ties are broken by id in the same direction
+pub struct Sort {
+    pub column: &'static SortColumn,
+    pub descending: bool,
+}
+
+/// Value of the sort column of the last row of a page
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CursorValue {
+    Int(i64),
+    Text(String),
+}
+
+/// Position after the last row of a page. Sent to clients hex encoded so they treat it as opaque
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub sort: String,
+    pub value: CursorValue,
+    pub id: i64,
+}
+
+/// What page of a listing to return
+pub struct PageRequest {
+    pub limit: i64,
+    pub sort: Sort,
+    pub after: Option<Cursor>,
+}
+
+/// Body of every paginated listing
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub links: PageLinks,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PageLinks {
+    pub next: Option<String>, // Same listing after the last row of this page, None on the last page
+}
+
+impl ToSql for CursorValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            CursorValue::Int(value) => value.to_sql(),
+            CursorValue::Text(value) => value.to_sql(),
+        }
+    }
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_string(self).expect("Cursor is always serializable");
+        json.bytes().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn decode(encoded: &str) -> Option<Cursor> {
+        if !encoded.len().is_multiple_of(2) || !encoded.is_ascii() {
+            return None;
+        }
+        let bytes = (0..encoded.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&encoded[index..index + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+impl Sort {
+    /// Parse a sort like "name" or "-price" against the columns a listing allows, the first is the default
+    pub fn parse(sort: Option<&str>, allowed: &'static [SortColumn]) -> Result<Sort, FieldError> {
+        let sort = match sort {
+            None => return Ok(Sort { column: &allowed[0], descending: false }),
+            Some(sort) => sort,
+        };
+        let (name, descending) = match sort.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (sort, false),
+        };
+        match allowed.iter().find(|column| column.name == name) {
+            Some(column) => Ok(Sort { column, descending }),
+            None => {
+                let names: Vec<&str> = allowed.iter().map(|column| column.name).collect();
+                Err(FieldError::new("sort", "not_allowed", format!("Can only sort by {}, prefix with - for descending", names.join(", "))))
+            }
+        }
+    }
+
+    /// The sort as clients send it
+    pub fn key(&self) -> String {
+        match self.descending {
+            true => format!("-{}", self.column.name),
+            false => self.column.name.to_string(),
+        }
+    }
+
+    /// ORDER BY clause, id_expr breaks ties
+    pub fn order_by(&self, id_expr: &str) -> String {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        format!("{} {}, {} {}", self.column.expr, direction, id_expr, direction)
+    }
+
+    /// Condition keeping the rows after the cursor bound to value_param and id_param, true without a cursor
+    pub fn after(&self, id_expr: &str, value_param: usize, id_param: usize) -> String {
+        let operator = if self.descending { "<" } else { ">" };
+        format!(
+            "(?{value} IS NULL OR {expr} {op} ?{value} OR ({expr} = ?{value} AND {id} {op} ?{id_param}))",
+            value = value_param, expr = self.column.expr, op = operator, id = id_expr, id_param = id_param
+        )
+    }
+}
+
+impl PageRequest {
+    /// Check the paging parameters of a listing request
+    pub fn new(limit: Option<i64>, after: Option<&str>, sort: Option<&str>, allowed: &'static [SortColumn]) -> Result<PageRequest, ApiError> {
+        let mut errors = Vec::new();
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=200).contains(&limit) {
+            errors.push(FieldError::new("limit", "range", "Must be between 1 and 200"));
+        }
+        let sort = match Sort::parse(sort, allowed) {
+            Ok(sort) => Some(sort),
+            Err(err) => {
+                errors.push(err);
+                None
+            }
+        };
+        let after = match (after, &sort) {
+            (Some(after), Some(sort)) => match Cursor::decode(after).filter(|cursor| cursor.sort == sort.key()) {
+                Some(cursor) => Some(cursor),
+                None => {
+                    errors.push(FieldError::new("after", "invalid", "Not a cursor of this listing and sort"));
+                    None
+                }
+            },
+            _ => None,
+        };
+        match sort {
+            Some(sort) if errors.is_empty() => Ok(PageRequest { limit, sort, after }),
+            _ => Err(ApiError::Validation(errors)),
+        }
+    }
+
+    /// Cursor value and id to bind for the after condition
+    pub fn after_params(&self) -> (Option<&CursorValue>, Option<i64>) {
+        match &self.after {
+            Some(cursor) => (Some(&cursor.value), Some(cursor.id)),
+            None => (None, None),
+        }
+    }
+
+    /// Rows to fetch, one more than the limit tells if there is a next page
+    pub fn fetch(&self) -> i64 {
+        self.limit + 1
+    }
+}
+
+impl<T> Page<T> {
+    /// Turn the rows fetched for a page into the page. cursor_of gives the sort value and id of a row,
+    /// next_link the link for a cursor
+    pub fn new(mut rows: Vec<T>, request: &PageRequest, cursor_of: impl Fn(&T, &str) -> (CursorValue, i64), next_link: impl Fn(&str) -> String) -> Page<T> {
+        let has_more = rows.len() as i64 > request.limit;
+        rows.truncate(request.limit as usize);
+        let next_cursor = match rows.last() {
+            Some(last) if has_more => {
+                let (value, id) = cursor_of(last, request.sort.column.name);
+                Some(Cursor { sort: request.sort.key(), value, id }.encode())
+            }
+            _ => None,
+        };
+        let next = next_cursor.as_deref().map(next_link);
+        Page { data: rows, next_cursor, links: PageLinks { next } }
+    }
+}
+
+/// Link to a listing with its query, query is expected to serialize without the cursor
+pub fn link<Q: Serialize>(path: &str, query: &Q, after: &str) -> String {
+    let query = serde_urlencoded::to_string(query).unwrap_or_default();
+    let after = serde_urlencoded::to_string([("after", after)]).unwrap_or_default();
+    match query.is_empty() {
+        true => format!("{}?{}", path, after),
+        false => format!("{}?{}&{}", path, query, after),
+    }
+}
@@ -0,0 +1,96 @@
+// src/payments.rs
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Error returned by a Payment Processor
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaymentError {
+    /// The card or the charge was refused
+    Declined(String),
+    /// The processor could not be reached or failed
+    Unavailable(String),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaymentError::Declined(message) => write!(f, "Payment declined: {}", message),
+            PaymentError::Unavailable(message) => write!(f, "Payment processor unavailable: {}", message),
+        }
+    }
+}
+
+/// Card payments go through a Payment Processor. Amounts are in cents.
+/// Every successful call returns the reference of the processor, which is stored with the payment
+pub trait PaymentProcessor: Send + Sync {
+    /// Charge a card, the token identifies the card or the authorisation at the processor
+    fn charge(&self, amount: i64, token: &str) -> Result<String, PaymentError>;
+    /// Refund an earlier charge
+    fn refund(&self, reference: &str, amount: i64) -> Result<String, PaymentError>;
+    /// Cancel an earlier charge before it is settled
+    fn void(&self, reference: &str) -> Result<String, PaymentError>;
+}
+
+/// Cards are handled by the in-store terminal. The terminal's authorisation code is sent as the
+/// token and recorded as reference, refunds and voids are done on the terminal as well
+pub struct TerminalProcessor;
+
+impl PaymentProcessor for TerminalProcessor {
+    fn charge(&self, _amount: i64, token: &str) -> Result<String, PaymentError> {
+        if token.trim().is_empty() {
+            return Err(PaymentError::Declined("Missing terminal authorisation code".to_string()));
+        }
+        Ok(token.to_string())
+    }
+
+    fn refund(&self, reference: &str, _amount: i64) -> Result<String, PaymentError> {
+        Ok(format!("refund-{}", reference))
+    }
+
+    fn void(&self, reference: &str) -> Result<String, PaymentError> {
+        Ok(format!("void-{}", reference))
+    }
+}
+
+/// Local processor that approves every card except tokens starting with "decline",
+/// tokens starting with "offline" behave as if the processor is down. Used in tests and for running the server without a terminal
+pub struct FakeProcessor;
+
+/// References of the fake processor are numbered for the whole process
+static FAKE_REFERENCE: AtomicU64 = AtomicU64::new(0);
+
+impl FakeProcessor {
+    fn next_reference(&self, prefix: &str) -> String {
+        format!("{}-{}", prefix, FAKE_REFERENCE.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+}
+
+impl PaymentProcessor for FakeProcessor {
+    fn charge(&self, amount: i64, token: &str) -> Result<String, PaymentError> {
+        if token.starts_with("decline") {
+            return Err(PaymentError::Declined(format!("Card {} refused {} cents", token, amount)));
+        }
+        if token.starts_with("offline") {
+            return Err(PaymentError::Unavailable("Fake processor is offline".to_string()));
+        }
+        Ok(self.next_reference("fake-charge"))
+    }
+
+    fn refund(&self, _reference: &str, _amount: i64) -> Result<String, PaymentError> {
+        Ok(self.next_reference("fake-refund"))
+    }
+
+    fn void(&self, _reference: &str) -> Result<String, PaymentError> {
+        Ok(self.next_reference("fake-void"))
+    }
+}
+
+/// Processor configured with PAYMENT_PROCESSOR, "terminal" (default) or "fake"
+pub fn get_payment_processor() -> Arc<dyn PaymentProcessor> {
+    match env::var("PAYMENT_PROCESSOR").as_deref() {
+        Ok("fake") => Arc::new(FakeProcessor),
+        _ => Arc::new(TerminalProcessor),
+    }
+}
@@ -0,0 +1,79 @@
+// src/preconditions.rs
+use crate::errors::ApiError;
+use rusqlite::{params, Connection};
+
+/// Header of writes that should only go through while the resource is at the version the client last saw
+pub const IF_MATCH_HEADER: &str = "if-match";
+
+/// Header of reads that only want the resource if it changed since the version the client has
+pub const IF_NONE_MATCH_HEADER: &str = "if-none-match";
+
+/// Resources that carry a version, by the table they are stored in
+#[derive(Debug, Clone, Copy)]
+pub enum Versioned {
+    Table,
+    Menu,
+    Order,
+}
+
+impl Versioned {
+    fn table(self) -> &'static str {
+        match self {
+            Versioned::Table => "tables",
+            Versioned::Menu => "menus",
+            Versioned::Order => "orders",
+        }
+    }
+}
+
+/// Strong ETag of a version of a resource
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Current version of a resource, None if it does not exist
+pub fn current_version(conn: &Connection, resource: Versioned, id: i64) -> rusqlite::Result<Option<i64>> {
+    let query = format!("SELECT version FROM {} WHERE id = ?1", resource.table());
+    match conn.query_row(&query, params![id], |row| row.get(0)) {
+        Ok(version) => Ok(Some(version)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Refuse a write sent with If-Match unless the resource is still at one of the listed versions.
+/// Returns the version that matched, which the write must still find unless it runs in the same transaction.
+/// Missing resources are left to the write to report
+pub fn check_if_match(conn: &Connection, if_match: Option<&str>, resource: Versioned, id: i64) -> Result<Option<i64>, ApiError> {
+    let if_match = match if_match {
+        Some(if_match) => if_match,
+        None => return Ok(None),
+    };
+    match current_version(conn, resource, id)? {
+        Some(version) if tags(if_match).any(|tag| tag == etag(version)) => Ok(Some(version)),
+        Some(version) if !tags(if_match).any(|tag| tag == "*") => Err(changed(version)),
+        _ => Ok(None),
+    }
+}
+
+/// Why a write guarded by a version changed nothing: the resource was changed since, or it does not exist
+pub fn not_written(conn: &Connection, resource: Versioned, id: i64, missing: &str) -> ApiError {
+    match current_version(conn, resource, id) {
+        Ok(Some(version)) => changed(version),
+        Ok(None) => ApiError::NotFound(missing.to_string()),
+        Err(err) => err.into(),
+    }
+}
+
+fn changed(version: i64) -> ApiError {
+    ApiError::PreconditionFailed(format!("Resource was changed since, its current ETag is {}", etag(version)))
+}
+
+/// True if the client already has this version. Weak tags compare equal to strong ones
+pub fn not_modified(if_none_match: Option<&str>, version: i64) -> bool {
+    if_none_match.is_some_and(|header| tags(header).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag(version)))
+}
+
+fn tags(header: &str) -> impl Iterator<Item = &str> {
+    header.split(',').map(str::trim)
+}
@@ -0,0 +1,124 @@
+// src/promotions.rs
+use crate::models::{AdjustmentResponse, BillLineResponse, DiscountKind, ManualDiscountResponse, PromotionKind, PromotionResponse, PromotionScope};
+
+/// Minute of the day for a "HH:MM" time
+pub fn minute_of_day(time: &str) -> Option<i64> {
+    let (hours, minutes) = time.split_once(':')?;
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    if (0..24).contains(&hours) && (0..60).contains(&minutes) {
+        Some(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+/// Check if an item ordered at a minute of the day falls in the window of a promotion.
+/// Promotions without a window always apply, windows can run past midnight
+fn in_window(promotion: &PromotionResponse, ordered_minute: Option<i64>) -> bool {
+    let (starts_at, ends_at) = match (&promotion.starts_at, &promotion.ends_at) {
+        (Some(starts_at), Some(ends_at)) => (minute_of_day(starts_at), minute_of_day(ends_at)),
+        _ => return true,
+    };
+    match (starts_at, ends_at, ordered_minute) {
+        (Some(start), Some(end), Some(minute)) if start <= end => start <= minute && minute < end,
+        (Some(start), Some(end), Some(minute)) => minute >= start || minute < end,
+        _ => false,
+    }
+}
+
+fn matches(promotion: &PromotionResponse, line: &BillLineResponse) -> bool {
+    match promotion.scope {
+        PromotionScope::Item => promotion.menu_id == Some(line.menu_id),
+        PromotionScope::Category => promotion.category.is_some() && promotion.category == line.category,
+        PromotionScope::Order => true,
+    }
+}
+
+/// Discount of one promotion on the lines it applies to, in cents
+/// Saturates rather than overflows, the bill caps discounts at what the lines cost anyway
+fn discount(promotion: &PromotionResponse, lines: &[&BillLineResponse]) -> i64 {
+    let eligible_amount = lines.iter().map(|line| line.amount).fold(0, i64::saturating_add);
+    let eligible_units = lines.iter().map(|line| line.quantity).fold(0, i64::saturating_add);
+    match promotion.kind {
+        PromotionKind::Percent => eligible_amount.saturating_mul(promotion.value.clamp(0, 100)) / 100,
+        PromotionKind::Fixed if promotion.scope == PromotionScope::Order => promotion.value.min(eligible_amount),
+        PromotionKind::Fixed => promotion.value.saturating_mul(eligible_units).min(eligible_amount),
+        PromotionKind::BuyXGetY => {
+            let buy = promotion.buy_quantity.and_then(|buy| usize::try_from(buy).ok()).unwrap_or(0);
+            let get = promotion.get_quantity.and_then(|get| usize::try_from(get).ok()).unwrap_or(0);
+            let group = match buy.checked_add(get) {
+                Some(group) if buy >= 1 && get >= 1 => group,
+                _ => return 0,
+            };
+            // The cheapest units of every full group are free
+            let mut unit_prices: Vec<i64> = lines
+                .iter()
+                .flat_map(|line| std::iter::repeat_n(line.unit_price, line.quantity.max(0) as usize))
+                .collect();
+            unit_prices.sort_by(|a, b| b.cmp(a));
+            unit_prices
+                .chunks_exact(group)
+                .map(|group| group[buy..].iter().copied().fold(0, i64::saturating_add))
+                .fold(0, i64::saturating_add)
+        }
+    }
+}
+
+/// Adjustment lines of all promotions that apply to the bill, one line per promotion.
+/// Coupon promotions only apply once their coupon is redeemed on the order
+pub fn promotion_adjustments(lines: &[BillLineResponse], promotions: &[PromotionResponse], coupon_promotion_ids: &[i64]) -> Vec<AdjustmentResponse> {
+    promotions
+        .iter()
+        .filter(|promotion| promotion.active)
+        .filter(|promotion| promotion.coupon_code.is_none() || coupon_promotion_ids.contains(&promotion.id))
+        .filter_map(|promotion| {
+            let eligible: Vec<&BillLineResponse> = lines
+                .iter()
+                .filter(|line| matches(promotion, line) && in_window(promotion, line.ordered_minute))
+                .collect();
+            let amount = discount(promotion, &eligible);
+            if amount <= 0 {
+                return None;
+            }
+            Some(AdjustmentResponse {
+                kind: "promotion".to_string(),
+                promotion_id: Some(promotion.id),
+                discount_id: None,
+                description: promotion.name.clone(),
+                reason_code: None,
+                amount: -amount,
+            })
+        })
+        .collect()
+}
+
+/// Adjustment line of a manual discount on the subtotal
+pub fn manual_discount_adjustment(discount: &ManualDiscountResponse, subtotal: i64) -> AdjustmentResponse {
+    let amount = match discount.kind {
+        DiscountKind::Percent => subtotal.saturating_mul(discount.value.clamp(0, 100)) / 100,
+        DiscountKind::Fixed => discount.value,
+    };
+    AdjustmentResponse {
+        kind: "manual_discount".to_string(),
+        promotion_id: None,
+        discount_id: Some(discount.id),
+        description: format!("Manual discount ({})", discount.reason_code.as_str()),
+        reason_code: Some(discount.reason_code.as_str().to_string()),
+        amount: -amount.max(0),
+    }
+}
+
+/// Discounts can't take the total below zero. Adjustments are reduced from the last one backwards
+pub fn cap_adjustments(subtotal: i64, adjustments: &mut Vec<AdjustmentResponse>) {
+    let mut excess = -(subtotal + adjustments.iter().map(|adjustment| adjustment.amount).sum::<i64>());
+    for adjustment in adjustments.iter_mut().rev() {
+        if excess <= 0 {
+            break;
+        }
+        let reduction = excess.min(-adjustment.amount);
+        adjustment.amount += reduction;
+        excess -= reduction;
+    }
+    adjustments.retain(|adjustment| adjustment.amount != 0);
+}
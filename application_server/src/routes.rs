@@ -7,146 +7,889 @@ use crate::handlers::{
     create_menu_handler,
     list_order_handler,
     delete_order_item_handler,
+    delete_order_item_for_table_handler,
+    create_table_order_handler,
+    get_order_handler,
+    list_table_orders_handler,
+    list_order_items_handler,
+    get_order_item_handler,
+    patch_order_item_handler,
     list_order_items_for_table_handler,
-    get_order_item_for_table_handler
+    get_order_item_for_table_handler,
+    get_bill_handler,
+    split_bill_handler,
+    list_sub_bills_handler,
+    pay_sub_bill_handler,
+    create_payment_handler,
+    list_payments_handler,
+    refund_payment_handler,
+    void_payment_handler,
+    list_promotion_handler,
+    create_promotion_handler,
+    redeem_coupon_handler,
+    create_manual_discount_handler,
+    assign_table_server_handler,
+    list_service_charge_rules_handler,
+    create_service_charge_rule_handler,
+    daily_report_handler,
+    get_table_handler,
+    update_table_handler,
+    patch_table_handler,
+    delete_table_handler,
+    get_menu_handler,
+    update_menu_handler,
+    patch_menu_handler,
+    delete_menu_handler,
+    event_log_handler,
+    list_webhooks_handler,
+    create_webhook_handler,
+    get_webhook_handler,
+    patch_webhook_handler,
+    delete_webhook_handler,
+    list_dead_letters_handler,
+    redeliver_handler,
+    graphql_handler,
+    live_updates_handler,
+    event_stream_handler,
+    table_history_handler,
+    audit_log_handler,
+    batch_handler,
+    import_menus_handler,
+    export_menus_handler
 };
+use crate::idempotency::IDEMPOTENCY_KEY_HEADER;
+use crate::preconditions::{IF_MATCH_HEADER, IF_NONE_MATCH_HEADER};
+use crate::models::{MenuImportQuery, MenuExportQuery, SeatQuery, TableItemsQuery, ReportQuery, TableListQuery, MenuListQuery, OrderListQuery, LiveUpdatesQuery, EventStreamQuery, EventLogQuery, TableHistoryQuery, AuditQuery};
+use crate::versions::{render, ApiVersion, DEPRECATED_SINCE};
+use warp::filters::BoxedFilter;
+use warp::reply::Response;
 use warp::{Filter, Rejection, Reply};
 use rusqlite::Connection;
 use crate::db::get_db_conn;
+use crate::payments::{get_payment_processor, PaymentProcessor};
 use std::convert::Infallible;
+use std::sync::Arc;
+use serde_json::json;
+use crate::errors::{ApiError, request_id, stamp_request_id};
+use crate::openapi;
+use crate::graphql;
+use async_graphql::http::{GraphiQLSource, WebSocketProtocols};
+use async_graphql_warp::{graphql_protocol, GraphQLBadRequest, GraphQLWebSocket};
+use warp::ws::Ws;
 
-/// Middleware to handle errors and convert them into a JSON response
-/// For now it handles Route Not Found and Deserialization Error.
-/// We can add custom action to handle different type of error
-async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
-
-    // If route not found
-    if err.is_not_found() {
-        Ok(warp::reply::with_status(
-            warp::reply::json(&format!("Mahadi Error: {:?}", err)),
-            warp::http::StatusCode::NOT_FOUND,
-        ))
-    } else if let Some(_) = err.find::<warp::filters::body::BodyDeserializeError>() {
+/// Swagger UI page, its assets are loaded from a CDN
+const SWAGGER_UI: &str = include_str!("swagger_ui.html");
+
+/// Largest menu file that can be imported, in bytes
+const MENU_FILE_LIMIT: u64 = 1024 * 1024;
+
+/// Middleware to handle errors and convert them into the JSON error envelope
+/// Rejections of warp itself (unknown routes, bad bodies, queries and headers) are mapped to an ApiError
+async fn handle_rejection(err: Rejection) -> Result<ApiError, Infallible> {
+    let api_error = if err.is_not_found() {
+        // If route not found
+        ApiError::NotFound("Route not found".to_string())
+    } else if let Some(body_err) = err.find::<warp::filters::body::BodyDeserializeError>() {
         // If fail to deserialize request body
-        Ok(warp::reply::with_status(
-            warp::reply::json(&format!("Error: Failed to deserialize request body")),
-            warp::http::StatusCode::BAD_REQUEST,
-        ))
+        ApiError::BadRequest("Failed to deserialize request body".to_string(), Some(json!(body_err.to_string())))
+    } else if let Some(GraphQLBadRequest(graphql_err)) = err.find::<GraphQLBadRequest>() {
+        ApiError::BadRequest("Invalid GraphQL request".to_string(), Some(json!(graphql_err.to_string())))
+    } else if let Some(query_err) = err.find::<warp::reject::InvalidQuery>() {
+        ApiError::BadRequest("Invalid query string".to_string(), Some(json!(query_err.to_string())))
+    } else if let Some(header_err) = err.find::<warp::reject::MissingHeader>() {
+        ApiError::bad_request(header_err.to_string())
+    } else if err.find::<warp::reject::InvalidHeader>().is_some() {
+        ApiError::bad_request("Invalid header")
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        ApiError::bad_request("Request body must be JSON")
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        ApiError::bad_request("Request body is too large")
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        ApiError::bad_request("Content-Length is required")
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        ApiError::MethodNotAllowed
     } else {
-        // Default to Internal Server Error for other errors
-        Ok(warp::reply::with_status(
-            warp::reply::json(&format!("Error: {:?}", err)),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    }
+        // Anything else is unexpected and only logged
+        ApiError::Internal(format!("Unhandled rejection: {:?}", err))
+    };
+    Ok(api_error)
+}
+
+/// Mark the response of a deprecated path, the path keeps working until clients have moved on
+fn deprecated<T: Reply>(reply: T) -> warp::reply::WithHeader<T> {
+    warp::reply::with_header(reply, "deprecation", DEPRECATED_SINCE)
 }
 
 /// Helper function to provide a database connection to route handlers
 /// Returns a New Db connection Per Route
 fn with_db() -> impl Filter<Extract = (Connection,), Error = Infallible> + Clone {
-    warp::any().map(|| get_db_conn())
+    warp::any().map(get_db_conn)
 }
 
-/// This Route lists all orders. GET request
+/// Helper function to provide the configured payment processor to route handlers
+fn with_processor() -> impl Filter<Extract = (Arc<dyn PaymentProcessor>,), Error = Infallible> + Clone {
+    warp::any().map(get_payment_processor)
+}
+
+/// This Route lists orders a page at a time. GET request
+/// Filtered by status, table_id, created_from and created_to, sorted by id or created_at
 pub fn list_all_orders_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("orders")
+    warp::path!("orders")
         .and(warp::get())
         .and(with_db())
-        .and_then(|conn| list_order_handler(conn))
+        .and(warp::query::<OrderListQuery>())
+        .and_then(list_order_handler)
 }
 
 
 /// This Route creates a new order
-/// Its a POST request and expects table_id: i64 and menu_ids: vec![i64]
-/// If menu_ids is empty, return BAD REQUEST
-/// If there is already existing order (status=0) for this table_id, try to add new items t the existing order. Return success or error message
-/// If no exisiting order or order with (status=1), creates a new order and return id
+/// Its a POST request and expects table_id: i64 and menu_ids: vec![i64], or items: [{menu_id, quantity, note, seat}]
+/// party_size is optional, without it the number of seats ordered for is used for the service charge
+/// If both menu_ids and items are empty, return BAD REQUEST
+/// If the table has an open order, the items are added to it and OK is returned with its id
+/// Otherwise, e.g. when its last order is closed, a new order is created and CREATED is returned with its id
+/// With an Idempotency-Key header a retry replays the first response instead of adding the items again
 pub fn create_order_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("orders"/"create")
+    warp::path!("orders")
         .and(warp::post())
         .and(with_db())
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
         .and(warp::body::json())
-        .and_then(|conn, req_body| create_order_handler(conn, req_body))
-        
+        .and_then(create_order_handler)
+}
+
+/// Deprecated alias of create_order_route. POST /orders/create
+pub fn create_order_alias_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/"create")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
+        .and(warp::body::json())
+        .and_then(create_order_handler)
+        .map(deprecated)
+}
+
+/// This Route retrieves an order with its items and bill figures. /orders/{order_id}
+/// Sends the version as ETag, answers NOT MODIFIED if If-None-Match has it
+pub fn get_order_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64)
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_NONE_MATCH_HEADER))
+        .and_then(|order_id, conn, if_none_match| get_order_handler(conn, order_id, if_none_match))
 }
 
-/// This Route to delete specific menu from table.
-/// Its a delete request. /orders/{table_id}/items/{item_id}
-/// If item found for this table, deleted the item and return success/error message 
-/// If this is the las item in this table, update order status=1 marking it as complete
-pub fn delete_item_from_order_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("orders"/i64/"items"/i64)
+/// This Route lists the items of an order. /orders/{order_id}/items
+/// Add group_by=seat to get the items grouped per seat
+pub fn list_order_items_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"items")
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::query::<TableItemsQuery>())
+        .and_then(|order_id, conn, query| list_order_items_handler(conn, order_id, query))
+}
+
+/// This Route retrieves an item of an order. /orders/{order_id}/items/{item_id}
+pub fn get_order_item_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"items"/i64)
+        .and(warp::get())
+        .and(with_db())
+        .and_then(|order_id, order_item_id, conn| get_order_item_handler(conn, order_id, order_item_id))
+}
+
+/// This Route removes an item from an open order. /orders/{order_id}/items/{item_id}
+/// If this is the last item of the order, the order is deleted unless payments were made on it
+pub fn delete_order_item_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"items"/i64)
         .and(warp::delete())
         .and(with_db())
-        .and_then(|table_id, menu_id, conn| delete_order_item_handler(conn, table_id, menu_id))
-        
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and_then(|order_id, order_item_id, conn, if_match| delete_order_item_handler(conn, order_id, order_item_id, if_match))
+}
+
+/// Deprecated, use delete_order_item_route or patch_order_item_route. /orders/{table_id}/items/{menu_id}?seat={seat}
+/// Only served without a version prefix, with the meaning the path always had there: the ids are of the table and the menu.
+/// Under /v1 and /v2 the same path removes an item of an order by its id.
+/// Takes one unit of the menu off the open order of this table, the item is deleted when its last unit goes
+/// If this is the last item in this table, the order is deleted unless payments were made on it
+pub fn delete_item_from_order_route() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"items"/i64)
+        .and(warp::delete())
+        .and(with_db())
+        .and(warp::query::<SeatQuery>())
+        .and_then(|table_id, menu_id, conn, query| delete_order_item_for_table_handler(conn, table_id, menu_id, query))
+        .map(deprecated)
+        .map(Reply::into_response)
 }
 
-/// This Route lists all tables
+/// This Route sets the quantity of an item of an open order. PATCH /orders/{order_id}/items/{item_id}
+/// Body: {"quantity": 3}
+pub fn patch_order_item_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"items"/i64)
+        .and(warp::patch())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|order_id, order_item_id, conn, if_match, patch| patch_order_item_handler(conn, order_id, order_item_id, if_match, patch))
+}
+
+/// This Route lists the orders of a table a page at a time, the newest first. /tables/{table_id}/orders
+pub fn list_table_orders_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64/"orders")
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::query::<OrderListQuery>())
+        .and_then(|table_id, conn, query| list_table_orders_handler(conn, table_id, query))
+}
+
+/// This Route orders for a table. /tables/{table_id}/orders
+/// It expects the same body and Idempotency-Key header as create_order_route without the table_id
+pub fn create_table_order_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64/"orders")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
+        .and(warp::body::json())
+        .and_then(|table_id, conn, key, req_body| create_table_order_handler(conn, table_id, key, req_body))
+}
+
+/// This Route runs several order operations in one transaction. POST /batch
+/// It expects {"mode": "all_or_nothing" or "continue_on_error", "operations": [{"op": "add_items", ...}, ...]}
+pub fn batch_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("batch")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(batch_handler)
+}
+
+/// This Route lists tables a page at a time, filtered by code and section, sorted by id or code
 pub fn list_tables_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("tables")
+    warp::path!("tables")
         .and(warp::get())
         .and(with_db())
-        .and_then(|conn| list_table_handler(conn))
+        .and(warp::query::<TableListQuery>())
+        .and_then(list_table_handler)
 }
 
 /// This Route creates a table.
-/// It expects a code in the request POST body. Returns id on successfull creation
+/// It expects a code and optionally a section, table_type and server in the request POST body. Returns id on successfull creation
 pub fn create_table_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("tables"/"create")
+    warp::path!("tables")
         .and(warp::post())
         .and(with_db())
         .and(warp::body::json())
-        .and_then(|conn, req_body| create_table_handler(conn, req_body))
+        .and_then(create_table_handler)
 }
 
-/// This Route lists all menus for a table. /tables/{table_id}/items
+/// Deprecated alias of create_table_route. POST /tables/create
+pub fn create_table_alias_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/"create")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(create_table_handler)
+        .map(deprecated)
+}
+
+/// This Route retrieves a table. /tables/{table_id}
+/// Archived tables are returned with archived set
+/// Sends the version as ETag, answers NOT MODIFIED if If-None-Match has it
+pub fn get_table_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64)
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_NONE_MATCH_HEADER))
+        .and_then(|table_id, conn, if_none_match| get_table_handler(conn, table_id, if_none_match))
+}
+
+/// This Route replaces a table. /tables/{table_id}
+/// It expects the same body as creating a table, fields that are left out are cleared
+pub fn update_table_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64)
+        .and(warp::put())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|table_id, conn, if_match, req_body| update_table_handler(conn, table_id, if_match, req_body))
+}
+
+/// This Route updates some fields of a table. /tables/{table_id}
+/// Fields that are left out are kept, null clears section, table_type or server
+pub fn patch_table_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64)
+        .and(warp::patch())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|table_id, conn, if_match, req_body| patch_table_handler(conn, table_id, if_match, req_body))
+}
+
+/// This Route deletes a table. /tables/{table_id}
+/// A table with past orders is archived, hidden from the listing and closed for new orders.
+/// Returns CONFLICT while the table has an open order
+pub fn delete_table_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64)
+        .and(warp::delete())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and_then(|table_id, conn, if_match| delete_table_handler(conn, table_id, if_match))
+}
+
+/// This Route assigns a server to a table. /tables/{table_id}/server
+/// It expects a server in the request PUT body, null unassigns the table
+pub fn assign_table_server_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64/"server")
+        .and(warp::put())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|table_id, conn, if_match, req_body| assign_table_server_handler(conn, table_id, if_match, req_body))
+}
+
+/// Deprecated, use list_order_items_route. /tables/{table_id}/items
+/// Lists the items of the open order of a table, add group_by=seat to get the items grouped per seat
 pub fn list_order_items_for_table_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("tables"/i64/"items")
+    warp::path!("tables"/i64/"items")
         .and(warp::get())
         .and(with_db())
-        .and_then(|table_id, conn| list_order_items_for_table_handler(conn, table_id))
+        .and(warp::query::<TableItemsQuery>())
+        .and_then(|table_id, conn, query| list_order_items_for_table_handler(conn, table_id, query))
+        .map(deprecated)
 }
 
-/// This Route retrieves a specific menu for table. /tables/{table_id}/items/{item_id}
+/// Deprecated, use get_order_item_route. /tables/{table_id}/items/{menu_id}?seat={seat}
+/// Retrieves the item of a menu on the open order of a table
 pub fn get_item_from_order_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("tables"/i64/"items"/i64)
+    warp::path!("tables"/i64/"items"/i64)
         .and(warp::get())
         .and(with_db())
-        .and_then(|table_id, menu_id, conn| get_order_item_for_table_handler(conn, table_id, menu_id))
-        
+        .and(warp::query::<SeatQuery>())
+        .and_then(|table_id, menu_id, conn, query| get_order_item_for_table_handler(conn, table_id, menu_id, query))
+        .map(deprecated)
+}
+
+/// This Route returns the itemised bill of the open order of a table. /tables/{table_id}/bill
+/// Amounts are in cents
+pub fn get_bill_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64/"bill")
+        .and(warp::get())
+        .and(with_db())
+        .and_then(|table_id, conn| get_bill_handler(conn, table_id))
+}
+
+/// This Route splits the bill of a table into sub-bills. /tables/{table_id}/bill/split
+/// It expects a mode (even, seat or custom), guests for an even split and allocations for a custom split
+/// Sub-bills always add up to the bill total. Returns CONFLICT once a sub-bill is paid
+pub fn split_bill_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64/"bill"/"split")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(|table_id, conn, req_body| split_bill_handler(conn, table_id, req_body))
+}
+
+/// This Route lists the sub-bills of a table. /tables/{table_id}/bill/split
+pub fn list_sub_bills_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64/"bill"/"split")
+        .and(warp::get())
+        .and(with_db())
+        .and_then(|table_id, conn| list_sub_bills_handler(conn, table_id))
+}
+
+/// This Route pays a single sub-bill. /bills/{sub_bill_id}/pay
+/// It expects the same body as a payment on the order
+pub fn pay_sub_bill_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("bills"/i64/"pay")
+        .and(warp::post())
+        .and(with_db())
+        .and(with_processor())
+        .and(warp::body::json())
+        .and_then(|sub_bill_id, conn, processor, req_body| pay_sub_bill_handler(conn, processor, sub_bill_id, req_body))
+}
+
+/// This Route records a payment on an order. /orders/{order_id}/payments
+/// It expects a tender (cash, card or voucher), an amount in cents including the tip, a tip and a card_token for cards
+/// Partial payments are allowed, the order is closed once fully paid
+pub fn create_payment_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"payments")
+        .and(warp::post())
+        .and(with_db())
+        .and(with_processor())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|order_id, conn, processor, if_match, req_body| create_payment_handler(conn, processor, order_id, if_match, req_body))
+}
+
+/// This Route lists the payments of an order. /orders/{order_id}/payments
+pub fn list_payments_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"payments")
+        .and(warp::get())
+        .and(with_db())
+        .and_then(|order_id, conn| list_payments_handler(conn, order_id))
+}
+
+/// This Route refunds a payment. /payments/{payment_id}/refund
+/// It expects a reason in the request POST body
+pub fn refund_payment_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("payments"/i64/"refund")
+        .and(warp::post())
+        .and(with_db())
+        .and(with_processor())
+        .and(warp::body::json())
+        .and_then(|payment_id, conn, processor, req_body| refund_payment_handler(conn, processor, payment_id, req_body))
+}
+
+/// This Route voids a payment of an open order. /payments/{payment_id}/void
+/// It expects a reason in the request POST body
+pub fn void_payment_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("payments"/i64/"void")
+        .and(warp::post())
+        .and(with_db())
+        .and(with_processor())
+        .and(warp::body::json())
+        .and_then(|payment_id, conn, processor, req_body| void_payment_handler(conn, processor, payment_id, req_body))
+}
+
+/// This Route lists all promotions
+pub fn list_promotions_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("promotions")
+        .and(warp::get())
+        .and(with_db())
+        .and_then(list_promotion_handler)
+}
+
+/// This Route creates a promotion
+/// It expects a name, a kind (percent, fixed or buy_x_get_y) and a scope (item, category or order) with the
+/// menu_id or category it applies to. starts_at and ends_at ("HH:MM") make it a happy hour,
+/// a coupon_code makes it apply only to orders the coupon is redeemed on, up to usage_limit times
+pub fn create_promotion_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("promotions")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(create_promotion_handler)
+}
+
+/// Deprecated alias of create_promotion_route. POST /promotions/create
+pub fn create_promotion_alias_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("promotions"/"create")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(create_promotion_handler)
+        .map(deprecated)
+}
+
+/// This Route redeems a coupon on an order. /orders/{order_id}/coupons
+/// It expects a code in the request POST body and returns the recalculated bill
+pub fn redeem_coupon_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"coupons")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|order_id, conn, if_match, req_body| redeem_coupon_handler(conn, order_id, if_match, req_body))
+}
+
+/// This Route gives a manual discount on an order. /orders/{order_id}/discounts
+/// Needs the X-Staff-Role: manager header, which is not authenticated. It expects a kind (percent or fixed), a value and a reason_code
+/// (service_recovery, complaint, staff_meal, loyalty or manager_comp) and returns the recalculated bill
+pub fn create_manual_discount_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("orders"/i64/"discounts")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::header::optional::<String>("x-staff-role"))
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|order_id, conn, staff_role, if_match, req_body| create_manual_discount_handler(conn, order_id, staff_role, if_match, req_body))
+}
+
+/// This Route lists all service charge rules
+pub fn list_service_charge_rules_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("service-charges")
+        .and(warp::get())
+        .and(with_db())
+        .and_then(list_service_charge_rules_handler)
+}
+
+/// This Route creates a service charge rule
+/// It expects a min_party_size and percent, parties larger than min_party_size pay the service charge.
+/// section and table_type limit the rule to those tables, the most specific rule of a table is used
+pub fn create_service_charge_rule_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("service-charges")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(create_service_charge_rule_handler)
+}
+
+/// Deprecated alias of create_service_charge_rule_route. POST /service-charges/create
+pub fn create_service_charge_rule_alias_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("service-charges"/"create")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(create_service_charge_rule_handler)
+        .map(deprecated)
+}
+
+/// This Route returns the end-of-day report. /reports/daily?date={YYYY-MM-DD}
+/// Without a date it reports today
+pub fn daily_report_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("reports"/"daily")
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::query::<ReportQuery>())
+        .and_then(daily_report_handler)
 }
 
-/// This Route lists all menus
+/// This Route lists menus a page at a time, filtered by name and category, sorted by id, name or price
 pub fn list_menus_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("menus")
+    warp::path!("menus")
         .and(warp::get())
         .and(with_db())
-        .and_then(|conn| list_menu_handler(conn))
+        .and(warp::query::<MenuListQuery>())
+        .and_then(list_menu_handler)
         
 }
 
 ///  This Route creates a menu
-/// It expects a name in request POST body
+/// It expects a name and optionally a price in cents and a category in request POST body
 pub fn create_menu_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    return warp::path!("menus"/"create")
+    warp::path!("menus")
         .and(warp::post())
         .and(with_db())
         .and(warp::body::json())
-        .and_then(|conn, req_body| create_menu_handler(conn, req_body))
+        .and_then(create_menu_handler)
 }
 
-/// Combine all routes
-pub fn restaurent_routes()->impl Filter<Extract = impl Reply, Error = Rejection> + Clone{
-    let routes = create_order_route()
+/// Deprecated alias of create_menu_route. POST /menus/create
+pub fn create_menu_alias_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("menus"/"create")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(create_menu_handler)
+        .map(deprecated)
+}
+
+/// This Route imports menus from a CSV or JSON file. POST /menus/import?format=csv&dry_run=true
+/// The format is taken from the Content-Type if not given, rows are matched to menus by name
+pub fn import_menus_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("menus"/"import")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::query::<MenuImportQuery>())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(MENU_FILE_LIMIT))
+        .and(warp::body::bytes())
+        .and_then(import_menus_handler)
+}
+
+/// This Route exports the menus that are not archived as a CSV or JSON file. /menus/export?format=csv
+pub fn export_menus_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("menus"/"export")
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::query::<MenuExportQuery>())
+        .and_then(export_menus_handler)
+}
+
+/// This Route retrieves a menu. /menus/{menu_id}
+/// Archived menus are returned with archived set
+/// Sends the version as ETag, answers NOT MODIFIED if If-None-Match has it
+pub fn get_menu_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("menus"/i64)
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_NONE_MATCH_HEADER))
+        .and_then(|menu_id, conn, if_none_match| get_menu_handler(conn, menu_id, if_none_match))
+}
+
+/// This Route replaces a menu. /menus/{menu_id}
+/// It expects the same body as creating a menu. Items already ordered keep their price
+pub fn update_menu_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("menus"/i64)
+        .and(warp::put())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|menu_id, conn, if_match, req_body| update_menu_handler(conn, menu_id, if_match, req_body))
+}
+
+/// This Route updates some fields of a menu. /menus/{menu_id}
+/// Fields that are left out are kept, null clears the category
+pub fn patch_menu_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("menus"/i64)
+        .and(warp::patch())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(|menu_id, conn, if_match, req_body| patch_menu_handler(conn, menu_id, if_match, req_body))
+}
+
+/// This Route deletes a menu. /menus/{menu_id}
+/// A menu that was ordered or has promotions is archived, hidden from the listing and closed for new orders
+pub fn delete_menu_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("menus"/i64)
+        .and(warp::delete())
+        .and(with_db())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and_then(|menu_id, conn, if_match| delete_menu_handler(conn, menu_id, if_match))
+}
+
+/// This Route reads the log of the changes to tables and orders. /events/log?since={id}
+/// Every change is logged in its own transaction, consumers keep the last_id they read and ask for the events since
+pub fn event_log_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("events"/"log")
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::query::<EventLogQuery>())
+        .and_then(event_log_handler)
+}
+
+/// This Route rebuilds the orders of a table as they were at a time from their events. /tables/{table_id}/history?as_of={time}
+/// Items removed since are shown as they were then
+pub fn table_history_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("tables"/i64/"history")
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::query::<TableHistoryQuery>())
+        .and_then(|table_id, conn, query| table_history_handler(conn, table_id, query))
+}
+
+/// This Route reads the audit log, the newest entries first. /audit?entity_type={type}&entity_id={id}&actor={staff id}
+/// Needs the X-Staff-Role: manager header, which is not authenticated
+pub fn audit_log_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("audit")
+        .and(warp::get())
+        .and(with_db())
+        .and(warp::header::optional::<String>("x-staff-role"))
+        .and(warp::query::<AuditQuery>())
+        .and_then(audit_log_handler)
+}
+
+/// This Route lists all webhooks, without their secrets
+pub fn list_webhooks_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("webhooks")
+        .and(warp::get())
+        .and(with_db())
+        .and_then(list_webhooks_handler)
+}
+
+/// This Route creates a webhook
+/// It expects a url and the kinds of events to send to it, the secret signing them is generated if not given
+pub fn create_webhook_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("webhooks")
+        .and(warp::post())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(create_webhook_handler)
+}
+
+/// This Route gets a webhook. /webhooks/{webhook_id}
+pub fn get_webhook_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("webhooks"/i64)
+        .and(warp::get())
+        .and(with_db())
+        .and_then(|webhook_id, conn| get_webhook_handler(conn, webhook_id))
+}
+
+/// This Route updates some fields of a webhook, e.g. {"active": false} to pause it. /webhooks/{webhook_id}
+pub fn patch_webhook_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("webhooks"/i64)
+        .and(warp::patch())
+        .and(with_db())
+        .and(warp::body::json())
+        .and_then(|webhook_id, conn, patch| patch_webhook_handler(conn, webhook_id, patch))
+}
+
+/// This Route deletes a webhook with its deliveries. /webhooks/{webhook_id}
+pub fn delete_webhook_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("webhooks"/i64)
+        .and(warp::delete())
+        .and(with_db())
+        .and_then(|webhook_id, conn| delete_webhook_handler(conn, webhook_id))
+}
+
+/// This Route lists the deliveries of a webhook that failed every attempt. /webhooks/{webhook_id}/dead-letters
+pub fn list_dead_letters_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("webhooks"/i64/"dead-letters")
+        .and(warp::get())
+        .and(with_db())
+        .and_then(|webhook_id, conn| list_dead_letters_handler(conn, webhook_id))
+}
+
+/// This Route sends a dead delivery again. /webhooks/{webhook_id}/deliveries/{delivery_id}/redeliver
+pub fn redeliver_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("webhooks"/i64/"deliveries"/i64/"redeliver")
+        .and(warp::post())
+        .and(with_db())
+        .and_then(|webhook_id, delivery_id, conn| redeliver_handler(conn, webhook_id, delivery_id))
+}
+
+/// This Route serves the OpenAPI document of all routes. /openapi.json
+pub fn openapi_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("openapi.json")
+        .and(warp::get())
+        .map(|| warp::reply::json(openapi::document()))
+}
+
+/// This Route serves Swagger UI for the OpenAPI document. /docs
+pub fn docs_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("docs")
+        .and(warp::get())
+        .map(|| warp::reply::html(SWAGGER_UI))
+}
+
+/// This Route runs GraphQL queries and mutations. POST /graphql
+/// It expects {"query": ..., "variables": ...} and shares the models with the REST routes
+pub fn graphql_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("graphql")
+        .and(warp::post())
+        .and(with_db())
+        .and(async_graphql_warp::graphql(graphql::schema()))
+        .and_then(graphql_handler)
+}
+
+/// This Route serves GraphiQL to try out queries. /graphql
+pub fn graphiql_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let page = GraphiQLSource::build().endpoint("/graphql").subscription_endpoint("/graphql/ws").finish();
+    warp::path!("graphql")
+        .and(warp::get())
+        .map(move || warp::reply::html(page.clone()))
+}
+
+/// This Route runs GraphQL subscriptions over a WebSocket. /graphql/ws
+/// Each connection gets its own database connection for the fields of the changes it receives
+pub fn graphql_subscription_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let schema = graphql::schema();
+    warp::path!("graphql" / "ws")
+        .and(warp::get())
+        .and(warp::ws())
+        .and(graphql_protocol())
+        .map(move |ws: Ws, protocol: WebSocketProtocols| {
+            let schema = schema.clone();
+            let reply = ws.on_upgrade(move |socket| {
+                GraphQLWebSocket::new(socket, schema, protocol).with_data(graphql::data(get_db_conn())).serve()
+            });
+            warp::reply::with_header(reply, "sec-websocket-protocol", protocol.sec_websocket_protocol())
+        })
+}
+
+/// This Route pushes changes to orders and their items over a WebSocket. /ws?topics=table:3,kitchen&resume=...
+/// Clients poll no more, they subscribe to a table, the kitchen or a station and resume with the token of the last event
+pub fn live_updates_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("ws")
+        .and(warp::get())
+        .and(warp::query::<LiveUpdatesQuery>())
+        .and(warp::ws())
+        .and_then(live_updates_handler)
+}
+
+/// This Route streams changes to orders as Server-Sent Events for displays that can't use WebSockets. /events?table_id=3
+/// Browsers reconnect with a Last-Event-ID header and get the events they missed first
+pub fn event_stream_route() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("events")
+        .and(warp::get())
+        .and(warp::query::<EventStreamQuery>())
+        .and(warp::header::optional::<String>("last-event-id"))
+        .and_then(event_stream_handler)
+}
+
+/// Combine all routes of the API, as served under every version prefix
+/// Writes to a table, a menu or an order take an If-Match header with the ETag the client last saw
+/// and answer PRECONDITION FAILED if it was changed since
+/// Every route must have an entry in openapi::operations, a test requests each of them
+fn api_routes() -> BoxedFilter<(Response,)> {
+    create_order_route()
+    .or(create_order_alias_route())
+    .or(get_order_route())
+    .or(list_order_items_route())
+    .or(get_order_item_route())
+    .or(delete_order_item_route())
+    .or(list_table_orders_route())
+    .or(create_table_order_route())
+    .or(batch_route())
     .or(create_table_route())
+    .or(create_table_alias_route())
     .or(create_menu_route())
+    .or(create_menu_alias_route())
     .or(list_tables_route())
     .or(list_menus_route())
     .or(list_all_orders_route())
-    .or(delete_item_from_order_route())
+    .or(patch_order_item_route())
     .or(list_order_items_for_table_route())
-    .or(get_item_from_order_route());
+    .or(get_item_from_order_route())
+    .or(get_bill_route())
+    .or(split_bill_route())
+    .or(list_sub_bills_route())
+    .or(pay_sub_bill_route())
+    .or(create_payment_route())
+    .or(list_payments_route())
+    .or(refund_payment_route())
+    .or(void_payment_route())
+    .or(list_promotions_route())
+    .or(create_promotion_route())
+    .or(create_promotion_alias_route())
+    .or(redeem_coupon_route())
+    .or(create_manual_discount_route())
+    .or(assign_table_server_route())
+    .or(list_service_charge_rules_route())
+    .or(create_service_charge_rule_route())
+    .or(create_service_charge_rule_alias_route())
+    .or(daily_report_route())
+    .or(get_table_route())
+    .or(update_table_route())
+    .or(patch_table_route())
+    .or(delete_table_route())
+    .or(import_menus_route())
+    .or(export_menus_route())
+    .or(get_menu_route())
+    .or(update_menu_route())
+    .or(patch_menu_route())
+    .or(delete_menu_route())
+    .or(event_log_route())
+    .or(table_history_route())
+    .or(audit_log_route())
+    .or(list_webhooks_route())
+    .or(create_webhook_route())
+    .or(get_webhook_route())
+    .or(patch_webhook_route())
+    .or(delete_webhook_route())
+    .or(list_dead_letters_route())
+    .or(redeliver_route())
+    .map(Reply::into_response)
+    .boxed()
+}
+
+/// Mount the API under the prefix of a version, its responses are reshaped by the version
+fn versioned(version: ApiVersion) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path(version.prefix())
+        .and(api_routes())
+        .and_then(move |reply| render(version, true, reply))
+}
+
+/// Combine all routes
+/// The API is served under /v1 and /v2, and without a prefix as v1 for tablets already deployed,
+/// which also keep deleting items by table and menu
+pub fn restaurent_routes()->impl Filter<Extract = impl Reply, Error = Infallible> + Clone{
+    let unversioned = delete_item_from_order_route().or(api_routes()).unify();
+    let routes = versioned(ApiVersion::V1)
+    .or(versioned(ApiVersion::V2))
+    .or(unversioned.and_then(|reply| render(ApiVersion::V1, false, reply)))
+    .or(openapi_route())
+    .or(docs_route())
+    .or(graphql_route())
+    .or(graphiql_route())
+    .or(graphql_subscription_route())
+    .or(live_updates_route())
+    .or(event_stream_route());
 
-    routes.recover(handle_rejection)
+    // Every response carries a request id, error envelopes have it in the body too
+    warp::header::headers_cloned()
+        .map(request_id)
+        .and(routes.recover(handle_rejection))
+        .map(stamp_request_id)
 }
\ No newline at end of file
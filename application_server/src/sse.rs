@@ -0,0 +1,67 @@
+// src/sse.rs
+use crate::events::{self, Event, ResumePoint};
+use futures_util::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::sse;
+
+/// A feed being streamed to a display and the events waiting to be sent on it
+struct Feed {
+    table_id: Option<i64>,
+    last_seq: u64,
+    pending: VecDeque<sse::Event>,
+    receiver: broadcast::Receiver<Event>,
+}
+
+impl Feed {
+    /// Queue an event if it is for the table of the feed and was not sent already
+    fn queue(&mut self, event: &Event) {
+        if event.seq <= self.last_seq {
+            return;
+        }
+        self.last_seq = event.seq;
+        if self.table_id.is_none_or(|table_id| Some(table_id) == event.table_id) {
+            let message = sse::Event::default().id(events::resume_token(event.seq)).event(event.kind.as_str());
+            match message.json_data(event) {
+                Ok(message) => self.pending.push_back(message),
+                Err(err) => eprintln!("Failed to serialize event {}: {}", event.seq, err),
+            }
+        }
+    }
+
+    /// Tell the display events were missed, it should reload and go on from the id of this one
+    fn resync(&mut self, last_seq: u64) {
+        self.last_seq = last_seq;
+        let message = sse::Event::default().id(events::resume_token(last_seq)).event("resync").data("Events were missed, reload");
+        self.pending.push_back(message);
+    }
+}
+
+/// Events of a table, or of all of them, as Server-Sent Events. The id of each event is the resume token
+/// a browser sends back as Last-Event-ID when it reconnects, the events after it are replayed first
+pub fn feed(table_id: Option<i64>, resume: Option<ResumePoint>) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    let subscription = events::subscribe_from(resume);
+    let mut feed = Feed { table_id, last_seq: 0, pending: VecDeque::new(), receiver: subscription.receiver };
+    match &subscription.missed {
+        Some(missed) => missed.iter().for_each(|event| feed.queue(event)),
+        None => feed.resync(subscription.last_seq),
+    }
+    feed.last_seq = subscription.last_seq;
+    stream::unfold(feed, |mut feed| async move {
+        loop {
+            if let Some(message) = feed.pending.pop_front() {
+                return Some((Ok(message), feed));
+            }
+            match feed.receiver.recv().await {
+                Ok(event) => feed.queue(&event),
+                // Fell behind the live events, catch up from the history
+                Err(RecvError::Lagged(_)) => match events::since(feed.last_seq) {
+                    Some(missed) => missed.iter().for_each(|event| feed.queue(event)),
+                    None => feed.resync(events::latest_seq()),
+                },
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
@@ -0,0 +1,169 @@
+// src/validation.rs
+use crate::events::EventKind;
+use crate::promotions;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::borrow::Cow;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+/// A single failed rule of a request, field is the path to the value e.g. items[0].menu_id
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, code: &str, message: impl Into<String>) -> FieldError {
+        FieldError { field: field.into(), code: code.to_string(), message: message.into() }
+    }
+}
+
+/// Flatten the errors of a validated request into field errors, sorted by field
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut result = Vec::new();
+    collect(errors, "", &mut result);
+    result.sort_by(|a, b| a.field.cmp(&b.field));
+    result
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, result: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = |field: &str| if prefix.is_empty() { field.to_string() } else { format!("{}.{}", prefix, field) };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                for error in errors {
+                    // Rules across fields are reported on the field they name
+                    let field = match error.params.get("field").and_then(|field| field.as_str()) {
+                        Some(named) if field == "__all__" => named,
+                        _ => field.as_ref(),
+                    };
+                    result.push(FieldError::new(path(field), &error.code, message(error)));
+                }
+            }
+            ValidationErrorsKind::Struct(errors) => collect(errors, &path(field), result),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect(errors, &format!("{}[{}]", path(field), index), result);
+                }
+            }
+        }
+    }
+}
+
+/// Message of a failed rule, described from its parameters if the rule has no message of its own
+fn message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).filter(|value| !value.is_null()).map(|value| value.to_string());
+    match (error.code.as_ref(), param("min"), param("max")) {
+        ("length", Some(min), Some(max)) => format!("Must be between {} and {} characters", min, max),
+        ("length", None, Some(max)) => format!("Must be at most {} characters", max),
+        ("length", Some(min), None) => format!("Must be at least {} characters", min),
+        ("range", Some(min), Some(max)) => format!("Must be between {} and {}", min, max),
+        ("range", None, Some(max)) => format!("Must be at most {}", max),
+        ("range", Some(min), None) => format!("Must be at least {}", min),
+        _ => "Is invalid".to_string(),
+    }
+}
+
+/// Codes like T-01: letters, digits, spaces, '-' and '_', not starting or ending with a space
+pub fn code_chars(value: &str) -> Result<(), ValidationError> {
+    not_blank(value)?;
+    if value.trim() != value || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ') {
+        return Err(invalid("charset", "May only contain letters, digits, spaces, '-' and '_'"));
+    }
+    Ok(())
+}
+
+/// Free text like names, anything printable but not only whitespace
+pub fn printable(value: &str) -> Result<(), ValidationError> {
+    not_blank(value)?;
+    if value.chars().any(char::is_control) {
+        return Err(invalid("charset", "May not contain control characters"));
+    }
+    Ok(())
+}
+
+/// Every id of a list must be positive
+pub fn positive_ids(ids: &[i64]) -> Result<(), ValidationError> {
+    match ids.iter().all(|id| *id > 0) {
+        true => Ok(()),
+        false => Err(invalid("range", "Ids must be at least 1")),
+    }
+}
+
+/// Status of an order, open or closed
+pub fn order_status(value: &str) -> Result<(), ValidationError> {
+    match value {
+        "open" | "closed" => Ok(()),
+        _ => Err(invalid("choice", "Must be open or closed")),
+    }
+}
+
+/// A date as YYYY-MM-DD or a time as YYYY-MM-DD HH:MM:SS, a T may separate date and time
+pub fn timestamp(value: &str) -> Result<(), ValidationError> {
+    let shape = |pattern: &str| {
+        value.len() == pattern.len() && value.chars().zip(pattern.chars()).all(|(c, p)| match p {
+            '9' => c.is_ascii_digit(),
+            ' ' => c == ' ' || c == 'T',
+            _ => c == p,
+        })
+    };
+    let in_range = |range: std::ops::Range<usize>, min: u32, max: u32| value.get(range).and_then(|part| part.parse().ok()).is_some_and(|part: u32| (min..=max).contains(&part));
+    let date = shape("9999-99-99") || shape("9999-99-99 99:99:99");
+    match date && in_range(5..7, 1, 12) && in_range(8..10, 1, 31) {
+        true => Ok(()),
+        false => Err(invalid("format", "Must be YYYY-MM-DD or YYYY-MM-DD HH:MM:SS")),
+    }
+}
+
+/// A time of the day as HH:MM
+pub fn time_of_day(value: &str) -> Result<(), ValidationError> {
+    match value.len() == 5 && promotions::minute_of_day(value).is_some() {
+        true => Ok(()),
+        false => Err(invalid("format", "Must be HH:MM")),
+    }
+}
+
+/// Where webhooks are sent, an http or https URL
+pub fn webhook_url(value: &str) -> Result<(), ValidationError> {
+    let host = value.strip_prefix("https://").or_else(|| value.strip_prefix("http://"));
+    match host {
+        Some(host) if !host.is_empty() && !host.starts_with('/') && !value.chars().any(|c| c.is_whitespace() || c.is_control()) => Ok(()),
+        _ => Err(invalid("url", "Must be an http or https URL")),
+    }
+}
+
+/// Kinds of events a webhook is sent, at least one and each a known kind
+pub fn event_kinds(kinds: &[String]) -> Result<(), ValidationError> {
+    match kinds.iter().all(|kind| EventKind::parse(kind).is_some()) {
+        true => Ok(()),
+        false => {
+            let names: Vec<&str> = EventKind::ALL.iter().map(|kind| kind.as_str()).collect();
+            let (last, others) = names.split_last().expect("There are event kinds");
+            let message = format!("Must be {} or {}", others.join(", "), last);
+            Err(ValidationError::new("choice").with_message(Cow::Owned(message)))
+        }
+    }
+}
+
+fn not_blank(value: &str) -> Result<(), ValidationError> {
+    match value.trim().is_empty() {
+        true => Err(invalid("blank", "Can't be blank")),
+        false => Ok(()),
+    }
+}
+
+/// A failed rule across several fields of a request, reported on the field named
+pub fn invalid_field(field: &'static str, code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = invalid(code, message);
+    error.add_param(Cow::Borrowed("field"), &field);
+    error
+}
+
+fn invalid(code: &'static str, message: &'static str) -> ValidationError {
+    ValidationError::new(code).with_message(Cow::Borrowed(message))
+}
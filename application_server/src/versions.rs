@@ -0,0 +1,156 @@
+// src/versions.rs
+use crate::errors::ErrorEnvelope;
+use serde_json::{json, Map, Value};
+use std::env;
+use warp::http::header::{HeaderMap, HeaderValue, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
+use warp::hyper::body::{to_bytes, Body};
+use warp::reply::Response;
+use warp::Reply;
+
+/// Amounts in cents that v2 sends as decimal amounts, e.g. 1250 as 12.5
+const MONEY_FIELDS: &[&str] = &[
+    "price", "unit_price", "amount", "subtotal", "service_charge", "total", "tips", "tip", "tendered",
+    "change_due", "paid", "balance_due", "discounts", "service_charges",
+];
+
+/// When the deprecated paths and versions were deprecated, 2026-10-19, as an RFC 9745 Deprecation header
+pub const DEPRECATED_SINCE: &str = "@1792368000";
+
+/// Versions of the API, every version is mounted under its prefix and serves the same handlers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiVersion {
+    V1, // Shapes as the handlers return them, also served without a prefix for tablets already deployed
+    V2, // Decimal money, menus nested in order items and a state in place of archived and status
+}
+
+impl ApiVersion {
+    pub const LATEST: ApiVersion = ApiVersion::V2;
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        }
+    }
+
+    /// HTTP date after which the version goes away, configured with e.g.
+    /// API_V1_SUNSET="Fri, 31 Dec 2027 23:59:59 GMT"
+    fn sunset(self) -> Option<String> {
+        env::var(format!("API_{}_SUNSET", self.prefix().to_uppercase())).ok()
+    }
+
+    /// Reshape a response body of the handlers into the shape of this version
+    pub fn serialize(self, body: Value) -> Value {
+        match self {
+            ApiVersion::V1 => body,
+            ApiVersion::V2 => v2(body),
+        }
+    }
+}
+
+/// Turn the reply of a handler into the response of a version. mounted is true under the version prefix,
+/// then links in the body point under it too. Error envelopes and bodiless replies are left alone
+pub async fn render(version: ApiVersion, mounted: bool, reply: impl Reply) -> Result<Response, warp::Rejection> {
+    let response = reply.into_response();
+    let is_json = response.headers().get(CONTENT_TYPE).is_some_and(|value| value == "application/json");
+    // Replies that are not errors can still fail, e.g. a batch that was rolled back.
+    // Files are sent as they are so they can be imported again
+    let is_envelope = response.extensions().get::<ErrorEnvelope>().is_some();
+    let is_file = response.headers().contains_key(CONTENT_DISPOSITION);
+    let mut response = match !is_envelope && !is_file && is_json && (mounted || version != ApiVersion::V1) {
+        true => {
+            let (mut parts, body) = response.into_parts();
+            let bytes = to_bytes(body).await.unwrap_or_default();
+            let body = match serde_json::from_slice::<Value>(&bytes) {
+                Ok(mut body) => {
+                    if mounted {
+                        prefix_links(&mut body, version.prefix());
+                    }
+                    parts.headers.remove(CONTENT_LENGTH);
+                    Body::from(version.serialize(body).to_string())
+                }
+                Err(_) => Body::from(bytes),
+            };
+            Response::from_parts(parts, body)
+        }
+        false => response,
+    };
+    sunset_headers(version, version.sunset().as_deref(), response.headers_mut());
+    Ok(response)
+}
+
+/// Announce the end of a version with a Sunset header, the deprecation and where clients should move to
+pub fn sunset_headers(version: ApiVersion, sunset: Option<&str>, headers: &mut HeaderMap) {
+    let sunset = match sunset.and_then(|sunset| HeaderValue::from_str(sunset).ok()) {
+        Some(sunset) if version != ApiVersion::LATEST => sunset,
+        _ => return,
+    };
+    headers.insert("sunset", sunset);
+    headers.insert("deprecation", HeaderValue::from_static(DEPRECATED_SINCE));
+    let successor = format!("</{}/>; rel=\"successor-version\"", ApiVersion::LATEST.prefix());
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.append("link", link);
+    }
+}
+
+/// Listing links point at the unprefixed paths
+fn prefix_links(body: &mut Value, prefix: &str) {
+    if let Some(Value::String(next)) = body.pointer_mut("/links/next") {
+        if next.starts_with('/') {
+            *next = format!("/{}{}", prefix, next);
+        }
+    }
+}
+
+/// v2 shape of a v1 body
+fn v2(body: Value) -> Value {
+    match body {
+        Value::Array(values) => Value::Array(values.into_iter().map(v2).collect()),
+        Value::Object(object) => Value::Object(v2_object(object)),
+        body => body,
+    }
+}
+
+fn v2_object(object: Map<String, Value>) -> Map<String, Value> {
+    let mut menu = Map::new();
+    let mut reshaped = Map::new();
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            ("menu_id", value) => {
+                menu.insert("id".to_string(), value);
+            }
+            ("menu_name", value) => {
+                menu.insert("name".to_string(), value);
+            }
+            ("menus", Value::Array(items)) => {
+                reshaped.insert("items".to_string(), v2(Value::Array(items)));
+            }
+            ("archived", Value::Bool(archived)) => {
+                reshaped.insert("state".to_string(), json!(if archived { "archived" } else { "active" }));
+            }
+            ("status", value) => {
+                reshaped.insert("state".to_string(), value);
+            }
+            ("order_status", value) => {
+                reshaped.insert("order_state".to_string(), value);
+            }
+            (key, Value::Number(cents)) if MONEY_FIELDS.contains(&key) => {
+                let amount = cents.as_i64().map_or(Value::Number(cents), |cents| json!(cents as f64 / 100.0));
+                reshaped.insert(key.to_string(), amount);
+            }
+            (key, value) => {
+                reshaped.insert(key.to_string(), v2(value));
+            }
+        }
+    }
+    // Order items and bill lines carry their menu as an object, promotions keep menu_id as they only refer to it
+    if menu.contains_key("name") {
+        if let Some(category) = reshaped.remove("category") {
+            menu.insert("category".to_string(), category);
+        }
+        reshaped.insert("menu".to_string(), Value::Object(menu));
+    } else if let Some(menu_id) = menu.remove("id") {
+        reshaped.insert("menu_id".to_string(), menu_id);
+    }
+    reshaped
+}
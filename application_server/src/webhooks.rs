@@ -0,0 +1,238 @@
+// src/webhooks.rs
+use crate::events::{self, Event};
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use serde_json::json;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Hex HMAC-SHA256 of "{timestamp}.{body}" with the secret of the webhook, prefixed with sha256=
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+/// Unix time in seconds the delivery was signed at, receivers should refuse old ones
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+/// Id of the delivery, the same on every attempt so receivers can skip the ones they already have
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+
+/// How often the due deliveries are sent
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Deliveries sent at once, the rest wait for the next poll
+const BATCH: i64 = 50;
+
+/// Events of the log queued at once, the rest are queued on the next poll
+const LOG_BATCH: i64 = 500;
+
+/// How long a receiver has to answer before the attempt counts as failed
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a delivery is attempted and how long to wait in between, in seconds.
+/// The wait doubles after every failed attempt up to max_delay
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: i64,
+    pub base_delay: i64,
+    pub max_delay: i64,
+}
+
+impl Default for RetryPolicy {
+    /// Eight attempts over about 20 minutes
+    fn default() -> RetryPolicy {
+        RetryPolicy { max_attempts: 8, base_delay: 10, max_delay: 3600 }
+    }
+}
+
+impl RetryPolicy {
+    /// Seconds to wait before the next attempt after the given number of attempts failed
+    pub fn delay(&self, attempts: i64) -> i64 {
+        let doublings = (attempts - 1).clamp(0, 30) as u32;
+        self.base_delay.saturating_mul(1 << doublings).min(self.max_delay)
+    }
+}
+
+/// A secret for a webhook created without one
+pub fn generate_secret() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Signature of a body sent at a time, as sent in the signature header
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Queue the deliveries of the events logged after the cursor of each webhook, to the active webhooks with their kinds,
+/// and move the cursors past them. Paused webhooks skip the events. Returns how many were queued.
+/// The cursors move in the transaction of the deliveries, so an event is queued once even if the server stops
+pub fn enqueue(conn: &Connection) -> rusqlite::Result<usize> {
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let webhooks: Vec<(i64, String, bool, i64)> = {
+        let mut stmt = tx.prepare("SELECT id, events, active, last_event_id FROM webhooks ORDER BY id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let Some(oldest) = webhooks.iter().map(|(_, _, _, cursor)| *cursor).min() else {
+        return Ok(0);
+    };
+    let logged = events::logged_after(&tx, oldest, LOG_BATCH)?;
+    let Some(&(last_id, _)) = logged.last() else {
+        return Ok(0);
+    };
+    let mut queued = 0;
+    for (webhook_id, kinds, active, cursor) in &webhooks {
+        let wanted = logged.iter().filter(|(id, event)| id > cursor && *active && kinds.split(',').any(|kind| kind == event.kind.as_str()));
+        for (id, event) in wanted {
+            tx.execute(
+                "INSERT INTO webhook_deliveries (webhook_id, event, payload) VALUES (?1, ?2, ?3)",
+                params![webhook_id, event.kind.as_str(), payload(*id, event)],
+            )?;
+            queued += 1;
+        }
+        tx.execute("UPDATE webhooks SET last_event_id = ?1 WHERE id = ?2 AND last_event_id < ?1", params![last_id, webhook_id])?;
+    }
+    tx.commit()?;
+    Ok(queued)
+}
+
+/// The body of a delivery, the id is that of the event in the log and the same for every webhook
+fn payload(id: i64, event: &Event) -> String {
+    let mut data = json!(event);
+    // The seq of live events does not apply to events read from the log
+    if let Some(data) = data.as_object_mut() {
+        data.remove("seq");
+    }
+    json!({"id": id, "event": event.kind.as_str(), "data": data}).to_string()
+}
+
+/// A pending delivery with what is needed to send it
+struct Delivery {
+    id: i64,
+    url: String,
+    secret: String,
+    event: String,
+    payload: String,
+    attempts: i64,
+}
+
+/// Why an attempt failed, with the status if the receiver answered
+struct Failure {
+    status: Option<u16>,
+    message: String,
+}
+
+/// Pending deliveries of active webhooks whose next attempt is due, oldest first
+fn due(conn: &Connection) -> rusqlite::Result<Vec<Delivery>> {
+    let mut stmt = conn.prepare(
+        "SELECT d.id, w.url, w.secret, d.event, d.payload, d.attempts
+        FROM webhook_deliveries as d
+        JOIN webhooks as w on w.id = d.webhook_id
+        WHERE d.status = 'pending' AND w.active = 1 AND d.next_attempt_at <= CURRENT_TIMESTAMP
+        ORDER BY d.id LIMIT ?1",
+    )?;
+    let rows = stmt.query_map(params![BATCH], |row| {
+        Ok(Delivery { id: row.get(0)?, url: row.get(1)?, secret: row.get(2)?, event: row.get(3)?, payload: row.get(4)?, attempts: row.get(5)? })
+    })?;
+    rows.collect()
+}
+
+/// Record the outcome of an attempt. A failed delivery is retried later, or dead after its last attempt
+fn record(conn: &Connection, delivery: &Delivery, outcome: &Result<u16, Failure>, policy: &RetryPolicy) -> rusqlite::Result<()> {
+    let attempts = delivery.attempts + 1;
+    match outcome {
+        Ok(status) => conn.execute(
+            "UPDATE webhook_deliveries SET status = 'delivered', attempts = ?1, last_status = ?2, last_error = NULL, delivered_at = CURRENT_TIMESTAMP WHERE id = ?3",
+            params![attempts, status, delivery.id],
+        )?,
+        Err(failure) => conn.execute(
+            "UPDATE webhook_deliveries SET status = ?1, attempts = ?2, last_status = ?3, last_error = ?4, next_attempt_at = datetime('now', ?5) WHERE id = ?6",
+            params![
+                if attempts >= policy.max_attempts { "dead" } else { "pending" },
+                attempts,
+                failure.status,
+                failure.message,
+                format!("+{} seconds", policy.delay(attempts)),
+                delivery.id,
+            ],
+        )?,
+    };
+    Ok(())
+}
+
+/// Sends the deliveries of the events published to the webhooks. Every step opens its own database
+/// connection as the REST routes do, none is held while waiting on a receiver
+pub struct Dispatcher {
+    connect: Box<dyn Fn() -> Connection + Send + Sync>,
+    client: reqwest::Client,
+    policy: RetryPolicy,
+}
+
+impl Dispatcher {
+    pub fn new(connect: impl Fn() -> Connection + Send + Sync + 'static, policy: RetryPolicy) -> Dispatcher {
+        let client = reqwest::Client::builder().timeout(TIMEOUT).build().expect("Failed to create HTTP client");
+        Dispatcher { connect: Box::new(connect), client, policy }
+    }
+
+    pub fn enqueue(&self) -> rusqlite::Result<usize> {
+        enqueue(&(self.connect)())
+    }
+
+    /// Attempt the deliveries that are due, returns how many were accepted by their receivers
+    pub async fn deliver_due(&self) -> rusqlite::Result<usize> {
+        let due = due(&(self.connect)())?;
+        let outcomes = join_all(due.iter().map(|delivery| self.send(delivery))).await;
+        let conn = (self.connect)();
+        for (delivery, outcome) in due.iter().zip(&outcomes) {
+            record(&conn, delivery, outcome, &self.policy)?;
+        }
+        Ok(outcomes.iter().filter(|outcome| outcome.is_ok()).count())
+    }
+
+    /// POST a delivery, any 2xx answer accepts it
+    async fn send(&self, delivery: &Delivery) -> Result<u16, Failure> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64);
+        let response = self.client
+            .post(&delivery.url)
+            .header("content-type", "application/json")
+            .header(SIGNATURE_HEADER, sign(&delivery.secret, timestamp, &delivery.payload))
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(DELIVERY_HEADER, delivery.id)
+            .header(EVENT_HEADER, &delivery.event)
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|err| Failure { status: None, message: err.to_string() })?;
+        let status = response.status();
+        match status.is_success() {
+            true => Ok(status.as_u16()),
+            false => Err(Failure { status: Some(status.as_u16()), message: format!("Receiver answered {}", status) }),
+        }
+    }
+}
+
+/// Queue the deliveries of the events logged and send them until the server stops. The live events only wake
+/// the dispatcher up, what is queued is read from the log so nothing is missed while it lags or is stopped
+pub async fn run(dispatcher: Dispatcher) {
+    let mut receiver = events::subscribe();
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            _ = poll.tick() => {
+                if let Err(err) = dispatcher.deliver_due().await {
+                    eprintln!("Failed to deliver webhooks: {}", err);
+                }
+            },
+        }
+        if let Err(err) = dispatcher.enqueue() {
+            eprintln!("Failed to queue webhooks: {}", err);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+// src/websocket.rs
+use crate::errors::ApiError;
+use crate::events::{self, Event, ResumePoint};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tokio::sync::broadcast::error::RecvError;
+use warp::ws::{Message, WebSocket};
+
+/// Topics a client can be subscribed to at once
+const MAX_TOPICS: usize = 20;
+
+/// What a client can subscribe to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Topic {
+    Table(i64), // table:{id}, the orders of a table and their items
+    Kitchen, // kitchen, every item ordered, changed or removed
+    Station(String), // station:{name}, the items of the menus prepared at a station
+}
+
+impl Topic {
+    pub fn parse(topic: &str) -> Option<Topic> {
+        match topic.split_once(':') {
+            None if topic == "kitchen" => Some(Topic::Kitchen),
+            Some(("table", id)) => id.parse().ok().filter(|id| *id > 0).map(Topic::Table),
+            Some(("station", name)) if !name.is_empty() && name.len() <= 50 => Some(Topic::Station(name.to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        match self {
+            Topic::Table(table_id) => event.table_id == Some(*table_id),
+            Topic::Kitchen => event.kind.is_item(),
+            Topic::Station(name) => event.kind.is_item() && event.station.as_deref() == Some(name),
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Topic::Table(table_id) => write!(f, "table:{}", table_id),
+            Topic::Kitchen => write!(f, "kitchen"),
+            Topic::Station(name) => write!(f, "station:{}", name),
+        }
+    }
+}
+
+/// Parse topics, comma separated in the query or a list in a message
+pub fn parse_topics<'a>(topics: impl IntoIterator<Item = &'a str>) -> Result<Vec<Topic>, ApiError> {
+    let mut parsed = Vec::new();
+    for topic in topics.into_iter().map(str::trim).filter(|topic| !topic.is_empty()) {
+        let topic = Topic::parse(topic).ok_or_else(|| ApiError::bad_request(format!("Unknown topic {}, use table:{{id}}, kitchen or station:{{name}}", topic)))?;
+        if !parsed.contains(&topic) {
+            parsed.push(topic);
+        }
+    }
+    if parsed.len() > MAX_TOPICS {
+        return Err(ApiError::bad_request(format!("At most {} topics can be subscribed to", MAX_TOPICS)));
+    }
+    Ok(parsed)
+}
+
+/// Messages from the client, e.g. {"action": "subscribe", "topics": ["table:3"]}
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+enum ClientMessage {
+    Subscribe { topics: Vec<String> },
+    Unsubscribe { topics: Vec<String> },
+}
+
+/// Messages to the client. Every event carries the resume token to reconnect with
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    /// The topics subscribed to now, sent after every change to them
+    Subscribed { topics: Vec<String>, resume_token: String },
+    Event { topics: Vec<String>, resume_token: String, event: &'a Event },
+    /// Events were missed and can't be replayed, reload over REST and go on from here
+    Resync { resume_token: String },
+    Error { message: String },
+}
+
+impl ServerMessage<'_> {
+    fn to_message(&self) -> Message {
+        Message::text(serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+/// A connected client and what it has been sent
+struct Session {
+    topics: Vec<Topic>,
+    last_seq: u64,
+}
+
+impl Session {
+    fn subscribed(&self) -> ServerMessage<'static> {
+        let topics = self.topics.iter().map(Topic::to_string).collect();
+        ServerMessage::Subscribed { topics, resume_token: events::resume_token(self.last_seq) }
+    }
+
+    /// The message of an event for the topics it matches, None if it matches none or was sent already
+    fn deliver<'a>(&mut self, event: &'a Event) -> Option<ServerMessage<'a>> {
+        if event.seq <= self.last_seq {
+            return None;
+        }
+        self.last_seq = event.seq;
+        let topics: Vec<String> = self.topics.iter().filter(|topic| topic.matches(event)).map(Topic::to_string).collect();
+        if topics.is_empty() {
+            return None;
+        }
+        Some(ServerMessage::Event { topics, resume_token: events::resume_token(event.seq), event })
+    }
+
+    /// Change the topics as the client asked, the reply is the new subscription or what was wrong
+    fn handle(&mut self, text: &str) -> ServerMessage<'static> {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(err) => return ServerMessage::Error { message: format!("Invalid message: {}", err) },
+        };
+        let (subscribe, topics) = match message {
+            ClientMessage::Subscribe { topics } => (true, topics),
+            ClientMessage::Unsubscribe { topics } => (false, topics),
+        };
+        let topics = match parse_topics(topics.iter().map(String::as_str)) {
+            Ok(topics) => topics,
+            Err(err) => return ServerMessage::Error { message: err.envelope().message },
+        };
+        let mut changed = self.topics.clone();
+        if subscribe {
+            changed.extend(topics.into_iter().filter(|topic| !self.topics.contains(topic)));
+        } else {
+            changed.retain(|topic| !topics.contains(topic));
+        }
+        if changed.len() > MAX_TOPICS {
+            return ServerMessage::Error { message: format!("At most {} topics can be subscribed to", MAX_TOPICS) };
+        }
+        self.topics = changed;
+        self.subscribed()
+    }
+}
+
+/// Push the events of the topics to a client until it goes away. Events after the resume point are
+/// replayed first, if they can't be the client is told to resync
+pub async fn serve(socket: WebSocket, topics: Vec<Topic>, resume: Option<ResumePoint>) {
+    let (mut sender, mut receiver) = socket.split();
+    let subscription = events::subscribe_from(resume);
+    let mut events = subscription.receiver;
+    let mut session = Session { topics, last_seq: 0 };
+    let mut messages = Vec::new();
+    match &subscription.missed {
+        Some(missed) => messages.extend(missed.iter().filter_map(|event| session.deliver(event)).map(|message| message.to_message())),
+        None => messages.push(ServerMessage::Resync { resume_token: events::resume_token(subscription.last_seq) }.to_message()),
+    }
+    session.last_seq = subscription.last_seq;
+    messages.push(session.subscribed().to_message());
+    for message in messages {
+        if sender.send(message).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let reply = tokio::select! {
+            message = receiver.next() => match message {
+                Some(Ok(message)) if message.is_text() => vec![session.handle(message.to_str().unwrap_or_default()).to_message()],
+                Some(Ok(message)) if message.is_close() => break,
+                Some(Ok(_)) => continue, // Pings are answered by warp
+                _ => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => session.deliver(&event).map(|message| message.to_message()).into_iter().collect(),
+                // Fell behind the live events, catch up from the history
+                Err(RecvError::Lagged(_)) => match events::since(session.last_seq) {
+                    Some(missed) => missed.iter().filter_map(|event| session.deliver(event)).map(|message| message.to_message()).collect(),
+                    None => {
+                        session.last_seq = events::latest_seq();
+                        vec![ServerMessage::Resync { resume_token: events::resume_token(session.last_seq) }.to_message()]
+                    }
+                },
+                Err(RecvError::Closed) => break,
+            },
+        };
+        for message in reply {
+            if sender.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+}
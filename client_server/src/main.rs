@@ -1,19 +1,20 @@
 use reqwest::Client;
 use serde_json::Value;
+use rand::Rng;
 use rand::seq::SliceRandom;
 use tokio::time::{Duration, timeout};
 
 
 async fn create_tables()->Vec<i64>{
     let client = Client::new();
-    let table_codes = vec!["T-01", "T-02", "T-03", "T-04", "T-05"];
+    let table_codes = ["T-01", "T-02", "T-03", "T-04", "T-05"];
     let mut table_ids = Vec::new();
 
-    for index in 0..5 {
+    for code in table_codes {
         // Simulate creating a table
         let response: Value = client
-            .post("http://localhost:3030/tables/create")
-            .json(&serde_json::json!({"code": table_codes[index]}))
+            .post("http://localhost:3030/v1/tables")
+            .json(&serde_json::json!({"code": code}))
             .send()
             .await
             .expect("Failed to create table")
@@ -25,7 +26,7 @@ async fn create_tables()->Vec<i64>{
         table_ids.push(response["id"].as_i64().expect("Missing or invalid id"));
     }
 
-    return table_ids;
+    table_ids
 }
 
 async fn create_menus()->Vec<i64>{
@@ -33,11 +34,11 @@ async fn create_menus()->Vec<i64>{
     let menu_names = ["Menu-01", "Menu-02", "Menu-03", "Menu-04", "Menu-05"];
     let mut menu_ids = Vec::new();
 
-    for index in 0..5 {
+    for name in menu_names {
         // Simulate creating a menu
         let response: Value = client
-            .post("http://localhost:3030/menus/create")
-            .json(&serde_json::json!({"name": menu_names[index]}))
+            .post("http://localhost:3030/v1/menus")
+            .json(&serde_json::json!({"name": name, "price": rand::thread_rng().gen_range(5..=30) * 50}))
             .send()
             .await
             .expect("Failed to create table")
@@ -49,7 +50,7 @@ async fn create_menus()->Vec<i64>{
         menu_ids.push(response["id"].as_i64().expect("Missing or invalid id"));
     }
 
-    return menu_ids;
+    menu_ids
 }
 
 
@@ -62,13 +63,17 @@ async fn order_simulation(client: &Client, table_ids: &[i64], menu_ids: &[i64])
             let mut menu_subarray = menu_ids.to_vec();
             menu_subarray.shuffle(&mut rand::thread_rng());
             menu_subarray.truncate(3);
+            // Every menu goes to a random seat of the table
+            let items: Vec<Value> = menu_subarray
+                .iter()
+                .map(|menu_id| serde_json::json!({"menu_id": menu_id, "seat": rand::thread_rng().gen_range(1..=4)}))
+                .collect();
             tokio::spawn(async move {
                 // 1. Create Order
                 let response = client
-                    .post("http://localhost:3030/orders/create")
+                    .post(format!("http://localhost:3030/v1/tables/{}/orders", table_id))
                     .json(&serde_json::json!({
-                        "table_id": table_id,
-                        "menu_ids": menu_subarray,
+                        "items": items,
                     }))
                     .send()
                     .await
@@ -78,11 +83,14 @@ async fn order_simulation(client: &Client, table_ids: &[i64], menu_ids: &[i64])
                     .expect("Failed to parse response");
 
                 println!("Created Order for table {} with menus {:?}: {:?}", table_id, menu_subarray, response);
+                let Some(order_id) = response["id"].as_i64() else {
+                    return;
+                };
                 tokio::time::sleep(Duration::from_secs(1)).await;
 
-                // 2. Get All Items from Order by Table ID
+                // 2. Get All Items of the Order
                 let response = client
-                    .get(&format!("http://localhost:3030/tables/{}/items", table_id))
+                    .get(format!("http://localhost:3030/v1/orders/{}/items", order_id))
                     .send()
                     .await
                     .expect("Failed to get all items")
@@ -90,8 +98,14 @@ async fn order_simulation(client: &Client, table_ids: &[i64], menu_ids: &[i64])
                     .await
                     .expect("Failed to parse response");
 
+                let mut item_id = None;
                 if let Some(items) = response.as_array() {
                     let mut new_array = Vec::new();
+                    // The item of the first menu ordered is read and removed below
+                    item_id = items
+                        .iter()
+                        .find(|item| item.get("menu_id").and_then(|v| v.as_i64()) == menu_subarray.first().copied())
+                        .and_then(|item| item.get("id").and_then(|v| v.as_i64()));
                 
                     for item in items {
                         if let (Some(menu), Some(time), Some(quantity)) = (
@@ -99,7 +113,8 @@ async fn order_simulation(client: &Client, table_ids: &[i64], menu_ids: &[i64])
                             item.get("cooking_time").and_then(|v| v.as_i64()),
                             item.get("quantity").and_then(|v| v.as_i64()),
                         ) {
-                            let new_item = (menu, time, quantity);
+                            let seat = item.get("seat").and_then(|v| v.as_i64());
+                            let new_item = (menu, seat, time, quantity);
                             new_array.push(new_item);
                         }
                     }
@@ -109,10 +124,10 @@ async fn order_simulation(client: &Client, table_ids: &[i64], menu_ids: &[i64])
                 }
                 tokio::time::sleep(Duration::from_secs(1)).await;
 
-                // 3. Get Specific Item from Table
-                if let Some(menu_id) = menu_subarray.first() {
+                // 3. Get Specific Item of the Order
+                if let Some(item_id) = item_id {
                     let response = client
-                        .get(&format!("http://localhost:3030/tables/{}/items/{}", table_id, *menu_id))
+                        .get(format!("http://localhost:3030/v1/orders/{}/items/{}", order_id, item_id))
                         .send()
                         .await
                         .expect("Failed to get specific item")
@@ -120,14 +135,14 @@ async fn order_simulation(client: &Client, table_ids: &[i64], menu_ids: &[i64])
                         .await
                         .expect("Failed to parse response");
 
-                    println!("Menu {} from table {} is: Menu: {:?}, Cooking Time: {:?}, Quantity: {:?}", menu_id, table_id, response["menu_name"].as_str(), response["cooking_time"].as_i64(), response["quantity"].as_i64());
+                    println!("Item {} from table {} is: Menu: {:?}, Cooking Time: {:?}, Quantity: {:?}", item_id, table_id, response["menu_name"].as_str(), response["cooking_time"].as_i64(), response["quantity"].as_i64());
                     tokio::time::sleep(Duration::from_secs(1)).await;
                 }
 
-                // 4. Remove One Item from Table
-                if let Some(menu_id) = menu_subarray.first() {
+                // 4. Remove One Item from the Order
+                if let Some(item_id) = item_id {
                     let response = client
-                        .delete(&format!("http://localhost:3030/orders/{}/items/{}", table_id, *menu_id))
+                        .delete(format!("http://localhost:3030/v1/orders/{}/items/{}", order_id, item_id))
                         .send()
                         .await
                         .expect("Failed to remove item")
@@ -135,7 +150,7 @@ async fn order_simulation(client: &Client, table_ids: &[i64], menu_ids: &[i64])
                         .await
                         .expect("Failed to parse response");
 
-                    println!("Removed Menu {} from Table {}: {:?}", menu_id, table_id, response);
+                    println!("Removed Item {} from Table {}: {:?}", item_id, table_id, response);
                     tokio::time::sleep(Duration::from_secs(1)).await;
                 }
             })